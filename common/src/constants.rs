@@ -56,6 +56,17 @@ pub const USERTHEMES: &[(&str,&str)] = &[
 pub const UPVOTE: &str = "+";
 pub const DOWNVOTE: &str = "-";
 pub const VOTETYPE: &str = "vote";
+pub const REACTIONTYPE: &str = "reaction";
+
+/// The reactions anyone can put on a post (one per user per post). The key is what's stored in the
+/// api, so don't change those once people are using them; the label is free to change
+pub const REACTIONS: &[(&str,&str)] = &[
+    ("plusone", "👍"),
+    ("heart", "❤️"),
+    ("laugh", "😆"),
+    ("wow", "😮"),
+    ("thinking", "🤔")
+];
 
 pub const POPSCORE1SORT: &str = "popScore1_desc";
 pub const ANYSYSTEM: &str = "any";
@@ -63,6 +74,7 @@ pub const PTCSYSTEM: &str = "ptc";
 pub const MARKUPBBCODE: &str = "bbcode";
 pub const DOCSPARENTHASH: &str = "system-docparent";
 pub const DOCSGROUPUSERNAME: &str = "docsgroup";
+pub const USERCONFIGVARIABLE: &str = "sbs-rust-userconfig"; //Settings synced to the account
 //pub const MARKUP12y: &str = "12y";
 //pub const MARKUP12y2: &str = "12y2";
 
@@ -104,6 +116,13 @@ pub const THREADTYPES : &[&str] = &[
     SBSPageType::DOCUMENTATION
 ];
 
+/// What shows up when browsing a tag (keyword)
+pub const TAGTYPES : &[&str] = &[
+    SBSPageType::PROGRAM,
+    SBSPageType::RESOURCE,
+    SBSPageType::FORUMTHREAD
+];
+
 pub const FORUMCATEGORYTYPES : &[&str] = &[
     SBSPageType::FORUMCATEGORY,
     SBSPageType::SUBMISSIONS,
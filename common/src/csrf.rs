@@ -0,0 +1,32 @@
+use sha1::{Digest, Sha1};
+
+//Synchronizer tokens against cross-site form posts. The token is derived from the session token, which
+//is already a secret only the user's browser has, so there's nothing to store: any page rendered for a
+//session embeds the same token, and a POST has to send it back. Hashing it means the session itself
+//never ends up in a page. Logged out, the secret is a random pre-session cookie instead (the server
+//hands one out with the first page), so the token is still different for every browser.
+
+/// The form field the token goes in (see render::csrf_input)
+pub const CSRFFIELD: &str = "csrf";
+
+/// The token every form posted with the given secret (the session, or the pre-session if logged out)
+/// must carry. No secret, no token: those forms can't be posted
+pub fn csrf_token(secret: Option<&str>) -> String
+{
+    match secret {
+        Some(secret) if !secret.is_empty() => {
+            let mut hasher = Sha1::new();
+            hasher.update(b"sbs-csrf:");
+            hasher.update(secret.as_bytes());
+            hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+        },
+        _ => String::new()
+    }
+}
+
+/// Whether what was sent is the token for the secret. Missing is never valid
+pub fn csrf_valid(secret: Option<&str>, sent: Option<&str>) -> bool
+{
+    let token = csrf_token(secret);
+    !token.is_empty() && sent == Some(token.as_str())
+}
@@ -0,0 +1,111 @@
+//A plain line diff for comparing revisions. Longest common subsequence on whatever's left after
+//trimming the lines both sides start and end with, which is usually most of it for an edit.
+
+/// Past this many (lines old * lines new) in the changed middle, don't bother finding what's common
+const MAXDIFFWORK: usize = 4_000_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str)
+}
+
+/// What it takes to go from old to new, line by line
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>>
+{
+    let old_lines = old.lines().collect::<Vec<&str>>();
+    let new_lines = new.lines().collect::<Vec<&str>>();
+
+    let prefix = old_lines.iter().zip(new_lines.iter()).take_while(|(o, n)| o == n).count();
+    let suffix = old_lines[prefix..].iter().rev().zip(new_lines[prefix..].iter().rev()).take_while(|(o, n)| o == n).count();
+    let old_middle = &old_lines[prefix..old_lines.len() - suffix];
+    let new_middle = &new_lines[prefix..new_lines.len() - suffix];
+
+    let mut result = old_lines[..prefix].iter().map(|l| DiffLine::Same(l)).collect::<Vec<DiffLine>>();
+
+    if old_middle.len() * new_middle.len() > MAXDIFFWORK {
+        //Too big to be clever about: everything in the middle just changed
+        result.extend(old_middle.iter().map(|l| DiffLine::Removed(l)));
+        result.extend(new_middle.iter().map(|l| DiffLine::Added(l)));
+    }
+    else {
+        //lengths[i][j] is the longest common run of old_middle[i..] and new_middle[j..]
+        let width = new_middle.len() + 1;
+        let mut lengths = vec![0u32; (old_middle.len() + 1) * width];
+        for i in (0..old_middle.len()).rev() {
+            for j in (0..new_middle.len()).rev() {
+                lengths[i * width + j] = if old_middle[i] == new_middle[j] { lengths[(i + 1) * width + j + 1] + 1 }
+                    else { lengths[(i + 1) * width + j].max(lengths[i * width + j + 1]) };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < old_middle.len() && j < new_middle.len() {
+            if old_middle[i] == new_middle[j] {
+                result.push(DiffLine::Same(old_middle[i]));
+                i += 1;
+                j += 1;
+            }
+            else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+                result.push(DiffLine::Removed(old_middle[i]));
+                i += 1;
+            }
+            else {
+                result.push(DiffLine::Added(new_middle[j]));
+                j += 1;
+            }
+        }
+        result.extend(old_middle[i..].iter().map(|l| DiffLine::Removed(l)));
+        result.extend(new_middle[j..].iter().map(|l| DiffLine::Added(l)));
+    }
+
+    result.extend(old_lines[old_lines.len() - suffix..].iter().map(|l| DiffLine::Same(l)));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DiffLine::*;
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(diff_lines("", ""), vec![]);
+        assert_eq!(diff_lines("", "a\nb"), vec![Added("a"), Added("b")]);
+        assert_eq!(diff_lines("a\nb", ""), vec![Removed("a"), Removed("b")]);
+    }
+
+    #[test]
+    fn identical_text() {
+        assert_eq!(diff_lines("a\nb\nc", "a\nb\nc"), vec![Same("a"), Same("b"), Same("c")]);
+    }
+
+    #[test]
+    fn pure_insert() {
+        assert_eq!(diff_lines("a\nc", "a\nb\nc"), vec![Same("a"), Added("b"), Same("c")]);
+        assert_eq!(diff_lines("a", "a\na"), vec![Same("a"), Added("a")]);
+    }
+
+    #[test]
+    fn pure_delete() {
+        assert_eq!(diff_lines("a\nb\nc", "a\nc"), vec![Same("a"), Removed("b"), Same("c")]);
+    }
+
+    #[test]
+    fn finds_common_lines_in_the_middle() {
+        assert_eq!(diff_lines("a\nx\nb\ny\nc", "a\nb\nz\nc"),
+            vec![Same("a"), Removed("x"), Same("b"), Removed("y"), Added("z"), Same("c")]);
+    }
+
+    #[test]
+    fn too_much_work_replaces_the_middle() {
+        //Just over MAXDIFFWORK, with one line in common that a real diff would keep
+        let old = (0..2001).map(|i| if i == 1000 { String::from("common") } else { format!("old {}", i) }).collect::<Vec<_>>().join("\n");
+        let new = (0..2001).map(|i| if i == 1000 { String::from("common") } else { format!("new {}", i) }).collect::<Vec<_>>().join("\n");
+        let result = diff_lines(&old, &new);
+        assert_eq!(result.len(), 4002);
+        assert!(result[..2001].iter().all(|l| matches!(l, Removed(_))));
+        assert!(result[2001..].iter().all(|l| matches!(l, Added(_))));
+        assert!(result.contains(&Removed("common")));
+    }
+}
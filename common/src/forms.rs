@@ -64,6 +64,14 @@ pub struct PostForm
     pub post: String, //Always needed on post, of course
 }
 
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DirectMessageForm
+{
+    pub recipients: String, //Usernames, separated by spaces
+    pub title: String,
+    pub post: String
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct PageForm
 {
@@ -103,6 +111,27 @@ pub struct VoteForm
     pub vote: String
 }
 
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ReactionForm
+{
+    pub reaction: String //A key from REACTIONS. Sending the one you already have takes it off
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WatchForm
+{
+    pub watch: bool //Whether to start or stop watching
+}
+
+/// A file posted to one of our multipart routes. The data hasn't been read yet; it's streamed
+/// straight to the api by [`crate::prefab::upload_image`]
+pub struct FileUploadForm
+{
+    pub filename: String,
+    pub mime: String,
+    pub data: contentapi::endpoints::UploadStream
+}
+
 // ------------------------
 // *    QUERY PARAMS      *
 // ------------------------
@@ -143,12 +172,28 @@ pub struct ThreadQuery {
     pub selected: Option<i64>
 }
 
-#[derive(Serialize, Deserialize, Default)]
-#[serde(default)]
+#[derive(Default, Debug)]
 pub struct AdminSearchParams {
     pub banpage: u32,
     pub logpage: u32,
-    pub bans_only: bool
+    pub logtypes: Vec<i8> //Empty means all of them
+}
+
+impl AdminSearchParams {
+    /// The log type checkboxes all share one name, which serde_urlencoded can't put into a struct, so 
+    /// this is built from the raw query pairs instead. Garbage values are ignored, same as a bad page
+    pub fn from_query(pairs: Vec<(String, String)>) -> Self {
+        let mut result = Self::default();
+        for (key, value) in pairs {
+            match key.as_str() {
+                "banpage" => result.banpage = value.parse().unwrap_or_default(),
+                "logpage" => result.logpage = value.parse().unwrap_or_default(),
+                "logtype" => if let Ok(logtype) = value.parse() { result.logtypes.push(logtype) },
+                _ => {}
+            }
+        }
+        result
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -6,6 +6,7 @@ use crate::view::*;
 
 use contentapi::conversion::*;
 use contentapi::*;
+use contentapi::query::*;
 use contentapi::permissions::can_user_action;
 
 
@@ -19,6 +20,7 @@ pub static THREADKEY: &str = "thread";
 pub static CATEGORYKEY: &str = "category";
 pub static PREMESSAGEKEY: &str = "premessage";
 pub static PREMESSAGEINDEXKEY: &str = "premessage_index";
+pub static WATCHKEY: &str = "watch";
 
 struct Keygen();
 
@@ -238,7 +240,7 @@ pub fn posts_to_replytree(posts: &Vec<Message>) -> Vec<ReplyTree>
     for post in posts.iter().skip(1) {
         if let Some(data) = get_replydata(post) {
             if root.insert_post(post, &data).is_none() {
-                println!("WARN: could not find place for message {}, reply to {}", render::i(&post.id), data.direct);
+                tracing::warn!("could not find place for message {}, reply to {}", render::i(&post.id), data.direct);
             }
         }
     }
@@ -288,28 +290,28 @@ pub fn get_category_request(hash: Option<String>, fcid: Option<i64>) -> FullRequ
 }
 
 
-pub fn get_thread_request(categories: &Vec<CleanedPreCategory>, limit: i32, skip: i32, get_stickies: bool) -> FullRequest
+pub fn get_thread_request(categories: &Vec<CleanedPreCategory>, limit: i32, skip: i32, get_stickies: bool) -> Result<FullRequest, QueryError>
 {
     let mut request = FullRequest::new();
-    add_value!(request, "page_type", ContentType::PAGE);
-    add_value!(request, "allowed_types", THREADTYPES);
 
     let mut keys = Vec::new();
 
     for ref category in categories.iter()
     {
         let category_id = category.id;
-        let sticky_key = Keygen::stickies(category_id);
-        request.values.insert(sticky_key.clone(), category.stickies.clone().into());
+        let stickies = Param::value(&Keygen::stickies(category_id), category.stickies.clone());
 
         //Standard threads get (for latest N threads)
-        let base_query = format!("parentId = {{{{{category_id}}}}} and contentType = @page_type and literalType in @allowed_types and !notdeleted()");
+        let base_query = Query::field("parentId").eq(Param::literal(category_id))
+            .and(Query::field("contentType").eq(Param::value("page_type", ContentType::PAGE)))
+            .and(Query::field("literalType").is_in(Param::value("allowed_types", THREADTYPES)))
+            .and(Macro::notdeleted());
 
         //Regular thread request. Needs to specifically NOT be the stickies
         let mut threads_request = build_request!(
             RequestType::content,
             String::from(THREADFIELDS),
-            format!("{} and id not in @{}", base_query, sticky_key),
+            base_query.clone().and(Query::field("id").not_in(stickies.clone())).attach(&mut request)?,
             String::from("lastActionDate_desc"),//"lastCommentId_desc,lastRevisionId_desc"),
             limit,
             skip
@@ -325,7 +327,7 @@ pub fn get_thread_request(categories: &Vec<CleanedPreCategory>, limit: i32, skip
             let mut sticky_request = build_request!(
                 RequestType::content,
                 String::from(THREADFIELDS),
-                format!("{} and id in @{}", base_query, sticky_key),
+                base_query.clone().and(Query::field("id").is_in(stickies)).attach(&mut request)?,
                 String::from("lastCommentId_desc")
             );
 
@@ -339,33 +341,35 @@ pub fn get_thread_request(categories: &Vec<CleanedPreCategory>, limit: i32, skip
         let mut count_request = build_request!(
             RequestType::content, 
             String::from("specialCount,parentId,literalType,contentType,id"), 
-            base_query.clone()
+            base_query.attach(&mut request)?
         );
         count_request.name = Some(Keygen::threadcount(category_id));
         request.requests.push(count_request);
     }
 
-    //How many string allocations is this? I mean it shouldn't matter but ugh
-    let comment_query = format!("!basiccomments() and ({})", 
-        keys.iter().map(|k| format!("id in @{}.lastCommentId", k)).collect::<Vec<String>>().join(" or "));
-    let user_query = format!("!notdeleted() and (id in @message.createUserId or {})",
-        keys.iter().map(|k| format!("id in @{}.createUserId", k)).collect::<Vec<String>>().join(" or "));
+    let comment_query = Macro::basiccomments()
+        .and(Query::any(keys.iter().map(|k| Query::field("id").is_in(Param::result(k, "lastCommentId")))));
 
     let comment_request = build_request!(
         RequestType::message,
         String::from("id,createDate,contentId,createUserId"),
-        comment_query);
+        comment_query.attach(&mut request)?);
     request.requests.push(comment_request);
 
+    let user_query = Macro::notdeleted()
+        .and(Query::any(std::iter::once(Param::result("message", "createUserId"))
+            .chain(keys.iter().map(|k| Param::result(k, "createUserId")))
+            .map(|p| Query::field("id").is_in(p))));
+
     let user_request = build_request!(
         RequestType::user,
         String::from("*"),
-        user_query);
+        user_query.attach(&mut request)?);
     request.requests.push(user_request);
 
     //println!("Threads request: {:?}", &request);
 
-    request
+    Ok(request)
 }
 
 //"prepost" means the main query before finding the main data before gathering the posts. The post offset
@@ -443,6 +447,15 @@ pub fn get_prepost_request(fpid: Option<i64>, post_id: Option<i64>, ftid: Option
     category_request.name = Some(String::from(CATEGORYKEY));
     request.requests.push(category_request);
 
+    //Whether the user is watching the thread. You only ever get your own watches, so this is empty if not logged in
+    let mut watch_request = build_request!(
+        RequestType::watch,
+        String::from("*"),
+        format!("contentId in @{}.id", THREADKEY)
+    );
+    watch_request.name = Some(String::from(WATCHKEY));
+    request.requests.push(watch_request);
+
     //OK one last ACTUAL thing: need to get the premessage index if it was there
     if post_limited {
         let mut index_request = build_request!(
@@ -7,6 +7,9 @@ pub mod forms;
 pub mod links;
 pub mod view;
 pub mod prefab;
+pub mod diff;
+pub mod csrf;
+pub mod routes;
 
 use std::collections::HashMap;
 
@@ -70,6 +73,8 @@ pub struct MainLayoutData {
     pub user_token: Option<String>,
     pub about_api: contentapi::About, 
     pub raw_alert: Option<String>,
+    pub csrf_token: String, //Goes in every form, see csrf
+    pub csp_nonce: String,  //Inline scripts need this or the content security policy blocks them. Empty if there isn't one
 
     #[cfg(feature = "profiling")]
     pub profiler: onestop::OneList<onestop::OneDuration>
@@ -116,6 +121,18 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<contentapi::query::QueryError> for Error {
+    fn from(error: contentapi::query::QueryError) -> Self {
+        Error::Other(error.to_string()) 
+    }
+}
+
+impl From<contentapi::conversion::ResultError> for Error {
+    fn from(error: contentapi::conversion::ResultError) -> Self {
+        Error::Data(error.to_string(), error.raw().to_string()) 
+    }
+}
+
 impl From<Box<dyn std::error::Error>> for Error {
     fn from(error: Box<dyn std::error::Error>) -> Self {
         Error::Other(error.to_string()) 
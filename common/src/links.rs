@@ -3,24 +3,29 @@
 use super::*;
 use contentapi::*;
 use contentapi::forms::*;
-use render::i;
+use routes::*;
 
 /// Extend LinkConfig to have additional functionality
 impl LinkConfig {
 
+    /// The full url for a route (see the routes module for building them)
+    pub fn url(&self, route: Route) -> String {
+        format!("{}{}", self.http_root, route)
+    }
+
     pub fn image(&self, hash: &str, query: &QueryImage) -> String 
     {
         match serde_urlencoded::to_string(&query) {
             Ok(querystring) => format!("{}/{}?{}", self.file_root, hash, querystring),
             Err(error) => {
-                println!("Serde_qs failed? Not printing link for {}. Error: {}", hash, error);
+                tracing::error!("Serde_qs failed? Not printing link for {}. Error: {}", hash, error);
                 format!("#ERRORFOR-{}",hash)
             }
         }
     }
 
     pub fn user(&self, user: &User) -> String {
-        format!("{}/user/{}", self.http_root, user.username)
+        self.url(routes::user(&user.username))
     }
 
     pub fn image_default(&self, hash: &str) -> String { 
@@ -32,19 +37,56 @@ impl LinkConfig {
     //}
 
     pub fn activity(&self) -> String {
-        format!("{}/activity", self.http_root)
+        self.url(routes::activity())
+    }
+
+    /// The event stream for new activity (see pages::live)
+    pub fn live_activity(&self) -> String {
+        self.url(routes::live_activity())
+    }
+
+    /// The event stream for new posts on the given thread (see pages::live)
+    pub fn live_thread(&self, thread: &Content) -> String {
+        self.url(routes::live_thread(thread.id.unwrap_or_default()))
+    }
+
+    pub fn direct_messages(&self) -> String {
+        self.url(routes::messages())
+    }
+
+    /// The form to start a new conversation, optionally with the recipient already filled in
+    pub fn direct_message_new(&self, recipient: Option<&User>) -> String {
+        self.url(routes::messages_new().query(&NewMessageQuery { to: recipient.map(|u| u.username.clone()) }))
+    }
+
+    /// The revision history of a page (or thread)
+    pub fn page_history(&self, page: &Content) -> String {
+        self.url(routes::page_history(opt_s!(page.hash)))
+    }
+
+    pub fn tags(&self) -> String {
+        self.url(routes::tags())
+    }
+
+    /// Everything tagged with the given keyword. Keywords can be anything but spaces, so they're percent encoded
+    pub fn tag(&self, keyword: &str) -> String {
+        self.url(routes::tag(keyword))
+    }
+
+    pub fn userhome_watches(&self) -> String {
+        self.url(routes::userhome_watches())
     }
 
     pub fn imagebrowser(&self) -> String {
-        format!("{}/widget/imagebrowser", self.http_root)
+        self.url(routes::widget_imagebrowser())
     }
 
     pub fn votewidget(&self, content: &Content) -> String {
-        format!("{}/widget/votes/{}", self.http_root, i(&content.id))
+        self.url(routes::widget_votes(content.id.unwrap_or_default()))
     }
 
     pub fn qr_generator(&self, content: &Content) -> String {
-        format!("{}/widget/qr/{}", self.http_root, opt_s!(content.hash))
+        self.url(routes::widget_qr(opt_s!(content.hash)))
     }
 
     pub fn forum_category(&self, category: &Content) -> String {
@@ -54,64 +96,78 @@ impl LinkConfig {
     /// Create a category link using the current link system, which only uses the hash AVOID AS MUCH AS POSSIBLE!
     /// The implementation of the links may change!
     pub fn forum_category_unsafe(&self, hash: &str) -> String {
-        format!("{}/forum/category/{}", self.http_root, hash) 
+        self.url(routes::forum_category(hash))
     }
 
     pub fn forum_thread(&self, thread: &Content) -> String {
-        format!("{}/forum/thread/{}", self.http_root, opt_s!(thread.hash))
+        self.url(routes::forum_thread(opt_s!(thread.hash)))
+    }
+
+    /// The id of the post's element on the thread page
+    pub fn forum_post_id(post: &Message) -> String {
+        format!("post_{}", post.id.unwrap_or_default())
     }
 
     pub fn forum_post_hash(post: &Message) -> String {
-        format!("#post_{}", post.id.unwrap_or_default())
+        format!("#{}", Self::forum_post_id(post))
     }
 
     pub fn forum_post(&self, post: &Message, thread: &Content) -> String {
-        format!("{}/forum/thread/{}/{}{}", self.http_root, opt_s!(thread.hash), post.id.unwrap_or_default(), Self::forum_post_hash(post))
+        self.url(routes::forum_post(opt_s!(thread.hash), post.id.unwrap_or_default()).fragment(&Self::forum_post_id(post)))
     }
 
 
     pub fn forum_thread_editor_new(&self, category: &Content) -> String {
-        format!("{}/forum/edit/thread?category={}", self.http_root, opt_s!(category.hash))
+        self.url(routes::forum_edit_thread().query(&NewThreadQuery { category: opt_s!(category.hash).to_string() }))
     }
 
     pub fn forum_thread_editor_edit(&self, thread: &Content) -> String {
-        format!("{}/forum/edit/thread?thread={}", self.http_root, opt_s!(thread.hash))
+        self.url(routes::forum_edit_thread().query(&EditThreadQuery { thread: opt_s!(thread.hash).to_string() }))
+    }
+
+    /// POST a WatchForm here to start or stop watching the thread (or page)
+    pub fn forum_thread_watch(&self, thread: &Content) -> String {
+        self.url(routes::forum_watch(thread.id.unwrap_or_default()))
     }
 
     pub fn forum_thread_delete(&self, thread: &Content) -> String {
-        format!("{}/forum/delete/thread/{}", self.http_root, i(&thread.id))
+        self.url(routes::forum_delete_thread(thread.id.unwrap_or_default()))
     }
 
     /// Get the link to the post editor for a brand new post. You HAVE to specify which thread you're posting on, but
     /// you can also optionally specify which post you're replying to.
     pub fn forum_post_editor_new(&self, thread: &Content, reply_to: Option<&Message>) -> String {
-        format!("{}/forum/edit/post?thread={}{}", self.http_root, opt_s!(thread.hash),
-            if let Some(reply) = reply_to {
-                format!("&reply={}", i(&reply.id))
-            } else {
-                String::from("")
-            })
+        self.url(routes::forum_edit_post().query(&NewPostQuery {
+            thread: opt_s!(thread.hash).to_string(),
+            reply: reply_to.and_then(|reply| reply.id),
+            widget: None
+        }))
     }
 
     /// Get the link to the post editor to edit the given message. You don't need extra data in this case, since 
     /// the message to edit has all the info you need
     pub fn forum_post_editor_edit(&self, post: &Message) -> String {
-        format!("{}/forum/edit/post?post={}", self.http_root, i(&post.id))
+        self.url(routes::forum_edit_post().query(&EditPostQuery { post: post.id.unwrap_or_default(), widget: None }))
     }
 
     pub fn forum_post_editor(&self) -> String {
-        format!("{}/forum/edit/post", self.http_root)
+        self.url(routes::forum_edit_post())
     }
 
 
+    /// Get the link to toggle a reaction on a post. You'll need to POST to this
+    pub fn forum_post_react(&self, post: &Message) -> String {
+        self.url(routes::forum_react(post.id.unwrap_or_default()))
+    }
+
     /// Get the link to delete a post. You'll need to POST to this to delete
     pub fn forum_post_delete(&self, post: &Message) -> String {
-        format!("{}/forum/delete/post/{}", self.http_root, i(&post.id))
+        self.url(routes::forum_delete_post(post.id.unwrap_or_default()))
     }
 
 
     pub fn page_editor_new(&self, mode: &str) -> String {
-        format!("{}/page/edit?mode={}", self.http_root, mode)
+        self.url(routes::page_edit().query(&NewPageQuery { mode: mode.to_string() }))
     }
 
     //pub fn page_editor_new_ptc(&self) -> String {
@@ -119,16 +175,16 @@ impl LinkConfig {
     //}
 
     pub fn page_editor_edit(&self, page: &Content) -> String {
-        format!("{}/page/edit?page={}", self.http_root, opt_s!(page.hash))
+        self.url(routes::page_edit().query(&EditPageQuery { page: opt_s!(page.hash).to_string() }))
     }
 
     pub fn page_delete(&self, page: &Content) -> String {
-        format!("{}/page/delete/{}", self.http_root, i(&page.id))
+        self.url(routes::page_delete(page.id.unwrap_or_default()))
     }
 
 
     pub fn search_category(&self, category: i64) -> String {
-        format!("{}/search?category={}", self.http_root, category)
+        self.url(routes::search().query(&[("category", category)]))
     }
 
 }
@@ -1,13 +1,23 @@
 
+use std::time::Duration;
+
 use super::*;
 use contentapi::*;
 use contentapi::endpoints::*;
+use contentapi::cache::CacheScope;
 use serde_json::Value;
 use crate::constants::*;
 use contentapi::conversion::*;
+use contentapi::query::*;
+use futures_util::{StreamExt, TryStreamExt};
 
 //This is for pre-constructed searches SPECIFICALLY within the API, hence "prefab".
 
+//How long the (mostly static) prefab data can be cached. Only applies if the api context has a cache!
+pub const SYSTEMCACHETTL: Duration = Duration::from_secs(30);
+pub const CATEGORYCACHETTL: Duration = Duration::from_secs(60);
+pub const DOCUMENTATIONCACHETTL: Duration = Duration::from_secs(120);
+
 // ------------------------------
 //     CATEGORIES (FOR PAGES)
 // ------------------------------
@@ -35,7 +45,7 @@ pub async fn get_all_categories(context: &mut ApiContext, limit: Option<Vec<i64>
         )
     ));
 
-    let result = context.post_request_profiled_opt(&request, "all_categories").await?;
+    let result = context.post_request_cached(&request, "all_categories", CATEGORYCACHETTL, CacheScope::Anonymous).await?;
     conversion::cast_result_required::<Content>(&result, &RequestType::content.to_string()).map_err(|e| e.into())
 }
 
@@ -59,6 +69,69 @@ pub async fn get_content_vote(context: &ApiContext, content_id: i64) -> Result<O
     Ok(engagement.pop())
 }
 
+/// The given user's reaction on each of the given posts (that they reacted to), by post id
+pub async fn get_post_reactions(context: &ApiContext, user_id: i64, message_ids: Vec<i64>) -> Result<HashMap<i64, String>, ApiError>
+{
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let mut request = FullRequest::new();
+    let query = Query::field("userId").eq(Param::value("userId", user_id))
+        .and(Query::field("type").eq(Param::value("type", REACTIONTYPE)))
+        .and(Query::field("messageId").is_in(Param::value("messageIds", message_ids)))
+        .attach(&mut request)?;
+    request.requests.push(build_request!(RequestType::message_engagement, String::from("*"), query));
+
+    let result = context.post_request(&request).await?;
+    let engagement = conversion::cast_result_required::<MessageEngagement>(&result, &RequestType::message_engagement.to_string())?;
+    Ok(engagement.into_iter().filter_map(|e| Some((e.messageId?, e.engagement?))).collect())
+}
+
+
+/// The image type the data starts with, if it's one we allow. The browser's idea of the type is just
+/// whatever the file was named, so it's never trusted
+pub fn sniff_image(start: &[u8]) -> Option<&'static str>
+{
+    if start.starts_with(b"\x89PNG\r\n\x1a\n") { Some("image/png") }
+    else if start.starts_with(b"\xFF\xD8\xFF") { Some("image/jpeg") }
+    else if start.starts_with(b"GIF87a") || start.starts_with(b"GIF89a") { Some("image/gif") }
+    else if start.len() >= 12 && start.starts_with(b"RIFF") && &start[8..12] == b"WEBP" { Some("image/webp") }
+    else { None }
+}
+
+/// How much of an upload to read before deciding what it is (see sniff_image)
+const SNIFFLENGTH: usize = 12;
+
+/// Upload an image for the current user through the api (avatars, page images, etc). Like the api's own
+/// upload route, anyone can see the result. The type is checked from the data itself, not what was sent
+pub async fn upload_image(context: &ApiContext, form: crate::forms::FileUploadForm) -> Result<Content, Error>
+{
+    //Only the first few bytes are read here, then they're put back in front of the rest of the stream
+    let mut data = form.data;
+    let mut start: Vec<contentapi::endpoints::Bytes> = Vec::new();
+    while start.iter().map(|b| b.len()).sum::<usize>() < SNIFFLENGTH {
+        match data.next().await {
+            Some(chunk) => start.push(chunk.map_err(|e| Error::Other(format!("Couldn't read the upload: {}", e)))?),
+            None => break
+        }
+    }
+    let head = start.concat();
+    let mime = match sniff_image(&head) {
+        Some(mime) => mime,
+        None => return Err(Error::Data(String::from("Only images (png, jpeg, gif, webp) can be uploaded here!"), form.mime))
+    };
+
+    let object = Content {
+        name: Some(form.filename.clone()),
+        permissions: Some(make_permissions! { "0": "R" }),
+        ..Default::default()
+    };
+
+    let data = futures_util::stream::iter(start.into_iter().map(Ok)).chain(data);
+    let upload = contentapi::forms::FileUpload { object, filename: form.filename, mime: String::from(mime) };
+    Ok(context.upload_file(&upload, Box::pin(data)).await?)
+}
+
 
 // ---------------------------
 //   SPECIAL SYSTEM CONTENT
@@ -76,7 +149,8 @@ pub async fn get_system_any(context: &mut ApiContext, ty: &str) -> Result<Option
         String::from("id") // Combined with 'pop', even if there are multiple alerts, we always get the last one
     );
     request.requests.push(alert_request);
-    let result = context.post_request_profiled_opt(&request, "get-system").await?;
+    //System content is the same for everyone, so logged in users can share the cached copy
+    let result = context.post_request_cached(&request, "get-system", SYSTEMCACHETTL, CacheScope::Everyone).await?;
     let mut content = cast_result_required::<Content>(&result, "content")?;
     Ok(content.pop())
 }
@@ -164,49 +238,52 @@ pub fn get_alldocumentation_query() -> String {
 /// fields. Note that this may make several requests if there's more than 1000 pages of documentation
 pub async fn get_all_documentation(context: &mut ApiContext) -> Result<Vec<Content>, ApiError> 
 {
-    let mut result : Vec<Content> = Vec::new();
-    let mut skip = 0;
+    let mut request = FullRequest::new();
+    request.requests.push(build_request!(
+        RequestType::content,
+        String::from(DOCUMENTATIONFIELDS),
+        get_alldocumentation_query(),
+        String::from("id")
+    ));
 
-    //REMEMBER: loop is the "while true" of rust!!
-    loop {
-        let mut request = FullRequest::new();
+    context.stream_all_named(request, "all_documentation_treeonly", Some(DOCUMENTATIONCACHETTL)).try_collect().await
+}
 
-        let mut doc_request = build_request!(
-            RequestType::content,
-            String::from(DOCUMENTATIONFIELDS),
-            get_alldocumentation_query()
-        );
-        doc_request.order = Some(String::from("id")); //Just in case
-        doc_request.skip = skip;
+pub const DOCPARENTMINIMALFIELDS: &str = "id,hash,permissions";
 
-        request.requests.push(doc_request);
+pub async fn get_documentation_parent(context: &mut ApiContext, fields: &str) -> Result<Content, ApiError>
+{
+    context.get_content_by_hash(DOCSPARENTHASH, fields).await
+}
 
-        let api_result = context.post_request_profiled_opt(&request, &format!("all_documentation_treeonly_skip{}", skip)).await?;
-        let this_block = conversion::cast_result_required::<Content>(&api_result, &RequestType::content.to_string())?;
-        let result_count = this_block.len();
+pub async fn get_documentation_group(context: &mut ApiContext) -> Result<User, ApiError>
+{
+    context.get_user_by_username(DOCSGROUPUSERNAME, "*").await //User has lots of required fields, just do *
+}
 
-        //Add the current result 
-        result.extend(this_block);
 
-        if result_count != REQUESTRESULTLIMIT {
-            break;
-        }
+// ------------------------------
+//     ACCOUNT SETTINGS
+// ------------------------------
 
-        //Request the next block of documentation (hopefully this won't be necessary!)
-        skip += REQUESTRESULTLIMIT as i64;
+/// The settings the current user synced to their account, if they did (and they still make sense)
+pub async fn get_account_config(context: &ApiContext) -> Result<Option<UserConfig>, ApiError>
+{
+    match context.get_uservariable(USERCONFIGVARIABLE).await?.and_then(|v| v.value) {
+        Some(value) => Ok(serde_json::from_str::<UserConfig>(&value).map_err(|e| {
+            tracing::warn!("Ignoring unreadable account settings: {}", e);
+        }).ok()),
+        None => Ok(None)
     }
-
-    Ok(result)
 }
 
-pub const DOCPARENTMINIMALFIELDS: &str = "id,hash,permissions";
-
-pub async fn get_documentation_parent(context: &mut ApiContext, fields: &str) -> Result<Content, ApiError>
+pub async fn set_account_config(context: &ApiContext, config: &UserConfig) -> Result<UserVariable, Error>
 {
-    context.get_content_by_hash(DOCSPARENTHASH, fields).await
+    let value = serde_json::to_string(config)?;
+    Ok(context.post_uservariable(USERCONFIGVARIABLE, &value).await?)
 }
 
-pub async fn get_documentation_group(context: &mut ApiContext) -> Result<User, ApiError>
+pub async fn delete_account_config(context: &ApiContext) -> Result<UserVariable, ApiError>
 {
-    context.get_user_by_username(DOCSGROUPUSERNAME, "*").await //User has lots of required fields, just do *
-}
\ No newline at end of file
+    context.post_uservariable_delete(USERCONFIGVARIABLE).await
+}
@@ -14,6 +14,7 @@ use crate::render::*;
 use crate::constants::*;
 use crate::forum::*;
 use crate::pagination::*;
+use crate::routes::Route;
 
 
 // ----------------------------
@@ -22,26 +23,26 @@ use crate::pagination::*;
 
 //To build the forum path at the top
 pub struct ForumPathItem {
-    pub link: String,
+    pub link: Route,
     pub title: String
 }
 
 impl ForumPathItem {
     pub fn from_category(category: &Content) -> Self {
         Self {
-            link: format!("/forum/category/{}", opt_s!(category.hash)),
+            link: routes::forum_category(opt_s!(category.hash)),
             title: String::from(opt_s!(category.name, "NOTFOUND"))
         }
     }
     pub fn from_thread(thread: &Content) -> Self {
         Self {
-            link: format!("/forum/thread/{}", opt_s!(thread.hash)),
+            link: routes::forum_thread(opt_s!(thread.hash)),
             title: String::from(opt_s!(thread.name, "NOTFOUND"))
         }
     }
     pub fn root() -> Self {
         Self {
-            link: String::from("/forum"),
+            link: routes::forum(),
             title: String::from("Root")
         }
     }
@@ -52,7 +53,7 @@ pub fn forum_path(config: &LinkConfig, path: &Vec<ForumPathItem>) -> Markup {
         p."forumpath" {
             @for (index, segment) in path.iter().enumerate() {
                 @let last = index == path.len() - 1;
-                a."flatlink" href={(config.http_root)(segment.link)} {
+                a."flatlink" href=(config.url(segment.link.clone())) {
                     @if last { "[.]" }
                     @else { (segment.title) }
                 }
@@ -99,8 +100,14 @@ pub struct PostsConfig {
     pub render_page: bool,
     pub render_reply_chain: bool,
     pub render_reply_link: bool,
-    pub render_controls: bool
+    pub render_controls: bool,
     //pub render_sequence: bool
+    /// Whether the current user is watching the thread (for the watch toggle in the controls)
+    pub watching: bool,
+    /// The current user's reaction on each post, by post id
+    pub reactions: HashMap<i64,String>,
+    /// Shown above the posts, for when something the user tried on the thread didn't work
+    pub errors: Option<Vec<String>>
 }
 
 impl PostsConfig {
@@ -120,7 +127,10 @@ impl PostsConfig {
             render_reply_chain: false,
             render_reply_link: true,
             render_controls: true,
-            docs_content: None
+            docs_content: None,
+            watching: false,
+            reactions: HashMap::new(),
+            errors: None
         }
     }
     pub fn reply_mode(thread: ForumThread, related: HashMap<i64,Message>, users: HashMap<i64,User>, selected_post_id: Option<i64>) -> Self {
@@ -137,7 +147,10 @@ impl PostsConfig {
             render_reply_chain: true,
             render_reply_link: false,
             render_controls: false,
-            docs_content: None
+            docs_content: None,
+            watching: false,
+            reactions: HashMap::new(),
+            errors: None
         }
     }
 }
@@ -234,6 +247,10 @@ pub fn render_posts(context: &mut PageContext, config: PostsConfig) -> Markup
                         b { "Created: " }
                         time datetime=(d(&thread.thread.createDate)) { (timeago_o(&thread.thread.createDate)) }
                     }
+                    (keyword_list(&data.links, &thread.thread))
+                    @if thread.thread.lastRevisionId.is_some() {
+                        a."flatlink" href=(data.links.page_history(&thread.thread)) { "History" }
+                    }
                     iframe."votes" src={(data.links.votewidget(&thread.thread))}{}
                 }
             }
@@ -245,6 +262,9 @@ pub fn render_posts(context: &mut PageContext, config: PostsConfig) -> Markup
         //for loop, it then displays pages, which is on the bottom of the thread, so it might seem confusing.
         //maybe the id should be changed to an anchor, idr how to do that.
         section #"thread-top" data-selected=[config.selected_post_id] {
+            @if config.errors.is_some() {
+                (errorlist(config.errors.clone()))
+            }
             @if data.user_config.toppagination_posts {
                 @if let Some(ref pagelist) = pagelist_html {
                     (pagelist)
@@ -259,30 +279,37 @@ pub fn render_posts(context: &mut PageContext, config: PostsConfig) -> Markup
             }
             @else {
                 //As usual, I'm reusing pagelist to make centered and spaced content
-                p."aside pagelist" { "No posts yet (will you be the first?)" }
+                p."aside pagelist" #"noposts" { "No posts yet (will you be the first?)" }
+            }
+            //New posts show up here as they're made, but only if you're looking at the end of the thread
+            @if config.render_controls && config.pages.as_ref().and_then(|p| p.last()).map(|p| p.current).unwrap_or(true) {
+                div #"liveposts" data-live=(data.links.live_thread(&thread.thread)) {}
             }
             @if config.render_controls {
                 @if let Some(ref user) = context.layout_data.user {
                     @if can_create_post(user, &thread.thread) {
                         hr."smaller";
-                        iframe."postwidget pagelist" #"createpost" src={(data.links.forum_post_editor_new(&thread.thread, None))"&widget=true"} {}
+                        iframe."postwidget pagelist" #"createpost" src=(data.links.url(routes::forum_edit_post().query(&routes::NewPostQuery {
+                            thread: opt_s!(thread.thread.hash).to_string(), reply: None, widget: Some(true) }))) {}
                     }
                 }
             }
             @if let Some(ref pagelist) = pagelist_html {
                 (pagelist)
             }
-            //Only display the thread controls if it's NOT a regular page
             @if config.render_controls {
                 @if let Some(ref user) = context.layout_data.user {
                     //TODO: again, reusing pagelist may be inappropriate. IDK
-                    @if !is_pagetype {
-                        div."smallseparate pagelist" {
+                    div."smallseparate pagelist" {
+                        //Anyone can watch anything, but only display the other thread controls if it's NOT a regular page
+                        (watch_toggle(data, &thread.thread, config.watching))
+                        @if !is_pagetype {
                             @if can_edit_thread(user, &thread.thread) {
                                 a."coolbutton" #"editthread" href=(data.links.forum_thread_editor_edit(&thread.thread)) { "Edit thread" }
                             }
                             @if can_delete_thread(user, &thread.thread) {
                                 form."nospacing" #"deletethread" method="POST" action=(data.links.forum_thread_delete(&thread.thread)) {
+                                    (csrf_input(data))
                                     input."coolbutton notheme" data-confirmdelete=(format!("thread '{}'", opt_s!(&thread.thread.name))) type="submit" value="Delete thread";
                                 }
                             }
@@ -294,6 +321,60 @@ pub fn render_posts(context: &mut PageContext, config: PostsConfig) -> Markup
     }
 }
 
+/// The reactions on a post, each a button that toggles yours (if you're allowed). The rest of the reactions
+/// are tucked into a little picker so every post isn't covered in buttons
+fn post_reactions(layout_data: &MainLayoutData, config: &PostsConfig, post: &Message) -> Markup
+{
+    let counts = post.engagement.as_ref().and_then(|e| e.get(REACTIONTYPE));
+    let count = |key: &str| counts.and_then(|c| c.get(key)).copied().unwrap_or(0);
+    let mine = post.id.and_then(|id| config.reactions.get(&id)).map(|r| r.as_str());
+    let can_react = config.render_controls && layout_data.user.is_some();
+    let reaction_button = |key: &str, label: &str, count: i64| html! {
+        form."reaction nospacing" method="POST" action=(layout_data.links.forum_post_react(post)) {
+            (csrf_input(layout_data))
+            input type="hidden" name="reaction" value=(key);
+            button."flatlink notheme" type="submit" title=(key) data-current[mine == Some(key)] {
+                (label) @if count > 0 { " " (count) }
+            }
+        }
+    };
+
+    html! {
+        div."reactions smallseparate" {
+            @for (key, label) in REACTIONS.iter().filter(|(k, _)| count(k) > 0) {
+                @if can_react {
+                    (reaction_button(key, label, count(key)))
+                }
+                @else {
+                    span."reaction aside" title=(key) { (label) " " (count(key)) }
+                }
+            }
+            @if can_react && REACTIONS.iter().any(|(k, _)| count(k) == 0) {
+                details."reactionpicker" {
+                    summary."aside" title="React" { "☺+" }
+                    div."smallseparate" {
+                        @for (key, label) in REACTIONS.iter().filter(|(k, _)| count(k) == 0) {
+                            (reaction_button(key, label, 0))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The button to start or stop watching a thread (or page), whichever the user isn't doing now
+pub fn watch_toggle(data: &MainLayoutData, content: &Content, watching: bool) -> Markup
+{
+    html! {
+        form."nospacing" #"watchthread" method="POST" action=(data.links.forum_thread_watch(content)) {
+            (csrf_input(data))
+            input type="hidden" name="watch" value=(b(!watching));
+            input."coolbutton" type="submit" value=(if watching { "Unwatch" } else { "Watch" }) data-watching[watching];
+        }
+    }
+}
+
 fn images_to_attr(config: &LinkConfig, images: &Vec<serde_json::Value>) -> String 
 {
     serde_json::to_string(
@@ -301,13 +382,13 @@ fn images_to_attr(config: &LinkConfig, images: &Vec<serde_json::Value>) -> Strin
             match i.as_str() {
                 Some(string) => config.image_default(string),
                 None => {
-                    println!("ERROR: IMAGE HASH NOT STRING: {}", i);
+                    tracing::error!("IMAGE HASH NOT STRING: {}", i);
                     String::new()
                 }
             }
         }).collect::<Vec<String>>()
     ).unwrap_or_else(|err| {
-        println!("ERROR: COULD NOT SERIALIZE PAGE IMAGES: {}", err);
+        tracing::error!("COULD NOT SERIALIZE PAGE IMAGES: {}", err);
         String::new()
     })
 }
@@ -439,6 +520,7 @@ pub fn render_page(data: &MainLayoutData, bbcode: &mut BBCode, thread: &ForumThr
                     }
                     @if can_delete {
                         form."nospacing" #"deletepage" method="POST" action=(data.links.page_delete(&thread.thread)) {
+                            (csrf_input(data))
                             input."coolbutton notheme" data-confirmdelete=(format!("page '{}'", opt_s!(&thread.thread.name))) type="submit" value="Delete page";
                         }
                     }
@@ -473,7 +555,7 @@ pub fn render_content(content: &Content, bbcode: &mut BBCode) -> Markup {
         html!(
             div."content" data-markup=(markup) data-prerendered[markup == MARKUPBBCODE] {
                 @if markup == MARKUPBBCODE {
-                    (PreEscaped(&bbcode.parse_profiled_opt(text, format!("program-{}", i(&content.id)))))
+                    (PreEscaped(&bbcode_html(bbcode, text, format!("program-{}", i(&content.id)))))
                 }
                 @else {
                     (text)
@@ -512,19 +594,14 @@ pub fn post_item(layout_data: &MainLayoutData, bbcode: &mut BBCode, config: &Pos
     if let Some(replies) = get_replydata(post) {
         reply_post = config.related.get(&replies.direct);
         if reply_post.is_none() {
-            println!("ERROR: couldn't find related post {}!", replies.direct)
+            tracing::error!("couldn't find related post {}!", replies.direct)
         }
         if config.render_reply_link {
             let query = ThreadQuery {
                 reply: Some(replies.top),
                 selected: post.id
             };
-            match serde_urlencoded::to_string(query) {
-                Ok(query) => {
-                    reply_chain_link = Some(format!("{}/widget/thread?{}", &layout_data.links.http_root, query)); //, forum_post_hash(post)));
-                },
-                Err(error) => println!("ERROR: couldn't encode thread query!: {}", error)
-            }
+            reply_chain_link = Some(layout_data.links.url(routes::widget_thread().query(&query)));
         }
     }
 
@@ -550,6 +627,7 @@ pub fn post_item(layout_data: &MainLayoutData, bbcode: &mut BBCode, config: &Pos
                                 }
                                 @if can_user_delete_message(&current_user, post) {
                                     form."postdelete nospacing" method="POST" action=(layout_data.links.forum_post_delete(post)) {
+                                        (csrf_input(layout_data))
                                         input."flatlink notheme" title="Delete" data-confirmdelete=(format!("post '{}'", opt_s!(&post.text))) type="submit" value="✖";
                                     }
                                     //a."postreply flatlink" title="Delete" href=(layout_data.links.forum_post_delete(post)) { "✖" }
@@ -566,9 +644,10 @@ pub fn post_item(layout_data: &MainLayoutData, bbcode: &mut BBCode, config: &Pos
                     (post_reply(layout_data, bbcode, reply_post, &config.thread.thread, &config.users))
                 }
                 @if let Some(text) = &post.text {
-                    div."content bbcode" data-postid=(i(&post.id)) { (PreEscaped(bbcode.parse_profiled_opt(text, format!("post-{}",i(&post.id))))) }
+                    div."content bbcode" data-postid=(i(&post.id)) { (PreEscaped(bbcode_html(bbcode, text, format!("post-{}",i(&post.id))))) }
                 }
                 div."postfooter mediumseparate" {
+                    (post_reactions(layout_data, config, post))
                     @if let Some(reply_link) = reply_chain_link {
                         details."repliesview aside" style="display:none" {
                             summary { "View conversation" }
@@ -604,7 +683,7 @@ pub fn post_reply(layout_data: &MainLayoutData, bbcode: &mut BBCode, post: &Mess
                 //Ignoring graphemes for now, sorry. In NEARLY all cases, 200 bytes should be enough to fill 
                 //a line, unless you're being ridiculous
                 //@let text = if text.len() > 200 { &text[0..200] } else { &text };
-                div."content bbcode postpreview" { (PreEscaped(bbcode.parse_profiled_opt(text, format!("reply-{}",i(&post.id))))) }
+                div."content bbcode postpreview" { (PreEscaped(bbcode_html(bbcode, text, format!("reply-{}",i(&post.id))))) }
             }
         }
     }
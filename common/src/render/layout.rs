@@ -3,9 +3,10 @@
 use super::super::*;
 
 use contentapi::forms::*;
+use crate::routes::Route;
 
 //Render basic navigation link with only text as the body
-pub fn main_nav_link(data: &MainLayoutData, text: &str, emoji: &str, href: &str, id: Option<&str>) -> Markup {
+pub fn main_nav_link(data: &MainLayoutData, text: &str, emoji: &str, href: Route, id: Option<&str>) -> Markup {
     main_nav_link_raw(data, html!{
         span."navemoji" title=(text) { (emoji) }
         span."navtext" { (text) }
@@ -13,15 +14,15 @@ pub fn main_nav_link(data: &MainLayoutData, text: &str, emoji: &str, href: &str,
 }
 
 //Produce a link for site navigation which supports highlighting if on current page. Body can be "anything"
-pub fn main_nav_link_raw(data: &MainLayoutData, body: Markup, href: &str, id: Option<&str>) -> Markup {
+pub fn main_nav_link_raw(data: &MainLayoutData, body: Markup, href: Route, id: Option<&str>) -> Markup {
     let mut class = String::from("plainlink headertab");
     let compare_path = match &data.override_nav_path {
         Some(path) => path,
         None => data.current_path.as_str()
     };
-    if compare_path.starts_with(href) { class.push_str(" current"); }
+    if compare_path.starts_with(href.as_str()) { class.push_str(" current"); }
     html! {
-        a.(class) href={(data.links.http_root) (href)} id=[id] { (body) }
+        a.(class) href=(data.links.url(href)) id=[id] { (body) }
     }
 }
 
@@ -29,18 +30,18 @@ pub fn header(data: &MainLayoutData) -> Markup {
     html! {
         header."controlbar" {
             nav {
-                a."plainlink" #"homelink" href={(data.links.http_root)"/"} {
+                a."plainlink" #"homelink" href=(data.links.url(routes::index())) {
                     img src={(data.links.resource_root)"/favicon.ico"} alt="Website Logo";
                 }
-                (main_nav_link(data,"Activity","🕒", "/activity",Some("mainactivitylink")))
-                (main_nav_link(data,"Browse","🎮", "/search",Some("mainbrowselink")))
-                (main_nav_link(data,"Forums","📰", "/forum",Some("mainforumlink")))
-                (main_nav_link(data,"Docs","📖", "/documentation",Some("maindocumentationlink")))
-                (main_nav_link(data,"Search","🔎", "/allsearch",Some("mainsearchlink")))
+                (main_nav_link(data,"Activity","🕒", routes::activity(),Some("mainactivitylink")))
+                (main_nav_link(data,"Browse","🎮", routes::search(),Some("mainbrowselink")))
+                (main_nav_link(data,"Forums","📰", routes::forum(),Some("mainforumlink")))
+                (main_nav_link(data,"Docs","📖", routes::documentation(),Some("maindocumentationlink")))
+                (main_nav_link(data,"Search","🔎", routes::allsearch(),Some("mainsearchlink")))
                 @if let Some(user) = &data.user {
                     @if user.admin {
                         //We were already using 'admin', so keep using it! 
-                        (main_nav_link(data,"Admin","🔒","/admin",None))
+                        (main_nav_link(data,"Admin","🔒",routes::admin(),None))
                     }
                 }
             }
@@ -49,10 +50,10 @@ pub fn header(data: &MainLayoutData) -> Markup {
                     (main_nav_link_raw(data,html! {
                         span { (user.username) }
                         img src=(data.links.image(&user.avatar, &QueryImage::avatar(100)));
-                    },"/userhome",None))
+                    },routes::userhome(),None))
                 }
                 @else {
-                    (main_nav_link(data,"Login","Login","/login",None))
+                    (main_nav_link(data,"Login","Login",routes::login(),None))
                 }
             }
         }
@@ -70,8 +71,8 @@ pub fn footer(data: &MainLayoutData) -> Markup {
         footer class="controlbar smallseparate" {
             span #"api_about" { (data.about_api.environment) " - " (data.about_api.version) }
             div #"footer-spacer" {}
-            (main_nav_link(data,"Settings","Settings","/sessionsettings",Some("footer-settings")))
-            (main_nav_link(data,"About","About", "/about",Some("footer-about")))
+            (main_nav_link(data,"Settings","Settings",routes::sessionsettings(),Some("footer-settings")))
+            (main_nav_link(data,"About","About", routes::about(),Some("footer-about")))
         }
     }
 }
@@ -98,7 +99,7 @@ pub fn basic_skeleton(data: &MainLayoutData, head_inner: Markup, body_inner: Mar
                 (data.links.style("/base.css"))
                 (data.links.style("/themes.css"))
                 (data.links.script("/base.js"))
-                script {
+                script nonce=(data.csp_nonce) {
                     (PreEscaped("var SBSBASEURL = \"")) (data.links.http_root) (PreEscaped("\";"))
                 }
                 (head_inner)
@@ -110,7 +111,7 @@ pub fn basic_skeleton(data: &MainLayoutData, head_inner: Markup, body_inner: Mar
                 (body_inner) 
                 //Gotta do it HERE so everything has already run!
                 @if let Some(profile_data) = profile_data {
-                    script {
+                    script nonce=(data.csp_nonce) {
                         "var profiler_data = "(PreEscaped(serde_json::to_string(&profile_data).unwrap_or(String::from("{} /* COULD NOT SERIALIZE */"))))";"
                     }
                 }
@@ -12,6 +12,23 @@ use super::*;
 // *     FORMAT FUNCTIONS     *
 // ----------------------------
 
+/// Turn bbcode into html, profiled under the given name (if profiling is on). The time goes to the metrics either way
+pub fn bbcode_html(bbcode: &mut BBCode, text: &str, name: String) -> String {
+    timed_bbcode(|| bbcode.parse_profiled_opt(text, name))
+}
+
+/// Same as bbcode_html, for when there's nothing to profile
+pub fn bbcode_html_unprofiled(bbcode: &BBCode, text: &str) -> String {
+    timed_bbcode(|| bbcode.parse(text))
+}
+
+fn timed_bbcode(parse: impl FnOnce() -> String) -> String {
+    let start = std::time::Instant::now();
+    let html = parse();
+    contentapi::metrics::global().observe_bbcode(start.elapsed());
+    html
+}
+
 pub fn timeago_future(time: &chrono::DateTime<chrono::Utc>) -> String {
     let duration = time.signed_duration_since(chrono::Utc::now());
     match duration.to_std() {
@@ -135,6 +152,13 @@ impl LinkConfig
     }
 }
 
+/// The hidden csrf field. EVERY form that posts needs this (before any file input), or the post is rejected
+pub fn csrf_input(data: &MainLayoutData) -> Markup {
+    html! {
+        input type="hidden" name=(crate::csrf::CSRFFIELD) value=(data.csrf_token);
+    }
+}
+
 pub fn errorlist(errors: Option<Vec<String>>) -> Markup {
     html! {
         div."errorlist" {
@@ -147,6 +171,19 @@ pub fn errorlist(errors: Option<Vec<String>>) -> Markup {
     }
 }
 
+/// A content's keywords, each a link to everything else tagged with it. Nothing at all if there aren't any
+pub fn keyword_list(links: &LinkConfig, content: &Content) -> Markup {
+    html! {
+        @if let Some(keywords) = content.keywords.as_ref().filter(|k| !k.is_empty()) {
+            div."keywords smallseparate" {
+                @for keyword in keywords {
+                    a."keyword flatlink" href=(links.tag(keyword)) { "#" (keyword) }
+                }
+            }
+        }
+    }
+}
+
 
 #[derive(Default)]
 pub struct PostTextboxConfig {
@@ -61,6 +61,7 @@ pub fn page_card(links: &LinkConfig, page: &Content, users: &HashMap<i64, User>)
                 div."cardtext" {
                     a."flatlink" href=(link) { h3 { (opt_s!(page.name)) } }
                     div."description" { (opt_s!(page.description)) }
+                    (keyword_list(links, page))
                 }
                 //Conditionally render the "cardimage" container
                 @if let Some(images) = values.get(SBSValue::IMAGES).and_then(|k| k.as_array()) {
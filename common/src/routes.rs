@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+
+//Every path the site serves, defined exactly once in sbs_routes below. That one table gets expanded
+//twice: here, into a function per route that builds its link (a Route), and in the server, into the
+//warp filter that matches it. So there's no way to link somewhere the server doesn't serve.
+//
+//Each entry is `name("literal" / {param: Type} / ...);`, and the root is just `index();`.
+//Params are whole path segments, and only String and i64 are supported. String params are percent
+//encoded in links (see ToSegment), and decoded again before the route sees them (see from_segment).
+//Query strings aren't part of the path: the ones more than one route or page share are defined below,
+//so the route reads the same struct the link was made from.
+
+/// The route table. Give it the name of a macro and it calls that macro with every route (see route_links)
+#[macro_export]
+macro_rules! sbs_routes {
+    ($callback:ident) => {
+        $callback!{
+            index();
+            about("about");
+            activity("activity");
+            admin("admin");
+            allsearch("allsearch");
+            documentation("documentation");
+            integrationtest("integrationtest");
+            search("search");
+            sessionsettings("sessionsettings");
+
+            forum("forum");
+            forum_category("forum" / "category" / {hash: String});
+            forum_thread("forum" / "thread" / {hash: String});
+            forum_post("forum" / "thread" / {hash: String} / {post: i64});
+            forum_edit_thread("forum" / "edit" / "thread");
+            forum_edit_post("forum" / "edit" / "post");
+            forum_watch("forum" / "watch" / {thread: i64});
+            forum_react("forum" / "react" / {post: i64});
+            forum_delete_thread("forum" / "delete" / "thread" / {thread: i64});
+            forum_delete_post("forum" / "delete" / "post" / {post: i64});
+
+            page("page"); //Only for old links with ?pid=
+            page_edit("page" / "edit");
+            page_delete("page" / "delete" / {page: i64});
+            page_history("page" / {hash: String} / "history");
+
+            tags("tags");
+            tag("tags" / {keyword: String});
+
+            user("user" / {username: String});
+            userhome("userhome");
+            userhome_watches("userhome" / "watches");
+            messages("messages");
+            messages_new("messages" / "new");
+
+            login("login");
+            logout("logout");
+            register("register");
+            register_confirm("register" / "confirm");
+            recover("recover");
+
+            live_activity("live" / "activity");
+            live_thread("live" / "thread" / {thread: i64});
+
+            widget_bbcodepreview("widget" / "bbcodepreview");
+            widget_contentpreview("widget" / "contentpreview");
+            widget_imagebrowser("widget" / "imagebrowser");
+            widget_qr("widget" / "qr" / {hash: String});
+            widget_recentactivity("widget" / "recentactivity");
+            widget_thread("widget" / "thread");
+            widget_votes("widget" / "votes" / {content: i64});
+
+            healthz("healthz");
+            readyz("readyz");
+            metrics("metrics");
+        }
+    };
+}
+
+/// A link to somewhere on the site, without the http root. Make one with the functions in this module,
+/// then LinkConfig::url gives the full url
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    url: String
+}
+
+impl Route {
+    pub fn as_str(&self) -> &str {
+        &self.url
+    }
+
+    /// Add a query string, from anything serde_urlencoded can serialize. Fields that are None are left out
+    pub fn query<Q: Serialize + ?Sized>(mut self, query: &Q) -> Self {
+        match serde_urlencoded::to_string(query) {
+            Ok(querystring) if querystring.is_empty() => {},
+            Ok(querystring) => {
+                self.url.push(if self.url.contains('?') { '&' } else { '?' });
+                self.url.push_str(&querystring);
+            },
+            Err(error) => tracing::error!("Couldn't serialize query for {}, leaving it off: {}", self.url, error)
+        }
+        self
+    }
+
+    /// Jump to the element with the given id
+    pub fn fragment(mut self, id: &str) -> Self {
+        self.url.push('#');
+        self.url.push_str(id);
+        self
+    }
+}
+
+impl std::fmt::Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.url)
+    }
+}
+
+/// How a route param goes into a link
+pub trait ToSegment {
+    fn to_segment(&self) -> String;
+}
+
+impl ToSegment for &str {
+    /// Percent encode everything but the unreserved characters
+    fn to_segment(&self) -> String {
+        self.bytes().map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b)
+        }).collect()
+    }
+}
+
+impl ToSegment for i64 {
+    fn to_segment(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// Undo the percent encoding of a String param. Anything that isn't a valid escape is left as is
+pub fn from_segment(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escape) {
+            (b'%', Some(byte)) => { decoded.push(byte); i += 3; },
+            (byte, _) => { decoded.push(byte); i += 1; }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Turns the route table into a link function per route. String params are taken as &str
+macro_rules! route_links {
+    ($( $name:ident ( $($seg:tt)/ * ); )*) => {
+        $( route_links!(@link $name [] [] $($seg)*); )*
+    };
+    (@link $name:ident [$(($arg:ident : $argty:ident))*] [$(($part:expr))*]) => {
+        pub fn $name($($arg: route_links!(@argtype $argty)),*) -> Route {
+            let parts: Vec<String> = vec![$($part),*];
+            Route { url: format!("/{}", parts.join("/")) }
+        }
+    };
+    (@link $name:ident [$($args:tt)*] [$($parts:tt)*] $lit:literal $($rest:tt)*) => {
+        route_links!(@link $name [$($args)*] [$($parts)* (String::from($lit))] $($rest)*);
+    };
+    (@link $name:ident [$($args:tt)*] [$($parts:tt)*] {$param:ident : $ty:ident} $($rest:tt)*) => {
+        route_links!(@link $name [$($args)* ($param : $ty)] [$($parts)* (ToSegment::to_segment(&$param))] $($rest)*);
+    };
+    (@argtype String) => { &str };
+    (@argtype i64) => { i64 };
+}
+
+sbs_routes!(route_links);
+
+// -------------------------------------------
+// *     Queries shared by links and routes    *
+// -------------------------------------------
+
+/// ?category= on forum_edit_thread: make a new thread in the category
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewThreadQuery {
+    pub category: String
+}
+
+/// ?thread= on forum_edit_thread: edit the thread
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EditThreadQuery {
+    pub thread: String
+}
+
+/// ?thread= on forum_edit_post: make a new post on the thread, maybe as a reply
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewPostQuery {
+    pub thread: String,
+    pub reply: Option<i64>,
+    pub widget: Option<bool>
+}
+
+/// ?post= on forum_edit_post: edit the post
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EditPostQuery {
+    pub post: i64,
+    pub widget: Option<bool>
+}
+
+/// ?mode= on page_edit: make a new page of the given kind
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewPageQuery {
+    pub mode: String
+}
+
+/// ?page= on page_edit: edit the page
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EditPageQuery {
+    pub page: String
+}
+
+/// messages_new, optionally with the recipient filled in
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NewMessageQuery {
+    pub to: Option<String>
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_round_trip() {
+        for raw in ["c++ fun", "100%", "a/b?c#d", "ünïcødé", ""] {
+            assert_eq!(from_segment(&raw.to_segment()), raw);
+        }
+    }
+
+    #[test]
+    fn bad_escapes_are_left_alone() {
+        assert_eq!(from_segment("50%"), "50%");
+        assert_eq!(from_segment("%zz%4"), "%zz%4");
+        assert_eq!(from_segment("a+b"), "a+b");
+    }
+}
@@ -1,4 +1,5 @@
 use contentapi::*;
+use contentapi::query::*;
 use crate::constants::*;
 use crate::forms::*;
 use crate::forum::can_delete_thread;
@@ -7,41 +8,39 @@ use crate::prefab::*;
 
 /// Generate the complicated FullRequest for the given search. Could be a "From" if 
 /// the search included a per-page I guess...
-pub fn get_search_request(search: &PageSearch, per_page: i32) -> FullRequest
+pub fn get_search_request(search: &PageSearch, per_page: i32) -> Result<FullRequest, QueryError>
 {
     //Build up the request based on the search, then render
     let mut request = FullRequest::new();
-    add_value!(request, "type", ContentType::PAGE);
-    add_value!(request, "systemtype", ContentType::SYSTEM);
-    add_value!(request, "forcontent", SBSValue::FORCONTENT);
-    add_value!(request, "submissions_type", SBSPageType::SUBMISSIONS);
 
+    let parent_query = Query::field("literalType").eq(Param::value("submissions_type", SBSPageType::SUBMISSIONS))
+        .and(Query::field("contentType").eq(Param::value("systemtype", ContentType::SYSTEM)));
     let mut parent_request = build_request!(
         RequestType::content, 
         String::from("id,literalType,contentType"), 
-        String::from("literalType = @submissions_type and contentType = @systemtype")
+        parent_query.attach(&mut request)?
     ); 
     parent_request.name = Some("submissions".to_string());
     request.requests.push(parent_request);
 
-    let mut query = String::from("contentType = @type and !notdeleted() and parentId in @submissions.id"); 
+    let mut query = Query::field("contentType").eq(Param::value("type", ContentType::PAGE))
+        .and(Macro::notdeleted())
+        .and(Query::field("parentId").is_in(Param::result("submissions", "id")));
 
     if let Some(stext) = &search.search {
-        add_value!(request, "text", format!("%{}%", stext));
-        query.push_str(" and (name like @text or !keywordlike(@text))");
+        let text = Param::value("text", format!("%{}%", stext));
+        query = query.and(Query::field("name").like(text.clone()).or(Macro::keywordlike(text)));
     }
 
     if let Some(category) = search.category {
         if category != 0 {
-            add_value!(request, "categoryTag", vec![format!("{}{}", CATEGORYPREFIX, category)]);
-            query.push_str(" and !valuekeyin(@categoryTag)");
+            query = query.and(Macro::valuekeyin(Param::value("categoryTag", vec![format!("{}{}", CATEGORYPREFIX, category)])));
         }
     }
 
     if let Some(user_id) = search.user_id {
         if user_id != 0 {
-            add_value!(request, "userId", user_id);
-            query.push_str(" and createUserId = @userId");
+            query = query.and(Query::field("createUserId").eq(Param::value("userId", user_id)));
         }
     }
 
@@ -50,21 +49,19 @@ pub fn get_search_request(search: &PageSearch, per_page: i32) -> FullRequest
     if let Some(subtype) = &search.subtype 
     {
         if !subtype.is_empty() {
-            add_value!(request, "systemkey", SBSValue::SYSTEMS);
-            add_value!(request, "subtype", subtype.clone());
-            add_value!(request, "ptcsystem", format!("%{}%", PTCSYSTEM));
-            query.push_str(" and literalType = @subtype");
+            let systemkey = Param::value("systemkey", SBSValue::SYSTEMS);
+            query = query.and(Query::field("literalType").eq(Param::value("subtype", subtype.clone())));
             //Ignore certain search criteria
             if subtype == SBSPageType::PROGRAM {
                 //MUST have a key unless the user specifies otherwise
                 if !search.removed {
-                    add_value!(request, "dlkeylist", vec![SBSValue::DOWNLOADKEY]);
-                    query.push_str(" and (!valuekeyin(@dlkeylist) or !valuelike(@systemkey, @ptcsystem))");
+                    query = query.and(Macro::valuekeyin(Param::value("dlkeylist", vec![SBSValue::DOWNLOADKEY]))
+                        .or(Macro::valuelike(systemkey.clone(), Param::value("ptcsystem", format!("%{}%", PTCSYSTEM)))));
                 }
 
                 if search.system != ANYSYSTEM {
-                    add_value!(request, "system", format!("%{}%", search.system)); //Systems is actually a json list but this should be fine
-                    query.push_str(" and !valuelike(@systemkey, @system)");
+                    //Systems is actually a json list but this should be fine
+                    query = query.and(Macro::valuelike(systemkey, Param::value("system", format!("%{}%", search.system))));
                 }
             }
         }
@@ -73,7 +70,7 @@ pub fn get_search_request(search: &PageSearch, per_page: i32) -> FullRequest
     let main_request = build_request!(
         RequestType::content, 
         String::from("id,hash,parentId,contentType,literalType,values,name,description,createUserId,createDate,lastRevisionId,popScore1"), 
-        query, 
+        query.attach(&mut request)?, 
         search.order.clone(), 
         per_page,
         search.page * per_page
@@ -83,21 +80,19 @@ pub fn get_search_request(search: &PageSearch, per_page: i32) -> FullRequest
     let user_request = build_request!(
         RequestType::user,
         String::from("*"),
-        String::from("id in @content.createUserId")
+        Query::field("id").is_in(Param::result("content", "createUserId")).attach(&mut request)?
     );
     request.requests.push(user_request);
 
-    //add_value!(request, "categorytype", SBSPageType::CATEGORY);
     let mut category_request = build_request!(
         RequestType::content,
         String::from(CATEGORYFIELDS),
         get_allcategory_query()
-        //format!("{} and literalType = @categorytype", CATEGORYSEARCHBASE) 
     );
     category_request.name = Some(String::from("categories"));
     request.requests.push(category_request);
 
-    request
+    Ok(request)
 }
 
 //Both of these are the same as threads for now
@@ -114,7 +114,7 @@ pub fn get_all_docpaths(documentation: &Vec<Content>) -> HashMap<String, Vec<&Co
                 }
             }
         }
-        println!("WARNING: documentation {} didn't have a docpath!", opt_s!(doc.name));
+        tracing::warn!("documentation {} didn't have a docpath!", opt_s!(doc.name));
     }
 
     result
@@ -178,14 +178,14 @@ pub fn get_doctree<'a>(documentation: &'a Vec<Content>) -> DocTreeNode<'a>
             //This indicates the path did NOT start with /, meaning we don't know where to place it. We COULD make an assumption I guess...
             //but I'll wait until later to do that
             if !root_path.is_empty() { 
-                println!("{} DOCUMENTATION DROPPED WITH NON-ROOTED PATH", content.len());
+                tracing::warn!("{} DOCUMENTATION DROPPED WITH NON-ROOTED PATH", content.len());
                 continue;
             }
 
             root_node.add_content_fill_path(&path_parts[1..], content);
         }
         else {
-            println!("{} DOCUMENTATION DROPPED WITH EMPTY PATH", content.len());
+            tracing::warn!("{} DOCUMENTATION DROPPED WITH EMPTY PATH", content.len());
             continue;
         }
     }
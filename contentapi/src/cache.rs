@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use sha1::{Digest, Sha1};
+
+use super::*;
+
+//A small in-memory cache for request results. Most anonymous traffic asks for the exact same
+//things over and over (the system alert on EVERY page, the category list, all the documentation),
+//so there's no reason to bother the backend for each one. Entries are keyed on the full request
+//AND who's asking (see CacheScope), so nobody ever sees results meant for someone else. Tokens are
+//never kept as keys, only a hash of them.
+
+/// Who a cached result can be given to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheScope {
+    Anonymous,  //Only cached for people who aren't logged in; logged in requests always go to the backend
+    Everyone,   //The result is the same no matter who asks, so everyone shares one entry
+    PerUser     //Cached separately for each user
+}
+
+struct CacheEntry {
+    result: RequestResult,
+    expires: Instant,
+    last_used: u64
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    tick: u64
+}
+
+/// A bounded LRU of request results, meant to be shared by every ApiContext. A capacity of 0 disables it
+pub struct ResponseCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, state: Mutex::new(CacheState::default()), hits: AtomicU64::new(0), misses: AtomicU64::new(0) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Whether a request in the given scope from the given user (None for anonymous) can use the cache at all
+    pub fn applies(scope: CacheScope, user_token: Option<&str>) -> bool {
+        scope != CacheScope::Anonymous || user_token.is_none()
+    }
+
+    /// The key for a request in the given scope from the given user (None for anonymous). The request is run 
+    /// through serde_json::Value first so the values map always serializes in the same (sorted) order
+    pub fn key(request: &FullRequest, scope: CacheScope, user_token: Option<&str>) -> Result<String, serde_json::Error> {
+        let who = match (scope, user_token) {
+            (CacheScope::Everyone, _) | (_, None) => String::from("*"),
+            (_, Some(token)) => Sha1::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+        };
+        Ok(format!("{}|{}", who, serde_json::to_value(request)?))
+    }
+
+    pub fn get(&self, key: &str) -> Option<RequestResult>
+    {
+        let mut state = self.state.lock().ok()?;
+        state.tick += 1;
+        let tick = state.tick;
+        let now = Instant::now();
+
+        let result = match state.entries.get_mut(key) {
+            Some(entry) if entry.expires > now => {
+                entry.last_used = tick;
+                Some(entry.result.clone())
+            },
+            Some(_) => {
+                state.entries.remove(key);
+                None
+            },
+            None => None
+        };
+
+        if result.is_some() { self.hits.fetch_add(1, Ordering::Relaxed); }
+        else { self.misses.fetch_add(1, Ordering::Relaxed); }
+        result
+    }
+
+    pub fn insert(&self, key: String, result: RequestResult, ttl: Duration)
+    {
+        if !self.enabled() { return; }
+        if let Ok(mut state) = self.state.lock() {
+            state.tick += 1;
+            let tick = state.tick;
+
+            //Clear out the stale stuff first, then the least recently used if that wasn't enough
+            if state.entries.len() >= self.capacity && !state.entries.contains_key(&key) {
+                let now = Instant::now();
+                state.entries.retain(|_, e| e.expires > now);
+                while state.entries.len() >= self.capacity {
+                    let oldest = state.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(k, _)| k.clone());
+                    match oldest {
+                        Some(oldest) => { state.entries.remove(&oldest); },
+                        None => break
+                    }
+                }
+            }
+
+            state.entries.insert(key, CacheEntry { result, expires: Instant::now() + ttl, last_used: tick });
+        }
+    }
+
+    /// Total (hits, misses) since startup
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_never_hold_the_token() {
+        let request = FullRequest::new();
+        let everyone = ResponseCache::key(&request, CacheScope::Everyone, Some("secret")).unwrap();
+        assert_eq!(everyone, ResponseCache::key(&request, CacheScope::Everyone, None).unwrap());
+        let peruser = ResponseCache::key(&request, CacheScope::PerUser, Some("secret")).unwrap();
+        assert!(!peruser.contains("secret"));
+        assert_ne!(peruser, ResponseCache::key(&request, CacheScope::PerUser, Some("other")).unwrap());
+        assert_ne!(peruser, everyone);
+    }
+
+    #[test]
+    fn anonymous_scope_skips_logged_in_users() {
+        assert!(ResponseCache::applies(CacheScope::Anonymous, None));
+        assert!(!ResponseCache::applies(CacheScope::Anonymous, Some("token")));
+        assert!(ResponseCache::applies(CacheScope::Everyone, Some("token")));
+        assert!(ResponseCache::applies(CacheScope::PerUser, Some("token")));
+    }
+}
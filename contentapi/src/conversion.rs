@@ -33,3 +33,151 @@ pub fn cast_result_required<T>(result: &RequestResult, name: &str) -> Result<Vec
 {
     cast_result(result, name)?.ok_or(format!("Couldn't find key {}", name).into())
 }
+
+
+// ------------------------------
+//    TYPED RESULT EXTRACTION
+// ------------------------------
+
+/// Something went wrong pulling a named result out of a RequestResult. Both variants carry the
+/// raw json involved, since that's the only way to figure out what the api actually sent
+#[derive(Debug)]
+pub enum ResultError {
+    Missing(String, String),        //Key, then the raw json for the whole result
+    Parse(String, String, String)   //Key, the parse error, then the raw json for that key
+}
+
+impl ResultError {
+    pub fn raw(&self) -> &str {
+        match self {
+            Self::Missing(_, raw) => raw,
+            Self::Parse(_, _, raw) => raw
+        }
+    }
+}
+
+impl std::fmt::Display for ResultError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Missing(key, _) => write!(f, "Result '{}' was missing from the api response", key),
+            Self::Parse(key, error, _) => write!(f, "Couldn't parse result '{}': {}", key, error)
+        }
+    }
+}
+
+impl std::error::Error for ResultError { }
+
+/// Build a whole struct of results at once; use #[derive(FromRequestResult)] rather than implementing this
+pub trait FromRequestResult: Sized {
+    fn from_result(result: &RequestResult) -> Result<Self, ResultError>;
+}
+
+/// The name a result gets when the request isn't given one (it's just the type)
+pub trait DefaultResultName {
+    const RESULT_NAME: &'static str;
+}
+
+macro_rules! default_result_name {
+    ($($type:ty => $name:literal),*$(,)?) => {
+        $(impl DefaultResultName for $type { const RESULT_NAME: &'static str = $name; })*
+    };
+}
+
+default_result_name!{
+    User => "user",
+    Content => "content",
+    Message => "message",
+    Activity => "activity",
+    AdminLog => "adminlog",
+    UserBan => "ban",
+    ContentEngagement => "content_engagement",
+    MessageEngagement => "message_engagement",
+    Watch => "watch",
+    UserVariable => "uservariable",
+    KeywordAggregate => "keyword_aggregate"
+}
+
+/// What the FromRequestResult derive uses for each field. Optional results that are missing are just empty
+pub fn extract_result<T>(result: &RequestResult, name: &str, optional: bool) -> Result<Vec<T>, ResultError> where T: for<'a> Deserialize<'a>
+{
+    match result.objects.get(name) {
+        Some(objects) => objects.iter().map(|o| <T as Deserialize>::deserialize(o)).collect::<Result<Vec<T>, _>>()
+            .map_err(|e| ResultError::Parse(name.to_string(), e.to_string(), serde_json::to_string(objects).unwrap_or_default())),
+        None if optional => Ok(Vec::new()),
+        None => Err(ResultError::Missing(name.to_string(), serde_json::to_string(&result.objects).unwrap_or_default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn result_with(objects: Value) -> RequestResult {
+        RequestResult {
+            search: FullRequest::new(),
+            databaseTimes: HashMap::new(),
+            objects: serde_json::from_value(objects).unwrap(),
+            totalTime: 0.0,
+            nonDbTime: 0.0,
+            requestUser: None
+        }
+    }
+
+    #[test]
+    fn missing_required_result() {
+        let result = result_with(json!({ "content": [{ "id": 5 }] }));
+        match extract_result::<User>(&result, "user", false) {
+            Err(ResultError::Missing(key, raw)) => {
+                assert_eq!(key, "user");
+                assert_eq!(raw, r#"{"content":[{"id":5}]}"#);
+            },
+            other => panic!("Expected a missing result, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn missing_optional_result() {
+        let result = result_with(json!({}));
+        assert!(extract_result::<User>(&result, "user", true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bad_row() {
+        let result = result_with(json!({ "user": [{ "id": 1 }, 5] }));
+        match extract_result::<User>(&result, "user", false) {
+            Err(error @ ResultError::Parse(..)) => assert_eq!(error.raw(), r#"[{"id":1},5]"#),
+            other => panic!("Expected a parse error, got {:?}", other)
+        }
+    }
+
+    const STICKYKEY: &str = "stickies";
+
+    #[derive(FromRequestResult)]
+    struct Derived {
+        watches: Vec<Watch>,                                   //Default name
+        #[result("thread")] threads: Vec<Content>,             //Literal name
+        #[result(STICKYKEY, optional)] stickies: Vec<Content>, //Any expression, and optional
+        #[result(optional)] variables: Vec<UserVariable>       //Default name, but optional
+    }
+
+    #[test]
+    fn derive_uses_names_and_optional() {
+        let result = result_with(json!({
+            "watch": [{ "contentId": 1 }],
+            "thread": [{ "id": 2 }, { "id": 3 }],
+            "stickies": [{ "id": 4 }]
+        }));
+        let derived = Derived::from_result(&result).unwrap();
+        assert_eq!(derived.watches[0].contentId, Some(1));
+        assert_eq!(derived.threads.iter().map(|t| t.id).collect::<Vec<_>>(), vec![Some(2), Some(3)]);
+        assert_eq!(derived.stickies[0].id, Some(4));
+        assert!(derived.variables.is_empty());
+
+        //Optional ones can go missing, the rest can't
+        let result = result_with(json!({ "watch": [], "thread": [] }));
+        assert!(Derived::from_result(&result).is_ok());
+        let result = result_with(json!({ "thread": [] }));
+        assert!(matches!(Derived::from_result(&result), Err(ResultError::Missing(key, _)) if key == "watch"));
+    }
+}
@@ -3,10 +3,14 @@ use core::fmt::Debug;
 
 use serde::Serialize;
 use serde::de::DeserializeOwned;
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use tracing::Instrument;
 
 use forms;
 
 use super::*;
+use crate::policy::RequestPolicy;
+use crate::cache::{CacheScope, ResponseCache};
 
 //There is some "context" that represents a current user and their client connection,
 //as well as the api endpoint to connect to. This is used to craft requests on your behalf
@@ -14,14 +18,15 @@ use super::*;
 
 //These are the specific types of errors we'll care about from the api. In all instances, the String
 //is a minimal amount of data to show the users. The rest is for logging
+//The AboutRequest is boxed so every Result carrying an ApiError doesn't pay for it
 //#[derive(Error, Debug)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ApiError
 {
-    NonRequest(AboutRequest, String),   //Something not pertaining to the actual request itself happened!
-    Parse(AboutRequest, String, Option<Vec<u8>>),        //Something didn't parse correctly! This is common enough to be its own error
-    Network(AboutRequest, String),      //Is the API reachable? Endpoint not necessary most likely; this indicates an error beyond 404
-    Request(AboutRequest, String, u16), //Oh something went wrong with the request itself! Probably a 400 or 500 error
+    NonRequest(Box<AboutRequest>, String),   //Something not pertaining to the actual request itself happened!
+    Parse(Box<AboutRequest>, String, Option<Vec<u8>>),        //Something didn't parse correctly! This is common enough to be its own error
+    Network(Box<AboutRequest>, String),      //Is the API reachable? Endpoint not necessary most likely; this indicates an error beyond 404
+    Request(Box<AboutRequest>, String, u16), //Oh something went wrong with the request itself! Probably a 400 or 500 error
     Other(String)                       //Avoid this at all costs, if you can
 }
 
@@ -47,19 +52,19 @@ impl ApiError {
     pub fn to_verbose_string(&self) -> String {
         match self {
             Self::NonRequest(about,err) => 
-                format!("[{}]{} - Something happened before we could reach the backend: {}", about.verb, about.endpoint, err),
+                format!("{} - Something happened before we could reach the backend: {}", about.describe(), err),
             Self::Parse(about,err,data) => 
                 if let Some(data) = data {
-                    format!("[{}]{} - Couldn't parse response from backend: {}. Data:\n{}", 
-                        about.verb, about.endpoint, err, String::from_utf8_lossy(data).into_owned()) 
+                    format!("{} - Couldn't parse response from backend: {}. Data:\n{}", 
+                        about.describe(), err, String::from_utf8_lossy(data).into_owned()) 
                 }
                 else { 
-                    format!("[{}]{} - 'ParseError': COULDN'T GET BYTES FROM BODY OF RESPONSE (THIS IS BAD!): {}", about.verb, about.endpoint, err) 
+                    format!("{} - 'ParseError': COULDN'T GET BYTES FROM BODY OF RESPONSE (THIS IS BAD!): {}", about.describe(), err) 
                 },
             Self::Network(about,err) => 
-                format!("[{}]{} - The backend seems to be unreachable: {}", about.verb, about.endpoint, err),
+                format!("{} - The backend seems to be unreachable: {}", about.describe(), err),
             Self::Request(about,err,api_status_code) => 
-                format!("[{}]{} - Bad request to API ({}): {}", about.verb, about.endpoint, api_status_code, err),
+                format!("{} - Bad request to API ({}): {}", about.describe(), api_status_code, err),
             Self::Other(err) =>
                 format!("Generic API error: {}", err)
         }
@@ -73,7 +78,7 @@ impl From<Box<dyn std::error::Error>> for ApiError {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct AboutRequest {
     //This is GET/POST/etc. I don't care for it to be an enum, since I'm just printing it
     pub verb: String,
@@ -81,21 +86,34 @@ pub struct AboutRequest {
     //Restricted data, which should probably not even be logged to the console! So what do
     //we do with it? It's mostly just for debugging I think, there may be a flag to enable
     //printing the restricted data
-    pub post_data: Option<String>
+    pub post_data: Option<String>,
+    //The id of the incoming (page) request that caused this api request, if any. Filled in by the
+    //ApiContext, so you don't need to set it yourself
+    pub request_id: Option<String>
+}
+
+impl AboutRequest {
+    /// A short description for logs and error messages, like "[POST]/request (request 1a2b-3)"
+    pub fn describe(&self) -> String {
+        match &self.request_id {
+            Some(id) => format!("[{}]{} (request {})", self.verb, self.endpoint, id),
+            None => format!("[{}]{}", self.verb, self.endpoint)
+        }
+    }
 }
 
 /// This is needed so often: just convert any generic error into a "no request" error,
 /// assuming you have the AboutRequest...
 macro_rules! noreqerr {
     ($result:expr, $req:ident) => {
-        $result.map_err(|e| ApiError::NonRequest($req.clone(), e.to_string()))
+        $result.map_err(|e| ApiError::NonRequest(Box::new($req.clone()), e.to_string()))
     };
 }
 
 /// This isn't needed as often: just convert any generic error into a "network" error
 macro_rules! neterr {
     ($result:expr, $req:ident) => {
-        $result.map_err(|e| ApiError::Network($req.clone(), e.to_string()))
+        $result.map_err(|e| ApiError::Network(Box::new($req.clone()), e.to_string()))
     };
 }
 
@@ -105,16 +123,48 @@ macro_rules! parseerr {
         parseerr!($result, $req, None)
     };
     ($result:expr, $req:ident, $data:expr) => {
-        $result.map_err(|e| ApiError::Parse($req.clone(), e.to_string(), $data))
+        $result.map_err(|e| ApiError::Parse(Box::new($req.clone()), e.to_string(), $data))
     };
 }
 
+/// Data for [`ApiContext::upload_file`], streamed straight to the api as it arrives
+pub type UploadStream = std::pin::Pin<Box<dyn Stream<Item = Result<Bytes, UploadError>> + Send>>;
+pub type UploadError = Box<dyn std::error::Error + Send + Sync>;
+pub use hyper::body::Bytes;
+
+/// The hyper client used for all api requests. Clones share the same connection pool
+pub type ApiClient = hyper::client::Client<hyper::client::HttpConnector>;
+
+/// Connection pool settings for [`build_client`]
+#[derive(Clone, Debug)]
+pub struct ClientSettings {
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout: Option<std::time::Duration>, //None keeps idle connections forever
+    pub http2: bool //Prior knowledge http2 ONLY; the backend must support h2c!
+}
+
+/// Build a client meant to be shared by every ApiContext (see [`ApiContext::with_client`]) so 
+/// connections to the backend are kept alive and reused between page requests
+pub fn build_client(settings: &ClientSettings) -> ApiClient {
+    hyper::client::Client::builder()
+        .pool_max_idle_per_host(settings.pool_max_idle_per_host)
+        .pool_idle_timeout(settings.pool_idle_timeout)
+        .http2_only(settings.http2)
+        .build_http()
+}
+
 //You'll want to create a new api context to make multiple requests, as it's more efficient.
-//Maybe one per request?
+//Maybe one per request? Give it the shared client from build_client so connections are reused. Clones
+//share everything (client, cache, profiler), they're only for running requests side by side
+#[derive(Clone)]
 pub struct ApiContext {
     api_url: String,
-    client: hyper::client::Client<hyper::client::HttpConnector>,
+    client: ApiClient,
     user_token: Option<String>,
+    policy: RequestPolicy,
+    cache: Option<std::sync::Arc<ResponseCache>>,
+    live: Option<std::sync::Arc<crate::live::LiveHub>>,
+    request_id: Option<String>,
 
     #[cfg(feature = "profiling")]
     pub profiler: onestop::OneList<onestop::OneDuration>
@@ -125,6 +175,10 @@ impl ApiContext {
         Self {
             api_url, user_token,
             client : hyper::client::Client::new(),
+            policy: RequestPolicy::default(),
+            cache: None,
+            live: None,
+            request_id: None,
 
             #[cfg(feature = "profiling")]
             profiler: onestop::OneList::<onestop::OneDuration>::new()
@@ -136,14 +190,69 @@ impl ApiContext {
         Self {
             api_url, user_token,
             client : hyper::client::Client::new(),
+            policy: RequestPolicy::default(),
+            cache: None,
+            live: None,
+            request_id: None,
             profiler
         }
     }
 
+    /// Use the given (shared) client instead of the one made just for this context
+    pub fn with_client(mut self, client: ApiClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Allow [`ApiContext::post_request_cached`] to use the given (shared) cache
+    pub fn with_cache(mut self, cache: std::sync::Arc<ResponseCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Use the given timeout/retry/breaker policy for all requests from this context
+    pub fn with_policy(mut self, policy: RequestPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Tag every request (and error) from this context with the id of the page request that made it
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.request_id = Some(request_id);
+        self
+    }
+
+    /// Share live update sockets through the given hub (see [`ApiContext::live_updates`])
+    pub fn with_live(mut self, live: std::sync::Arc<crate::live::LiveHub>) -> Self {
+        self.live = Some(live);
+        self
+    }
+
+    /// Every live push this user can see, from last_id on (see [`crate::live::LiveHub::updates`]). Without
+    /// a shared hub, the stream gets a socket all to itself
+    pub fn live_updates(&self, last_id: Option<i64>) -> impl Stream<Item = Result<std::sync::Arc<crate::live::LiveData>, ApiError>> + Send + 'static {
+        let hub = self.live.clone().unwrap_or_else(|| std::sync::Arc::new(crate::live::LiveHub::new(Default::default())));
+        let client = crate::live::LiveClient {
+            url: self.get_endpoint("/live/ws"),
+            user_token: self.user_token.clone(),
+            request_id: self.request_id.clone(),
+            settings: hub.settings().clone()
+        };
+        hub.updates(client, last_id)
+    }
+
     pub fn get_endpoint(&self, endpoint: &str) -> String {
         format!("{}{}", self.api_url, endpoint)
     }
 
+    /// Stamp the request with this context's request id (if it doesn't already have one)
+    fn identify(&self, mut request: AboutRequest) -> AboutRequest {
+        if request.request_id.is_none() {
+            request.request_id = self.request_id.clone();
+        }
+        request
+    }
+
     /// All requests to the API start off the same
     fn get_request_builder(&self, request: &AboutRequest, method: hyper::Method) -> Result<hyper::http::request::Builder, ApiError> 
     {
@@ -163,6 +272,14 @@ impl ApiContext {
 
     //Once a response comes back from the API, figure out the appropriate errors or data to parse and return
     async fn handle_response<T: DeserializeOwned>(response: hyper::Response<hyper::Body>, about: AboutRequest) -> Result<T, ApiError> {
+        let result = Self::read_response(response, about).await;
+        if let Err(error) = &result {
+            crate::metrics::global().observe_api_error(error);
+        }
+        result
+    }
+
+    async fn read_response<T: DeserializeOwned>(response: hyper::Response<hyper::Body>, about: AboutRequest) -> Result<T, ApiError> {
         let status = response.status();
         let u_status = status.as_u16();
 
@@ -176,25 +293,122 @@ impl ApiContext {
         }
         else {
             match String::from_utf8(body.into_iter().collect()) {
-                Ok(error) => Err(ApiError::Request(about, error, u_status)),
-                Err(error) => Err(ApiError::Request(about, format!("RESPONSE BODY UTF-8 ERROR: {}", error), u_status))
+                Ok(error) => Err(ApiError::Request(Box::new(about), error, u_status)),
+                Err(error) => Err(ApiError::Request(Box::new(about), format!("RESPONSE BODY UTF-8 ERROR: {}", error), u_status))
             }
         }
     }
 
+    /// Send the request, following the policy: fail fast if the breaker is open, time out each attempt, 
+    /// and retry network failures (only if the caller says the request is safe to repeat). The body is
+    /// rebuilt for each attempt since hyper bodies can't be reused; make_body gives the content type and 
+    /// body, or None for no body at all. Streamed bodies can only be made once, so never retry those!
+    async fn send_with_policy(&self, request: &AboutRequest, method: hyper::Method, 
+        make_body: impl FnMut() -> Option<(String, hyper::Body)>, retryable: bool) -> 
+        Result<hyper::Response<hyper::Body>, ApiError>
+    {
+        let span = tracing::info_span!("api", 
+            request_id = request.request_id.as_deref().unwrap_or("-"), 
+            verb = %request.verb, endpoint = %request.endpoint, 
+            status = tracing::field::Empty, duration_ms = tracing::field::Empty);
+        let start = std::time::Instant::now();
+        let result = self.send_attempts(request, method, make_body, retryable).instrument(span.clone()).await;
+
+        let elapsed = start.elapsed();
+        crate::metrics::global().observe_backend(&request.endpoint, elapsed);
+        if let Err(error) = &result {
+            crate::metrics::global().observe_api_error(error);
+        }
+
+        let duration_ms = elapsed.as_millis() as u64;
+        span.record("duration_ms", duration_ms);
+        span.in_scope(|| match &result {
+            Ok(response) => {
+                span.record("status", response.status().as_u16());
+                tracing::debug!(status = response.status().as_u16(), duration_ms, "api request finished");
+            },
+            Err(error) => tracing::warn!(duration_ms, "api request failed: {}", error.to_user_string())
+        });
+        result
+    }
+
+    async fn send_attempts(&self, request: &AboutRequest, method: hyper::Method, 
+        mut make_body: impl FnMut() -> Option<(String, hyper::Body)>, retryable: bool) -> 
+        Result<hyper::Response<hyper::Body>, ApiError>
+    {
+        if let Err(remaining) = self.policy.breaker.allow() {
+            return Err(ApiError::Network(Box::new(request.clone()), 
+                format!("Backend marked unavailable after repeated failures, not retrying for another {}s", remaining.as_secs() + 1)));
+        }
+
+        let attempts = self.policy.attempts(retryable);
+        let mut attempt = 1;
+
+        loop 
+        {
+            let mut reqbuilder = self.get_request_builder(request, method.clone())?;
+            let body = match make_body() {
+                Some((content_type, body)) => {
+                    reqbuilder = reqbuilder.header("Content-Type", content_type);
+                    body
+                },
+                None => hyper::Body::empty()
+            };
+            let req = noreqerr!(reqbuilder.body(body), request)?;
+
+            #[cfg(feature = "postdump")]
+            tracing::debug!("Request: {:?}", &req);
+
+            //Mapping the request error to a string is PERFECTLY ok in this library because these errors are
+            //NOT from stuff like 400 or 500 statuses, they're JUST from network errors (it's localhost so
+            //it should never happen, and I'm fine with funky output for the few times there are downtimes)
+            //The timeout covers the whole body too: a backend that sends headers and then stalls is just as stuck
+            let result = match self.policy.timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, self.fetch(req, request)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(ApiError::Network(Box::new(request.clone()), format!("Backend took longer than {}ms to respond", timeout.as_millis())))
+                },
+                None => self.fetch(req, request).await
+            };
+
+            //A gateway error means whatever is in front of the backend couldn't reach it, so that's a network failure too
+            let failed = match &result {
+                Ok(response) => RequestPolicy::is_gateway_failure(response.status().as_u16()),
+                Err(_) => true
+            };
+
+            if !failed {
+                self.policy.breaker.record_success();
+                return result;
+            }
+            if attempt >= attempts {
+                self.policy.breaker.record_failure();
+                return result;
+            }
+
+            let backoff = self.policy.backoff(attempt);
+            tracing::warn!("Retrying {} (attempt {} of {}) in {}ms", request.describe(), attempt + 1, attempts, backoff.as_millis());
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    /// Send one request and read the whole response, so the caller can put a single timeout around all of it
+    async fn fetch(&self, req: hyper::Request<hyper::Body>, request: &AboutRequest) -> Result<hyper::Response<hyper::Body>, ApiError>
+    {
+        let response = neterr!(self.client.request(req).await, request)?;
+        let (parts, body) = response.into_parts();
+        let body = neterr!(hyper::body::to_bytes(body).await, request)?;
+        Ok(hyper::Response::from_parts(parts, hyper::Body::from(body)))
+    }
+
     //Construct a basic GET request to the given endpoint (including ?params) using the given
     //request context. Automatically add bearer headers and all that. Errors on the appropriate
     //status codes, message is assumed to be parsed from body
     pub async fn basic_get_request<T: DeserializeOwned>(&self, request: AboutRequest) -> Result<T, ApiError>
     {
-        let reqbuilder = self.get_request_builder(&request, hyper::Method::GET)?;
-        let req = noreqerr!(reqbuilder.body(hyper::Body::empty()), request)?;
-
-        //Mapping the request error to a string is PERFECTLY ok in this library because these errors are
-        //NOT from stuff like 400 or 500 statuses, they're JUST from network errors (it's localhost so
-        //it should never happen, and I'm fine with funky output for the few times there are downtimes)
-        let response = neterr!(self.client.request(req).await, request)?;
-        
+        let request = self.identify(request);
+        let response = self.send_with_policy(&request, hyper::Method::GET, || None, true).await?;
         Self::handle_response(response, request).await
     }
 
@@ -202,17 +416,12 @@ impl ApiContext {
     //request context. Automatically add bearer headers and all that
     pub async fn basic_post_request<U: Serialize+Debug, T: DeserializeOwned>(&self, request: AboutRequest, data: &U) -> Result<T, ApiError>
     {
-        let reqbuilder = self.get_request_builder(&request, hyper::Method::POST)?
-            .header("Content-Type", "application/json");
+        let request = self.identify(request);
+        //Posts to /request are just reads, so they're as safe to retry as a GET. Nothing else is!
+        let retryable = request.endpoint == "/request";
         let json = noreqerr!(serde_json::ser::to_string(data), request)?; //Even though this is serde, it's not a parse error because it's before the request
-        let req = noreqerr!(reqbuilder.body(hyper::Body::from(json)), request)?; 
-
-        #[cfg(feature = "postdump")]
-        println!("Request: {:?}", &req);
-
-        let response = self.client.request(req).await
-            .map_err(|e| ApiError::Network(request.clone(), e.to_string()))?;
-
+        let response = self.send_with_policy(&request, hyper::Method::POST, 
+            || Some((String::from("application/json"), hyper::Body::from(json.clone()))), retryable).await?;
         Self::handle_response(response, request).await
     }
 }
@@ -223,7 +432,8 @@ macro_rules! make_get_endpoint {
             self.basic_get_request(AboutRequest{ 
                 endpoint: String::from($endpoint),
                 verb: String::from("GET"),
-                post_data: None
+                post_data: None,
+                ..Default::default()
             }).await
         }
     };
@@ -235,7 +445,8 @@ macro_rules! make_post_endpoint {
             self.basic_post_request(AboutRequest{ 
                 endpoint: String::from($endpoint),
                 verb: String::from("POST"),
-                post_data: Some(format!("{:#?}", data))
+                post_data: Some(format!("{:#?}", data)),
+                ..Default::default()
             }, data).await
         }
     };
@@ -278,7 +489,6 @@ impl ApiContext
     make_post_endpoint!{post_email_recover<String,bool>("/user/sendpasswordrecovery")}
     make_post_endpoint!{post_register_confirm<forms::RegisterConfirm,String>("/user/confirmregistration")}
     make_post_endpoint!{post_usersensitive<forms::UserSensitive,String>("/user/privatedata")} //Returns token now
-    make_post_endpoint!{post_request<FullRequest,RequestResult>("/request")}
     make_post_endpoint!{post_userupdate<User,User>("/write/user")}
     //make_post_endpoint!{post_content<Content,Content>("/write/content")}
     make_post_endpoint!{post_message<Message,Message>("/write/message")}
@@ -287,6 +497,19 @@ impl ApiContext
 
     //These endpoints don't really fit into the normal "make_post_endpoint" macro
 
+    /// Run the request. The times the backend reports for it go to the metrics
+    pub async fn post_request(&self, request: &FullRequest) -> Result<RequestResult, ApiError>
+    {
+        let result: RequestResult = self.basic_post_request(AboutRequest{ 
+            endpoint: String::from("/request"),
+            verb: String::from("POST"),
+            post_data: Some(format!("{:#?}", request)),
+            ..Default::default()
+        }, request).await?;
+        crate::metrics::global().observe_request_result(&result);
+        Ok(result)
+    }
+
     pub async fn post_content(&self, content: &Content, message: Option<String>) -> Result<Content, ApiError>
     {
         let msgParam = EditMessageParam::new(message);
@@ -295,16 +518,28 @@ impl ApiContext
         self.basic_post_request(AboutRequest{ 
             endpoint: format!("/write/content?{}", msgQuery),
             verb: String::from("POST"),
-            post_data: Some(format!("")), 
+            post_data: Some(String::new()),
+            ..Default::default()
         }, content).await
     }
 
+    /// The content exactly as it was at the given revision (an activity id, same as lastRevisionId)
+    pub async fn get_revision(&self, revision_id: i64) -> Result<Content, ApiError>
+    {
+        self.basic_get_request(AboutRequest{ 
+            endpoint: format!("/history/{}", revision_id),
+            verb: String::from("GET"),
+            ..Default::default()
+        }).await
+    }
+
     pub async fn post_delete_content(&self, content_id: i64) -> Result<Content, ApiError> 
     {
         self.basic_post_request(AboutRequest{ 
             endpoint: format!("/delete/content/{}", content_id),
             verb: String::from("POST"),
-            post_data: None, 
+            post_data: None,
+            ..Default::default()
         }, &true).await
     }
 
@@ -313,7 +548,8 @@ impl ApiContext
         self.basic_post_request(AboutRequest{ 
             endpoint: format!("/delete/message/{}", message_id),
             verb: String::from("POST"),
-            post_data: None, 
+            post_data: None,
+            ..Default::default()
         }, &true).await
     }
 
@@ -322,10 +558,129 @@ impl ApiContext
         self.basic_post_request(AboutRequest{ 
             endpoint: format!("/shortcuts/content/{}/setengagement/{}", content_id, engagement_type),
             verb: String::from("POST"),
-            post_data: Some(engagement.to_string()), 
+            post_data: Some(engagement.to_string()),
+            ..Default::default()
+        }, &engagement.to_string()).await
+    }
+
+    /// Same as content engagement: one engagement of each type per user, and an empty engagement removes it
+    pub async fn post_set_message_engagement(&self, message_id: i64, engagement_type: &str, engagement: &str) -> Result<MessageEngagement, ApiError>
+    {
+        self.basic_post_request(AboutRequest{ 
+            endpoint: format!("/shortcuts/message/{}/setengagement/{}", message_id, engagement_type),
+            verb: String::from("POST"),
+            post_data: Some(engagement.to_string()),
+            ..Default::default()
         }, &engagement.to_string()).await
     }
 
+    /// Start watching the given content. The watch starts out caught up, only comments after this count as unread
+    pub async fn post_watch_add(&self, content_id: i64) -> Result<Watch, ApiError>
+    {
+        self.basic_post_request(AboutRequest{ 
+            endpoint: format!("/shortcuts/watch/add/{}", content_id),
+            verb: String::from("POST"),
+            post_data: None,
+            ..Default::default()
+        }, &true).await
+    }
+
+    pub async fn post_watch_delete(&self, content_id: i64) -> Result<Watch, ApiError>
+    {
+        self.basic_post_request(AboutRequest{ 
+            endpoint: format!("/shortcuts/watch/delete/{}", content_id),
+            verb: String::from("POST"),
+            post_data: None,
+            ..Default::default()
+        }, &true).await
+    }
+
+    /// Mark everything on the given (watched) content as read
+    pub async fn post_watch_clear(&self, content_id: i64) -> Result<Watch, ApiError>
+    {
+        self.basic_post_request(AboutRequest{ 
+            endpoint: format!("/shortcuts/watch/clear/{}", content_id),
+            verb: String::from("POST"),
+            post_data: None,
+            ..Default::default()
+        }, &true).await
+    }
+
+    /// Set the current user's variable with the given key, creating it if it isn't there. The key goes
+    /// straight into the url, so keep it plain
+    pub async fn post_uservariable(&self, key: &str, value: &str) -> Result<UserVariable, ApiError>
+    {
+        self.basic_post_request(AboutRequest{ 
+            endpoint: format!("/shortcuts/uservariable/{}", key),
+            verb: String::from("POST"),
+            post_data: None,
+            ..Default::default()
+        }, &value).await
+    }
+
+    pub async fn post_uservariable_delete(&self, key: &str) -> Result<UserVariable, ApiError>
+    {
+        self.basic_post_request(AboutRequest{ 
+            endpoint: format!("/shortcuts/uservariable/delete/{}", key),
+            verb: String::from("POST"),
+            post_data: None,
+            ..Default::default()
+        }, &true).await
+    }
+
+    /// Upload a file as multipart form data to /file, streaming the data straight through as it arrives (so 
+    /// the whole file is never held in memory). The upload's object is the metadata for the new file content;
+    /// the content type is forced to FILE. Uploads are never retried, the data is gone once it's sent!
+    pub async fn upload_file(&self, upload: &forms::FileUpload, data: UploadStream) -> Result<Content, ApiError>
+    {
+        let request = AboutRequest {
+            endpoint: String::from("/file"),
+            verb: String::from("POST"),
+            post_data: Some(format!("multipart file upload: {}", upload.filename)),
+            ..Default::default()
+        };
+        let request = self.identify(request);
+
+        let mut object = upload.object.clone();
+        object.contentType = Some(ContentType::FILE);
+        let object = noreqerr!(serde_json::to_string(&object), request)?;
+
+        //The boundary just has to not show up in the data. Nobody is going to upload this on purpose
+        let boundary = format!("contentapi-upload-{:x}", std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default());
+        //Quotes and newlines would break the part headers
+        let filename: String = upload.filename.chars().map(|c| if c == '"' || c == '\r' || c == '\n' { '_' } else { c }).collect();
+        let mime = if upload.mime.is_empty() { "application/octet-stream" } else { &upload.mime };
+
+        let head = format!(
+            "--{0}\r\nContent-Disposition: form-data; name=\"object\"\r\nContent-Type: application/json\r\n\r\n{1}\r\n\
+             --{0}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{2}\"\r\nContent-Type: {3}\r\n\r\n",
+            boundary, object, filename, mime);
+        let tail = format!("\r\n--{}--\r\n", boundary);
+
+        let body_stream = futures_util::stream::once(futures_util::future::ready(Ok(Bytes::from(head))))
+            .chain(data)
+            .chain(futures_util::stream::once(futures_util::future::ready(Ok(Bytes::from(tail)))));
+
+        let mut body = Some(hyper::Body::wrap_stream(body_stream));
+        let content_type = format!("multipart/form-data; boundary={}", boundary);
+        let response = self.send_with_policy(&request, hyper::Method::POST, 
+            || body.take().map(|b| (content_type.clone(), b)), false).await?;
+        Self::handle_response(response, request).await
+    }
+
+    /// Upload a file that's already base64 encoded to /file/asobject. Only use this for small files you
+    /// already have in memory; the whole thing goes up as one big json string
+    pub async fn upload_file_base64(&self, upload: &forms::FileUploadAsObject) -> Result<Content, ApiError>
+    {
+        self.basic_post_request(AboutRequest{ 
+            endpoint: String::from("/file/asobject"),
+            verb: String::from("POST"),
+            post_data: Some(format!("base64 file upload: {} bytes", upload.base64blob.len())),
+            ..Default::default()
+        }, upload).await
+    }
+
     /// This MAY OR MAY NOT profile depending on your featureset!
     pub async fn post_request_profiled_opt(&mut self, request: &FullRequest, _name: &str) -> Result<RequestResult, ApiError> 
     {
@@ -348,6 +703,104 @@ impl ApiContext
         return self.post_request(request).await;
     }
 
+    /// Same as post_request_profiled_opt, but the result may come from the shared cache (if there is one). 
+    /// The scope says who the result can be shared with (see CacheScope); only use Everyone for things 
+    /// that really are the same for every user, permissions included!
+    pub async fn post_request_cached(&mut self, request: &FullRequest, name: &str, ttl: std::time::Duration, scope: CacheScope) -> 
+        Result<RequestResult, ApiError> 
+    {
+        let cache = match self.cache {
+            Some(ref cache) if cache.enabled() && ResponseCache::applies(scope, self.user_token.as_deref()) => cache.clone(),
+            _ => return self.post_request_profiled_opt(request, name).await
+        };
+
+        let key = ResponseCache::key(request, scope, self.user_token.as_deref()).map_err(|e| ApiError::Other(e.to_string()))?;
+        let cached = cache.get(&key);
+
+        #[cfg(feature = "profiling")]
+        self.profiler.add(onestop::OneDuration::from_duration(std::time::Duration::ZERO, 
+            format!("{}-cache{}", name, if cached.is_some() { "hit" } else { "miss" })));
+
+        if let Some(result) = cached {
+            return Ok(result);
+        }
+
+        let result = self.post_request_profiled_opt(request, name).await?;
+        cache.insert(key, result.clone(), ttl);
+        Ok(result)
+    }
+
+    /// Run the request over and over, skipping further into the FIRST request's results each time, until
+    /// it comes back with a short page. You get the whole result for each page: everything after the first
+    /// request is sent along every time, so anything chained off it (like "id in @content.createUserId")
+    /// always matches the page it came with. The first request's limit is the page size (0 means the
+    /// most the api allows), and it's ordered by id if it has no order, otherwise skip isn't stable.
+    /// If cache_ttl is given, pages go through [`ApiContext::post_request_cached`]
+    pub fn stream_pages<'a>(&'a mut self, mut request: FullRequest, name: &'a str, cache_ttl: Option<std::time::Duration>) ->
+        impl Stream<Item = Result<RequestResult, ApiError>> + 'a
+    {
+        let mut page_size = REQUESTRESULTLIMIT as i64;
+        let mut start = 0;
+        if let Some(first) = request.requests.first_mut() {
+            if first.limit > 0 && first.limit < page_size {
+                page_size = first.limit;
+            }
+            if first.order.is_none() {
+                first.order = Some(String::from("id"));
+            }
+            first.limit = page_size;
+            start = first.skip;
+        }
+
+        //State is (context, request, next skip, done)
+        futures_util::stream::unfold((self, request, start, false), move |(context, mut request, skip, done)| async move
+        {
+            if done { return None; }
+
+            let primary = match request.requests.first_mut() {
+                Some(first) => {
+                    first.skip = skip;
+                    first.name.clone().unwrap_or_else(|| first.r#type.clone())
+                },
+                None => return Some((Err(ApiError::Other(String::from("Can't page through an empty request"))), (context, request, skip, true)))
+            };
+
+            let page_name = format!("{}-skip{}", name, skip);
+            let result = match cache_ttl {
+                Some(ttl) => context.post_request_cached(&request, &page_name, ttl, CacheScope::Anonymous).await,
+                None => context.post_request_profiled_opt(&request, &page_name).await
+            };
+
+            match result {
+                Ok(page) => {
+                    let count = page.objects.get(&primary).map(|o| o.len() as i64).unwrap_or(0);
+                    Some((Ok(page), (context, request, skip + page_size, count < page_size)))
+                },
+                Err(error) => Some((Err(error), (context, request, skip, true)))
+            }
+        })
+    }
+
+    /// Every row from the FIRST request in the given request, no matter how many there are. Pages through
+    /// the results as you go, see [`ApiContext::stream_pages`]; the other requests only matter if the first
+    /// one is chained off of them
+    pub fn stream_all<'a, T: DeserializeOwned + 'a>(&'a mut self, request: FullRequest) -> impl Stream<Item = Result<T, ApiError>> + 'a
+    {
+        self.stream_all_named(request, "stream_all", None)
+    }
+
+    /// Same as [`ApiContext::stream_all`] but with the name you want in the profiler, and optionally cached
+    pub fn stream_all_named<'a, T: DeserializeOwned + 'a>(&'a mut self, request: FullRequest, name: &'a str, cache_ttl: Option<std::time::Duration>) ->
+        impl Stream<Item = Result<T, ApiError>> + 'a
+    {
+        let primary = request.requests.first().map(|r| r.name.clone().unwrap_or_else(|| r.r#type.clone())).unwrap_or_default();
+
+        self.stream_pages(request, name, cache_ttl)
+            .map(move |page| -> Result<Vec<T>, ApiError> { Ok(conversion::cast_result_required::<T>(&page?, &primary)?) })
+            .map_ok(|items| futures_util::stream::iter(items.into_iter().map(Ok)))
+            .try_flatten()
+    }
+
     //Some special wrappers
 
     /// This consumes the error and returns "None", since it could just be that the token is stupid. In the future,
@@ -384,41 +837,3 @@ impl ApiContext
     }
 
 }
-
-
-
-//pub async fn upload_file<'a>(context: &Context, form: &mut forms::FileUpload<'_>) -> Result<Content, ApiError>
-//{
-//    //println!("Received form: {:?}, length: {}", form, form.file.len());
-//
-//    //First step is to get a temporary path. 
-//    let named_file = tempfile::NamedTempFile::new().map_err(precondition_error!())?;
-//    let temp_path = named_file.into_temp_path(); //When this goes out of scope, the file is supposedly deleted. So DON'T SHADOW IT
-//
-//    //Now, persist the uploaded file to the path. Remember, temp_path needs to be persisted, so don't transfer ownership!
-//    form.file.persist_to(&temp_path).await.map_err(precondition_error!())?;
-//
-//    let mut content = Content::default();
-//    content.contentType = Some(ContentType::FILE);
-//
-//    //This is the data we're uploading. We'll be filling in the base64 next
-//    let mut data = FileUploadAsObject {
-//        base64blob : String::new(), 
-//        object : content//Content::new(ContentType::FILE) 
-//    };
-//    
-//    //OK now that it's on the filesystem, gotta read it as base64
-//    let file = std::fs::File::open(&temp_path).map_err(precondition_error!())?;
-//    let mut b64reader = base64_stream::ToBase64Reader::new(&file);
-//    b64reader.read_to_string(&mut data.base64blob).map_err(precondition_error!())?;
-//
-//    let result = basic_post_request("/file/asobject", &data, context).await;
-//
-//    //This ensures the compiler will complain if temp_path goes out of scope
-//    if let Err(error) = temp_path.close() {
-//        println!("Couldn't delete temporary file (this is ok): {}", error);
-//    }
-//    
-//    result
-//
-//}
\ No newline at end of file
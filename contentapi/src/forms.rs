@@ -62,6 +62,16 @@ pub struct FileUploadAsObject {
     pub base64blob: String, //This could be a VERY LARGE string!!!
 }
 
+/// Everything about a multipart upload to /file except the file itself, which is streamed separately 
+/// (see [`crate::endpoints::ApiContext::upload_file`]). The object becomes the new file content, so 
+/// set whatever name, values, permissions and keywords you want on it
+#[derive(Debug, Default)]
+pub struct FileUpload {
+    pub object: Content,
+    pub filename: String,
+    pub mime: String
+}
+
 /// Write configuration for registration (like if it's enabled or not; admin only)
 /// Not TECHNICALLY just a form, you can also get this as a result from the API
 /// (but it's in a weird place)
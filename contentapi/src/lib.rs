@@ -8,11 +8,25 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+pub use contentapi_derive::FromRequestResult;
+pub use conversion::FromRequestResult;
+
+//The derive refers to everything as ::contentapi, which only works in here if we're our own dependency
+#[cfg(test)]
+extern crate self as contentapi;
+
 pub mod endpoints;
 pub mod forms;
 pub mod conversion;
 pub mod search;
 pub mod permissions;
+pub mod query;
+pub mod policy;
+pub mod cache;
+pub mod live;
+pub mod metrics;
+#[cfg(feature = "mock")]
+pub mod mock;
 
 //ALL REQUESTS ARE BOUND BY THIS LIMIT!
 pub const REQUESTRESULTLIMIT : usize = 1000;
@@ -126,6 +140,91 @@ byte_enum!{ UserAction => {
     (DELETE:8i8)
 }}
 
+/// Every kind of admin log the backend writes (the type on [`AdminLog`]). Unlike the byte_enum! "enums",
+/// this is a real enum so logs can be matched on, see [`AdminLog::log_type`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AdminLogType {
+    None = 0,
+    ContentCreate = 1,
+    ContentUpdate = 2,
+    ContentDelete = 3,
+    UserCreate = 4,
+    UserUpdate = 5,
+    UserDelete = 6,
+    UserRename = 7,
+    GroupAssign = 8,
+    GroupUnassign = 9,
+    RegistrationConfig = 10,
+    Rethread = 11,
+    BanCreate = 12,
+    BanEdit = 13
+}
+
+/// What the target of an admin log refers to; the backend just stores the id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdminLogTarget {
+    User(i64),
+    Content(i64)
+}
+
+impl AdminLogType {
+    pub const ALL: &'static [AdminLogType] = &[
+        Self::None, Self::ContentCreate, Self::ContentUpdate, Self::ContentDelete, Self::UserCreate, Self::UserUpdate,
+        Self::UserDelete, Self::UserRename, Self::GroupAssign, Self::GroupUnassign, Self::RegistrationConfig, 
+        Self::Rethread, Self::BanCreate, Self::BanEdit
+    ];
+
+    pub const BANS: &'static [AdminLogType] = &[ Self::BanCreate, Self::BanEdit ];
+
+    pub fn from_i8(value: i8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|t| t.to_i8() == value)
+    }
+
+    pub fn to_i8(self) -> i8 {
+        self as i8
+    }
+
+    /// Short name for the type, good for a label
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::ContentCreate => "Content create",
+            Self::ContentUpdate => "Content update",
+            Self::ContentDelete => "Content delete",
+            Self::UserCreate => "User create",
+            Self::UserUpdate => "User update",
+            Self::UserDelete => "User delete",
+            Self::UserRename => "User rename",
+            Self::GroupAssign => "Group assign",
+            Self::GroupUnassign => "Group unassign",
+            Self::RegistrationConfig => "Registration config",
+            Self::Rethread => "Rethread",
+            Self::BanCreate => "Ban create",
+            Self::BanEdit => "Ban edit"
+        }
+    }
+
+    /// What the initiator did to the target, to go between the two: "(initiator) created (target)"
+    pub fn action(self) -> &'static str {
+        match self {
+            Self::None => "did something to",
+            Self::ContentCreate => "created",
+            Self::ContentUpdate => "updated",
+            Self::ContentDelete => "deleted",
+            Self::UserCreate => "registered",
+            Self::UserUpdate => "updated user",
+            Self::UserDelete => "deleted user",
+            Self::UserRename => "renamed",
+            Self::GroupAssign => "added to a group:",
+            Self::GroupUnassign => "removed from a group:",
+            Self::RegistrationConfig => "changed the registration config",
+            Self::Rethread => "moved messages into",
+            Self::BanCreate => "banned",
+            Self::BanEdit => "edited the ban for"
+        }
+    }
+}
+
 string_enum!{ RequestType => {
     user,
     content,
@@ -160,7 +259,7 @@ string_enum!{ RequestType => {
 // *     RESULTS FROM API      *
 // -----------------------------
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct About
 {
     pub version: String,
@@ -175,6 +274,17 @@ pub struct SpecialCount
     pub specialCount: i32
 }
 
+/// How many (readable) contents use a keyword
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct KeywordAggregate
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<i64>
+}
+
 
 // ----------------------------------
 // *     VIEWS (READ AND WRITE)     *
@@ -299,6 +409,24 @@ pub struct ContentEngagement
     pub contentId: Option<i64>,
 }
 
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct MessageEngagement
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id : Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userId: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r#type : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub engagement : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub createDate : Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub messageId: Option<i64>,
+}
+
 
 //#[serde_with::skip_serializing_none] //MUST COME BEFORE
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
@@ -352,6 +480,46 @@ pub struct Activity
     pub action: Option<i8>
 }
 
+/// A user following some content. The last ids are how far the user has read; anything newer is unread
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Watch
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contentId: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userId: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lastCommentId: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lastActivityId: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub createDate: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub editDate: Option<DateTime<Utc>>
+}
+
+/// A bit of data the api stores per user under a key. Only the owner can ever see their variables
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct UserVariable
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userId: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub createDate: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub editDate: Option<DateTime<Utc>>
+}
+
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 #[serde(default)]
 pub struct AdminLog
@@ -370,13 +538,33 @@ pub struct AdminLog
     pub target: Option<i64>
 }
 
+impl AdminLog {
+    /// The type of log; None if it's missing or a type this crate doesn't know about yet
+    pub fn log_type(&self) -> Option<AdminLogType> {
+        self.r#type.and_then(AdminLogType::from_i8)
+    }
+
+    /// What the target id points to, based on the type of log
+    pub fn target(&self) -> Option<AdminLogTarget> {
+        let target = self.target?;
+        match self.log_type()? {
+            AdminLogType::ContentCreate | AdminLogType::ContentUpdate | AdminLogType::ContentDelete | 
+            AdminLogType::Rethread => Some(AdminLogTarget::Content(target)),
+            AdminLogType::UserCreate | AdminLogType::UserUpdate | AdminLogType::UserDelete | AdminLogType::UserRename |
+            AdminLogType::GroupAssign | AdminLogType::GroupUnassign | AdminLogType::BanCreate | 
+            AdminLogType::BanEdit => Some(AdminLogTarget::User(target)),
+            AdminLogType::None | AdminLogType::RegistrationConfig => None
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct UserPrivate
 {
     pub email: String
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct RequestResult
 {
     pub search: FullRequest,
@@ -398,7 +586,7 @@ pub struct RequestResult
 //from anywhere to anywhere and have the lifetimes of the internals strongly tied to the
 //struct. It's about HOW you're using the struct, not simply "saving memory". 
 //#[serde_with::skip_serializing_none] //MUST COME BEFORE
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Request
 {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -450,7 +638,7 @@ macro_rules! build_request {
     };
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct FullRequest
 {
     pub values: HashMap<String, serde_json::Value>, //HashMap<String, Box<Serialize>>,
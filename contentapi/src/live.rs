@@ -0,0 +1,463 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{SinkExt, Stream, StreamExt};
+use hyper::header::InvalidHeaderValue;
+use serde::de::DeserializeOwned;
+use tokio::sync::broadcast;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Error as WsError, Message as WsMessage};
+
+use super::*;
+use crate::conversion::ResultError;
+use crate::endpoints::{AboutRequest, ApiError};
+
+//The api's live updates: a websocket that pushes every new message, activity, watch change etc. the
+//current user is allowed to see, as it happens. Each push is a batch of events plus the objects
+//they're about. Every event has an id, so a dropped connection can pick up right where it left off
+//(as long as the api still remembers that far back)
+
+/// The kinds of events the api pushes (the type on [`LiveEvent`], and the keys of [`LiveData::objects`])
+pub enum LiveEventType {}
+
+impl LiveEventType {
+    pub const MESSAGE: &'static str = "message_event";
+    pub const ACTIVITY: &'static str = "activity_event";
+    pub const WATCH: &'static str = "watch_event";
+    pub const USER: &'static str = "user_event";
+    pub const USERVARIABLE: &'static str = "uservariable_event";
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct LiveEvent
+{
+    pub id: i64,
+    pub userId: i64,
+    pub action: i8, //UserAction
+    pub date: Option<DateTime<Utc>>,
+    pub r#type: String, //LiveEventType
+    pub refId: i64      //The id of the thing the event is about (message id for message_event, etc)
+}
+
+/// One push from the live endpoint. Objects are grouped by event type, then by name (just like
+/// a RequestResult), so messages are in objects["message_event"]["message"], their authors in
+/// objects["message_event"]["user"], and so on
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct LiveData
+{
+    pub lastId: i64,
+    pub events: Vec<LiveEvent>,
+    pub objects: HashMap<String, HashMap<String, Vec<Value>>>
+}
+
+impl LiveData {
+    /// The events of the given type, in order
+    pub fn events_of<'a>(&'a self, event_type: &'a str) -> impl Iterator<Item = &'a LiveEvent> + 'a {
+        self.events.iter().filter(move |e| e.r#type == event_type)
+    }
+
+    /// Pull the named objects for an event type out of the push. Missing objects are just empty;
+    /// the api leaves out anything there's nothing of
+    pub fn objects_of<T>(&self, event_type: &str, name: &str) -> Result<Vec<T>, ResultError> where T: DeserializeOwned {
+        match self.objects.get(event_type).and_then(|o| o.get(name)) {
+            Some(objects) => objects.iter().map(|o| T::deserialize(o)).collect::<Result<Vec<T>, _>>()
+                .map_err(|e| ResultError::Parse(format!("{}.{}", event_type, name), e.to_string(), serde_json::to_string(objects).unwrap_or_default())),
+            None => Ok(Vec::new())
+        }
+    }
+}
+
+/// Both directions of the websocket speak this. Requests from us just need a type (and an id if
+/// we care about matching up the response); everything the api sends has a type
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct LiveMessage
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>
+}
+
+/// How hard to try to stay connected
+#[derive(Clone, Debug)]
+pub struct LiveSettings {
+    pub connect_timeout: Duration,
+    pub reconnect_delay: Duration,      //Wait before the first reconnect; doubles each failure after
+    pub max_reconnect_delay: Duration,
+    pub max_reconnects: u32,            //Give up (and end every stream on the socket) after this many failed or dropped connections in a row
+    pub ping_interval: Duration,        //Ping the api if it's been quiet this long, so dead connections are noticed
+    pub replay_length: usize            //How many recent pushes a shared socket keeps for streams that join late
+}
+
+impl Default for LiveSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            reconnect_delay: Duration::from_millis(500),
+            max_reconnect_delay: Duration::from_secs(30),
+            max_reconnects: 8,
+            ping_interval: Duration::from_secs(30),
+            replay_length: 32
+        }
+    }
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// One backend websocket, as one user. Only the hub (see LiveHub) opens these
+#[derive(Clone, Debug)]
+pub(crate) struct LiveClient {
+    pub(crate) url: String,
+    pub(crate) user_token: Option<String>,
+    pub(crate) request_id: Option<String>,
+    pub(crate) settings: LiveSettings
+}
+
+/// What came of waiting on the socket for a while
+enum LiveRead {
+    Data(LiveData),
+    Quiet,      //Nothing for a whole ping interval, but it's still there (as far as we know)
+    Dropped     //Closed, broken, or stopped answering pings; reopen it
+}
+
+impl LiveClient
+{
+    fn about(&self) -> AboutRequest {
+        AboutRequest {
+            endpoint: String::from("/live/ws"),
+            verb: String::from("WS"),
+            post_data: None,
+            request_id: self.request_id.clone()
+        }
+    }
+
+    /// Open the websocket, resuming after last_id if given (otherwise you only get events from now on)
+    async fn connect(&self, last_id: Option<i64>) -> Result<Socket, ApiError>
+    {
+        let about = self.about();
+        let mut url = match self.url.strip_prefix("http") {
+            Some(rest) => format!("ws{}", rest), //https becomes wss
+            None => self.url.clone()
+        };
+        if let Some(last_id) = last_id {
+            url = format!("{}?lastId={}", url, last_id);
+        }
+        let mut request = url.into_client_request().map_err(|e| ApiError::NonRequest(Box::new(about.clone()), e.to_string()))?;
+        //The token goes where it does for every other request. Urls get logged (by us, proxies, the api...)
+        if let Some(token) = &self.user_token {
+            let value = format!("Bearer {}", token).parse().map_err(|e: InvalidHeaderValue| ApiError::NonRequest(Box::new(about.clone()), e.to_string()))?;
+            request.headers_mut().insert("Authorization", value);
+        }
+
+        match tokio::time::timeout(self.settings.connect_timeout, tokio_tungstenite::connect_async(request)).await {
+            Ok(Ok((socket, _))) => Ok(socket),
+            Ok(Err(WsError::Http(response))) => {
+                let body = String::from_utf8_lossy(response.body().as_deref().unwrap_or_default()).into_owned();
+                Err(ApiError::Request(Box::new(about), body, response.status().as_u16()))
+            },
+            Ok(Err(error)) => Err(ApiError::Network(Box::new(about), error.to_string())),
+            Err(_) => Err(ApiError::Network(Box::new(about), String::from("Timed out connecting to the live endpoint")))
+        }
+    }
+
+    async fn send(socket: &mut Socket, message: &LiveMessage) -> Result<(), WsError> {
+        socket.send(WsMessage::Text(serde_json::to_string(message).unwrap_or_default())).await
+    }
+
+    /// Wait (up to a ping interval) for the next push on an open socket, keeping track of the last event id
+    /// seen. pinged remembers whether the last quiet interval already sent a ping. Err means the api told 
+    /// us to stop.
+    async fn next_data(&self, socket: &mut Socket, last_id: &mut Option<i64>, pinged: &mut bool) -> Result<LiveRead, ApiError>
+    {
+        loop
+        {
+            let text = match tokio::time::timeout(self.settings.ping_interval, socket.next()).await {
+                Ok(Some(Ok(WsMessage::Text(text)))) => text,
+                Ok(Some(Ok(WsMessage::Close(_)))) | Ok(None) => return Ok(LiveRead::Dropped),
+                Ok(Some(Ok(_))) => continue, //Websocket pings are answered for us, and the api doesn't send binary
+                Ok(Some(Err(error))) => {
+                    tracing::warn!("Live connection broke: {}", error);
+                    return Ok(LiveRead::Dropped);
+                },
+                Err(_) => {
+                    //Nothing for a whole interval: ping, and if we already did that, assume it's dead
+                    if *pinged {
+                        tracing::warn!("Live connection stopped responding");
+                        return Ok(LiveRead::Dropped);
+                    }
+                    *pinged = true;
+                    let ping = LiveMessage { r#type: String::from("ping"), ..Default::default() };
+                    return Ok(match Self::send(socket, &ping).await {
+                        Ok(_) => LiveRead::Quiet,
+                        Err(_) => LiveRead::Dropped
+                    });
+                }
+            };
+            *pinged = false;
+
+            let message = serde_json::from_str::<LiveMessage>(&text)
+                .map_err(|e| ApiError::Parse(Box::new(self.about()), e.to_string(), Some(text.clone().into_bytes())))?;
+
+            match message.r#type.as_str() {
+                "live" => {
+                    let data = serde_json::from_value::<LiveData>(message.data.unwrap_or_default())
+                        .map_err(|e| ApiError::Parse(Box::new(self.about()), e.to_string(), Some(text.into_bytes())))?;
+                    if data.lastId > 0 {
+                        *last_id = Some(data.lastId);
+                    }
+                    return Ok(LiveRead::Data(data));
+                },
+                //Our answer to asking where the events are up to (see LiveHub::run)
+                "lastId" => if let (None, Some(id)) = (*last_id, message.data.and_then(|d| d.as_i64())) {
+                    *last_id = Some(id);
+                },
+                "badtoken" => return Err(ApiError::Request(Box::new(self.about()), message.error.unwrap_or_else(|| String::from("Bad token")), 401)),
+                "error" | "unexpected" => tracing::warn!("Live endpoint error: {}", message.error.unwrap_or_default()),
+                _ => {} //Pings, userlists, responses to requests we didn't make, etc
+            }
+        }
+    }
+}
+
+/// What a shared socket sends to everyone on it. An error is the last thing it sends
+type LiveItem = Result<Arc<LiveData>, ApiError>;
+
+struct LiveFeed {
+    sender: broadcast::Sender<LiveItem>,
+    recent: VecDeque<Arc<LiveData>>
+}
+
+/// Shares the api's live websockets: every stream as the same user (all the tabs they have open) reads
+/// from one socket instead of each opening their own. The socket stays open only while someone's reading,
+/// reconnecting (with backoff, but not forever) and resuming from the last event it got if it drops
+pub struct LiveHub {
+    settings: LiveSettings,
+    feeds: Mutex<HashMap<Option<String>, LiveFeed>> //By user token
+}
+
+impl LiveHub
+{
+    pub fn new(settings: LiveSettings) -> Self {
+        Self { settings, feeds: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn settings(&self) -> &LiveSettings {
+        &self.settings
+    }
+
+    fn feeds(&self) -> std::sync::MutexGuard<'_, HashMap<Option<String>, LiveFeed>> {
+        self.feeds.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Every live push the user can see, from last_id on (or from now, if None). If the user's socket is
+    /// already open, the stream starts with whatever it still remembers after last_id. Ends on an error:
+    /// the api refusing the user, or the socket giving up on reconnecting
+    pub(crate) fn updates(self: &Arc<Self>, client: LiveClient, last_id: Option<i64>) -> impl Stream<Item = LiveItem> + Send + 'static
+    {
+        let (receiver, replay) = {
+            let mut feeds = self.feeds();
+            let feed = feeds.entry(client.user_token.clone()).or_insert_with(|| {
+                tokio::spawn(self.clone().run(client, last_id));
+                LiveFeed { sender: broadcast::channel(self.settings.replay_length.max(1)).0, recent: VecDeque::new() }
+            });
+            (feed.sender.subscribe(), feed.recent.clone())
+        };
+
+        //Each stream keeps its own place, so replayed pushes (and pushes that came in while subscribing)
+        //are never seen twice
+        futures_util::stream::unfold((receiver, replay, last_id, false), |(mut receiver, mut replay, mut seen, done)| async move
+        {
+            if done {
+                return None;
+            }
+            loop
+            {
+                let item = match replay.pop_front() {
+                    Some(data) => Ok(data),
+                    None => match receiver.recv().await {
+                        Ok(item) => item,
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            tracing::warn!("Live stream fell behind, skipped {} pushes", missed);
+                            continue;
+                        },
+                        Err(broadcast::error::RecvError::Closed) => return None
+                    }
+                };
+                let data = match item {
+                    Ok(data) => data,
+                    Err(error) => return Some((Err(error), (receiver, replay, seen, true)))
+                };
+                let data = match seen {
+                    Some(seen) if data.lastId <= seen => continue,
+                    Some(seen) if data.events.iter().any(|e| e.id <= seen) => {
+                        let mut newer = (*data).clone();
+                        newer.events.retain(|e| e.id > seen);
+                        Arc::new(newer)
+                    },
+                    _ => data
+                };
+                seen = Some(data.lastId);
+                return Some((Ok(data), (receiver, replay, seen, false)));
+            }
+        })
+    }
+
+    /// Whether anyone's still reading the feed. If not, it's removed (under the same lock as subscribing,
+    /// so nobody can join a feed that's going away)
+    fn keep_open(&self, key: &Option<String>) -> bool {
+        let mut feeds = self.feeds();
+        match feeds.get(key) {
+            Some(feed) if feed.sender.receiver_count() > 0 => true,
+            _ => {
+                feeds.remove(key);
+                false
+            }
+        }
+    }
+
+    fn publish(&self, key: &Option<String>, data: LiveData) {
+        let mut feeds = self.feeds();
+        if let Some(feed) = feeds.get_mut(key) {
+            let data = Arc::new(data);
+            if feed.recent.len() >= self.settings.replay_length {
+                feed.recent.pop_front();
+            }
+            feed.recent.push_back(data.clone());
+            let _ = feed.sender.send(Ok(data));
+        }
+    }
+
+    /// Tell everyone on the feed why it's ending, and remove it so the next stream starts over
+    fn finish(&self, key: &Option<String>, error: ApiError) {
+        if let Some(feed) = self.feeds().remove(key) {
+            let _ = feed.sender.send(Err(error));
+        }
+    }
+
+    /// The task behind a feed: holds the socket open for as long as anyone's reading from it
+    /// Wait a bit longer after each failure in a row, or give the error back once there've been too many
+    async fn backoff(&self, failures: &mut u32, delay: &mut Duration, error: ApiError) -> Result<(), ApiError>
+    {
+        *failures += 1;
+        if *failures > self.settings.max_reconnects {
+            tracing::warn!("Giving up on the live endpoint after {} tries: {}", failures, error.to_verbose_string());
+            return Err(error);
+        }
+        tracing::warn!("Lost the live endpoint, retrying in {}ms: {}", delay.as_millis(), error.to_verbose_string());
+        tokio::time::sleep(*delay).await;
+        *delay = std::cmp::min(*delay * 2, self.settings.max_reconnect_delay);
+        Ok(())
+    }
+
+    async fn run(self: Arc<Self>, client: LiveClient, mut last_id: Option<i64>)
+    {
+        let key = client.user_token.clone();
+        let mut socket: Option<Socket> = None;
+        let mut delay = self.settings.reconnect_delay;
+        let mut failures = 0;
+        let mut pinged = false;
+
+        while self.keep_open(&key)
+        {
+            let mut current = match socket.take() {
+                Some(socket) => socket,
+                None => match client.connect(last_id).await {
+                    Ok(mut socket) => {
+                        //Starting fresh: find out where the events are up to, so if this connection drops
+                        //before anything happens, the next one still picks up from here
+                        if last_id.is_none() {
+                            let request = LiveMessage { r#type: String::from("lastId"), ..Default::default() };
+                            let _ = LiveClient::send(&mut socket, &request).await;
+                        }
+                        pinged = false;
+                        socket
+                    },
+                    Err(error @ ApiError::Request(_, _, 401..=403)) => return self.finish(&key, error),
+                    Err(error) => {
+                        if let Err(error) = self.backoff(&mut failures, &mut delay, error).await {
+                            return self.finish(&key, error);
+                        }
+                        continue;
+                    }
+                }
+            };
+
+            match client.next_data(&mut current, &mut last_id, &mut pinged).await {
+                Ok(LiveRead::Data(data)) => {
+                    delay = self.settings.reconnect_delay;
+                    failures = 0;
+                    socket = Some(current);
+                    self.publish(&key, data);
+                },
+                Ok(LiveRead::Quiet) => socket = Some(current),
+                Ok(LiveRead::Dropped) => {
+                    let _ = current.close(None).await;
+                    //Only data proves the connection is good: one that keeps getting accepted and then closed
+                    //is as broken as one that can't connect
+                    let error = ApiError::Network(Box::new(client.about()), String::from("The live endpoint closed the connection"));
+                    if let Err(error) = self.backoff(&mut failures, &mut delay, error).await {
+                        return self.finish(&key, error);
+                    }
+                },
+                Err(error) => {
+                    let _ = current.close(None).await;
+                    return self.finish(&key, error);
+                }
+            }
+        }
+
+        if let Some(mut socket) = socket {
+            let _ = socket.close(None).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn gives_up_reconnecting() {
+        let settings = LiveSettings { reconnect_delay: Duration::from_millis(1), max_reconnects: 2, ..Default::default() };
+        //Nothing listens on port 1
+        let context = crate::endpoints::ApiContext::new(String::from("http://127.0.0.1:1"), None)
+            .with_live(Arc::new(LiveHub::new(settings)));
+        let mut updates = Box::pin(context.live_updates(None));
+        let result = tokio::time::timeout(Duration::from_secs(5), updates.next()).await.expect("Should give up quickly");
+        assert!(matches!(result, Some(Err(ApiError::Network(..)))));
+        assert!(updates.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_dropped_connections() {
+        //Accepts every socket and closes it right away
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let accepted = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let _ = socket.close(None).await;
+                }
+            }
+        });
+
+        let settings = LiveSettings { reconnect_delay: Duration::from_millis(1), max_reconnects: 2, ..Default::default() };
+        let context = crate::endpoints::ApiContext::new(format!("http://{}", address), None)
+            .with_live(Arc::new(LiveHub::new(settings)));
+        let mut updates = Box::pin(context.live_updates(None));
+        let result = tokio::time::timeout(Duration::from_secs(5), updates.next()).await.expect("Should give up quickly");
+        assert!(matches!(result, Some(Err(ApiError::Network(..)))));
+        assert!(updates.next().await.is_none());
+        assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 3); //The first, then max_reconnects more
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::endpoints::ApiError;
+
+//Counters and histograms for the /metrics endpoint, written out in the prometheus text format. There's
+//one registry for the whole process (see global): the api calls and bbcode parsing happen deep inside
+//code that has no way to reach any state, and these are process-wide numbers anyway. Labels must come
+//from a small fixed set of values (route names, endpoint paths), NEVER from anything a client sends.
+
+/// Upper bounds (in seconds) for request and backend latency buckets
+const LATENCYBUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Upper bounds (in seconds) for bbcode parsing, which should be a lot faster than anything on the network
+const RENDERBUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25];
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>, //NOT cumulative, one per bound; anything past the last bound is only in count
+    sum: f64,
+    count: u64
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self { bounds, counts: vec![0; bounds.len()], sum: 0.0, count: 0 }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(index) = self.bounds.iter().position(|b| value <= *b) {
+            self.counts[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, sep, self.count);
+        let braces = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, braces, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces, self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    requests: HashMap<(String, String, u16), u64>, //route, method, status
+    request_seconds: HashMap<String, Histogram>,  //route
+    backend_seconds: HashMap<String, Histogram>,  //endpoint
+    backend_reported_seconds: HashMap<String, Histogram>, //"total", "nondb", or the name of a request in databaseTimes
+    api_errors: HashMap<&'static str, u64>,       //ApiError variant
+    bbcode_seconds: Option<Histogram>
+}
+
+/// Everything /metrics reports. Use [`global`] rather than making your own
+#[derive(Debug, Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>
+}
+
+/// The registry for the whole process
+pub fn global() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+/// Escape a label value for the text format
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// The endpoint with anything that varies per call taken out, so "/history/123" and "/history/456" are one
+/// label: no query, ids become :id, and whatever engagement is being set becomes :type
+pub fn endpoint_label(endpoint: &str) -> String {
+    let path = endpoint.split('?').next().unwrap_or("");
+    let mut previous = "";
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        segments.push(if previous == "setengagement" { ":type" }
+            else if !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit()) { ":id" }
+            else { segment });
+        previous = segment;
+    }
+    segments.join("/")
+}
+
+fn variant_name(error: &ApiError) -> &'static str {
+    match error {
+        ApiError::NonRequest(_,_) => "NonRequest",
+        ApiError::Parse(_,_,_) => "Parse",
+        ApiError::Network(_,_) => "Network",
+        ApiError::Request(_,_,_) => "Request",
+        ApiError::Other(_) => "Other"
+    }
+}
+
+impl Metrics {
+    fn lock(&self) -> std::sync::MutexGuard<'_, MetricsState> {
+        //A poisoned lock means something panicked mid-update; a slightly wrong count is fine
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// A page (or any other) request finished. The route should be a name from a fixed list, not the path!
+    pub fn observe_request(&self, route: &str, method: &str, status: u16, elapsed: Duration) {
+        let mut state = self.lock();
+        *state.requests.entry((route.to_string(), method.to_string(), status)).or_default() += 1;
+        state.request_seconds.entry(route.to_string()).or_insert_with(|| Histogram::new(LATENCYBUCKETS)).observe(elapsed.as_secs_f64());
+    }
+
+    /// A call to the backend finished (all retries included), successfully or not
+    pub fn observe_backend(&self, endpoint: &str, elapsed: Duration) {
+        self.lock().backend_seconds.entry(endpoint_label(endpoint)).or_insert_with(|| Histogram::new(LATENCYBUCKETS)).observe(elapsed.as_secs_f64());
+    }
+
+    /// The times the backend says it took for a /request. These come in milliseconds
+    pub fn observe_request_result(&self, result: &crate::RequestResult) {
+        let mut state = self.lock();
+        let mut observe = |name: &str, ms: f64| {
+            state.backend_reported_seconds.entry(name.to_string()).or_insert_with(|| Histogram::new(LATENCYBUCKETS)).observe(ms / 1000.0);
+        };
+        observe("total", result.totalTime);
+        observe("nondb", result.nonDbTime);
+        for (name, ms) in &result.databaseTimes {
+            observe(name, *ms);
+        }
+    }
+
+    pub fn observe_api_error(&self, error: &ApiError) {
+        *self.lock().api_errors.entry(variant_name(error)).or_default() += 1;
+    }
+
+    pub fn observe_bbcode(&self, elapsed: Duration) {
+        self.lock().bbcode_seconds.get_or_insert_with(|| Histogram::new(RENDERBUCKETS)).observe(elapsed.as_secs_f64());
+    }
+
+    /// Everything so far, in the prometheus text exposition format
+    pub fn render(&self) -> String
+    {
+        let state = self.lock();
+        let mut out = String::new();
+
+        //Sorted so the output doesn't jump around between scrapes
+        fn sorted<K: Ord, V>(map: &HashMap<K, V>) -> Vec<(&K, &V)> {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            entries
+        }
+
+        out.push_str("# HELP sbs_http_requests_total Requests handled, by route, method and status\n");
+        out.push_str("# TYPE sbs_http_requests_total counter\n");
+        for ((route, method, status), count) in sorted(&state.requests) {
+            let _ = writeln!(out, "sbs_http_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}", label(route), label(method), status, count);
+        }
+
+        out.push_str("# HELP sbs_http_request_duration_seconds Time to handle a request, by route\n");
+        out.push_str("# TYPE sbs_http_request_duration_seconds histogram\n");
+        for (route, histogram) in sorted(&state.request_seconds) {
+            histogram.write(&mut out, "sbs_http_request_duration_seconds", &format!("route=\"{}\"", label(route)));
+        }
+
+        out.push_str("# HELP sbs_backend_request_duration_seconds Time for a call to the backend api (retries included), by endpoint\n");
+        out.push_str("# TYPE sbs_backend_request_duration_seconds histogram\n");
+        for (endpoint, histogram) in sorted(&state.backend_seconds) {
+            histogram.write(&mut out, "sbs_backend_request_duration_seconds", &format!("endpoint=\"{}\"", label(endpoint)));
+        }
+
+        out.push_str("# HELP sbs_backend_reported_duration_seconds Time the backend says a /request took: total, nondb, or the database time per named request\n");
+        out.push_str("# TYPE sbs_backend_reported_duration_seconds histogram\n");
+        for (name, histogram) in sorted(&state.backend_reported_seconds) {
+            histogram.write(&mut out, "sbs_backend_reported_duration_seconds", &format!("name=\"{}\"", label(name)));
+        }
+
+        out.push_str("# HELP sbs_api_errors_total Errors from calls to the backend api, by kind\n");
+        out.push_str("# TYPE sbs_api_errors_total counter\n");
+        for (variant, count) in sorted(&state.api_errors) {
+            let _ = writeln!(out, "sbs_api_errors_total{{kind=\"{}\"}} {}", variant, count);
+        }
+
+        out.push_str("# HELP sbs_bbcode_render_duration_seconds Time to turn one bit of bbcode into html\n");
+        out.push_str("# TYPE sbs_bbcode_render_duration_seconds histogram\n");
+        if let Some(histogram) = &state.bbcode_seconds {
+            histogram.write(&mut out, "sbs_bbcode_render_duration_seconds", "");
+        }
+
+        out
+    }
+}
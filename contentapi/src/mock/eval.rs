@@ -0,0 +1,405 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+//A tiny version of the api query language: enough to run every query this frontend sends.
+//Comparisons, and/or/parentheses, @value and @result.field references, {{literals}}, and the
+//handful of !macros() we actually use. Anything else is an error, just like the real api.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Ref(String),
+    Literal(String),
+    Op(&'static str),
+    Open,
+    Close,
+    Comma,
+    Bang
+}
+
+#[derive(Debug)]
+pub enum Operand {
+    Value(String),
+    Result(String, Vec<String>),
+    Literal(String)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op { Eq, Neq, Lt, Gt, Le, Ge, Like, NotLike, In, NotIn }
+
+#[derive(Debug)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(String, Op, Operand),
+    Macro(String, Vec<Operand>)
+}
+
+/// Everything a query might reference while being evaluated against a single object
+pub struct EvalContext<'a> {
+    pub values: &'a HashMap<String, Value>,
+    pub results: &'a HashMap<String, Vec<Value>>,
+    pub content: &'a [Value], //For macros that look at the parent content of messages/activity
+    pub now: DateTime<Utc>
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, String>
+{
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() { i += 1; continue; }
+        match c {
+            '(' => { tokens.push(Token::Open); i += 1; },
+            ')' => { tokens.push(Token::Close); i += 1; },
+            ',' => { tokens.push(Token::Comma); i += 1; },
+            '{' => {
+                let rest: String = chars[i..].iter().collect();
+                if !rest.starts_with("{{") { return Err(format!("Unexpected '{{' at {}", i)); }
+                let end = rest.find("}}").ok_or_else(|| String::from("Unterminated literal"))?;
+                tokens.push(Token::Literal(rest[2..end].to_string()));
+                i += rest[..end + 2].chars().count();
+            },
+            '@' => {
+                let start = i + 1;
+                i = start;
+                while i < chars.len() && is_word(chars[i]) { i += 1; }
+                if i == start { return Err(String::from("Empty @ reference")); }
+                tokens.push(Token::Ref(chars[start..i].iter().collect()));
+            },
+            '!' if chars.get(i + 1) == Some(&'=') => { tokens.push(Token::Op("<>")); i += 2; },
+            '!' => { tokens.push(Token::Bang); i += 1; },
+            '<' | '>' | '=' => {
+                let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+                match two.as_str() {
+                    "<>" => { tokens.push(Token::Op("<>")); i += 2; },
+                    "<=" => { tokens.push(Token::Op("<=")); i += 2; },
+                    ">=" => { tokens.push(Token::Op(">=")); i += 2; },
+                    _ => {
+                        tokens.push(Token::Op(match c { '<' => "<", '>' => ">", _ => "=" }));
+                        i += 1;
+                    }
+                }
+            },
+            c if is_word(c) => {
+                let start = i;
+                while i < chars.len() && is_word(chars[i]) { i += 1; }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            },
+            c => return Err(format!("Unexpected character '{}' in query", c))
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> { self.tokens.get(self.pos) }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+    fn keyword(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(w)) if w.eq_ignore_ascii_case(word))
+    }
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            other => Err(format!("Expected {:?}, got {:?}", token, other))
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.keyword("or") {
+            self.pos += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_atom()?;
+        while self.keyword("and") {
+            self.pos += 1;
+            left = Expr::And(Box::new(left), Box::new(self.parse_atom()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Ref(r)) => {
+                let mut parts = r.split('.').map(|s| s.to_string()).collect::<Vec<_>>();
+                let name = parts.remove(0);
+                if parts.is_empty() { Ok(Operand::Value(name)) }
+                else { Ok(Operand::Result(name, parts)) }
+            },
+            Some(Token::Literal(l)) => Ok(Operand::Literal(l)),
+            other => Err(format!("Expected @value or {{{{literal}}}}, got {:?}", other))
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.parse_or()?;
+                self.expect(Token::Close)?;
+                Ok(expr)
+            },
+            Some(Token::Bang) => {
+                let name = match self.next() {
+                    Some(Token::Ident(name)) => name,
+                    other => return Err(format!("Expected macro name, got {:?}", other))
+                };
+                self.expect(Token::Open)?;
+                let mut args = Vec::new();
+                if self.peek() != Some(&Token::Close) {
+                    loop {
+                        args.push(self.parse_operand()?);
+                        if self.peek() == Some(&Token::Comma) { self.pos += 1; }
+                        else { break; }
+                    }
+                }
+                self.expect(Token::Close)?;
+                Ok(Expr::Macro(name, args))
+            },
+            Some(Token::Ident(field)) => {
+                let op = match self.next() {
+                    Some(Token::Op("=")) => Op::Eq,
+                    Some(Token::Op("<>")) => Op::Neq,
+                    Some(Token::Op("<")) => Op::Lt,
+                    Some(Token::Op(">")) => Op::Gt,
+                    Some(Token::Op("<=")) => Op::Le,
+                    Some(Token::Op(">=")) => Op::Ge,
+                    Some(Token::Ident(w)) if w.eq_ignore_ascii_case("like") => Op::Like,
+                    Some(Token::Ident(w)) if w.eq_ignore_ascii_case("in") => Op::In,
+                    Some(Token::Ident(w)) if w.eq_ignore_ascii_case("not") => {
+                        match self.next() {
+                            Some(Token::Ident(w)) if w.eq_ignore_ascii_case("in") => Op::NotIn,
+                            Some(Token::Ident(w)) if w.eq_ignore_ascii_case("like") => Op::NotLike,
+                            other => return Err(format!("Expected 'in' or 'like' after 'not', got {:?}", other))
+                        }
+                    },
+                    other => return Err(format!("Expected operator after {}, got {:?}", field, other))
+                };
+                Ok(Expr::Compare(field, op, self.parse_operand()?))
+            },
+            other => Err(format!("Unexpected {:?} in query", other))
+        }
+    }
+}
+
+pub fn parse(query: &str) -> Result<Expr, String>
+{
+    let mut parser = Parser { tokens: tokenize(query)?, pos: 0 };
+    let expr = parser.parse_or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(format!("Unexpected trailing {:?} in query", &parser.tokens[parser.pos..]));
+    }
+    Ok(expr)
+}
+
+/// Pull a (possibly dotted) field out of an object. Arrays along the way get flattened,
+/// which is what you want for stuff like @message.values.re
+pub fn get_path(object: &Value, path: &[String]) -> Vec<Value>
+{
+    let mut current = vec![object.clone()];
+    for part in path {
+        current = current.into_iter().filter_map(|v| v.get(part).cloned()).flat_map(|v| {
+            match v {
+                Value::Array(items) => items,
+                Value::Null => Vec::new(),
+                other => vec![other]
+            }
+        }).collect();
+    }
+    current
+}
+
+fn resolve(operand: &Operand, ctx: &EvalContext) -> Result<Value, String>
+{
+    match operand {
+        Operand::Value(key) => ctx.values.get(key).cloned().ok_or_else(|| format!("Value @{} not found in request", key)),
+        Operand::Result(name, path) => {
+            let result = ctx.results.get(name).ok_or_else(|| format!("Result @{} not found (requests must come in order)", name))?;
+            Ok(Value::Array(result.iter().flat_map(|o| get_path(o, path)).collect()))
+        },
+        Operand::Literal(text) => Ok(serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.clone())))
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse().ok(),
+        Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        _ => None
+    }
+}
+
+/// Compare two json values the loose way a database would
+pub fn compare(a: &Value, b: &Value) -> Option<Ordering>
+{
+    match (a, b) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::String(x), Value::String(y)) => {
+            match (x.parse::<DateTime<Utc>>(), y.parse::<DateTime<Utc>>()) {
+                (Ok(dx), Ok(dy)) => Some(dx.cmp(&dy)),
+                _ => Some(x.cmp(y))
+            }
+        },
+        (x, y) => as_number(x)?.partial_cmp(&as_number(y)?)
+    }
+}
+
+fn loose_eq(a: &Value, b: &Value) -> bool {
+    compare(a, b) == Some(Ordering::Equal)
+}
+
+/// SQL-ish like: % is any run of characters, _ is any single character, case insensitive
+pub fn like(text: &str, pattern: &str) -> bool
+{
+    fn inner(t: &[char], p: &[char]) -> bool {
+        match p.first() {
+            None => t.is_empty(),
+            Some('%') => (0..=t.len()).any(|i| inner(&t[i..], &p[1..])),
+            Some('_') => !t.is_empty() && inner(&t[1..], &p[1..]),
+            Some(c) => !t.is_empty() && t[0] == *c && inner(&t[1..], &p[1..])
+        }
+    }
+    let t: Vec<char> = text.to_lowercase().chars().collect();
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    inner(&t, &p)
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string()
+    }
+}
+
+fn as_list(value: Value) -> Vec<Value> {
+    match value {
+        Value::Array(items) => items,
+        other => vec![other]
+    }
+}
+
+fn parent_content<'a>(object: &Value, ctx: &'a EvalContext) -> Option<&'a Value> {
+    let id = object.get("contentId")?;
+    ctx.content.iter().find(|c| c.get("id").map(|cid| loose_eq(cid, id)).unwrap_or(false))
+}
+
+fn is_deleted(object: &Value) -> bool {
+    object.get("deleted").and_then(as_number).unwrap_or(0.0) != 0.0
+}
+
+fn object_values(object: &Value) -> Option<&serde_json::Map<String, Value>> {
+    object.get("values").and_then(|v| v.as_object())
+}
+
+fn eval_macro(name: &str, args: &[Value], object: &Value, ctx: &EvalContext) -> Result<bool, String>
+{
+    let arg = |i: usize| args.get(i).cloned().ok_or_else(|| format!("!{}() is missing argument {}", name, i + 1));
+    match name {
+        "notdeleted" => Ok(!is_deleted(object)),
+        "basiccomments" => Ok(!is_deleted(object) &&
+            object.get("module").map(|m| m.is_null() || m.as_str() == Some("")).unwrap_or(true) &&
+            object.get("contentId").and_then(as_number).unwrap_or(0.0) > 0.0),
+        "basichistory" => Ok(object.get("contentId").and_then(as_number).unwrap_or(0.0) > 0.0),
+        "activebans" => Ok(object.get("expireDate").map(|d| compare(d, &Value::String(ctx.now.to_rfc3339())) == Some(Ordering::Greater)).unwrap_or(false)),
+        "userpage" => {
+            let user = arg(0)?;
+            Ok(object.get("contentType").map(|t| loose_eq(t, &Value::from(crate::ContentType::USERPAGE))).unwrap_or(false) &&
+               object.get("createUserId").map(|u| as_list(user.clone()).iter().any(|x| loose_eq(u, x))).unwrap_or(false) &&
+               !is_deleted(object))
+        },
+        "keywordlike" => {
+            let pattern = value_text(&arg(0)?);
+            Ok(get_path(object, &[String::from("keywords")]).iter().any(|k| like(&value_text(k), &pattern)))
+        },
+        "keywordin" => {
+            let keywords = as_list(arg(0)?);
+            Ok(get_path(object, &[String::from("keywords")]).iter().any(|k| keywords.iter().any(|w| value_text(k) == value_text(w))))
+        },
+        "literaltypein" => {
+            //Messages and activity check the content they're attached to
+            let types = as_list(arg(0)?);
+            let literal = if object.get("literalType").is_some() { object.get("literalType") }
+                          else { parent_content(object, ctx).and_then(|c| c.get("literalType")) };
+            Ok(literal.map(|l| types.iter().any(|t| loose_eq(l, t))).unwrap_or(false))
+        },
+        "valuekeyin" => {
+            let keys = as_list(arg(0)?);
+            Ok(object_values(object).map(|v| keys.iter().any(|k| v.contains_key(&value_text(k)))).unwrap_or(false))
+        },
+        "valuelike" => {
+            let key = value_text(&arg(0)?);
+            let pattern = value_text(&arg(1)?);
+            Ok(object_values(object).and_then(|v| v.get(&key)).map(|v| like(&value_text(v), &pattern)).unwrap_or(false))
+        },
+        "valuein" => {
+            let key = value_text(&arg(0)?);
+            let allowed = as_list(arg(1)?);
+            Ok(object_values(object).and_then(|v| v.get(&key)).map(|v| allowed.iter().any(|a| loose_eq(v, a) || value_text(v) == value_text(a))).unwrap_or(false))
+        },
+        other => Err(format!("Unknown macro !{}()", other))
+    }
+}
+
+pub fn eval(expr: &Expr, object: &Value, ctx: &EvalContext) -> Result<bool, String>
+{
+    match expr {
+        Expr::And(a, b) => Ok(eval(a, object, ctx)? && eval(b, object, ctx)?),
+        Expr::Or(a, b) => Ok(eval(a, object, ctx)? || eval(b, object, ctx)?),
+        Expr::Macro(name, args) => {
+            let args = args.iter().map(|a| resolve(a, ctx)).collect::<Result<Vec<_>,_>>()?;
+            eval_macro(name, &args, object, ctx)
+        },
+        Expr::Compare(field, op, operand) => {
+            let right = resolve(operand, ctx)?;
+            let left = object.get(field).cloned().unwrap_or(Value::Null);
+            Ok(match op {
+                Op::In => as_list(right).iter().any(|r| loose_eq(&left, r)),
+                Op::NotIn => !as_list(right).iter().any(|r| loose_eq(&left, r)),
+                Op::Eq => as_list(right).iter().any(|r| loose_eq(&left, r)),
+                Op::Neq => !as_list(right).iter().any(|r| loose_eq(&left, r)),
+                Op::Like | Op::NotLike => {
+                    let matched = !left.is_null() && like(&value_text(&left), &value_text(&right));
+                    matched == (*op == Op::Like)
+                },
+                Op::Lt | Op::Gt | Op::Le | Op::Ge => {
+                    //Comparing against a result list uses the first item, just like sql would
+                    let right = match right {
+                        Value::Array(items) => match items.into_iter().next() { Some(r) => r, None => return Ok(false) },
+                        other => other
+                    };
+                    match compare(&left, &right) {
+                        Some(ordering) => match op {
+                            Op::Lt => ordering == Ordering::Less,
+                            Op::Gt => ordering == Ordering::Greater,
+                            Op::Le => ordering != Ordering::Greater,
+                            _ => ordering != Ordering::Less
+                        },
+                        None => false
+                    }
+                }
+            })
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futures_util::{SinkExt, StreamExt};
+use hyper::{Body, StatusCode};
+use serde_json::{json, Value};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::{handshake::derive_accept_key, protocol::Role, Message as WsMessage};
+
+use crate::live::{LiveData, LiveEvent, LiveMessage};
+use super::{MockStore, can_action};
+
+//The live updates websocket. Writes that the real api would announce get recorded here, and every
+//connected socket is poked to go look for new events it's allowed to see. Reconnecting with a
+//lastId replays everything after it (the mock never forgets, unlike the real thing)
+
+/// Who gets to see a live event
+pub(super) enum Audience {
+    Everyone,
+    Readers(i64), //Only people who can read this content
+    User(i64)     //Only this user (watches, etc)
+}
+
+pub(super) struct LiveRecord {
+    event: LiveEvent,
+    objects: HashMap<String, Vec<Value>>,
+    audience: Audience
+}
+
+impl MockStore
+{
+    /// Record a live event for every connected socket (that can see it)
+    pub(super) fn push_live(&mut self, event_type: &str, action: i8, user_id: i64, ref_id: i64, objects: HashMap<String, Vec<Value>>, audience: Audience)
+    {
+        let id = self.live.len() as i64 + 1;
+        self.live.push(LiveRecord {
+            event: LiveEvent { id, userId: user_id, action, date: Some(chrono::Utc::now()), r#type: event_type.to_string(), refId: ref_id },
+            objects, audience
+        });
+        let _ = self.live_sender.send(id);
+    }
+
+    fn live_last_id(&self) -> i64 {
+        self.live.len() as i64
+    }
+
+    /// Everything after last_id the user can see, in one push. None if there's nothing
+    fn live_since(&self, user_id: Option<i64>, last_id: i64) -> Option<LiveData>
+    {
+        let user = user_id.and_then(|id| self.find("user", id));
+        let mut data = LiveData { lastId: self.live_last_id(), ..Default::default() };
+
+        for record in self.live.iter().skip(last_id.max(0) as usize) {
+            let visible = match record.audience {
+                Audience::Everyone => true,
+                Audience::Readers(id) => self.find("content", id).map(|c| can_action(user, 'R', c)).unwrap_or(true),
+                Audience::User(id) => user_id == Some(id)
+            };
+            if !visible {
+                continue;
+            }
+            let objects = data.objects.entry(record.event.r#type.clone()).or_default();
+            for (name, list) in &record.objects {
+                objects.entry(name.clone()).or_default().extend(list.iter().cloned());
+            }
+            data.events.push(record.event.clone());
+        }
+
+        if data.events.is_empty() { None } else { Some(data) }
+    }
+}
+
+/// Answer the websocket handshake and hand the connection off to its own task
+pub(super) fn socket(store: Arc<Mutex<MockStore>>, request: hyper::Request<Body>) -> hyper::Response<Body>
+{
+    let query = serde_urlencoded::from_str::<HashMap<String, String>>(request.uri().query().unwrap_or_default()).unwrap_or_default();
+    let key = request.headers().get("Sec-WebSocket-Key").and_then(|k| k.to_str().ok()).unwrap_or_default().to_string();
+
+    let respond = |status: StatusCode, message: &str| hyper::Response::builder().status(status)
+        .body(Body::from(message.to_string())).unwrap_or_default();
+
+    if key.is_empty() {
+        return respond(StatusCode::BAD_REQUEST, "Not a websocket request");
+    }
+
+    let (user_id, last_id) = match store.lock() {
+        Ok(store) => {
+            let user_id = match super::bearer_token(&request) {
+                Some(token) => match store.tokens.get(token) {
+                    Some(id) => Some(*id),
+                    None => return respond(StatusCode::UNAUTHORIZED, "Bad token")
+                },
+                None => None
+            };
+            //No lastId means "from now on"
+            let last_id = query.get("lastId").and_then(|l| l.parse::<i64>().ok()).unwrap_or_else(|| store.live_last_id());
+            (user_id, last_id)
+        },
+        Err(error) => return respond(StatusCode::INTERNAL_SERVER_ERROR, &format!("Mock store poisoned: {}", error))
+    };
+
+    tokio::spawn(async move {
+        if let Ok(upgraded) = hyper::upgrade::on(request).await {
+            serve(store, WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await, user_id, last_id).await;
+        }
+    });
+
+    hyper::Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes()))
+        .body(Body::empty())
+        .unwrap_or_default()
+}
+
+async fn serve(store: Arc<Mutex<MockStore>>, mut socket: WebSocketStream<hyper::upgrade::Upgraded>, user_id: Option<i64>, mut last_id: i64)
+{
+    let mut changes = match store.lock() {
+        Ok(store) => store.live_sender.subscribe(),
+        Err(_) => return
+    };
+
+    loop
+    {
+        let (pending, current_id) = match store.lock() {
+            Ok(store) => (store.live_since(user_id, last_id), store.live_last_id()),
+            Err(_) => return
+        };
+        last_id = current_id;
+        if let Some(data) = pending {
+            let message = LiveMessage { r#type: String::from("live"), data: serde_json::to_value(data).ok(), ..Default::default() };
+            if socket.send(WsMessage::Text(serde_json::to_string(&message).unwrap_or_default())).await.is_err() {
+                return;
+            }
+        }
+
+        tokio::select! {
+            changed = changes.changed() => if changed.is_err() { return; },
+            text = socket.next() => {
+                let request = match text {
+                    Some(Ok(WsMessage::Text(text))) => serde_json::from_str::<LiveMessage>(&text).unwrap_or_default(),
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => continue
+                };
+                let data = match request.r#type.as_str() {
+                    "lastId" => Some(json!(last_id)),
+                    _ => None
+                };
+                let response = LiveMessage { id: request.id, r#type: request.r#type, data, error: None };
+                if socket.send(WsMessage::Text(serde_json::to_string(&response).unwrap_or_default())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
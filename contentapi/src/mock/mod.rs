@@ -0,0 +1,832 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use hyper::{Body, Method, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use serde_json::{json, Value};
+
+use crate::*;
+
+mod eval;
+mod live;
+
+//An in-process fake of the contentapi backend, so pages can be rendered end to end in tests
+//without a real api running. It serves objects from fixture data, runs (a subset of) the query
+//language, and keeps any writes around so later requests see them. It is NOT a faithful copy
+//of the api: no rate limiting, no real search, permissions only as far as can_user_action goes.
+
+/// All the request types the api understands. Anything else is a 400, like the real thing
+static KNOWNTYPES: &[&str] = &["user", "content", "message", "activity", "watch", "adminlog", "uservariable",
+    "message_aggregate", "activity_aggregate", "content_engagement", "ban", "keyword_aggregate", "message_engagement", "userrelation"];
+
+type MockResult = Result<Value, (StatusCode, String)>;
+
+macro_rules! bad {
+    ($status:ident, $($arg:tt)*) => { (StatusCode::$status, format!($($arg)*)) };
+}
+
+/// The data a mock api starts with. Objects are keyed by request type ("user", "content", etc) and
+/// are stored exactly as the api would return them.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct MockData {
+    pub about: Option<About>,
+    pub passwords: HashMap<String, String>, //username : password, for /user/login
+    pub emails: HashMap<String, String>,    //username : email, for /user/privatedata
+    pub registration_enabled: bool,
+    pub objects: HashMap<String, Vec<Value>>
+}
+
+impl MockData {
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+struct MockStore {
+    data: MockData,
+    about: About,
+    tokens: HashMap<String, i64>,
+    next_token: u64,
+    files: HashMap<String, Vec<u8>>, //hash : data, for anything uploaded
+    live: Vec<live::LiveRecord>,     //Every live event ever, the index is the id (minus 1)
+    revisions: HashMap<i64, Value>,  //Revision (activity) id : the content as of that revision
+    live_sender: tokio::sync::watch::Sender<i64> //Pokes the live sockets when there's something new
+}
+
+/// A running mock api. Point an [`crate::endpoints::ApiContext`] at [`MockApi::url`]
+pub struct MockApi {
+    address: SocketAddr,
+    store: Arc<Mutex<MockStore>>
+}
+
+impl MockApi {
+    /// Start serving the given data on a random local port. Must be called from within a tokio runtime;
+    /// the server lives as long as the runtime does (so each #[tokio::test] gets its own)
+    pub fn start(data: MockData) -> Result<Self, hyper::Error>
+    {
+        let about = data.about.clone().unwrap_or(About {
+            version: String::from("mock"),
+            environment: String::from("test"),
+            runtime: String::from("mock"),
+            contact: String::from("nobody@localhost")
+        });
+        //Fixtures only have the current content, so that's all the history there is to start with
+        let mut revisions = HashMap::new();
+        for content in data.objects.get("content").into_iter().flatten() {
+            let latest = data.objects.get("activity").into_iter().flatten()
+                .filter(|a| get_i64(a, "contentId") == get_i64(content, "id"))
+                .filter_map(|a| get_i64(a, "id")).max();
+            if let Some(id) = latest {
+                revisions.insert(id, content.clone());
+            }
+        }
+        let store = Arc::new(Mutex::new(MockStore { data, about, tokens: HashMap::new(), next_token: 0, files: HashMap::new(),
+            live: Vec::new(), revisions, live_sender: tokio::sync::watch::channel(0).0 }));
+
+        let service_store = store.clone();
+        let make_service = make_service_fn(move |_| {
+            let store = service_store.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| handle(store.clone(), req)))
+            }
+        });
+
+        let server = hyper::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?.serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        Ok(MockApi { address, store })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// Get a token for the given user without going through /user/login
+    pub fn login(&self, username: &str) -> Option<String> {
+        self.store.lock().ok()?.login(username)
+    }
+
+    /// A copy of every object of the given type, including anything written through the api
+    pub fn objects(&self, rtype: &str) -> Vec<Value> {
+        self.store.lock().map(|s| s.data.objects.get(rtype).cloned().unwrap_or_default()).unwrap_or_default()
+    }
+
+    /// The data for a file uploaded to the api, by its hash
+    pub fn file(&self, hash: &str) -> Option<Vec<u8>> {
+        self.store.lock().ok()?.files.get(hash).cloned()
+    }
+
+    /// How many live websockets are open right now
+    pub fn live_sockets(&self) -> usize {
+        self.store.lock().map(|s| s.live_sender.receiver_count()).unwrap_or_default()
+    }
+}
+
+fn bearer_token(request: &hyper::Request<Body>) -> Option<&str>
+{
+    request.headers().get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+}
+
+async fn handle(store: Arc<Mutex<MockStore>>, request: hyper::Request<Body>) -> Result<hyper::Response<Body>, Infallible>
+{
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let query = serde_urlencoded::from_str::<HashMap<String, String>>(request.uri().query().unwrap_or_default()).unwrap_or_default();
+    if method == Method::GET && path == "/live/ws" {
+        return Ok(live::socket(store, request));
+    }
+    let token = bearer_token(&request).map(String::from);
+    let content_type = request.headers().get("Content-Type")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let body = hyper::body::to_bytes(request.into_body()).await.unwrap_or_default();
+
+    let result = match store.lock() {
+        Ok(mut store) => store.route(&method, &path, &query, token, &content_type, &body),
+        Err(error) => Err(bad!(INTERNAL_SERVER_ERROR, "Mock store poisoned: {}", error))
+    };
+
+    let response = match result {
+        Ok(value) => hyper::Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(value.to_string())),
+        Err((status, message)) => hyper::Response::builder()
+            .status(status)
+            .body(Body::from(message))
+    };
+
+    Ok(response.unwrap_or_else(|_| hyper::Response::new(Body::empty())))
+}
+
+fn parse_body<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T, (StatusCode, String)> {
+    serde_json::from_slice(body).map_err(|e| bad!(BAD_REQUEST, "Couldn't parse body: {}", e))
+}
+
+type MultipartParts = HashMap<String, (Option<String>, Vec<u8>)>;
+
+/// Pull the parts out of a multipart/form-data body as name : (filename, data). Only as much of
+/// the format as ApiContext::upload_file (and browsers) actually use
+fn parse_multipart(content_type: &str, body: &[u8]) -> Result<MultipartParts, (StatusCode, String)>
+{
+    let boundary = content_type.split(';')
+        .find_map(|p| p.trim().strip_prefix("boundary="))
+        .map(|b| format!("--{}", b.trim_matches('"')))
+        .ok_or_else(|| bad!(BAD_REQUEST, "Expected multipart/form-data, got '{}'", content_type))?;
+
+    let find = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).position(|w| w == needle);
+    let header_value = |headers: &str, key: &str| headers.split(';')
+        .find_map(|p| p.trim().strip_prefix(key))
+        .map(|v| v.trim_matches('"').to_string());
+
+    let mut parts = HashMap::new();
+    let mut rest = body;
+    while let Some(start) = find(rest, boundary.as_bytes()) {
+        rest = &rest[start + boundary.len()..];
+        if rest.starts_with(b"--") { break; }
+        let header_end = find(rest, b"\r\n\r\n").ok_or_else(|| bad!(BAD_REQUEST, "Multipart part has no end of headers"))?;
+        let headers = String::from_utf8_lossy(&rest[..header_end]).to_string();
+        rest = &rest[header_end + 4..];
+        let data_end = find(rest, format!("\r\n{}", boundary).as_bytes()).ok_or_else(|| bad!(BAD_REQUEST, "Multipart part never ends"))?;
+        let disposition = headers.lines().find(|l| l.to_lowercase().starts_with("content-disposition")).unwrap_or_default();
+        if let Some(name) = header_value(disposition, "name=") {
+            parts.insert(name, (header_value(disposition, "filename="), rest[..data_end].to_vec()));
+        }
+        rest = &rest[data_end + 2..];
+    }
+    Ok(parts)
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, (StatusCode, String)>
+{
+    let mut result = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(bad!(BAD_REQUEST, "Invalid base64 character '{}'", c as char))
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(result)
+}
+
+fn get_i64(object: &Value, field: &str) -> Option<i64> {
+    object.get(field).and_then(|v| v.as_i64())
+}
+
+fn truthy(value: Option<&Value>) -> bool {
+    match value {
+        Some(Value::Bool(b)) => *b,
+        Some(Value::Number(n)) => n.as_f64().unwrap_or(0.0) != 0.0,
+        _ => false
+    }
+}
+
+/// The same rules as [`crate::permissions::can_user_action`], but on raw json
+fn can_action(user: Option<&Value>, action: char, content: &Value) -> bool
+{
+    let permissions = match content.get("permissions").and_then(|p| p.as_object()) {
+        Some(permissions) => permissions,
+        None => return true //Fixtures don't always bother
+    };
+
+    let mut ids = vec![0];
+    if let Some(user) = user {
+        if action != 'R' && truthy(user.get("super")) {
+            return true;
+        }
+        ids.push(get_i64(user, "id").unwrap_or(0));
+        ids.extend(get_path_i64(user, "groups"));
+    }
+
+    ids.iter().any(|id| permissions.get(&id.to_string())
+        .and_then(|p| p.as_str())
+        .map(|p| p.to_uppercase().contains(action))
+        .unwrap_or(false))
+}
+
+fn get_path_i64(object: &Value, field: &str) -> Vec<i64> {
+    eval::get_path(object, &[field.to_string()]).iter().filter_map(|v| v.as_i64()).collect()
+}
+
+fn make_hash(name: &str, id: i64) -> String {
+    let slug = name.to_lowercase().chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect::<String>();
+    let slug = slug.split('-').filter(|s| !s.is_empty()).collect::<Vec<_>>().join("-");
+    if slug.is_empty() { format!("mock{}", id) } else { format!("{}-{}", slug, id) }
+}
+
+/// Tally engagement objects (content_engagement or message_engagement) into the
+/// { type : { engagement : count } } shape the api puts on content and messages
+fn tally_engagement(engagements: &[Value], field: &str, id: i64) -> Value
+{
+    let mut tally: HashMap<String, HashMap<String, i64>> = HashMap::new();
+    for engagement in engagements.iter().filter(|e| get_i64(e, field) == Some(id)) {
+        let etype = engagement.get("type").and_then(|t| t.as_str()).unwrap_or_default().to_string();
+        let value = engagement.get("engagement").and_then(|t| t.as_str()).unwrap_or_default().to_string();
+        *tally.entry(etype).or_default().entry(value).or_default() += 1;
+    }
+    json!(tally)
+}
+
+impl MockStore
+{
+    fn list(&self, rtype: &str) -> &[Value] {
+        self.data.objects.get(rtype).map(|l| l.as_slice()).unwrap_or(&[])
+    }
+
+    fn find(&self, rtype: &str, id: i64) -> Option<&Value> {
+        self.list(rtype).iter().find(|o| get_i64(o, "id") == Some(id))
+    }
+
+    fn next_id(&self, rtype: &str) -> i64 {
+        self.list(rtype).iter().filter_map(|o| get_i64(o, "id")).max().unwrap_or(0) + 1
+    }
+
+    fn login(&mut self, username: &str) -> Option<String> {
+        let user_id = self.list("user").iter()
+            .find(|u| u.get("username").and_then(|n| n.as_str()) == Some(username))
+            .and_then(|u| get_i64(u, "id"))?;
+        self.next_token += 1;
+        let token = format!("mocktoken-{}-{}", user_id, self.next_token);
+        self.tokens.insert(token.clone(), user_id);
+        Some(token)
+    }
+
+    fn route(&mut self, method: &Method, path: &str, query: &HashMap<String, String>, token: Option<String>, content_type: &str, body: &[u8]) -> MockResult
+    {
+        let user = token.and_then(|t| self.tokens.get(&t).copied()).and_then(|id| self.find("user", id)).cloned();
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+        let require_user = || user.clone().ok_or_else(|| bad!(UNAUTHORIZED, "You must be logged in"));
+
+        match (method, segments.as_slice())
+        {
+            (&Method::GET, ["status"]) => Ok(json!(self.about)),
+            (&Method::GET, ["user", "me"]) => require_user(),
+            (&Method::GET, ["user", "privatedata"]) => {
+                let user = require_user()?;
+                let username = user.get("username").and_then(|u| u.as_str()).unwrap_or_default();
+                Ok(json!({ "email": self.data.emails.get(username).cloned().unwrap_or_default() }))
+            },
+            (&Method::GET, ["user", "registrationconfig"]) => Ok(json!({ "enabled": self.data.registration_enabled })),
+            (&Method::POST, ["user", "registrationconfig"]) => {
+                let config: forms::RegistrationConfig = parse_body(body)?;
+                self.data.registration_enabled = config.enabled;
+                Ok(json!(config))
+            },
+            (&Method::POST, ["user", "login"]) => {
+                let login: forms::Login = parse_body(body)?;
+                if self.data.passwords.get(&login.username) != Some(&login.password) {
+                    return Err(bad!(BAD_REQUEST, "Username or password is incorrect"));
+                }
+                self.login(&login.username).map(|t| json!(t)).ok_or_else(|| bad!(BAD_REQUEST, "User not found"))
+            },
+            (&Method::POST, ["user", "register"]) => {
+                let register: forms::Register = parse_body(body)?;
+                if self.data.passwords.contains_key(&register.username) {
+                    return Err(bad!(BAD_REQUEST, "Username already taken"));
+                }
+                let user = json!({
+                    "id": self.next_id("user"),
+                    "type": UserType::USER,
+                    "username": register.username,
+                    "avatar": "0",
+                    "special": null,
+                    "super": false,
+                    "createDate": Utc::now(),
+                    "groups": []
+                });
+                self.data.passwords.insert(register.username.clone(), register.password);
+                self.data.emails.insert(register.username, register.email);
+                self.data.objects.entry(String::from("user")).or_default().push(user.clone());
+                Ok(user)
+            },
+            (&Method::POST, ["user", "sendregistrationcode"]) |
+            (&Method::POST, ["user", "sendpasswordrecovery"]) => Ok(json!(true)),
+            (&Method::POST, ["user", "confirmregistration"]) => {
+                let confirm: forms::RegisterConfirm = parse_body(body)?;
+                let username = self.data.emails.iter().find(|(_, e)| **e == confirm.email).map(|(u, _)| u.clone())
+                    .ok_or_else(|| bad!(BAD_REQUEST, "No user with that email"))?;
+                self.login(&username).map(|t| json!(t)).ok_or_else(|| bad!(BAD_REQUEST, "User not found"))
+            },
+            (&Method::POST, ["user", "privatedata"]) => {
+                let user = require_user()?;
+                let sensitive: forms::UserSensitive = parse_body(body)?;
+                let username = user.get("username").and_then(|u| u.as_str()).unwrap_or_default().to_string();
+                if self.data.passwords.get(&username) != Some(&sensitive.currentPassword) {
+                    return Err(bad!(BAD_REQUEST, "Current password is incorrect"));
+                }
+                if let Some(password) = sensitive.password { self.data.passwords.insert(username.clone(), password); }
+                if let Some(email) = sensitive.email { self.data.emails.insert(username.clone(), email); }
+                self.login(&username).map(|t| json!(t)).ok_or_else(|| bad!(BAD_REQUEST, "User not found"))
+            },
+            (&Method::POST, ["request"]) => self.request(user.as_ref(), parse_body(body)?),
+            (&Method::POST, ["write", kind]) => {
+                let written = self.write(kind, &require_user()?, parse_body(body)?)?;
+                //The edit message rides along in the query and ends up on the revision
+                if let (Some(message), Some(revision)) = (query.get("activityMessage"), get_i64(&written, "lastRevisionId")) {
+                    if let Some(activity) = self.data.objects.get_mut("activity").and_then(|l| l.iter_mut().find(|a| get_i64(a, "id") == Some(revision))) {
+                        activity["message"] = json!(message);
+                    }
+                }
+                Ok(written)
+            },
+            (&Method::GET, ["history", id]) => {
+                let id = id.parse::<i64>().map_err(|e| bad!(BAD_REQUEST, "Bad id: {}", e))?;
+                let revision = self.revisions.get(&id).ok_or_else(|| bad!(NOT_FOUND, "No revision {}", id))?;
+                let readable = get_i64(revision, "id").and_then(|cid| self.find("content", cid)).map(|c| can_action(user.as_ref(), 'R', c)).unwrap_or(false);
+                if !readable {
+                    return Err(bad!(NOT_FOUND, "No revision {}", id));
+                }
+                Ok(revision.clone())
+            },
+            (&Method::POST, ["file"]) => {
+                let user = require_user()?;
+                let mut parts = parse_multipart(content_type, body)?;
+                let (filename, data) = parts.remove("file").ok_or_else(|| bad!(BAD_REQUEST, "No file in upload"))?;
+                let mut object = match parts.remove("object") {
+                    Some((_, object)) => parse_body(&object)?,
+                    None => json!({})
+                };
+                if object.get("name").and_then(|n| n.as_str()).map(|n| n.is_empty()).unwrap_or(true) {
+                    object["name"] = json!(filename.unwrap_or_default());
+                }
+                self.upload(&user, object, data)
+            },
+            (&Method::POST, ["file", "asobject"]) => {
+                let user = require_user()?;
+                let upload: Value = parse_body(body)?;
+                let data = decode_base64(upload.get("base64blob").and_then(|b| b.as_str()).unwrap_or_default())?;
+                self.upload(&user, upload.get("object").cloned().unwrap_or_else(|| json!({})), data)
+            },
+            (&Method::POST, ["delete", kind, id]) => {
+                let id = id.parse::<i64>().map_err(|e| bad!(BAD_REQUEST, "Bad id: {}", e))?;
+                self.delete(kind, &require_user()?, id)
+            },
+            (&Method::POST, ["shortcuts", "content", id, "setengagement", etype]) => {
+                let id = id.parse::<i64>().map_err(|e| bad!(BAD_REQUEST, "Bad id: {}", e))?;
+                let engagement: String = parse_body(body)?;
+                self.set_engagement("content", id, etype, &require_user()?, engagement)
+            },
+            (&Method::POST, ["shortcuts", "message", id, "setengagement", etype]) => {
+                let id = id.parse::<i64>().map_err(|e| bad!(BAD_REQUEST, "Bad id: {}", e))?;
+                let engagement: String = parse_body(body)?;
+                self.set_engagement("message", id, etype, &require_user()?, engagement)
+            },
+            (&Method::POST, ["shortcuts", "watch", action, id]) => {
+                let id = id.parse::<i64>().map_err(|e| bad!(BAD_REQUEST, "Bad id: {}", e))?;
+                self.watch(action, id, &require_user()?)
+            },
+            (&Method::POST, ["shortcuts", "uservariable", "delete", key]) => {
+                self.uservariable(key, None, &require_user()?)
+            },
+            (&Method::POST, ["shortcuts", "uservariable", key]) => {
+                let value: String = parse_body(body)?;
+                self.uservariable(key, Some(value), &require_user()?)
+            },
+            _ => Err(bad!(NOT_FOUND, "Mock api doesn't know {} {}", method, path))
+        }
+    }
+
+    /// Everything of the given type the user is allowed to see, with all the computed fields filled in
+    fn visible(&self, rtype: &str, user: Option<&Value>) -> Vec<Value>
+    {
+        let user_id = user.and_then(|u| get_i64(u, "id"));
+        if rtype == "keyword_aggregate" {
+            return self.keyword_aggregate(user);
+        }
+        let parent_readable = |object: &Value| {
+            match get_i64(object, "contentId").and_then(|id| self.find("content", id)) {
+                Some(content) => can_action(user, 'R', content),
+                None => true
+            }
+        };
+
+        self.list(rtype).iter().filter(|object| {
+            match rtype {
+                "content" => can_action(user, 'R', object),
+                "message" | "activity" | "content_engagement" | "message_engagement" => parent_readable(object),
+                "watch" | "uservariable" => user_id.is_some() && get_i64(object, "userId") == user_id,
+                _ => true
+            }
+        }).map(|object| {
+            let mut object = object.clone();
+            let id = get_i64(&object, "id").unwrap_or(0);
+            match rtype {
+                "content" => {
+                    let comments = self.list("message").iter()
+                        .filter(|m| get_i64(m, "contentId") == Some(id) && !truthy(m.get("deleted")) &&
+                                    m.get("module").map(|m| m.is_null()).unwrap_or(true))
+                        .collect::<Vec<_>>();
+                    let last = comments.iter().max_by_key(|m| get_i64(m, "id"));
+                    let mut last_action = object.get("createDate").cloned().unwrap_or(Value::Null);
+                    if let Some(date) = last.and_then(|m| m.get("createDate")) {
+                        if eval::compare(date, &last_action) == Some(Ordering::Greater) || last_action.is_null() {
+                            last_action = date.clone();
+                        }
+                    }
+                    object["commentCount"] = json!(comments.len());
+                    object["lastCommentId"] = json!(last.and_then(|m| get_i64(m, "id")).unwrap_or(0));
+                    if object.get("lastActionDate").map(|d| eval::compare(&last_action, d) == Some(Ordering::Greater)).unwrap_or(true) {
+                        object["lastActionDate"] = last_action;
+                    }
+                    object["engagement"] = tally_engagement(self.list("content_engagement"), "contentId", id);
+                    object["watchCount"] = json!(self.list("watch").iter().filter(|w| get_i64(w, "contentId") == Some(id)).count());
+                },
+                "message" => {
+                    if let Some(content) = get_i64(&object, "contentId").and_then(|cid| self.find("content", cid)) {
+                        object["content_literalType"] = content.get("literalType").cloned().unwrap_or(Value::Null);
+                        object["content_contentType"] = content.get("contentType").cloned().unwrap_or(Value::Null);
+                    }
+                    object["engagement"] = tally_engagement(self.list("message_engagement"), "messageId", id);
+                },
+                _ => {}
+            }
+            object
+        }).collect()
+    }
+
+    /// Keywords are counted off whatever content the user can see, like the real api
+    fn keyword_aggregate(&self, user: Option<&Value>) -> Vec<Value>
+    {
+        let mut counts: HashMap<String, i64> = HashMap::new();
+        for content in self.visible("content", user).iter().filter(|c| !truthy(c.get("deleted"))) {
+            for keyword in content.get("keywords").and_then(|k| k.as_array()).into_iter().flatten().filter_map(|k| k.as_str()) {
+                *counts.entry(keyword.to_string()).or_default() += 1;
+            }
+        }
+        let mut aggregate = counts.into_iter().map(|(value, count)| json!({ "value": value, "count": count })).collect::<Vec<_>>();
+        aggregate.sort_by(|a, b| a["value"].as_str().cmp(&b["value"].as_str()));
+        aggregate
+    }
+
+    fn request(&self, user: Option<&Value>, search: FullRequest) -> MockResult
+    {
+        let mut results: HashMap<String, Vec<Value>> = HashMap::new(); //Unprojected, for chaining
+        let mut objects: HashMap<String, Vec<Value>> = HashMap::new();
+        let mut times: HashMap<String, f64> = HashMap::new();
+        let all_content = self.list("content").to_vec();
+
+        for request in &search.requests
+        {
+            if !KNOWNTYPES.contains(&request.r#type.as_str()) {
+                return Err(bad!(BAD_REQUEST, "Unknown request type {}", request.r#type));
+            }
+            let name = request.name.clone().unwrap_or_else(|| request.r#type.clone());
+            let mut list = self.visible(&request.r#type, user);
+
+            if let Some(query) = request.query.as_ref().filter(|q| !q.trim().is_empty()) {
+                let expr = eval::parse(query).map_err(|e| bad!(BAD_REQUEST, "Request {}: {}", name, e))?;
+                let context = eval::EvalContext { values: &search.values, results: &results, content: &all_content, now: Utc::now() };
+                let mut filtered = Vec::new();
+                for object in list {
+                    if eval::eval(&expr, &object, &context).map_err(|e| bad!(BAD_REQUEST, "Request {}: {}", name, e))? {
+                        filtered.push(object);
+                    }
+                }
+                list = filtered;
+            }
+
+            //Stable sort from the last order field to the first gives the right multi-field ordering
+            let order = request.order.clone().unwrap_or_default();
+            for field in order.split(',').map(|f| f.trim()).filter(|f| !f.is_empty() && *f != "random").rev() {
+                let (field, descending) = match field.strip_suffix("_desc") {
+                    Some(field) => (field, true),
+                    None => (field, false)
+                };
+                list.sort_by(|a, b| {
+                    let ordering = match (a.get(field), b.get(field)) {
+                        (Some(x), Some(y)) => eval::compare(x, y).unwrap_or(Ordering::Equal),
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (None, None) => Ordering::Equal
+                    };
+                    if descending { ordering.reverse() } else { ordering }
+                });
+            }
+
+            let fields = request.fields.split(',').map(|f| f.trim().to_string()).collect::<Vec<_>>();
+
+            let output = if fields.iter().any(|f| f == "specialCount") {
+                vec![json!({ "specialCount": list.len() })]
+            }
+            else {
+                let limit = if request.limit <= 0 { REQUESTRESULTLIMIT } else { (request.limit as usize).min(REQUESTRESULTLIMIT) };
+                list = list.into_iter().skip(request.skip.max(0) as usize).take(limit).collect();
+                list.iter().map(|object| {
+                    if fields.iter().any(|f| f == "*") {
+                        object.clone()
+                    }
+                    else {
+                        Value::Object(fields.iter().filter_map(|f| object.get(f).map(|v| (f.clone(), v.clone()))).collect())
+                    }
+                }).collect()
+            };
+
+            times.insert(name.clone(), 0.0);
+            objects.insert(name.clone(), output);
+            results.insert(name, list);
+        }
+
+        Ok(json!({
+            "search": search,
+            "databaseTimes": times,
+            "objects": objects,
+            "totalTime": 0.0,
+            "nonDbTime": 0.0,
+            "requestUser": user.and_then(|u| get_i64(u, "id"))
+        }))
+    }
+
+    fn write(&mut self, kind: &str, user: &Value, mut object: Value) -> MockResult
+    {
+        let rtype = match kind {
+            "content" | "message" | "user" | "ban" | "uservariable" | "watch" => kind,
+            _ => return Err(bad!(NOT_FOUND, "Mock api can't write {}", kind))
+        };
+        let user_id = get_i64(user, "id").unwrap_or(0);
+        let admin = truthy(user.get("super"));
+        let now = json!(Utc::now());
+        let id = get_i64(&object, "id").unwrap_or(0);
+
+        if id > 0 {
+            let existing = self.find(rtype, id).cloned().ok_or_else(|| bad!(NOT_FOUND, "No {} with id {}", rtype, id))?;
+            let allowed = match rtype {
+                "content" => can_action(Some(user), 'U', &existing),
+                "user" => admin || id == user_id,
+                "ban" => admin,
+                _ => admin || get_i64(&existing, "createUserId") == Some(user_id) || get_i64(&existing, "userId") == Some(user_id)
+            };
+            if !allowed {
+                return Err(bad!(FORBIDDEN, "You can't edit {} {}", rtype, id));
+            }
+            let mut merged = existing;
+            if let (Some(target), Some(source)) = (merged.as_object_mut(), object.as_object()) {
+                for (key, value) in source.iter().filter(|(_, v)| !v.is_null()) {
+                    target.insert(key.clone(), value.clone());
+                }
+            }
+            if rtype == "message" {
+                merged["editDate"] = now.clone();
+                merged["editUserId"] = json!(user_id);
+            }
+            object = merged;
+            if let Some(slot) = self.data.objects.get_mut(rtype).and_then(|l| l.iter_mut().find(|o| get_i64(o, "id") == Some(id))) {
+                *slot = object.clone();
+            }
+        }
+        else {
+            if rtype == "message" {
+                let parent = get_i64(&object, "contentId").and_then(|cid| self.find("content", cid))
+                    .ok_or_else(|| bad!(BAD_REQUEST, "Messages must be posted to existing content"))?;
+                if !can_action(Some(user), 'C', parent) {
+                    return Err(bad!(FORBIDDEN, "You can't post in content {}", get_i64(parent, "id").unwrap_or(0)));
+                }
+            }
+            let id = self.next_id(rtype);
+            object["id"] = json!(id);
+            object["createDate"] = now.clone();
+            match rtype {
+                "content" => {
+                    object["createUserId"] = json!(user_id);
+                    object["deleted"] = json!(false);
+                    if object.get("hash").and_then(|h| h.as_str()).map(|h| h.is_empty()).unwrap_or(true) {
+                        let name = object.get("name").and_then(|n| n.as_str()).unwrap_or_default().to_string();
+                        object["hash"] = json!(make_hash(&name, id));
+                    }
+                },
+                "user" => {},
+                "uservariable" | "watch" => { object["userId"] = json!(user_id); },
+                _ => { object["createUserId"] = json!(user_id); }
+            }
+            self.data.objects.entry(rtype.to_string()).or_default().push(object.clone());
+        }
+
+        let action = if id > 0 { UserAction::UPDATE } else { UserAction::CREATE };
+        let object_id = get_i64(&object, "id").unwrap_or(0);
+
+        //Content edits show up in activity, same as the real api. The activity is also the revision
+        if rtype == "content" {
+            let revision_id = self.next_id("activity");
+            object["lastRevisionId"] = json!(revision_id);
+            if let Some(slot) = self.data.objects.get_mut(rtype).and_then(|l| l.iter_mut().find(|o| get_i64(o, "id") == Some(object_id))) {
+                *slot = object.clone();
+            }
+            self.revisions.insert(revision_id, object.clone());
+            let activity = json!({
+                "id": revision_id,
+                "contentId": object_id,
+                "userId": user_id,
+                "date": now,
+                "action": action
+            });
+            self.data.objects.entry(String::from("activity")).or_default().push(activity.clone());
+            let objects = HashMap::from([
+                (String::from("activity"), vec![activity]), (String::from("content"), vec![object.clone()]), (String::from("user"), vec![user.clone()])
+            ]);
+            self.push_live(crate::live::LiveEventType::ACTIVITY, action, user_id, object_id, objects, live::Audience::Readers(object_id));
+        }
+        else if rtype == "message" {
+            let content_id = get_i64(&object, "contentId");
+            let parent = content_id.and_then(|cid| self.find("content", cid)).cloned();
+            let objects = HashMap::from([
+                (String::from("message"), vec![object.clone()]), (String::from("content"), parent.into_iter().collect()), (String::from("user"), vec![user.clone()])
+            ]);
+            self.push_live(crate::live::LiveEventType::MESSAGE, action, user_id, object_id, objects, 
+                content_id.map(live::Audience::Readers).unwrap_or(live::Audience::Everyone));
+        }
+        else if rtype == "watch" {
+            let objects = HashMap::from([(String::from("watch"), vec![object.clone()])]);
+            self.push_live(crate::live::LiveEventType::WATCH, action, user_id, object_id, objects, live::Audience::User(user_id));
+        }
+
+        Ok(object)
+    }
+
+    fn upload(&mut self, user: &Value, mut object: Value, data: Vec<u8>) -> MockResult
+    {
+        if data.is_empty() {
+            return Err(bad!(BAD_REQUEST, "Can't upload an empty file"));
+        }
+        object["id"] = json!(0);
+        object["contentType"] = json!(ContentType::FILE);
+        let object = self.write("content", user, object)?;
+        let hash = object.get("hash").and_then(|h| h.as_str()).unwrap_or_default().to_string();
+        self.files.insert(hash, data);
+        Ok(object)
+    }
+
+    fn delete(&mut self, kind: &str, user: &Value, id: i64) -> MockResult
+    {
+        let existing = self.find(kind, id).cloned().ok_or_else(|| bad!(NOT_FOUND, "No {} with id {}", kind, id))?;
+        let allowed = match kind {
+            "content" => can_action(Some(user), 'D', &existing),
+            "message" => truthy(user.get("super")) || get_i64(&existing, "createUserId") == get_i64(user, "id"),
+            _ => return Err(bad!(NOT_FOUND, "Mock api can't delete {}", kind))
+        };
+        if !allowed {
+            return Err(bad!(FORBIDDEN, "You can't delete {} {}", kind, id));
+        }
+        let slot = self.data.objects.get_mut(kind).and_then(|l| l.iter_mut().find(|o| get_i64(o, "id") == Some(id)))
+            .ok_or_else(|| bad!(NOT_FOUND, "No {} with id {}", kind, id))?;
+        slot["deleted"] = json!(true);
+        Ok(slot.clone())
+    }
+
+    /// The watch shortcuts: add, delete or clear (catch up) the user's watch on some content
+    fn watch(&mut self, action: &str, content_id: i64, user: &Value) -> MockResult
+    {
+        let user_id = get_i64(user, "id").unwrap_or(0);
+        let content = self.visible("content", Some(user)).into_iter().find(|c| get_i64(c, "id") == Some(content_id))
+            .ok_or_else(|| bad!(NOT_FOUND, "No content with id {}", content_id))?;
+        let last_comment = get_i64(&content, "lastCommentId").unwrap_or(0);
+        let list = self.data.objects.entry(String::from("watch")).or_default();
+        let existing = list.iter().position(|w| get_i64(w, "contentId") == Some(content_id) && get_i64(w, "userId") == Some(user_id));
+
+        let (event_action, watch) = match (action, existing) {
+            ("add", Some(index)) => return Ok(list[index].clone()),
+            ("add", None) => {
+                let watch = json!({
+                    "id": list.iter().filter_map(|w| get_i64(w, "id")).max().unwrap_or(0) + 1,
+                    "contentId": content_id,
+                    "userId": user_id,
+                    "lastCommentId": last_comment,
+                    "lastActivityId": 0,
+                    "createDate": Utc::now(),
+                    "editDate": Utc::now()
+                });
+                list.push(watch.clone());
+                (UserAction::CREATE, watch)
+            },
+            ("delete", Some(index)) => (UserAction::DELETE, list.remove(index)),
+            ("clear", Some(index)) => {
+                list[index]["lastCommentId"] = json!(last_comment);
+                list[index]["editDate"] = json!(Utc::now());
+                (UserAction::UPDATE, list[index].clone())
+            },
+            ("delete", None) | ("clear", None) => return Err(bad!(BAD_REQUEST, "You aren't watching content {}", content_id)),
+            _ => return Err(bad!(NOT_FOUND, "Mock api doesn't know watch/{}", action))
+        };
+
+        let watch_id = get_i64(&watch, "id").unwrap_or(0);
+        let objects = HashMap::from([(String::from("watch"), vec![watch.clone()])]);
+        self.push_live(crate::live::LiveEventType::WATCH, event_action, user_id, watch_id, objects, live::Audience::User(user_id));
+        Ok(watch)
+    }
+
+    /// Set (or with no value, delete) one of the user's variables
+    fn uservariable(&mut self, key: &str, value: Option<String>, user: &Value) -> MockResult
+    {
+        let user_id = get_i64(user, "id").unwrap_or(0);
+        let existing = self.list("uservariable").iter()
+            .find(|v| get_i64(v, "userId") == Some(user_id) && v.get("key").and_then(|k| k.as_str()) == Some(key))
+            .and_then(|v| get_i64(v, "id"));
+
+        let (action, variable) = match (value, existing) {
+            (Some(value), Some(id)) => (UserAction::UPDATE, self.write("uservariable", user, json!({ "id": id, "value": value, "editDate": Utc::now() }))?),
+            (Some(value), None) => (UserAction::CREATE, self.write("uservariable", user, json!({ "key": key, "value": value, "editDate": Utc::now() }))?),
+            (None, Some(id)) => {
+                let list = self.data.objects.entry(String::from("uservariable")).or_default();
+                let index = list.iter().position(|v| get_i64(v, "id") == Some(id)).unwrap_or_default();
+                (UserAction::DELETE, list.remove(index))
+            },
+            (None, None) => return Err(bad!(NOT_FOUND, "No uservariable {}", key))
+        };
+
+        let variable_id = get_i64(&variable, "id").unwrap_or(0);
+        let objects = HashMap::from([(String::from("uservariable"), vec![variable.clone()])]);
+        self.push_live(crate::live::LiveEventType::USERVARIABLE, action, user_id, variable_id, objects, live::Audience::User(user_id));
+        Ok(variable)
+    }
+
+    fn set_engagement(&mut self, kind: &str, id: i64, etype: &str, user: &Value, engagement: String) -> MockResult
+    {
+        let (rtype, field) = match kind {
+            "content" => ("content_engagement", "contentId"),
+            _ => ("message_engagement", "messageId")
+        };
+        let user_id = get_i64(user, "id");
+        let list = self.data.objects.entry(rtype.to_string()).or_default();
+        let is_mine = |e: &Value| get_i64(e, field) == Some(id) && get_i64(e, "userId") == user_id &&
+            e.get("type").and_then(|t| t.as_str()) == Some(etype);
+        let previous = list.iter().find(|e| is_mine(e)).cloned();
+        list.retain(|e| !is_mine(e));
+
+        //Empty engagement just removes it
+        if engagement.is_empty() || engagement == "-" {
+            return Ok(previous.unwrap_or_else(|| json!({})));
+        }
+
+        let new_id = list.iter().filter_map(|o| get_i64(o, "id")).max().unwrap_or(0) + 1;
+        let object = json!({
+            "id": new_id,
+            field: id,
+            "userId": user_id,
+            "type": etype,
+            "engagement": engagement,
+            "createDate": Utc::now()
+        });
+        list.push(object.clone());
+        Ok(object)
+    }
+}
@@ -0,0 +1,269 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+//How the ApiContext deals with a slow or broken backend. Every page makes several api calls, so
+//without a timeout a stalled backend hangs every handler, and without the breaker a dead backend
+//makes every page wait out the full timeout (several times!) before erroring.
+
+/// Timeouts, retries and circuit breaking for api requests. Cheap to clone; the breaker is shared
+/// between every clone, which is the point (each page request makes its own ApiContext)
+#[derive(Clone, Debug)]
+pub struct RequestPolicy {
+    pub timeout: Option<Duration>,  //Per attempt (the whole response, body included), not per call. None waits forever
+    pub retries: u32,               //Extra attempts for idempotent requests only (GET and /request)
+    pub retry_backoff: Duration,    //Wait before the first retry; doubles each retry after
+    pub breaker: Arc<CircuitBreaker>
+}
+
+impl RequestPolicy {
+    /// How many times a request can be sent in total
+    pub fn attempts(&self, retryable: bool) -> u32 {
+        if retryable { self.retries + 1 } else { 1 }
+    }
+
+    /// How long to wait after the given (1-based) attempt fails before sending the next one
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.retry_backoff.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+
+    /// Whether the status means the backend couldn't be reached at all (the proxy in front of it answered)
+    pub fn is_gateway_failure(status: u16) -> bool {
+        matches!(status, 502..=504)
+    }
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            retries: 0,
+            retry_backoff: Duration::from_millis(100),
+            breaker: Arc::new(CircuitBreaker::new(0, Duration::ZERO))
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+    probe_started: Option<Instant> //After the cooldown, the one call that's allowed through to test the backend
+}
+
+/// Opens after `threshold` calls in a row fail at the network level (timeouts, refused connections,
+/// gateway errors), then fails everything fast for `cooldown`. After the cooldown, ONE call is let
+/// through (half open); if it succeeds the breaker closes, if it fails it opens right back up, and
+/// everything else keeps failing fast until then. A threshold of 0 disables it.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self { threshold, cooldown, state: Mutex::new(BreakerState::default()) }
+    }
+
+    /// Whether a call can go through now. If not, about how long until one can. Once the cooldown is over
+    /// this lets exactly one call through; that call MUST end in record_success or record_failure. If it
+    /// never does (the caller went away), another probe is allowed after another cooldown
+    pub fn allow(&self) -> Result<(), Duration> {
+        if self.threshold == 0 { return Ok(()); }
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return Ok(())
+        };
+        let now = Instant::now();
+        match state.open_until {
+            None => Ok(()),
+            Some(until) if until > now => Err(until - now),
+            Some(_) => match state.probe_started {
+                Some(started) if started + self.cooldown > now => Err(started + self.cooldown - now),
+                _ => {
+                    state.probe_started = Some(now);
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.failures = 0;
+            state.open_until = None;
+            state.probe_started = None;
+        }
+    }
+
+    pub fn record_failure(&self) {
+        if self.threshold == 0 { return; }
+        if let Ok(mut state) = self.state.lock() {
+            state.failures = state.failures.saturating_add(1);
+            if state.failures >= self.threshold {
+                let now = Instant::now();
+                if !state.open_until.map(|until| until > now).unwrap_or(false) {
+                    tracing::warn!("api circuit breaker opened after {} failures, failing fast for {:?}", state.failures, self.cooldown);
+                }
+                state.open_until = Some(now + self.cooldown);
+                state.probe_started = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn breaker_opens_after_threshold() {
+        let breaker = CircuitBreaker::new(2, COOLDOWN);
+        assert!(breaker.allow().is_ok());
+        breaker.record_failure();
+        assert!(breaker.allow().is_ok());
+        breaker.record_failure();
+        assert!(breaker.allow().is_err());
+    }
+
+    #[test]
+    fn success_resets_the_count() {
+        let breaker = CircuitBreaker::new(2, COOLDOWN);
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(breaker.allow().is_ok());
+    }
+
+    #[test]
+    fn half_open_lets_one_probe_through() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        breaker.record_failure();
+        assert!(breaker.allow().is_err());
+        std::thread::sleep(COOLDOWN);
+        assert!(breaker.allow().is_ok());  //The probe
+        assert!(breaker.allow().is_err()); //Everyone else while it's out
+        breaker.record_success();
+        assert!(breaker.allow().is_ok());
+        assert!(breaker.allow().is_ok());
+    }
+
+    #[test]
+    fn failed_probe_opens_again() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        breaker.record_failure();
+        std::thread::sleep(COOLDOWN);
+        assert!(breaker.allow().is_ok());
+        breaker.record_failure();
+        let wait = breaker.allow().expect_err("Should be open again");
+        assert!(wait > COOLDOWN / 2);
+    }
+
+    #[test]
+    fn lost_probe_is_replaced_after_cooldown() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        breaker.record_failure();
+        std::thread::sleep(COOLDOWN);
+        assert!(breaker.allow().is_ok());
+        std::thread::sleep(COOLDOWN);
+        assert!(breaker.allow().is_ok());
+    }
+
+    #[test]
+    fn zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::new(0, COOLDOWN);
+        for _ in 0..10 { breaker.record_failure(); }
+        assert!(breaker.allow().is_ok());
+    }
+
+    #[test]
+    fn retries_only_when_retryable_and_back_off() {
+        let policy = RequestPolicy { retries: 2, retry_backoff: Duration::from_millis(100), ..Default::default() };
+        assert_eq!(policy.attempts(true), 3);
+        assert_eq!(policy.attempts(false), 1);
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert!(RequestPolicy::is_gateway_failure(503));
+        assert!(!RequestPolicy::is_gateway_failure(500));
+        assert!(!RequestPolicy::is_gateway_failure(404));
+    }
+
+    // -- The policy as the ApiContext applies it, against a fake backend that misbehaves on purpose --
+
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::endpoints::ApiContext;
+
+    const ABOUT: &str = r#"{"version":"1","environment":"test","runtime":"test","contact":"test"}"#;
+
+    /// Serve forever, answering each connection with whatever respond gives for that connection number
+    async fn backend(respond: impl Fn(u32) -> Option<String> + Send + Sync + 'static) -> (String, Arc<AtomicU32>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let count = Arc::new(AtomicU32::new(0));
+        let counter = count.clone();
+        let respond = Arc::new(respond);
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let number = counter.fetch_add(1, Ordering::SeqCst);
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let mut buffer = [0; 4096];
+                    let _ = socket.read(&mut buffer).await;
+                    match respond(number) {
+                        Some(response) => { let _ = socket.write_all(response.as_bytes()).await; },
+                        //Headers promising a body that never comes
+                        None => {
+                            let _ = socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\n{").await;
+                            tokio::time::sleep(Duration::from_secs(30)).await;
+                        }
+                    }
+                });
+            }
+        });
+        (url, count)
+    }
+
+    fn respond(status: &str, body: &str) -> Option<String> {
+        Some(format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body))
+    }
+
+    fn policy(retries: u32, threshold: u32) -> RequestPolicy {
+        RequestPolicy { 
+            timeout: Some(Duration::from_millis(200)), retries, retry_backoff: Duration::from_millis(1), 
+            breaker: Arc::new(CircuitBreaker::new(threshold, Duration::from_secs(60)))
+        }
+    }
+
+    #[tokio::test]
+    async fn gateway_errors_are_retried() {
+        let (url, count) = backend(|n| if n < 2 { respond("503 Service Unavailable", "down") } else { respond("200 OK", ABOUT) }).await;
+        let context = ApiContext::new(url, None).with_policy(policy(2, 0));
+        assert_eq!(context.get_about().await.unwrap().version, "1");
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn stalled_body_times_out() {
+        let (url, count) = backend(|_| None).await;
+        let context = ApiContext::new(url, None).with_policy(policy(1, 0));
+        let start = Instant::now();
+        assert!(matches!(context.get_about().await, Err(crate::endpoints::ApiError::Network(..))));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn open_breaker_fails_fast() {
+        let (url, count) = backend(|_| respond("502 Bad Gateway", "down")).await;
+        let context = ApiContext::new(url, None).with_policy(policy(0, 1));
+        assert!(context.get_about().await.is_err());
+        assert!(context.get_about().await.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
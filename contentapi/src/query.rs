@@ -0,0 +1,309 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use super::*;
+use crate::endpoints::ApiError;
+
+//Queries sent to the api are just strings with @references into the FullRequest values (or into
+//the results of previous requests). It's VERY easy to misspell a key or forget to add the value,
+//and you only find out when the backend gives you a 400. These types build the query text AND
+//the values together, so those mistakes are caught when the request is constructed instead.
+
+#[derive(Debug)]
+pub enum QueryError
+{
+    ValueConflict(String),  //The same key was given two different values
+    UnknownResult(String),  //A query referenced the result of a request that isn't in the FullRequest (yet)
+    BadKey(String),         //Keys must be simple identifiers or the api can't parse them
+    BadLiteral(String),     //A string literal with something in it that could break out of the {{}}
+    EmptyGroup              //An 'all' or 'any' with nothing inside; the api would reject this
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ValueConflict(key) => write!(f, "Query value @{} was given two different values", key),
+            Self::UnknownResult(name) => write!(f, "Query referenced result @{} but no request has that name", name),
+            Self::BadKey(key) => write!(f, "Query key '{}' is not a valid identifier", key),
+            Self::BadLiteral(literal) => write!(f, "Query literal '{}' has characters that aren't allowed in a literal", literal),
+            Self::EmptyGroup => write!(f, "Query contained an empty group")
+        }
+    }
+}
+
+impl std::error::Error for QueryError { }
+
+impl From<QueryError> for ApiError {
+    fn from(error: QueryError) -> Self {
+        ApiError::Other(error.to_string())
+    }
+}
+
+/// Something on the right side of a comparison or inside a macro call
+#[derive(Debug, Clone)]
+pub enum Param
+{
+    Value(String, Value),   //@key, and the value gets added to the request
+    Result(String, String), //@name.field, pulling from the results of a previous request
+    Literal(Value)          //{{value}}, embedded directly in the query text. Strings must be plain words (see check_literal)
+}
+
+impl Param {
+    pub fn value<T: Into<Value>>(key: &str, value: T) -> Self {
+        Param::Value(key.to_string(), value.into())
+    }
+    pub fn result(name: &str, field: &str) -> Self {
+        Param::Result(name.to_string(), field.to_string())
+    }
+    pub fn literal<T: Into<Value>>(value: T) -> Self {
+        Param::Literal(value.into())
+    }
+
+    fn write(&self, request: &mut FullRequest) -> Result<String, QueryError> {
+        match self {
+            Param::Value(key, value) => {
+                check_key(key)?;
+                if let Some(existing) = request.values.get(key) {
+                    if existing != value {
+                        return Err(QueryError::ValueConflict(key.clone()));
+                    }
+                }
+                else {
+                    request.values.insert(key.clone(), value.clone());
+                }
+                Ok(format!("@{}", key))
+            },
+            Param::Result(name, field) => {
+                check_key(name)?;
+                //The result has to come from a request that runs BEFORE this one. Unnamed requests
+                //are named after their type
+                if !request.requests.iter().any(|r| r.name.as_ref().unwrap_or(&r.r#type) == name) {
+                    return Err(QueryError::UnknownResult(name.clone()));
+                }
+                Ok(format!("@{}.{}", name, field))
+            },
+            Param::Literal(value) => {
+                match value {
+                    Value::String(s) => { check_literal(s)?; Ok(format!("{{{{{}}}}}", s)) },
+                    Value::Number(_) | Value::Bool(_) => Ok(format!("{{{{{}}}}}", value)),
+                    //Arrays, objects and null have no literal form the api understands; use a value instead
+                    other => Err(QueryError::BadLiteral(other.to_string()))
+                }
+            }
+        }
+    }
+}
+
+fn check_key(key: &str) -> Result<(), QueryError> {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Ok(())
+    }
+    else {
+        Err(QueryError::BadKey(key.to_string()))
+    }
+}
+
+/// Literals go straight into the query text and the api has no way to escape them, so a string literal
+/// is limited to characters that can't end the literal or start anything else. Anything from a user
+/// should be a Param::Value anyway!
+fn check_literal(literal: &str) -> Result<(), QueryError> {
+    if !literal.is_empty() && literal.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.') {
+        Ok(())
+    }
+    else {
+        Err(QueryError::BadLiteral(literal.to_string()))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node
+{
+    Compare(String, &'static str, Param),
+    Macro(&'static str, Vec<Param>),
+    And(Vec<Query>),
+    Or(Vec<Query>)
+}
+
+/// A typed query for a single [`Request`]. Build it up with [`Query::field`] and [`Macro`],
+/// combine with [`Query::and`] / [`Query::or`], then [`Query::attach`] it to the FullRequest
+#[derive(Debug, Clone)]
+pub struct Query {
+    node: Node
+}
+
+/// The left side of a comparison; finish it with one of the operators
+pub struct Field {
+    name: String
+}
+
+macro_rules! field_op {
+    ($name:ident => $op:literal) => {
+        pub fn $name(self, param: Param) -> Query {
+            Query { node: Node::Compare(self.name, $op, param) }
+        }
+    };
+}
+
+impl Field {
+    field_op!{eq => "="}
+    field_op!{neq => "<>"}
+    field_op!{lt => "<"}
+    field_op!{gt => ">"}
+    field_op!{le => "<="}
+    field_op!{ge => ">="}
+    field_op!{like => "like"}
+    field_op!{is_in => "in"}
+    field_op!{not_in => "not in"}
+}
+
+impl Query {
+    pub fn field(name: &str) -> Field {
+        Field { name: name.to_string() }
+    }
+
+    pub fn and(self, other: Query) -> Query {
+        match self.node {
+            Node::And(mut list) => { list.push(other); Query { node: Node::And(list) } },
+            node => Query { node: Node::And(vec![Query { node }, other]) }
+        }
+    }
+
+    pub fn or(self, other: Query) -> Query {
+        match self.node {
+            Node::Or(mut list) => { list.push(other); Query { node: Node::Or(list) } },
+            node => Query { node: Node::Or(vec![Query { node }, other]) }
+        }
+    }
+
+    /// Every query in the list must match
+    pub fn all<I: IntoIterator<Item = Query>>(queries: I) -> Query {
+        Query { node: Node::And(queries.into_iter().collect()) }
+    }
+
+    /// Any query in the list may match
+    pub fn any<I: IntoIterator<Item = Query>>(queries: I) -> Query {
+        Query { node: Node::Or(queries.into_iter().collect()) }
+    }
+
+    fn write(&self, request: &mut FullRequest, parent_and: Option<bool>) -> Result<String, QueryError> {
+        match &self.node {
+            Node::Compare(field, op, param) => Ok(format!("{} {} {}", field, op, param.write(request)?)),
+            Node::Macro(name, params) => Ok(format!("!{}({})", name,
+                params.iter().map(|p| p.write(request)).collect::<Result<Vec<_>,_>>()?.join(", "))),
+            Node::And(list) | Node::Or(list) => {
+                let is_and = matches!(self.node, Node::And(_));
+                if list.is_empty() {
+                    return Err(QueryError::EmptyGroup);
+                }
+                let joined = list.iter().map(|q| q.write(request, Some(is_and))).collect::<Result<Vec<_>,_>>()?
+                    .join(if is_and { " and " } else { " or " });
+                //Only need parentheses when we're nested inside the OTHER kind of group
+                if list.len() > 1 && parent_and == Some(!is_and) { Ok(format!("({})", joined)) }
+                else { Ok(joined) }
+            }
+        }
+    }
+
+    /// Add every value this query uses to the given request and produce the final query text. Fails if
+    /// a key is reused with a different value, or a result is referenced from a request that doesn't exist.
+    /// Attach queries in the same order you push the requests!
+    pub fn attach(&self, request: &mut FullRequest) -> Result<String, QueryError> {
+        self.write(request, None)
+    }
+}
+
+/// The special query macros understood by the api (the ones that look like !this())
+pub struct Macro;
+
+macro_rules! query_macro {
+    ($name:ident()) => {
+        pub fn $name() -> Query {
+            Query { node: Node::Macro(stringify!($name), Vec::new()) }
+        }
+    };
+    ($name:ident($($param:ident),+)) => {
+        pub fn $name($($param: Param),+) -> Query {
+            Query { node: Node::Macro(stringify!($name), vec![$($param),+]) }
+        }
+    };
+}
+
+impl Macro {
+    query_macro!{notdeleted()}
+    query_macro!{basiccomments()}
+    query_macro!{basichistory()}
+    query_macro!{activebans()}
+    query_macro!{userpage(user)}
+    query_macro!{keywordlike(keyword)}
+    query_macro!{keywordin(keywords)}
+    query_macro!{literaltypein(types)}
+    query_macro!{valuekeyin(keys)}
+    query_macro!{valuelike(key, value)}
+    query_macro!{valuein(keys, values)}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate as contentapi;
+
+    #[test]
+    fn values_are_added_with_the_query() {
+        let mut request = FullRequest::new();
+        let query = Query::field("contentType").eq(Param::value("type", 1)).and(Macro::notdeleted());
+        assert_eq!(query.attach(&mut request).unwrap(), "contentType = @type and !notdeleted()");
+        assert_eq!(request.values.get("type"), Some(&Value::from(1)));
+    }
+
+    #[test]
+    fn groups_only_get_parentheses_inside_the_other_kind() {
+        let mut request = FullRequest::new();
+        let a = || Query::field("a").eq(Param::literal(1));
+        let b = || Query::field("b").eq(Param::literal(2));
+        let c = || Query::field("c").eq(Param::literal(3));
+        assert_eq!(a().and(b()).and(c()).attach(&mut request).unwrap(), "a = {{1}} and b = {{2}} and c = {{3}}");
+        assert_eq!(a().and(b().or(c())).attach(&mut request).unwrap(), "a = {{1}} and (b = {{2}} or c = {{3}})");
+        assert_eq!(Query::any([a().and(b()), c()]).attach(&mut request).unwrap(), "(a = {{1}} and b = {{2}}) or c = {{3}}");
+        assert_eq!(Query::all([a()]).attach(&mut request).unwrap(), "a = {{1}}");
+        assert!(matches!(Query::all([]).attach(&mut request), Err(QueryError::EmptyGroup)));
+    }
+
+    #[test]
+    fn macros_join_their_params() {
+        let mut request = FullRequest::new();
+        let query = Macro::valuelike(Param::value("key", "system"), Param::value("like", "%ptc%"));
+        assert_eq!(query.attach(&mut request).unwrap(), "!valuelike(@key, @like)");
+        assert_eq!(Macro::keywordin(Param::value("keywords", vec!["game"])).attach(&mut request).unwrap(), "!keywordin(@keywords)");
+    }
+
+    #[test]
+    fn keys_can_be_reused_only_with_the_same_value() {
+        let mut request = FullRequest::new();
+        let same = Query::field("a").eq(Param::value("x", 1)).or(Query::field("b").eq(Param::value("x", 1)));
+        assert!(same.attach(&mut request).is_ok());
+        let conflict = Query::field("c").eq(Param::value("x", 2));
+        assert!(matches!(conflict.attach(&mut request), Err(QueryError::ValueConflict(_))));
+        assert!(matches!(Query::field("a").eq(Param::value("bad key", 1)).attach(&mut request), Err(QueryError::BadKey(_))));
+    }
+
+    #[test]
+    fn results_must_come_from_an_earlier_request() {
+        let mut request = FullRequest::new();
+        let query = Query::field("id").is_in(Param::result("content", "createUserId"));
+        assert!(matches!(query.attach(&mut request), Err(QueryError::UnknownResult(_))));
+        request.requests.push(build_request!(RequestType::content));
+        assert_eq!(query.attach(&mut request).unwrap(), "id in @content.createUserId");
+    }
+
+    #[test]
+    fn string_literals_cant_break_out() {
+        let mut request = FullRequest::new();
+        assert_eq!(Query::field("a").eq(Param::literal("plain_word-1.0")).attach(&mut request).unwrap(), "a = {{plain_word-1.0}}");
+        for bad in ["x}} or 1 = {{1", "two words", "@key", "a'b", ""] {
+            let query = Query::field("a").eq(Param::literal(bad));
+            assert!(matches!(query.attach(&mut request), Err(QueryError::BadLiteral(_))), "{} was allowed", bad);
+        }
+        assert!(matches!(Query::field("a").eq(Param::literal(vec![1])).attach(&mut request), Err(QueryError::BadLiteral(_))));
+    }
+}
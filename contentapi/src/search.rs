@@ -1,6 +1,7 @@
 use super::*;
 use endpoints::*;
 use conversion::*;
+use query::*;
 use crate as contentapi;
 
 macro_rules! get_something_by_field {
@@ -51,6 +52,47 @@ impl ApiContext
         get_something_by_field!(self, RequestType::message, Message, "id", id, fields)
     }
 
+    /// All of the current user's watches, newest first. You can only ever see your own, so this is
+    /// empty if nobody is logged in
+    pub async fn get_watches(&self) -> Result<Vec<Watch>, ApiError>
+    {
+        let mut request = FullRequest::new();
+        request.requests.push(build_request!(RequestType::watch, String::from("*"), String::new(), String::from("id_desc")));
+        let result = self.post_request(&request).await?;
+        Ok(cast_result_required::<Watch>(&result, &RequestType::watch.to_string())?)
+    }
+
+    /// The current user's watch on the given content, if they're watching it
+    pub async fn get_watch(&self, content_id: i64) -> Result<Option<Watch>, ApiError>
+    {
+        let mut request = FullRequest::new();
+        let query = Query::field("contentId").eq(Param::value("contentId", content_id)).attach(&mut request)?;
+        request.requests.push(build_request!(RequestType::watch, String::from("*"), query));
+        let result = self.post_request(&request).await?;
+        Ok(cast_result_required::<Watch>(&result, &RequestType::watch.to_string())?.pop())
+    }
+
+    /// Every revision of the given content, newest first. Revisions are the content's activity: the
+    /// edit message and who made the change, but not the content itself (see get_revision)
+    pub async fn get_revisions(&self, content_id: i64) -> Result<Vec<Activity>, ApiError>
+    {
+        let mut request = FullRequest::new();
+        let query = Query::field("contentId").eq(Param::value("contentId", content_id)).attach(&mut request)?;
+        request.requests.push(build_request!(RequestType::activity, String::from("*"), query, String::from("id_desc")));
+        let result = self.post_request(&request).await?;
+        Ok(cast_result_required::<Activity>(&result, &RequestType::activity.to_string())?)
+    }
+
+    /// The current user's variable with the given key, if they've set it
+    pub async fn get_uservariable(&self, key: &str) -> Result<Option<UserVariable>, ApiError>
+    {
+        let mut request = FullRequest::new();
+        let query = Query::field("key").eq(Param::value("key", key)).attach(&mut request)?;
+        request.requests.push(build_request!(RequestType::uservariable, String::from("*"), query));
+        let result = self.post_request(&request).await?;
+        Ok(cast_result_required::<UserVariable>(&result, &RequestType::uservariable.to_string())?.pop())
+    }
+
     pub async fn get_user_by_username(&self, username: &str, fields: &str) -> Result<User, ApiError>
     {
         get_something_by_field!(self, RequestType::user, User, "username", username, fields)
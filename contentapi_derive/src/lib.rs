@@ -0,0 +1,114 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, GenericArgument, Ident, PathArguments, Token, Type};
+
+//The derive for contentapi::conversion::FromRequestResult. Each field must be a Vec<T>, and is
+//filled from the result with the same name as the request that produced it:
+//
+//  #[derive(FromRequestResult)]
+//  struct ThreadData {
+//      #[result("thread")] threads: Vec<Content>,  //Named request
+//      #[result(STICKYKEY, optional)] stickies: Vec<Content>, //Any &str expression works; optional means empty if missing
+//      users: Vec<User>  //No attribute means the default name for the type (unnamed requests are named after their type)
+//  }
+
+struct ResultAttribute {
+    name: Option<Expr>,
+    optional: bool
+}
+
+impl Parse for ResultAttribute {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut attribute = ResultAttribute { name: None, optional: false };
+        while !input.is_empty() {
+            if input.peek(Ident) && input.fork().parse::<Ident>()? == "optional" {
+                input.parse::<Ident>()?;
+                attribute.optional = true;
+            }
+            else if attribute.name.is_none() {
+                attribute.name = Some(input.parse()?);
+            }
+            else {
+                return Err(input.error("expected at most one result name and 'optional'"));
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(attribute)
+    }
+}
+
+/// Pull T out of Vec<T>
+fn vec_item(ty: &Type) -> Option<&Type> {
+    if let Type::Path(path) = ty {
+        let last = path.path.segments.last()?;
+        if last.ident == "Vec" {
+            if let PathArguments::AngleBracketed(args) = &last.arguments {
+                if let Some(GenericArgument::Type(item)) = args.args.first() {
+                    return Some(item);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn derive_fields(input: &DeriveInput) -> syn::Result<Vec<TokenStream2>>
+{
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new(input.span(), "FromRequestResult needs a struct with named fields"))
+        },
+        _ => return Err(syn::Error::new(input.span(), "FromRequestResult only works on structs"))
+    };
+
+    let mut result = Vec::new();
+
+    for field in fields {
+        let ident = field.ident.as_ref().ok_or_else(|| syn::Error::new(field.span(), "Field must be named"))?;
+        let item = vec_item(&field.ty).ok_or_else(|| syn::Error::new(field.ty.span(), "FromRequestResult fields must be Vec<T>"))?;
+
+        let mut attribute = ResultAttribute { name: None, optional: false };
+        for attr in field.attrs.iter().filter(|a| a.path.is_ident("result")) {
+            attribute = attr.parse_args()?;
+        }
+
+        let name = match attribute.name {
+            Some(name) => quote!{ #name },
+            None => quote_spanned!{ field.ty.span() => <#item as ::contentapi::conversion::DefaultResultName>::RESULT_NAME }
+        };
+        let optional = attribute.optional;
+
+        result.push(quote! {
+            #ident: ::contentapi::conversion::extract_result::<#item>(result, #name, #optional)?
+        });
+    }
+
+    Ok(result)
+}
+
+#[proc_macro_derive(FromRequestResult, attributes(result))]
+pub fn derive_from_request_result(input: TokenStream) -> TokenStream
+{
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    match derive_fields(&input) {
+        Ok(fields) => quote! {
+            impl #impl_generics ::contentapi::conversion::FromRequestResult for #name #type_generics #where_clause {
+                fn from_result(result: &::contentapi::RequestResult) -> Result<Self, ::contentapi::conversion::ResultError> {
+                    Ok(Self {
+                        #(#fields),*
+                    })
+                }
+            }
+        }.into(),
+        Err(error) => error.to_compile_error().into()
+    }
+}
@@ -7,7 +7,7 @@ use common::render::layout::*;
 use common::view::*;
 use contentapi::*;
 use contentapi::forms::*;
-use contentapi::conversion::*;
+use contentapi::query::*;
 use maud::{html, Markup, PreEscaped};
 use serde::{Serialize, Deserialize};
 
@@ -25,7 +25,7 @@ pub fn render(data: MainLayoutData, activity: Vec<SbsActivity>, query: ActivityQ
         start: activity.last().and_then(|a| Some(a.date)),
         end: None
     };
-    let newerlink = format!("{}/activity?{}", data.links.http_root, serde_urlencoded::to_string(prev_query).unwrap_or_default());
+    let newerlink = data.links.url(routes::activity().query(&prev_query));
 
     layout(&data, html!{
         (data.links.style("/forpage/activity.css"))
@@ -44,9 +44,9 @@ pub fn render(data: MainLayoutData, activity: Vec<SbsActivity>, query: ActivityQ
                     a."coolbutton" href=(newerlink) { "Newer" }
                 }
                 @else {
-                    span #"newlinkplaceholder" style="display: none" { (newerlink) }
+                    span #"newlinkplaceholder" style="display: none" data-live=(data.links.live_activity()) { (newerlink) }
                 }
-                a."coolbutton" href={(data.links.http_root) "/activity?" (serde_urlencoded::to_string(next_query).unwrap_or_default())} { "Older" }
+                a."coolbutton" href=(data.links.url(routes::activity().query(&next_query))) { "Older" }
             }
         }
     }).into_string()
@@ -106,43 +106,41 @@ pub struct SbsActivity<'a> {
 
 
 /// Returns the constructed query and whether you need to invert the results from your normal order
-pub fn get_activity_request(query: &ActivityQuery, per_page: i32) -> FullRequest //(FullRequest, bool)
+pub fn get_activity_request(query: &ActivityQuery, per_page: i32) -> Result<FullRequest, QueryError> //(FullRequest, bool)
 {
     let mut request = FullRequest::new();
 
     //Note: the allowed list of types for activity is NOT the same as the allowed list of types for
     //displaying as a thread! We don't want to scare people by putting private threads in the activity
-    add_value!(request, "allowed_types", ACTIVITYTYPES); 
-    add_value!(request, "deleted", UserAction::DELETE);
+    let allowed_types = Param::value("allowed_types", ACTIVITYTYPES); 
 
-    let mut user_query = String::new();
-    let mut message_query = String::from("!basiccomments() and !literaltypein(@allowed_types)");
-    let mut activity_query = String::from("!basichistory() and (!literaltypein(@allowed_types) or action = @deleted)");
+    let mut message_query = Macro::basiccomments().and(Macro::literaltypein(allowed_types.clone()));
+    let mut activity_query = Macro::basichistory()
+        .and(Macro::literaltypein(allowed_types).or(Query::field("action").eq(Param::value("deleted", UserAction::DELETE))));
     let mut order_cd = "createDate_desc";
     let mut order_d = "date_desc";
 
-    let dq_part = if let Some(start) = query.start {
+    let date_filter = if let Some(start) = query.start {
         //NOTE: in order for these to be fairly accurate, we have to have millisecond precision
-        add_value!(request, "start", start.to_rfc3339_opts(SecondsFormat::Millis, true));
         //Strictly less than, it's the last date from the previous page
-        "< @start"
+        Some((Field::lt as fn(Field, Param) -> Query, Param::value("start", start.to_rfc3339_opts(SecondsFormat::Millis, true))))
     }
     else if let Some(end) = query.end {
-        add_value!(request, "end", end.to_rfc3339_opts(SecondsFormat::Millis, true));
         order_cd = "id";
         order_d = "date";
         //Strictly greater than, it's the first date from the next page
-        "> @end"
+        Some((Field::gt as fn(Field, Param) -> Query, Param::value("end", end.to_rfc3339_opts(SecondsFormat::Millis, true))))
     }
     else {
-        ""
+        None
     };
 
     // We ARE limiting by date, go ahead and finish constructing the queries
-    if ! dq_part.is_empty() {
-        message_query = format!("{} and createDate {}", message_query, dq_part);
-        activity_query = format!("{} and date {}", activity_query, dq_part);
-        user_query = format!("createDate {}", dq_part);
+    let mut user_query = String::new();
+    if let Some((op, date)) = date_filter {
+        message_query = message_query.and(op(Query::field("createDate"), date.clone()));
+        activity_query = activity_query.and(op(Query::field("date"), date.clone()));
+        user_query = op(Query::field("createDate"), date).attach(&mut request)?;
     }
 
     let mut user_request = build_request!(
@@ -158,7 +156,7 @@ pub fn get_activity_request(query: &ActivityQuery, per_page: i32) -> FullRequest
     let mut message_request = build_request!(
         RequestType::message,
         String::from("*"), //query, order, limit
-        message_query,
+        message_query.attach(&mut request)?,
         order_cd.to_string(),
         per_page
     );
@@ -169,32 +167,47 @@ pub fn get_activity_request(query: &ActivityQuery, per_page: i32) -> FullRequest
     let mut activity_request = build_request!(
         RequestType::activity,
         String::from("*"), //query, order, limit
-        activity_query,
+        activity_query.attach(&mut request)?,
         order_d.to_string(), //Activity has a stupid specially named date field
         per_page
     );
     activity_request.name = Some(String::from(ACTIVITYKEY));
     request.requests.push(activity_request);
 
-
+    let content_query = Query::field("id").is_in(Param::result(POSTACTIVITYKEY, "contentId"))
+        .or(Query::field("id").is_in(Param::result(ACTIVITYKEY, "contentId")));
     let content_request = build_request!(
         RequestType::content,
         String::from("id,name,hash,literalType"), //query, order, limit
-        format!("id in @{}.contentId or id in @{}.contentId", POSTACTIVITYKEY, ACTIVITYKEY)
+        content_query.attach(&mut request)?
     );
     request.requests.push(content_request);
 
+    let user_query = Query::any([
+        Param::result(USERACTIVITYKEY, "id"), 
+        Param::result(POSTACTIVITYKEY, "createUserId"), 
+        Param::result(ACTIVITYKEY, "userId")
+    ].into_iter().map(|p| Query::field("id").is_in(p)));
     let user_request = build_request!(
         RequestType::user,
         String::from("*"), //query, order, limit
-        format!("id in @{}.id or id in @{}.createUserId or id in @{}.userId", USERACTIVITYKEY, POSTACTIVITYKEY, ACTIVITYKEY)
+        user_query.attach(&mut request)?
     );
     request.requests.push(user_request);
 
     //println!("Activity request: {:#?}", &request);
 
-    request
+    Ok(request)
+
+}
 
+#[derive(FromRequestResult)]
+struct ActivityResult {
+    #[result(USERACTIVITYKEY)] user_activity: Vec<User>,
+    #[result(POSTACTIVITYKEY)] post_activity: Vec<Message>,
+    #[result(ACTIVITYKEY)] content_activity: Vec<Activity>,
+    content_raw: Vec<Content>,
+    users_raw: Vec<User>
 }
 
 macro_rules! getdef {
@@ -213,14 +226,10 @@ macro_rules! getdef {
 
 pub async fn get_render(mut context: PageContext, query: ActivityQuery, per_page: i32) -> Result<Response, Error>
 {
-    let request = get_activity_request(&query, per_page);
+    let request = get_activity_request(&query, per_page)?;
     let response = context.api_context.post_request_profiled_opt(&request, "activity-main").await?;
 
-    let user_activity = cast_result_required::<User>(&response, USERACTIVITYKEY)?;
-    let post_activity = cast_result_required::<Message>(&response, POSTACTIVITYKEY)?;
-    let content_activity = cast_result_required::<Activity>(&response, ACTIVITYKEY)?;
-    let content_raw = cast_result_required::<Content>(&response, "content")?;
-    let users_raw = cast_result_required::<User>(&response, "user")?;
+    let ActivityResult { user_activity, post_activity, content_activity, content_raw, users_raw } = ActivityResult::from_result(&response)?;
     let users = map_users(users_raw);
     let content = map_content(content_raw);
 
@@ -248,7 +257,7 @@ pub async fn get_render(mut context: PageContext, query: ActivityQuery, per_page
             user: this_user,
             action_text: String::from("posted on"), 
             activity_href: Some((Some(context.layout_data.links.forum_post(post, &this_content)),String::from(opt_s!(this_content.name)))),
-            extra_text: Some(context.bbcode.parse_profiled_opt(opt_s!(post.text), format!("post-{}", i(&post.id))))
+            extra_text: Some(bbcode_html(&mut context.bbcode, opt_s!(post.text), format!("post-{}", i(&post.id))))
         })
     }
 
@@ -27,7 +27,8 @@ pub struct AdminRenderData
     pub docpage_errors: Option<Vec<String>>,
     pub bans: Vec<UserBan>,
     pub logs: Vec<AdminLog>,
-    pub list_users: HashMap<i64, User>
+    pub list_users: HashMap<i64, User>,
+    pub list_content: HashMap<i64, Content> //Only the content that's the target of some log
 }
 
 impl AdminRenderData
@@ -45,7 +46,8 @@ impl AdminRenderData
             docpage_errors: None,
             bans: Vec::new(),
             logs: Vec::new(),
-            list_users: HashMap::new()
+            list_users: HashMap::new(),
+            list_content: HashMap::new()
         }
     }
 
@@ -94,7 +96,8 @@ pub fn render(render_data: AdminRenderData, search_params: AdminSearchParams) ->
                     p { "Go to the individual user's page to ban them" }
                     hr;
                     h3 { "Registration config:" }
-                    form method="POST" action={(data.links.http_root)"/admin?registrationconfig=1"} {
+                    form method="POST" action=(data.links.url(routes::admin().query(&[("registrationconfig", 1)]))) {
+                        (csrf_input(&data))
                         (errorlist(render_data.registrationconfig_errors))
                         label."inline" for="registrationconfig_enabled"{
                             span{"Allow registration:"} 
@@ -108,21 +111,24 @@ pub fn render(render_data: AdminRenderData, search_params: AdminSearchParams) ->
                     }
                     hr;
                     h3 #"update-frontpage" {"Set frontpage (HTML!):"}
-                    form."editor" method="POST" action={(data.links.http_root)"/admin?frontpage=1#update-frontpage"} {
+                    form."editor" method="POST" action=(data.links.url(routes::admin().query(&[("frontpage", 1)]).fragment("update-frontpage"))) {
+                        (csrf_input(&data))
                         (errorlist(render_data.frontpage_errors))
                         input type="hidden" name="id" value=(frontpage_id);
                         textarea type="text" name="text"{(frontpage_text)}
                         input type="submit" value="Update";
                     }
                     h3 #"update-alert" {"Set alert banner (HTML!):"}
-                    form."editor" method="POST" action={(data.links.http_root)"/admin?alert=1#update-alert"} {
+                    form."editor" method="POST" action=(data.links.url(routes::admin().query(&[("alert", 1)]).fragment("update-alert"))) {
+                        (csrf_input(&data))
                         (errorlist(render_data.banner_errors))
                         input type="hidden" name="id" value=(banner_id);
                         textarea type="text" name="text"{(banner_text)}
                         input type="submit" value="Update";
                     }
                     h3 #"update-docpage" {"Set Documentation preamble (HTML!):"}
-                    form."editor" method="POST" action={(data.links.http_root)"/admin?docscustom=1#update-docpage"} {
+                    form."editor" method="POST" action=(data.links.url(routes::admin().query(&[("docscustom", 1)]).fragment("update-docpage"))) {
+                        (csrf_input(&data))
                         (errorlist(render_data.docpage_errors))
                         input type="hidden" name="id" value=(docpage_id);
                         textarea type="text" name="text"{(docpage_text)}
@@ -135,9 +141,15 @@ pub fn render(render_data: AdminRenderData, search_params: AdminSearchParams) ->
                             label for="adminlogs_logpage" {"Page:"}
                             input."smallinput" #"adminlogs_logpage" name="logpage" value=(search_params.logpage);
                         }
-                        div."inline" {
-                            label for="adminlogs_bans_only" {"Bans only:"}
-                            input #"adminlogs_bans_only" name="bans_only" type="checkbox" value="true" checked[search_params.bans_only];
+                        div."inline smallseparate" #"adminlogs_types" {
+                            span { "Types (none for all):" }
+                            @for logtype in AdminLogType::ALL {
+                                label."inline" for={"adminlogs_type_"(logtype.to_i8())} {
+                                    span { (logtype.name()) }
+                                    input #{"adminlogs_type_"(logtype.to_i8())} name="logtype" type="checkbox" value=(logtype.to_i8()) 
+                                        checked[search_params.logtypes.contains(&logtype.to_i8())];
+                                }
+                            }
                         }
                         input type="submit" value="Update log search";
                     }
@@ -145,13 +157,9 @@ pub fn render(render_data: AdminRenderData, search_params: AdminSearchParams) ->
                         @for log in render_data.logs {
                             div."resultitem smallseparate" {
                                 time."aside" { (d(&log.createDate)) } //Let the javascript take care of the format maybe...
-                                span."logid" { "[" (i(&log.id)) "]" } 
-                                span."logmessage" { (opt_s!(log.text)) }
-                                @if let Some(initiator) = log.initiator {
-                                    @if let Some(user) = render_data.list_users.get(&initiator) {
-                                        a href=(data.links.user(user)) { "(" (user.username) ")" }
-                                    }
-                                }
+                                span."logid" title={"Id: " (i(&log.id)) ", Type: " (log.r#type.unwrap_or_default())} { "[" (i(&log.id)) "-" (log.r#type.unwrap_or_default()) "]" } 
+                                span."logabout" { (log_about(&data, &render_data.list_users, &render_data.list_content, &log)) }
+                                span."aside logmessage" { (opt_s!(log.text)) }
                             }
                         }
                     }
@@ -206,6 +214,39 @@ fn get_result_user(data: &MainLayoutData, all_users: &HashMap<i64, User>, user_i
     }
 }
 
+/// "(initiator) (did something to) (target)" for an admin log, with links to whoever and whatever
+fn log_about(data: &MainLayoutData, all_users: &HashMap<i64, User>, all_content: &HashMap<i64, Content>, log: &AdminLog) -> Markup
+{
+    html!{
+        @if let Some(initiator) = log.initiator {
+            (get_result_user(data, all_users, initiator))
+        }
+        @else {
+            span { "System" }
+        }
+        @match log.log_type() {
+            Some(logtype) => span { " " (logtype.action()) " " },
+            None => span { " did unknown action " (log.r#type.unwrap_or_default()) " to " }
+        }
+        @match log.target() {
+            Some(AdminLogTarget::User(id)) => (get_result_user(data, all_users, id)),
+            Some(AdminLogTarget::Content(id)) => {
+                @if let Some(content) = all_content.get(&id) {
+                    a href=(data.links.forum_thread(content)) { (opt_s!(content.name)) }
+                }
+                @else {
+                    span { "???(" (id) ")" }
+                }
+            },
+            None => {
+                @if let Some(target) = log.target {
+                    span { "(" (target) ")" }
+                }
+            }
+        }
+    }
+}
+
 /// Generate a basic admin render data, since there's so much required to render the admin page now. 
 /// Note that this is the absolute baseline, no errors etc
 async fn get_render_data(mut context: PageContext, search: &AdminSearchParams) -> Result<AdminRenderData, Error>
@@ -213,14 +254,13 @@ async fn get_render_data(mut context: PageContext, search: &AdminSearchParams) -
     //Need to go lookup some data, use the page to skip. We ask for "all" all the time, because we want
     //them to be LOGS, and admins can get to the user page to see if they're banned maybe...
     let mut request = FullRequest::new();
-    //TODO: add all the admin log types to the contentapi crate
-    add_value!(request, "bantypes", vec![12, 13]); //12 = ban_create, 13 = ban_edit
 
-    let query = if search.bans_only {
-        format!("type in @bantypes")
+    let query = if search.logtypes.is_empty() {
+        String::from("")
     }
     else {
-        String::from("")
+        add_value!(request, "logtypes", search.logtypes.clone());
+        String::from("type in @logtypes")
     };
 
     let logs_request = build_request!(
@@ -247,25 +287,36 @@ async fn get_render_data(mut context: PageContext, search: &AdminSearchParams) -
     let users_request = build_request!(
         RequestType::user,
         String::from("*"),
-        format!("id in @ban.createUserId or id in @ban.bannedUserId or id in @adminlog.initiator")
+        format!("id in @ban.createUserId or id in @ban.bannedUserId or id in @adminlog.initiator or id in @adminlog.target")
     );
     request.requests.push(users_request);
 
+    //Targets are just ids, so some of these (and some of the users) won't actually be targets. That's fine
+    let content_request = build_request!(
+        RequestType::content,
+        String::from("id,hash,name"),
+        String::from("id in @adminlog.target")
+    );
+    request.requests.push(content_request);
+
     let result = context.api_context.post_request_profiled_opt(&request, "all_admin_logs").await?;
     let bans = cast_result_required::<UserBan>(&result, "ban")?;
     let logs = cast_result_required::<AdminLog>(&result, "adminlog")?;
     let users = cast_result_required::<User>(&result, "user")?;
+    let content = cast_result_required::<Content>(&result, "content")?;
 
     //TODO: link users to bans and then actually find a way to display them!
 
-    Ok(AdminRenderData::new(
+    let mut render_data = AdminRenderData::new(
         context.layout_data,
         context.api_context.get_registrationconfig().await?,
         get_system_frontpage(&mut context.api_context).await?,
         get_system_alert(&mut context.api_context).await?,
         get_system_docscustom(&mut context.api_context).await?,
         bans, logs, map_users(users)
-    ))
+    );
+    render_data.list_content = content.into_iter().filter_map(|c| c.id.map(|id| (id, c))).collect();
+    Ok(render_data)
 }
 
 async fn get_base_render_data(context: PageContext) -> Result<AdminRenderData, Error>
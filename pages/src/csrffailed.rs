@@ -0,0 +1,17 @@
+use common::*;
+use common::render::layout::*;
+use maud::*;
+
+//Shown when a form comes back without the right csrf token (see common::csrf). That's almost always
+//an old page: a form from before you logged in or out, or from a previous login
+
+pub fn render(data: MainLayoutData) -> String
+{
+    layout(&data, html!{
+        section {
+            h1 { "That form expired" }
+            p { "The page you posted from is out of date (maybe you logged in or out since loading it)" }
+            p."aside" { "Go back, reload the page and try again. Copy anything you wrote first, reloading might lose it!" }
+        }
+    }).into_string()
+}
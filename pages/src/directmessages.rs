@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+
+use contentapi::*;
+use contentapi::conversion::*;
+use contentapi::endpoints::ApiContext;
+use contentapi::query::*;
+
+use common::*;
+use common::constants::SBSPageType;
+use common::forms::*;
+use common::forum::THREADFIELDS;
+use common::pagination::*;
+use common::render::*;
+use common::render::layout::*;
+use common::view::*;
+use maud::*;
+
+//Direct messages are just private threads: content only the participants can read or post in, all
+//under the one "directmessages" parent. Reading and replying happen on the normal thread page; all
+//that's special is starting one and the inbox. Unread tracking piggybacks on watches (opening a
+//conversation watches it, see forum_thread)
+
+/// One conversation in the inbox
+pub struct Conversation {
+    pub thread: Content,
+    pub last: Option<Message>,
+    pub participants: Vec<User>, //Everyone except you
+    pub unread: bool
+}
+
+pub fn render_inbox(data: MainLayoutData, conversations: Vec<Conversation>, users: HashMap<i64, User>, pages: Vec<PagelistItem>) -> String
+{
+    layout(&data, html!{
+        (data.links.style("/forpage/forum.css"))
+        section {
+            h1 { "Messages" }
+            @if data.user.is_some() {
+                div."smallseparate" {
+                    a."coolbutton" #"newmessage" href=(data.links.direct_message_new(None)) { "New message" }
+                }
+            }
+        }
+        section {
+            @if data.user.is_none() {
+                p."error" { "You must be logged in to see your messages!" }
+            }
+            @else if conversations.is_empty() {
+                p."aside pagelist" { "No conversations yet" }
+            }
+            @for (index, conversation) in conversations.iter().enumerate() {
+                div."thread" data-unread[conversation.unread] {
+                    div."threadinfo" {
+                        h3 {
+                            a."flatlink" href=(data.links.forum_thread(&conversation.thread)) { (opt_s!(conversation.thread.name)) }
+                            @if conversation.unread { " " span."unread" { "(new)" } }
+                        }
+                    }
+                    div."foruminfo aside mediumseparate" {
+                        div {
+                            b { "With: " }
+                            @for (pindex, user) in conversation.participants.iter().enumerate() {
+                                a."flatlink" href=(data.links.user(user)) { (user.username) }
+                                @if pindex < conversation.participants.len() - 1 { ", " }
+                            }
+                        }
+                        @if let Some(ref post) = conversation.last {
+                            div {
+                                b { "Last: " }
+                                a."flatlink" href=(data.links.forum_post(post, &conversation.thread)) {
+                                    time datetime=(d(&post.createDate)) { (timeago_o(&post.createDate)) }
+                                }
+                                @if let Some(user) = users.get(&post.createUserId.unwrap_or(0)) {
+                                    " by "
+                                    a."flatlink" href=(data.links.user(user)) { (user.username) }
+                                }
+                            }
+                        }
+                    }
+                    @if let Some(ref post) = conversation.last {
+                        p."aside" { (short_post(post)) }
+                    }
+                }
+                @if index < conversations.len() - 1 {
+                    hr."smaller";
+                }
+            }
+            div."smallseparate pagelist" {
+                @for page in pages {
+                    a."current"[page.current] href={(data.links.direct_messages())"?page="(page.page)} { (page.text) }
+                }
+            }
+        }
+    }).into_string()
+}
+
+//Rendering ALWAYS requires the form, even if it's just an empty one
+pub fn render_compose(data: MainLayoutData, form: DirectMessageForm, errors: Option<Vec<String>>) -> String
+{
+    layout(&data, html!{
+        (data.links.style("/forpage/forum.css"))
+        section {
+            h1 { "New message" }
+            //NOTE: NO ACTION! These kinds of pages always post to themselves
+            form."editor" #"dmedit_form" method="POST" {
+                (csrf_input(&data))
+                (errorlist(errors))
+                label for="dmedit_recipients" { "To:" }
+                input #"dmedit_recipients" type="text" name="recipients" value=(form.recipients) placeholder="Usernames, space separated" required;
+                label for="dmedit_title" { "Subject:" }
+                input #"dmedit_title" type="text" name="title" value=(form.title) required;
+                (post_textbox(PostTextboxConfig::basic(Some("Message:"), "post", &form.post)))
+                p."aside" { "Only the people in the conversation can see it, and anyone in it can reply" }
+                input type="submit" value="Send";
+            }
+        }
+    }).into_string()
+}
+
+const INBOXKEY: &str = "conversations";
+const LASTKEY: &str = "last";
+
+/// Everyone with permissions on a conversation is in it
+fn participant_ids(thread: &Content) -> Vec<i64> {
+    let mut ids: Vec<i64> = thread.permissions.as_ref().map(|p| p.keys().filter_map(|k| k.parse::<i64>().ok()).filter(|id| *id > 0).collect())
+        .unwrap_or_default();
+    ids.sort();
+    ids
+}
+
+pub async fn get_render(context: PageContext, per_page: i32, page: Option<i32>) -> Result<Response, Error>
+{
+    let user = match context.layout_data.user {
+        Some(ref user) => user.clone(),
+        None => return Ok(Response::Render(render_inbox(context.layout_data, Vec::new(), HashMap::new(), Vec::new())))
+    };
+    let page = page.unwrap_or(1).max(1) - 1;
+
+    let mut request = FullRequest::new();
+    let query = Macro::notdeleted().and(Query::field("literalType").eq(Param::value("dmtype", SBSPageType::DIRECTMESSAGE)))
+        .attach(&mut request)?;
+    let mut count_request = build_request!(RequestType::content, String::from("specialCount"), query.clone());
+    count_request.name = Some(String::from("count"));
+    request.requests.push(count_request);
+    let mut thread_request = build_request!(RequestType::content, String::from(THREADFIELDS), query, String::from("lastCommentId_desc"), per_page, per_page * page);
+    thread_request.name = Some(String::from(INBOXKEY));
+    request.requests.push(thread_request);
+    let last_query = Query::field("id").is_in(Param::result(INBOXKEY, "lastCommentId")).attach(&mut request)?;
+    let mut last_request = build_request!(RequestType::message, String::from("*"), last_query);
+    last_request.name = Some(String::from(LASTKEY));
+    request.requests.push(last_request);
+    let watch_query = Query::field("contentId").is_in(Param::result(INBOXKEY, "id")).attach(&mut request)?;
+    request.requests.push(build_request!(RequestType::watch, String::from("*"), watch_query));
+
+    let result = context.api_context.post_request(&request).await?;
+    let count = cast_result_required::<SpecialCount>(&result, "count")?.pop().map(|c| c.specialCount).unwrap_or(0);
+    let threads = cast_result_required::<Content>(&result, INBOXKEY)?;
+    let last = map_messages(cast_result_required::<Message>(&result, LASTKEY)?);
+    let watches = cast_result_required::<Watch>(&result, &RequestType::watch.to_string())?;
+
+    //Participants aren't something the api can chain on (they're permission keys), so that's one more trip
+    let mut user_ids = threads.iter().flat_map(participant_ids).collect::<Vec<i64>>();
+    user_ids.extend(last.values().filter_map(|m| m.createUserId));
+    user_ids.sort();
+    user_ids.dedup();
+    let mut request = FullRequest::new();
+    let user_query = Query::field("id").is_in(Param::value("ids", user_ids)).attach(&mut request)?;
+    request.requests.push(build_request!(RequestType::user, String::from("*"), user_query));
+    let result = context.api_context.post_request(&request).await?;
+    let users = map_users(cast_result_required::<User>(&result, &RequestType::user.to_string())?);
+
+    let conversations = threads.into_iter().map(|thread| {
+        let last = thread.lastCommentId.and_then(|id| last.get(&id)).cloned();
+        let seen = watches.iter().find(|w| w.contentId == thread.id).and_then(|w| w.lastCommentId).unwrap_or(0);
+        //Your own posts are never unread
+        let unread = last.as_ref().map(|m| m.createUserId != Some(user.id) && m.id.unwrap_or(0) > seen).unwrap_or(false);
+        let participants = participant_ids(&thread).into_iter().filter(|id| *id != user.id).filter_map(|id| users.get(&id).cloned()).collect();
+        Conversation { thread, last, participants, unread }
+    }).collect();
+
+    Ok(Response::Render(render_inbox(context.layout_data, conversations, users, get_pagelist(count, per_page, page))))
+}
+
+pub async fn get_compose_render(context: PageContext, to: Option<String>) -> Result<Response, Error>
+{
+    let form = DirectMessageForm { recipients: to.unwrap_or_default(), ..Default::default() };
+    Ok(Response::Render(render_compose(context.layout_data, form, None)))
+}
+
+/// The one content all direct messages live under
+async fn get_directmessages_parent(context: &ApiContext) -> Result<Content, Error>
+{
+    let mut request = FullRequest::new();
+    let query = Query::field("literalType").eq(Param::value("type", SBSPageType::DIRECTMESSAGES)).attach(&mut request)?;
+    request.requests.push(build_request!(RequestType::content, String::from("id,hash,literalType"), query));
+    let result = context.post_request(&request).await?;
+    cast_result_required::<Content>(&result, &RequestType::content.to_string())?.pop()
+        .ok_or_else(|| Error::Other(String::from("Couldn't find the direct messages parent! This is a programming error!")))
+}
+
+/// Craft the content we will be writing through the api for a new conversation between the given users
+pub async fn construct_conversation_content(context: &ApiContext, form: &DirectMessageForm, participants: &[i64]) -> Result<Content, Error>
+{
+    let parent = get_directmessages_parent(context).await?;
+    Ok(Content {
+        name: Some(form.title.clone()),
+        parentId: parent.id,
+        text: Some(String::new()),
+        contentType: Some(ContentType::PAGE),
+        literalType: Some(SBSPageType::DIRECTMESSAGE.to_string()),
+        //No "0" (everyone) entry, that's what makes it private
+        permissions: Some(participants.iter().map(|id| (id.to_string(), String::from("CR"))).collect()),
+        values: Some(make_values! {
+            "markup": "bbcode"
+        }),
+        ..Default::default()
+    })
+}
+
+/// Create the conversation and its first post. Errors are for the user, there's nothing to show if this fails
+async fn post_conversation(context: &ApiContext, user: &User, form: &DirectMessageForm) -> Result<(Content, Message), Vec<String>>
+{
+    let mut errors = Vec::new();
+    let mut participants = vec![user.id];
+    for username in form.recipients.split(|c: char| c.is_whitespace() || c == ',').filter(|u| !u.is_empty()) {
+        match context.get_user_by_username(username, "*").await {
+            Ok(recipient) => if !participants.contains(&recipient.id) { participants.push(recipient.id) },
+            Err(_) => errors.push(format!("No user named '{}'", username))
+        }
+    }
+    if participants.len() < 2 {
+        errors.push(String::from("You need to send this to someone (other than yourself)"));
+    }
+    if form.title.trim().is_empty() {
+        errors.push(String::from("The conversation needs a subject"));
+    }
+    if form.post.trim().is_empty() {
+        errors.push(String::from("You can't send an empty message"));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let content = construct_conversation_content(context, form, &participants).await.map_err(|e| vec![e.to_user_string()])?;
+    let thread = context.post_content(&content, None).await.map_err(|e| vec![e.to_user_string()])?;
+
+    let post_form = PostForm { content_id: thread.id.unwrap_or_default(), post: form.post.clone(), ..Default::default() };
+    let message = crate::forum_edit_post::construct_post_message(context, &post_form).await.map_err(|e| vec![e.to_user_string()])?;
+    let message = context.post_message(&message).await.map_err(|e| vec![e.to_user_string()])?;
+
+    //You've obviously read your own message
+    if let Err(error) = context.post_watch_add(thread.id.unwrap_or_default()).await {
+        tracing::warn!("Couldn't watch new conversation: {}", error.to_verbose_string());
+    }
+
+    Ok((thread, message))
+}
+
+pub async fn post_compose_render(context: PageContext, form: DirectMessageForm) -> Result<Response, Error>
+{
+    let user = context.layout_data.user.clone().ok_or_else(|| Error::Other(String::from("Not logged in!")))?;
+    match post_conversation(&context.api_context, &user, &form).await {
+        Ok((thread, message)) => Ok(Response::Redirect(context.layout_data.links.forum_post(&message, &thread))),
+        Err(errors) => Ok(Response::Render(render_compose(context.layout_data, form, Some(errors))))
+    }
+}
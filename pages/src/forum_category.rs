@@ -104,7 +104,7 @@ async fn build_categories_with_threads(context: &mut ApiContext, categories_clea
     Result<Vec<ForumCategory>, Error> 
 {
     //Next request: get the complicated dataset for each category (this somehow includes comments???)
-    let thread_request = get_thread_request(&categories_cleaned, limit, skip, true); //context.config.default_category_threads, 0);
+    let thread_request = get_thread_request(&categories_cleaned, limit, skip, true)?; //context.config.default_category_threads, 0);
     let thread_result = context.post_request_profiled_opt(&thread_request, "getthreads").await?;
 
     let messages_raw = cast_result_required::<Message>(&thread_result, "message")?;
@@ -34,6 +34,7 @@ pub fn render(data: MainLayoutData, form: PostForm, thread_info: Option<Content>
     let form_element = html! {
         //NOTE: NO ACTION! These kinds of pages always post to themselves
         form."editor" #"postedit_form" method="POST" data-widget=[if widget{Some("true")} else {None}] target=[if widget{Some("_top")} else {None}]{
+            (csrf_input(&data))
             @if !widget {
                 (errorlist(errors))
             }
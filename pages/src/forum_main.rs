@@ -46,7 +46,7 @@ async fn build_categories_with_threads(mut context: ApiContext, categories_clean
     Result<Vec<ForumCategory>, Error> 
 {
     //Next request: get the complicated dataset for each category (this somehow includes comments???)
-    let thread_request = get_thread_request(&categories_cleaned, limit, skip, false); 
+    let thread_request = get_thread_request(&categories_cleaned, limit, skip, false)?; 
     let thread_result = context.post_request_profiled_opt(&thread_request, "threads").await?;
 
     let messages_raw = cast_result_required::<Message>(&thread_result, "message")?;
@@ -65,7 +65,7 @@ pub async fn get_render(mut context: PageContext, order: &Vec<String>, show_thre
 {
     //First request: just get categories
     let request = get_category_request(None, None);
-    let category_result = context.api_context.post_request_profiled_opt(&request, "categories").await?;
+    let category_result = context.api_context.post_request_cached(&request, "categories", common::prefab::CATEGORYCACHETTL, contentapi::cache::CacheScope::Anonymous).await?;
     let mut categories_cleaned = CleanedPreCategory::from_many(cast_result_required::<Content>(&category_result, CATEGORYKEY)?)?;
 
     //Sort the categories by their name AGAINST the default list in the config. So, it should sort the categories
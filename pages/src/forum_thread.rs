@@ -3,6 +3,7 @@ use common::render::*;
 use common::constants::SBSPageType;
 use common::render::layout::*;
 use common::forum::*;
+use common::forms::{ReactionForm, WatchForm};
 use common::pagination::*;
 use common::render::forum::*;
 use common::view::*;
@@ -45,7 +46,7 @@ pub fn render(mut context: PageContext, config: PostsConfig) -> String {
 }
 
 async fn render_thread(mut context: PageContext, pre_request: FullRequest, per_page: i32, 
-    page: Option<i32>) -> Result<Response, Error> 
+    page: Option<i32>, errors: Option<Vec<String>>) -> Result<Response, Error> 
 {
     let mut page = page.unwrap_or(1) - 1; //we assume 1-based pages
 
@@ -56,10 +57,11 @@ async fn render_thread(mut context: PageContext, pre_request: FullRequest, per_p
     let mut categories_cleaned = CleanedPreCategory::from_many(cast_result_required::<Content>(&pre_result, CATEGORYKEY)?)?;
     let mut threads_raw = cast_result_required::<Content>(&pre_result, THREADKEY)?;
     let selected_post = cast_result_safe::<Message>(&pre_result, PREMESSAGEKEY)?.pop();
+    let mut watch = cast_result_safe::<Watch>(&pre_result, WATCHKEY)?.pop();
     if let Some(message_index) = cast_result_safe::<SpecialCount>(&pre_result, PREMESSAGEINDEXKEY)?.pop() {
         //The index is the special count. This means we change the page given. If page wasn't already 0, we warn
         if page != 0 {
-            println!("Page was nonzero ({}) while there was a message index ({})", page, message_index.specialCount);
+            tracing::warn!("Page was nonzero ({}) while there was a message index ({})", page, message_index.specialCount);
         }
         page = message_index.specialCount / per_page;
     }
@@ -72,6 +74,8 @@ async fn render_thread(mut context: PageContext, pre_request: FullRequest, per_p
     let thread_id = thread.id.ok_or(Error::Other(String::from("Thread result did not have id field?!")))?;
     let thread_create_uid = thread.createUserId.ok_or(Error::Other(String::from("Thread result did not have createUserId field!")))?;
     let comment_count = thread.commentCount.ok_or(Error::Other(String::from("Thread result did not have commentCount field!")))?;
+    let last_comment_id = thread.lastCommentId.unwrap_or(0);
+    let thread_type = thread.literalType.clone();
 
     let sequence_start = page * per_page; 
 
@@ -89,16 +93,57 @@ async fn render_thread(mut context: PageContext, pre_request: FullRequest, per_p
     let path = vec![ForumPathItem::root(), ForumPathItem::from_category(&category.category), ForumPathItem::from_thread(&thread)];
     let thread_tags_ids = get_tagged_categories(&thread);
     let mut full_thread = ForumThread::from_content(thread, &messages_raw, &category.stickies)?;
-    full_thread.categories = Some(get_all_categories(&mut context.api_context, Some(thread_tags_ids)).await?);
+    let pagelist = get_pagelist(comment_count as i32, per_page, page);
+    let on_last_page = pagelist.last().map(|p| p.current).unwrap_or(true);
+    let user_id = context.layout_data.user.as_ref().map(|u| u.id);
+    let post_ids = full_thread.posts.iter().filter_map(|p| p.id).collect::<Vec<i64>>();
+
+    //None of these depend on each other, so they all go at once (the clone shares the cache and client)
+    let mut category_context = context.api_context.clone();
+    let api = &context.api_context;
+    let watch_update = async move {
+        //Conversations are always watched, that's how the inbox knows what's unread (see directmessages)
+        if watch.is_none() && user_id.is_some() && thread_type.as_deref() == Some(SBSPageType::DIRECTMESSAGE) {
+            match api.post_watch_add(thread_id).await {
+                Ok(added) => watch = Some(added),
+                Err(error) => tracing::warn!("Couldn't watch conversation {}: {}", thread_id, error.to_verbose_string())
+            }
+        }
+
+        //Looking at the newest posts on a watched thread means you've caught up on it
+        if let Some(ref watch) = watch {
+            if watch.lastCommentId.unwrap_or(0) < last_comment_id && on_last_page {
+                if let Err(error) = api.post_watch_clear(thread_id).await {
+                    tracing::warn!("Couldn't clear watch on thread {}: {}", thread_id, error.to_verbose_string());
+                }
+            }
+        }
+        watch
+    };
+    let reactions = async move {
+        match user_id {
+            Some(user_id) => get_post_reactions(api, user_id, post_ids).await.map(Some),
+            None => Ok(None)
+        }
+    };
+    let (categories, reactions, watch) = futures_util::future::join3(
+        get_all_categories(&mut category_context, Some(thread_tags_ids)), reactions, watch_update).await;
+    full_thread.categories = Some(categories?);
+
     let mut post_config = PostsConfig::thread_mode(
         full_thread,
         map_messages(related_raw),
         map_users(users_raw),
         path,
-        get_pagelist(comment_count as i32, per_page, page),
+        pagelist,
         1 + per_page * page,
         selected_post.and_then(|m| m.id)
     );
+    post_config.watching = watch.is_some();
+    post_config.errors = errors;
+    if let Some(reactions) = reactions? {
+        post_config.reactions = reactions;
+    }
     if post_config.thread.thread.literalType.as_deref() == Some(SBSPageType::DOCUMENTATION) {
         post_config.docs_content = Some(get_all_documentation(&mut context.api_context).await?);
     }
@@ -107,12 +152,48 @@ async fn render_thread(mut context: PageContext, pre_request: FullRequest, per_p
 
 
 
+/// Start or stop watching a thread (or page), then go right back to it
+pub async fn watch_render(context: PageContext, thread_id: i64, form: WatchForm) -> Result<Response, Error>
+{
+    if form.watch {
+        context.api_context.post_watch_add(thread_id).await?;
+    }
+    else {
+        context.api_context.post_watch_delete(thread_id).await?;
+    }
+
+    let thread = context.api_context.get_content_by_id(thread_id, "id,hash").await?;
+    Ok(Response::Redirect(context.layout_data.links.forum_thread(&thread)))
+}
+
+/// Toggle the user's reaction on a post (sending the one they already have takes it off), then go back to the post
+pub async fn react_render(context: PageContext, post_id: i64, form: ReactionForm, per_page: i32) -> Result<Response, Error>
+{
+    let user = context.layout_data.user.as_ref().ok_or_else(|| Error::Other(String::from("You must be logged in to react!")))?;
+    //Someone sent something the buttons can't; show them the post again, with why nothing happened
+    if !constants::REACTIONS.iter().any(|(key, _)| *key == form.reaction) {
+        let errors = Some(vec![format!("Unknown reaction '{}'", form.reaction)]);
+        return match render_thread(context, get_prepost_request(None, Some(post_id), None, None), per_page, None, errors).await? {
+            Response::Render(page) => Ok(Response::RenderWithStatus(page, 400)),
+            other => Ok(other)
+        };
+    }
+
+    let post = context.api_context.get_message_by_id(post_id, "id,contentId").await?;
+    let current = get_post_reactions(&context.api_context, user.id, vec![post_id]).await?.remove(&post_id);
+    let reaction = if current.as_deref() == Some(form.reaction.as_str()) { "" } else { form.reaction.as_str() };
+    context.api_context.post_set_message_engagement(post_id, constants::REACTIONTYPE, reaction).await?;
+
+    let thread = context.api_context.get_content_by_id(post.contentId.unwrap_or_default(), "id,hash").await?;
+    Ok(Response::Redirect(context.layout_data.links.forum_post(&post, &thread)))
+}
+
 /// The normal endpoint for listing a thread
 pub async fn get_hash_render(context: PageContext, hash: String, per_page: i32, page: Option<i32>) -> Result<Response, Error> 
 {
     render_thread(context,
         get_prepost_request(None, None, None, Some(hash)), 
-        per_page, page).await
+        per_page, page, None).await
 }
 
 /// The normal endpoint for pinpointing a post
@@ -120,14 +201,14 @@ pub async fn get_hash_postid_render(context: PageContext, hash: String, post_id:
 {
     render_thread(context,
         get_prepost_request(None, Some(post_id), None, Some(hash)), 
-        per_page, None).await
+        per_page, None, None).await
 }
 
 pub async fn get_ftid_render(context: PageContext, ftid: i64, per_page: i32, page: Option<i32>) -> Result<Response, Error> 
 {
     render_thread(context,
         get_prepost_request(None, None, Some(ftid), None), 
-        per_page, page).await
+        per_page, page, None).await
 }
 
 //Most old links may be to posts directly? idk
@@ -136,5 +217,5 @@ pub async fn get_fpid_render(context: PageContext, fpid: i64, per_page: i32) ->
     //println!("WOW FPID: {}", fpid);
     render_thread(context,
         get_prepost_request(Some(fpid), None, None, None), 
-        per_page, None).await
+        per_page, None, None).await
 }
@@ -13,6 +13,8 @@ pub mod widget_thread;
 pub mod widget_votes;
 pub mod widget_qr;
 pub mod userhome;
+pub mod userhome_watches;
+pub mod directmessages;
 pub mod recover;
 pub mod register;
 pub mod registerconfirm;
@@ -27,8 +29,13 @@ pub mod integrationtest;
 pub mod forum_edit_thread;
 pub mod forum_edit_post;
 pub mod page_edit;
+pub mod page_history;
 pub mod documentation;
 pub mod searchall;
+pub mod tags;
+pub mod live;
+pub mod ratelimited;
+pub mod csrffailed;
 
 //Email errors are weird with their true/false return. 
 macro_rules! email_errors {
@@ -45,7 +52,7 @@ macro_rules! email_errors {
                 },
                 //If there's an error, we re-render the confirmation page with the errors.
                 Err(error) => {
-                    println!("Email endpoint raw error: {}", error.to_verbose_string());
+                    tracing::warn!("Email endpoint raw error: {}", error.to_verbose_string());
                     errors.push(error.to_user_string());
                 } 
             }
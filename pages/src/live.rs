@@ -0,0 +1,100 @@
+use common::*;
+use common::forum::*;
+use common::render::forum::*;
+use common::view::*;
+use contentapi::*;
+use contentapi::live::*;
+use futures_util::{Stream, StreamExt};
+use maud::html;
+
+//Pages that update themselves as things happen. The browser holds open an event stream (server-sent
+//events) and we hold open the api's live websocket on its behalf, as the same user, so nobody sees
+//anything they couldn't already (all of a user's tabs share one socket, see LiveHub). Each event's id is the api's event id: a browser that reconnects
+//sends it back (Last-Event-ID) and picks up right where it left off.
+
+/// One server-sent event for the browser
+pub struct LiveUpdate {
+    pub event: &'static str,
+    pub id: i64,
+    pub data: String
+}
+
+/// New posts on the given thread, rendered exactly as they'd show up on the page ("posts" events)
+pub async fn thread_stream(context: PageContext, thread_id: i64, last_id: Option<i64>) ->
+    Result<impl Stream<Item = LiveUpdate> + Send + 'static, Error>
+{
+    //Make sure the thread exists (and the user can see it) before holding anything open
+    let thread = context.api_context.get_content_by_id(thread_id, "*").await?;
+    let updates = Box::pin(context.api_context.live_updates(last_id));
+
+    Ok(futures_util::stream::unfold((context, thread, updates), |(mut context, thread, mut updates)| async move {
+        loop {
+            let data = match updates.next().await? {
+                Ok(data) => data,
+                Err(error) => {
+                    tracing::warn!("Live thread stream ended: {}", error.to_verbose_string());
+                    return None;
+                }
+            };
+            match render_new_posts(&mut context, &thread, &data).await {
+                Ok(Some(html)) => return Some((LiveUpdate { event: "posts", id: data.lastId, data: html }, (context, thread, updates))),
+                Ok(None) => {},
+                Err(error) => tracing::warn!("Couldn't render live posts: {:?}", error)
+            }
+        }
+    }))
+}
+
+async fn render_new_posts(context: &mut PageContext, thread: &Content, data: &LiveData) -> Result<Option<String>, Error>
+{
+    let created = data.events_of(LiveEventType::MESSAGE)
+        .filter(|e| e.action == UserAction::CREATE)
+        .map(|e| e.refId)
+        .collect::<Vec<i64>>();
+    let posts = data.objects_of::<Message>(LiveEventType::MESSAGE, "message")?.into_iter()
+        .filter(|m| m.contentId == thread.id && m.module.is_none() && created.contains(&m.id.unwrap_or_default()))
+        .collect::<Vec<Message>>();
+
+    if posts.is_empty() {
+        return Ok(None);
+    }
+
+    //Replies show what they're replying to, which is almost never in the push itself
+    let mut related = map_messages(Vec::new());
+    for reply in posts.iter().filter_map(get_replydata) {
+        if let std::collections::hash_map::Entry::Vacant(entry) = related.entry(reply.direct) {
+            if let Ok(message) = context.api_context.get_message_by_id(reply.direct, "*").await {
+                entry.insert(message);
+            }
+        }
+    }
+
+    let users = map_users(data.objects_of::<User>(LiveEventType::MESSAGE, "user")?);
+    let mut config = PostsConfig::reply_mode(ForumThread::from_content(thread.clone(), &Vec::new(), &Vec::new())?, related, users, None);
+    config.render_controls = true;
+    config.render_reply_link = true;
+
+    Ok(Some(html! {
+        @for post in &posts {
+            hr."smaller";
+            (post_item(&context.layout_data, &mut context.bbcode, &config, post, None))
+        }
+    }.into_string()))
+}
+
+/// How many new things there are for the activity page ("activity" events, the data is the count)
+pub fn activity_stream(context: PageContext, last_id: Option<i64>) -> impl Stream<Item = LiveUpdate> + Send + 'static
+{
+    context.api_context.live_updates(last_id)
+        .take_while(|data| {
+            if let Err(error) = data {
+                tracing::warn!("Live activity stream ended: {}", error.to_verbose_string());
+            }
+            futures_util::future::ready(data.is_ok())
+        })
+        .filter_map(|data| futures_util::future::ready(data.ok().and_then(|data| {
+            let count = data.events_of(LiveEventType::MESSAGE).filter(|e| e.action == UserAction::CREATE).count() +
+                data.events_of(LiveEventType::ACTIVITY).count();
+            if count > 0 { Some(LiveUpdate { event: "activity", id: data.lastId, data: count.to_string() }) } else { None }
+        })))
+}
@@ -13,7 +13,8 @@ pub fn render(data: MainLayoutData, login_errors: Option<Vec<String>>, recover_e
     layout(&data, html!{
         section {
             h1{"Login"}
-            form method="POST" action={(data.links.http_root)"/login"} {
+            form method="POST" action=(data.links.url(routes::login())) {
+                (csrf_input(&data))
                 (errorlist(login_errors))
                 label for="login_username"{"Username:"}
                 input #"login_username" type="text" required="" name="username";
@@ -28,7 +29,8 @@ pub fn render(data: MainLayoutData, login_errors: Option<Vec<String>>, recover_e
             hr;
             h2{"Password expired / forgotten?"}
             p.""{"Send an email with a temporary recovery code, which you can use to reset your password"}
-            form method="POST" action={(data.links.http_root)"/login?recover=1"} {
+            form method="POST" action=(data.links.url(routes::login().query(&[("recover", 1)]))) {
+                (csrf_input(&data))
                 (errorlist(recover_errors))
                 label for="recover_email" {"Email"}
                 input #"recover_email" type="email" name="email" required="" value=[email];
@@ -36,17 +38,17 @@ pub fn render(data: MainLayoutData, login_errors: Option<Vec<String>>, recover_e
             }
             p."aside"{
                 "Already have the recovery code? Go to the " 
-                a href={(data.links.http_root)"/recover"} {"recovery page"}
+                a href=(data.links.url(routes::recover())) {"recovery page"}
                 "."
             }
             hr;
             h2{"New to SmileBASIC Source?"}
             /* TODO: remove this when you're done! */
             //p."error" { "WARNING: ACCOUNT CREATION WILL GET RESET, THIS IS STILL A TEST WEBSITE!" }
-            p { a href={(data.links.http_root)"/register"}{"Register here"} }
+            p { a href=(data.links.url(routes::register())){"Register here"} }
             p."aside" { 
                 "If you already registered and need to enter the confirmation code, go to the " 
-                a href={(data.links.http_root)"/register/confirm"}{ "confirmation page" }
+                a href=(data.links.url(routes::register_confirm())){ "confirmation page" }
                 "."
             }
         }
@@ -84,9 +86,9 @@ pub async fn post_login_render(context: PageContext, login: &contentapi::forms::
     (Response, Option<String>)
 {
     match context.api_context.post_login(login).await {
-        Ok(token) => (Response::Redirect(String::from("/userhome")), Some(token)),
+        Ok(token) => (Response::Redirect(routes::userhome().to_string()), Some(token)),
         Err(error) => {
-            println!("Login raw error: {}", error.to_verbose_string());
+            tracing::info!("Login raw error: {}", error.to_verbose_string());
             (Response::Render(render(context.layout_data, Some(vec![error.to_user_string()]), None, None)), None)
         }
     }
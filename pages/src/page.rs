@@ -44,13 +44,11 @@ pub async fn get_pid_redirect(mut context: PageContext, query: PageQuery) -> Res
 
     let page = pages.pop().ok_or(Error::NotFound(String::from("Could not find page!")))?;
 
-    let mut url = format!("/forum/thread/{}", opt_s!(&page.hash));
+    let route = match messages.pop() {
+        Some(message @ Message { id: Some(id), .. }) => 
+            routes::forum_post(opt_s!(&page.hash), id).fragment(&LinkConfig::forum_post_id(&message)),
+        _ => routes::forum_thread(opt_s!(&page.hash))
+    };
 
-    if let Some(message) = messages.pop() {
-        if let Some(id) = message.id {
-            url = format!("{}/{}{}", url, id, LinkConfig::forum_post_hash(&message));
-        }
-    }
-
-    Ok(Response::Redirect(url))
+    Ok(Response::Redirect(route.to_string()))
 }
\ No newline at end of file
@@ -52,6 +52,7 @@ pub fn render(data: MainLayoutData, form: PageForm, mode: Option<String>, all_ca
                 h1 { (title) }
                 //NOTE: NO ACTION! These kinds of pages always post to themselves
                 form."editor" #"pageedit_form" data-mode=(real_mode) data-noupgrade method="POST" {
+                    (csrf_input(&data))
                     (errorlist(errors))
                     input #"pageedit_id" type="hidden" name="id" value=(form.id);
                     input #"pageedit_subtype" type="hidden" name="subtype" value=(form.subtype);
@@ -374,7 +375,7 @@ pub async fn construct_post_content_full(context: &mut ApiContext, form: &PageFo
         if let Some(ref mut perms) = fullpage.main.permissions {
             match get_documentation_group(context).await { 
                 Ok(docsuser) => { perms.insert(docsuser.id.to_string(), "CRUD".to_string()); },
-                Err(error) => { println!("Couldn't find docsgroup user!! This is bad: {}", error.to_verbose_string()); }
+                Err(error) => { tracing::error!("Couldn't find docsgroup user!! This is bad: {}", error.to_verbose_string()); }
             }
         }
     }
@@ -405,7 +406,7 @@ pub async fn post_render(mut context: PageContext, form: PageForm) ->
                         if let Some(ref mut ptc_page) = fullpage.ptc {
                             ptc_page.parentId = posted_page.id; //Make sure it's pointing to the right place
                             match context.api_context.post_content(&ptc_page, None).await { 
-                                Ok(p) => { println!("Wrote PTC page: {}", i(&p.id)); }, //might do something more later idk
+                                Ok(p) => { tracing::info!("Wrote PTC page: {}", i(&p.id)); }, //might do something more later idk
                                 Err(e) => { errors.push(e.to_user_string()); }
                             }
                         }
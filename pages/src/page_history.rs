@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use contentapi::*;
+use contentapi::conversion::*;
+use contentapi::query::*;
+
+use common::*;
+use common::diff::*;
+use common::render::*;
+use common::render::layout::*;
+use common::view::*;
+use maud::*;
+use serde::{Deserialize, Serialize};
+
+//Every edit to a page (or thread) is a revision, and the api keeps the content as of each one.
+//This lists them and diffs the text between any two; admins can also put an old one back
+
+/// Which two revisions to compare. Nothing means no comparison
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct HistoryQuery {
+    pub old: Option<i64>,
+    pub new: Option<i64>
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct RestoreForm {
+    pub revision: i64
+}
+
+/// Two revisions to show the difference between
+pub struct RevisionDiff {
+    pub old: Content,
+    pub new: Content,
+    pub old_id: i64,
+    pub new_id: i64
+}
+
+fn action_text(action: Option<i8>) -> &'static str {
+    match action {
+        Some(UserAction::CREATE) => "Created",
+        Some(UserAction::DELETE) => "Deleted",
+        _ => "Edited"
+    }
+}
+
+pub fn render(data: MainLayoutData, content: Content, revisions: Vec<Activity>, users: HashMap<i64, User>,
+    diff: Option<RevisionDiff>, errors: Option<Vec<String>>) -> String
+{
+    let history_link = data.links.page_history(&content);
+    //Default to comparing the newest with the one before it
+    let checked_new = diff.as_ref().map(|d| d.new_id).or_else(|| revisions.first().and_then(|r| r.id));
+    let checked_old = diff.as_ref().map(|d| d.old_id).or_else(|| revisions.get(1).and_then(|r| r.id));
+    let is_admin = data.user.as_ref().map(|u| u.admin).unwrap_or(false);
+
+    layout(&data, html!{
+        (data.links.style("/forpage/history.css"))
+        section {
+            h1 { "History: " (opt_s!(content.name)) }
+            a."flatlink" href=(data.links.forum_thread(&content)) { "Back to " (opt_s!(content.name)) }
+            (errorlist(errors))
+        }
+        @if let Some(diff) = diff {
+            section #"revisiondiff" {
+                h2 { "Revision " (diff.old_id) " → " (diff.new_id) }
+                @if diff.old.name != diff.new.name {
+                    p."aside" { "Renamed from " b { (opt_s!(diff.old.name)) } " to " b { (opt_s!(diff.new.name)) } }
+                }
+                @let lines = diff_lines(opt_s!(diff.old.text), opt_s!(diff.new.text));
+                @if lines.iter().all(|l| matches!(l, DiffLine::Same(_))) {
+                    p."aside" { "The text didn't change" }
+                }
+                @else {
+                    pre."diff" {
+                        @for line in &lines {
+                            @match line {
+                                DiffLine::Same(text) => div."same" { "  " (text) },
+                                DiffLine::Removed(text) => del { "- " (text) },
+                                DiffLine::Added(text) => ins { "+ " (text) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        section {
+            form method="GET" action=(history_link) {
+                table #"revisions" {
+                    tr { th { "Old" } th { "New" } th { "When" } th { "Who" } th { "What" } th { "Message" } }
+                    @for revision in &revisions {
+                        tr {
+                            td { input type="radio" name="old" value=(i(&revision.id)) checked[revision.id == checked_old]; }
+                            td { input type="radio" name="new" value=(i(&revision.id)) checked[revision.id == checked_new]; }
+                            td { time datetime=(d(&revision.date)) { (timeago_o(&revision.date)) } }
+                            td {
+                                @if let Some(user) = users.get(&revision.userId.unwrap_or(0)) {
+                                    a."flatlink" href=(data.links.user(user)) { (user.username) }
+                                }
+                            }
+                            td { (action_text(revision.action)) }
+                            td."aside" { (opt_s!(revision.message)) }
+                        }
+                    }
+                }
+                @if revisions.len() > 1 {
+                    input type="submit" value="Compare";
+                }
+            }
+        }
+        @if is_admin && revisions.len() > 1 {
+            section {
+                //NOTE: NO ACTION! Restoring posts to the history page itself
+                form."smallseparate" #"restorerevision" method="POST" {
+                    (csrf_input(&data))
+                    label for="restore_revision" { "Restore revision:" }
+                    select #"restore_revision" name="revision" {
+                        @for revision in revisions.iter().skip(1) {
+                            option value=(i(&revision.id)) { (i(&revision.id)) " (" (timeago_o(&revision.date)) ")" }
+                        }
+                    }
+                    input type="submit" value="Restore";
+                }
+            }
+        }
+    }).into_string()
+}
+
+async fn get_revision_users(context: &PageContext, revisions: &[Activity]) -> Result<HashMap<i64, User>, Error>
+{
+    let mut request = FullRequest::new();
+    let query = Query::field("id").is_in(Param::value("ids", revisions.iter().filter_map(|r| r.userId).collect::<Vec<i64>>()))
+        .attach(&mut request)?;
+    request.requests.push(build_request!(RequestType::user, String::from("*"), query));
+    let result = context.api_context.post_request(&request).await?;
+    Ok(map_users(cast_result_required::<User>(&result, &RequestType::user.to_string())?))
+}
+
+async fn render_history(context: PageContext, content: Content, query: HistoryQuery, errors: Option<Vec<String>>) -> Result<Response, Error>
+{
+    let content_id = content.id.ok_or_else(|| Error::Other(String::from("Content had no id!")))?;
+    let revisions = context.api_context.get_revisions(content_id).await?;
+    let users = get_revision_users(&context, &revisions).await?;
+
+    let diff = match (query.old, query.new) {
+        (Some(old_id), Some(new_id)) => {
+            //Only revisions of THIS content, the ids are global
+            if !revisions.iter().any(|r| r.id == Some(old_id)) || !revisions.iter().any(|r| r.id == Some(new_id)) {
+                return Err(Error::NotFound(String::from("Those revisions aren't part of this page's history!")));
+            }
+            //Older always on the left, no matter which way they were picked
+            let (old_id, new_id) = (old_id.min(new_id), old_id.max(new_id));
+            Some(RevisionDiff {
+                old: context.api_context.get_revision(old_id).await?,
+                new: context.api_context.get_revision(new_id).await?,
+                old_id, new_id
+            })
+        },
+        _ => None
+    };
+
+    Ok(Response::Render(render(context.layout_data, content, revisions, users, diff, errors)))
+}
+
+pub async fn get_render(context: PageContext, hash: String, query: HistoryQuery) -> Result<Response, Error>
+{
+    let content = context.api_context.get_content_by_hash(&hash, "*").await?;
+    render_history(context, content, query, None).await
+}
+
+/// Put the content back the way it was at some revision (as a new revision). Admins only. Only what you'd
+/// edit comes back; permissions and such stay as they are now
+pub async fn post_restore_render(context: PageContext, hash: String, form: RestoreForm) -> Result<Response, Error>
+{
+    let mut content = context.api_context.get_content_by_hash(&hash, "*").await?;
+    if !context.layout_data.user.as_ref().map(|u| u.admin).unwrap_or(false) {
+        return render_history(context, content, HistoryQuery::default(), Some(vec![String::from("Only admins can restore revisions!")])).await;
+    }
+
+    let content_id = content.id.unwrap_or_default();
+    if !context.api_context.get_revisions(content_id).await?.iter().any(|r| r.id == Some(form.revision)) {
+        return Err(Error::NotFound(String::from("That revision isn't part of this page's history!")));
+    }
+
+    let revision = context.api_context.get_revision(form.revision).await?;
+    content.name = revision.name;
+    content.text = revision.text;
+    content.description = revision.description;
+    content.values = revision.values;
+    content.keywords = revision.keywords;
+    context.api_context.post_content(&content, Some(format!("Restored revision {}", form.revision))).await?;
+
+    Ok(Response::Redirect(context.layout_data.links.page_history(&content)))
+}
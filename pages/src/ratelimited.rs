@@ -0,0 +1,22 @@
+use common::*;
+use common::render::layout::*;
+use maud::*;
+
+//Shown instead of whatever was posted when someone goes over a rate limit (see the main crate's
+//ratelimit module). Nothing here asks the api for anything; being rate limited should be cheap
+
+pub fn render(data: MainLayoutData, retry_after: u64) -> String
+{
+    layout(&data, html!{
+        section {
+            h1 { "Slow down!" }
+            p { "You're doing that too often. Wait a bit and try again" }
+            p."aside" { 
+                "You can try again in " 
+                @if retry_after < 60 { (retry_after) " second" @if retry_after != 1 { "s" } }
+                @else { (retry_after.div_ceil(60)) " minute" @if retry_after > 60 { "s" } }
+            }
+            p."aside" { "If you were posting something, your browser's back button should still have it" }
+        }
+    }).into_string()
+}
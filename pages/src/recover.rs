@@ -10,7 +10,8 @@ pub fn render(data: MainLayoutData, errors: Option<Vec<String>>, email: Option<S
         section {
             h1 {"Recover account"}
             p {"You'll receive an email shortly with the code to recover your account!"}
-            form method="POST" action={(data.links.http_root)"/recover"} { //Must be exact!
+            form method="POST" action=(data.links.url(routes::recover())) { //Must be exact!
+                (csrf_input(&data))
                 (errorlist(errors))
                 label for="recover_email"{"Email (to identify account):"}
                 input #"recover_email" type="email" required="" name="currentEmail" value=[&email];
@@ -37,7 +38,7 @@ pub async fn post_render(context: PageContext, sensitive: &UserSensitive) -> (Re
 {
     match context.api_context.post_usersensitive(sensitive).await {
         Ok(token) => {
-            (Response::Redirect(String::from("/userhome")), Some(token))
+            (Response::Redirect(routes::userhome().to_string()), Some(token))
         },
         Err(error) => {
             (Response::Render(render(context.layout_data, Some(vec![error.to_user_string()]), Some(sensitive.currentEmail.clone()))), None)
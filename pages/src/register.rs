@@ -8,7 +8,8 @@ pub fn render(data: MainLayoutData, errors: Option<Vec<String>>, username: Optio
     layout(&data, html!{
         section {
             h1 { "Register" }
-            form #"register_form" method="POST" action={(data.links.http_root)"/register"} {
+            form #"register_form" method="POST" action=(data.links.url(routes::register())) {
+                (csrf_input(&data))
                 (errorlist(errors))
                 label for="register_username" {"Username:"}
                 input #"register_username" type="text" name="username" value=[username];
@@ -18,7 +19,7 @@ pub fn render(data: MainLayoutData, errors: Option<Vec<String>>, username: Optio
                 input #"register_email" type="email" name="email" value=[email];
                 p."aside" { 
                     "We only use your email for account recovery and verification. All code is open source, see: "
-                    a href={(data.links.http_root)"/about"} {"About"}
+                    a href=(data.links.url(routes::about())) {"About"}
                 }
                 input type="submit" value="Register";
             }
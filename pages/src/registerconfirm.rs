@@ -27,7 +27,8 @@ pub fn render(data: MainLayoutData, confirm_errors: Option<Vec<String>>, email_e
                 p {"If you've already registered, you'll receive a confirmation email shortly. Re-enter your email and the "
                    "confirmation code here to complete your registration." }
             }
-            form #"complete_form" method="POST" action={(data.links.http_root)"/register/confirm"} {
+            form #"complete_form" method="POST" action=(data.links.url(routes::register_confirm())) {
+                (csrf_input(&data))
                 (errorlist(confirm_errors))
                 label for="complete_email" {"Email:"}
                 input #"complete_email" type="text" name="email" required="" value=[&email];
@@ -40,7 +41,8 @@ pub fn render(data: MainLayoutData, confirm_errors: Option<Vec<String>>, email_e
             p {"The email comes from smilebasicsource@gmail.com. It may be in your spam folder, and it may take up to a couple minutes "
                "to get through email filters. If you didn't receive it, you can send it again here:" }
             //Post to the special endpoint still under the "confirm" umbrella, so errors will be rendered "on the same page"
-            form #"resend_form" method="POST" action={(data.links.http_root)"/register/confirm?resend=1"} {
+            form #"resend_form" method="POST" action=(data.links.url(routes::register_confirm().query(&[("resend", 1)]))) {
+                (csrf_input(&data))
                 (errorlist(email_errors))
                 @if resend_success {
                     p."success"{"Email resent!"}
@@ -64,7 +66,7 @@ pub async fn post_render(context: PageContext, confirm: &contentapi::forms::Regi
     {
         //If confirmation is successful, we get a token back. We login and redirect to the userhome page
         Ok(token) => {
-            (Response::Redirect(String::from("/userhome")), Some(token))
+            (Response::Redirect(routes::userhome().to_string()), Some(token))
         },
         //If there's an error, we re-render the confirmation page with the errors.
         Err(error) => {
@@ -126,7 +126,7 @@ fn page_navigation(data: &MainLayoutData, search: &PageSearch) -> Markup {
 
 pub async fn get_render(context: PageContext, search: PageSearch, per_page: i32) -> Result<Response, Error> 
 {
-    let request = get_search_request(&search, per_page);
+    let request = get_search_request(&search, per_page)?;
 
     let result = context.api_context.post_request(&request).await?;
     //println!("RESULT: {:#?}", &result);
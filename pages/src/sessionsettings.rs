@@ -1,18 +1,32 @@
-
 use common::render::*;
 use common::*;
+use common::prefab::*;
 use common::render::layout::*;
 use maud::*;
+use serde::Deserialize;
+
+//Settings always go in a cookie, but logged in users can also sync them to their account (a uservariable)
+//so they follow them to other devices. Synced settings win over the cookie, see RequestContext::generate
+
+/// Which way to sync on save: true saves to the account, false stops syncing. Nothing just keeps doing
+/// whatever it was doing before
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct SyncQuery {
+    pub sync: Option<bool>
+}
 
-pub fn render(data: MainLayoutData, errors: Option<Vec<String>>) -> String 
+pub fn render(data: MainLayoutData, synced: bool, errors: Option<Vec<String>>) -> String 
 {
     let settings = &data.user_config;
+    let action = data.links.url(routes::sessionsettings());
     //Need to split category search into parts 
     //let search_system = match &search.system { Some(system) => system, None => };
     layout(&data, html!{
         section {
             h1 { "Local session settings" }
-            form method="POST" action={(data.links.http_root)"/sessionsettings"} {
+            form method="POST" action=(action) {
+                (csrf_input(&data))
                 (errorlist(errors))
                 div."inline smallseparate" {
                     label for="settings-theme" {"Theme:"}
@@ -30,9 +44,63 @@ pub fn render(data: MainLayoutData, errors: Option<Vec<String>>) -> String
                     label for="settings-toppaginationposts" { "Top Pagination (posts): " }
                     input."" #"settings-toppaginationposts" type="checkbox" name="toppagination_posts" checked[settings.toppagination_posts] value="true";
                 }
-                input type="submit" value="Save";
+                div."inline smallseparate" {
+                    input type="submit" value="Save";
+                    @if data.user.is_some() {
+                        @if synced {
+                            input #"settings-unsync" type="submit" formaction={(action)"?sync=false"} value="Stop syncing";
+                        }
+                        @else {
+                            input #"settings-sync" type="submit" formaction={(action)"?sync=true"} value="Save to account";
+                        }
+                    }
+                }
+            }
+            @if synced {
+                p."aside" #"settings-synced" { "These settings are synced to your account, so they follow you to any device you log in on" }
+            }
+            @else {
+                p."aside" { "These settings are persisted in a cookie and only available on this device" }
             }
-            p."aside" { "These settings are persisted in a cookie and only available on this device" }
         }
     }).into_string()
 }
+
+/// Whether the current user has settings synced to their account. Never for anonymous users
+async fn is_synced(context: &PageContext) -> Result<bool, Error>
+{
+    match context.layout_data.user {
+        Some(_) => Ok(get_account_config(&context.api_context).await?.is_some()),
+        None => Ok(false)
+    }
+}
+
+pub async fn get_render(context: PageContext) -> Result<Response, Error>
+{
+    let synced = is_synced(&context).await?;
+    Ok(Response::Render(render(context.layout_data, synced, None)))
+}
+
+/// Save the settings to the account as asked (the cookie is the caller's job, it always gets saved).
+/// Any problems just show up on the page, the settings still apply to this device
+pub async fn post_render(mut context: PageContext, form: UserConfig, sync: Option<bool>, mut errors: Vec<String>) -> Response
+{
+    let mut synced = false;
+    if context.layout_data.user.is_some() {
+        let synced_before = is_synced(&context).await.unwrap_or_else(|error| { errors.push(error.to_user_string()); false });
+        let result = match sync.unwrap_or(synced_before) {
+            true => set_account_config(&context.api_context, &form).await.map(|_| true),
+            false if synced_before => delete_account_config(&context.api_context).await.map(|_| false).map_err(|e| e.into()),
+            false => Ok(false)
+        };
+        match result {
+            Ok(result) => synced = result,
+            Err(error) => {
+                errors.push(error.to_user_string());
+                synced = synced_before;
+            }
+        }
+    }
+    context.layout_data.user_config = form;
+    Response::Render(render(context.layout_data, synced, if errors.is_empty() { None } else { Some(errors) }))
+}
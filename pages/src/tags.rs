@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use contentapi::*;
+use contentapi::conversion::*;
+use contentapi::query::*;
+
+use common::*;
+use common::constants::*;
+use common::render::*;
+use common::render::layout::*;
+use common::render::submissions::*;
+use common::view::*;
+use maud::*;
+
+//Browsing by keyword ("tags" to people). The cloud comes straight from the api's keyword aggregate,
+//the tag pages are just a content search for the keyword
+
+const TAGCLOUDLIMIT: i32 = 200;
+const TAGPAGELIMIT: i32 = 200;
+
+pub fn render(data: MainLayoutData, keywords: Vec<KeywordAggregate>) -> String
+{
+    //Sizes go by log of the count, otherwise one popular tag makes everything else tiny
+    let max = keywords.iter().filter_map(|k| k.count).max().unwrap_or(1).max(2) as f64;
+    let size = |count: i64| 0.9 + 1.3 * (count.max(1) as f64).ln() / max.ln();
+    layout(&data, html!{
+        section {
+            h1 { "Tags" }
+            @if keywords.is_empty() {
+                p."aside" { "Nothing has been tagged yet" }
+            }
+            div."tagcloud" {
+                @for keyword in &keywords {
+                    @let count = keyword.count.unwrap_or(0);
+                    a."flatlink" href=(data.links.tag(opt_s!(keyword.value))) title=(format!("{} tagged", count))
+                        style=(format!("font-size: {:.2}em", size(count))) { (opt_s!(keyword.value)) }
+                }
+            }
+        }
+    }).into_string()
+}
+
+pub fn render_tag(data: MainLayoutData, keyword: &str, pages: Vec<Content>, threads: Vec<Content>, users: HashMap<i64, User>) -> String
+{
+    layout(&data, html!{
+        (data.links.style("/forpage/forum.css"))
+        section {
+            h1 { "#" (keyword) }
+            a."flatlink" href=(data.links.tags()) { "All tags" }
+        }
+        @if pages.is_empty() && threads.is_empty() {
+            section {
+                p."aside" { "Nothing is tagged " b { (keyword) } }
+            }
+        }
+        @if !pages.is_empty() {
+            section."results" {
+                h2 { "Programs and resources" }
+                div."cardslist" {
+                    @for page in &pages {
+                        (page_card(&data.links, page, &users))
+                    }
+                }
+            }
+        }
+        @if !threads.is_empty() {
+            section {
+                h2 { "Threads" }
+                @for (index, thread) in threads.iter().enumerate() {
+                    div."thread" {
+                        div."threadinfo" {
+                            h3 { a."flatlink" href=(data.links.forum_thread(thread)) { (opt_s!(thread.name)) } }
+                        }
+                        div."foruminfo aside mediumseparate" {
+                            @if let Some(user) = users.get(&thread.createUserId.unwrap_or(0)) {
+                                div { b { "By: " } a."flatlink" href=(data.links.user(user)) { (user.username) } }
+                            }
+                            div { b { "Posts: " } (i(&thread.commentCount)) }
+                            div {
+                                b { "Created: " }
+                                time datetime=(d(&thread.createDate)) { (timeago_o(&thread.createDate)) }
+                            }
+                        }
+                    }
+                    @if index < threads.len() - 1 {
+                        hr."smaller";
+                    }
+                }
+            }
+        }
+    }).into_string()
+}
+
+pub async fn get_render(context: PageContext) -> Result<Response, Error>
+{
+    let mut request = FullRequest::new();
+    request.requests.push(build_request!(RequestType::keyword_aggregate, String::from("*"), String::new(), String::from("count_desc"), TAGCLOUDLIMIT));
+    let result = context.api_context.post_request(&request).await?;
+    let mut keywords = cast_result_required::<KeywordAggregate>(&result, &RequestType::keyword_aggregate.to_string())?;
+    keywords.sort_by_key(|k| k.value.as_ref().map(|v| v.to_lowercase()));
+    Ok(Response::Render(render(context.layout_data, keywords)))
+}
+
+pub async fn get_tag_render(context: PageContext, keyword: String) -> Result<Response, Error>
+{
+    let mut request = FullRequest::new();
+    let content_query = Macro::notdeleted()
+        .and(Query::field("literalType").is_in(Param::value("types", TAGTYPES)))
+        .and(Macro::keywordin(Param::value("keyword", vec![keyword.clone()])));
+    let content_request = build_request!(
+        RequestType::content,
+        String::from("*"),
+        content_query.attach(&mut request)?,
+        String::from("id_desc"),
+        TAGPAGELIMIT
+    );
+    request.requests.push(content_request);
+    let user_request = build_request!(
+        RequestType::user,
+        String::from("*"),
+        Query::field("id").is_in(Param::result("content", "createUserId")).attach(&mut request)?
+    );
+    request.requests.push(user_request);
+    let result = context.api_context.post_request(&request).await?;
+    let content = cast_result_required::<Content>(&result, &RequestType::content.to_string())?;
+    let users = map_users(cast_result_required::<User>(&result, &RequestType::user.to_string())?);
+
+    let (threads, pages) = content.into_iter().partition(|c| c.literalType.as_deref() == Some(SBSPageType::FORUMTHREAD));
+    Ok(Response::Render(render_tag(context.layout_data, &keyword, pages, threads, users)))
+}
@@ -57,10 +57,15 @@ pub fn render(data: MainLayoutData, mut bbcode: BBCode, user_package: UserPackag
                         @if user.admin {
                             div #"adminicon" title="Administrator / Moderator" { "🌟" }
                         }
+                        @if let Some(current_user) = &data.user {
+                            @if current_user.id != user.id {
+                                a."flatlink" #"messagelink" href=(data.links.direct_message_new(Some(&user))) { "Send message" }
+                            }
+                        }
                     }
                     //If the user has no bio, that's ok! 
                     @if let Some(userpage) = user_package.userpage {
-                        div."content" #"userbio" { (PreEscaped(bbcode.parse_profiled_opt(opt_s!(userpage.text), format!("userpage-{}", i(&userpage.id))))) } 
+                        div."content" #"userbio" { (PreEscaped(bbcode_html(&mut bbcode, opt_s!(userpage.text), format!("userpage-{}", i(&userpage.id))))) } 
                     }
                 }
             }
@@ -95,6 +100,7 @@ pub fn render(data: MainLayoutData, mut bbcode: BBCode, user_package: UserPackag
                     h3 { "Ban controls:" }
                     @if let Some(ban) = &user_package.ban {
                         form #"unbanform" method="POST" action={(data.links.user(&user))"?unban=1#admincontrols"} {
+                            (csrf_input(&data))
                             (errorlist(unban_errors))
                             p."error" { 
                                 "ALREADY" 
@@ -112,6 +118,7 @@ pub fn render(data: MainLayoutData, mut bbcode: BBCode, user_package: UserPackag
                     }
                     @else {
                         form #"banform" method="POST" action={(data.links.user(&user))"?ban=1#admincontrols"} {
+                            (csrf_input(&data))
                             (errorlist(ban_errors))
                             label for="ban_hours"{"Ban hours:"}
                             input #"ban_hours" type="text" required="" name="hours" placeholder="0 = 100 years";
@@ -128,6 +135,7 @@ pub fn render(data: MainLayoutData, mut bbcode: BBCode, user_package: UserPackag
                     hr;
                     h3 #"update-user" {"Update user info:"}
                     form method="POST" action={(data.links.user(&user))"?userinfo=1#update-user"} { 
+                        (csrf_input(&data))
                         p."aside" { 
                             "You can override a user's username and avatar here. Note that they'll be able to change it back "
                             "by default unless you 'full ban' them. So, this form is only useful when full banning a user."
@@ -146,6 +154,14 @@ pub fn render(data: MainLayoutData, mut bbcode: BBCode, user_package: UserPackag
 }
 
 
+#[derive(FromRequestResult)]
+struct UserResult {
+    users_raw: Vec<User>,
+    content_raw: Vec<Content>,
+    bans_raw: Vec<UserBan>,
+    #[result("badges")] badges_raw: Vec<Content>
+}
+
 pub async fn get_render_internal(mut context: PageContext, username: String, ban_errors: Option<Vec<String>>,
     unban_errors: Option<Vec<String>>, userset_errors: Option<Vec<String>>) -> Result<Response, Error>
 {
@@ -190,10 +206,7 @@ pub async fn get_render_internal(mut context: PageContext, username: String, ban
     let result = context.api_context.post_request(&request).await?;
 
     //Now try to parse two things out of it
-    let mut users_raw = contentapi::conversion::cast_result_required::<User>(&result, "user")?;
-    let mut content_raw = contentapi::conversion::cast_result_required::<Content>(&result, "content")?;
-    let mut bans_raw = contentapi::conversion::cast_result_required::<UserBan>(&result, "ban")?;
-    let badges_raw = contentapi::conversion::cast_result_required::<Content>(&result, "badges")?;
+    let UserResult { mut users_raw, mut content_raw, mut bans_raw, badges_raw } = UserResult::from_result(&result)?;
 
     let user = users_raw.pop();
     
@@ -204,7 +217,7 @@ pub async fn get_render_internal(mut context: PageContext, username: String, ban
         search.subtype = None; //Don't worry about the type, show ALL submissions
         search.user_id = Some(user.id);
         search.order = "id_desc".to_string(); //not sure...
-        let request = get_search_request(&search, 0); //Just ask for as much as possible
+        let request = get_search_request(&search, 0)?; //Just ask for as much as possible
 
         let result = context.api_context.post_request(&request).await?;
         let docsgroup = get_documentation_group(&mut context.api_context).await?;
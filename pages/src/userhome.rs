@@ -1,5 +1,6 @@
 use common::*;
 use common::forms::BasicPage;
+use common::forms::FileUploadForm;
 use common::forms::UserUpdate;
 use common::render::*;
 use common::render::layout::*;
@@ -39,11 +40,15 @@ pub fn render(data: MainLayoutData, private: Option<contentapi::UserPrivate>, us
                         }
                         //Might turn this into a collbutton
                         div."smallseparate" #"userlinks" {
-                            a."flatlink" #"publiclink" href={(data.links.http_root)"/user/"(user.username)} {"User page"}
+                            a."flatlink" #"publiclink" href=(data.links.user(user)) {"User page"}
                             span{"/"}
                             a."flatlink" #"privatethreadslink" href=(data.links.forum_category_unsafe("private-threads")) {"Private Threads"}
                             span{"/"}
-                            a."flatlink" #"logoutlink" href={(data.links.http_root)"/logout"} {"Logout"}
+                            a."flatlink" #"messageslink" href=(data.links.direct_messages()) {"Messages"}
+                            span{"/"}
+                            a."flatlink" #"watcheslink" href=(data.links.userhome_watches()) {"Watches"}
+                            span{"/"}
+                            a."flatlink" #"logoutlink" href=(data.links.url(routes::logout())) {"Logout"}
                         }
                     }
                 }
@@ -51,7 +56,8 @@ pub fn render(data: MainLayoutData, private: Option<contentapi::UserPrivate>, us
                 h3 #"update-userbio" {"Update bio:"}
                 // "Editor" forms are special forms which are meant for editing content instead of whatever other 
                 //  forms do.
-                form."editor" method="POST" action={(data.links.http_root)"/userhome?bio=1#update-userbio"} {
+                form."editor" method="POST" action=(data.links.url(routes::userhome().query(&[("bio", 1)]).fragment("update-userbio"))) {
+                    (csrf_input(&data))
                     (errorlist(bio_errors))
                     input type="hidden" name="id" value=(bio_id);
                     textarea #"update_userbio" type="text" name="text"{(bio_text)}
@@ -59,7 +65,8 @@ pub fn render(data: MainLayoutData, private: Option<contentapi::UserPrivate>, us
                 }
                 hr;
                 h3 #"update-user"{"Update info:"}
-                form method="POST" action={(data.links.http_root)"/userhome#update-user"} { 
+                form method="POST" action=(data.links.url(routes::userhome().fragment("update-user"))) { 
+                    (csrf_input(&data))
                     (errorlist(update_errors))
                     label for="update_username"{"Username:"}
                     input #"update_username" type="text" name="username" value=(user.username);
@@ -68,6 +75,12 @@ pub fn render(data: MainLayoutData, private: Option<contentapi::UserPrivate>, us
                     p."aside"{"Copy key/hash from image browser below"}
                     input type="submit" value="Update";
                 }
+                form method="POST" action=(data.links.url(routes::userhome().query(&[("avatar", 1)]).fragment("update-user"))) enctype="multipart/form-data" {
+                    (csrf_input(&data))
+                    label for="upload_avatar"{"Or upload a new avatar:"}
+                    input #"upload_avatar" type="file" name="file" accept="image/*" required;
+                    input type="submit" value="Upload";
+                }
             }
             section {
                 iframe."imagebrowser" src={(data.links.imagebrowser())} {}
@@ -75,7 +88,8 @@ pub fn render(data: MainLayoutData, private: Option<contentapi::UserPrivate>, us
             section {
                 h3 #"update-sensitive"{"Update sensitive info"}
                 p{"Only set the fields you want to change, except 'current password', which is required"}
-                form method="POST" action={(data.links.http_root)"/userhome?sensitive=1#update-sensitive"} autocomplete="off" {
+                form method="POST" action=(data.links.url(routes::userhome().query(&[("sensitive", 1)]).fragment("update-sensitive"))) autocomplete="off" {
+                    (csrf_input(&data))
                     (errorlist(private_errors))
                     //<label for="sensitive_username">New Username:</label>
                     //<input id="sensitve_username" type="text" autocomplete="new-password" name="username" value="">
@@ -153,6 +167,30 @@ pub async fn post_info_render(mut context: PageContext, update: UserUpdate) -> R
     get_render_internal(context, Some(errors), None, None).await 
 }
 
+/// Upload a new avatar and set it on the user in one go. Like post_info_render, any error is an error
+/// from rendering userhome, the upload errors are shown on the page
+pub async fn post_avatar_render(mut context: PageContext, upload: FileUploadForm) -> Result<Response, Error>
+{
+    let mut errors = Vec::new();
+    if let Some(mut current_user) = context.layout_data.user.clone() {
+        match prefab::upload_image(&context.api_context, upload).await {
+            Ok(content) => {
+                current_user.avatar = content.hash.unwrap_or_default();
+                match context.api_context.post_userupdate(&current_user).await { 
+                    Ok(new_user) => context.layout_data.user = Some(new_user),
+                    Err(error) => errors.push(error.to_user_string())
+                }
+            },
+            Err(error) => errors.push(error.to_user_string())
+        }
+    }
+    else {
+        errors.push(String::from("Couldn't pull user data, are you still logged in?"));
+    }
+
+    get_render_internal(context, Some(errors), None, None).await 
+}
+
 /// Complicated function for posting a simple user bio yeesh
 pub async fn post_userbio(data: &MainLayoutData, context: &ApiContext, form: &BasicPage) -> Result<Content, Error>
 {
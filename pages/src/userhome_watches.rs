@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use contentapi::*;
+use contentapi::conversion::*;
+use contentapi::query::*;
+
+use common::*;
+use common::forum::THREADFIELDS;
+use common::render::*;
+use common::render::forum::watch_toggle;
+use common::render::layout::*;
+use maud::*;
+
+/// A watched thread (or page) and how many new posts it has since the user last looked
+pub struct WatchedThread {
+    pub thread: Content,
+    pub watch: Watch,
+    pub unread: i32
+}
+
+pub fn render(data: MainLayoutData, watched: Vec<WatchedThread>) -> String
+{
+    layout(&data, html!{
+        (data.links.style("/forpage/forum.css"))
+        section {
+            h1 { "Watches" }
+            p."aside" { "Threads and pages you're following. Looking at the newest posts on one marks it as read" }
+        }
+        section {
+            @if data.user.is_none() {
+                p."error" { "You must be logged in to see your watches!" }
+            }
+            @else if watched.is_empty() {
+                p."aside pagelist" { "You're not watching anything (use the watch button at the bottom of a thread)" }
+            }
+            @for (index, item) in watched.iter().enumerate() {
+                div."thread" data-unread=(item.unread) {
+                    div."threadinfo" {
+                        h3 { a."flatlink" href=(data.links.forum_thread(&item.thread)) { (opt_s!(item.thread.name)) } }
+                    }
+                    div."foruminfo aside mediumseparate" {
+                        div { b { "Unread: " } (item.unread) }
+                        div { b { "Posts: " } (i(&item.thread.commentCount)) }
+                        div {
+                            b { "Last activity: " }
+                            time datetime=(d(&item.thread.lastActionDate)) { (timeago_o(&item.thread.lastActionDate)) }
+                        }
+                        (watch_toggle(&data, &item.thread, true))
+                    }
+                }
+                @if index < watched.len() - 1 {
+                    hr."smaller";
+                }
+            }
+        }
+    }).into_string()
+}
+
+fn unread_key(content_id: i64) -> String { format!("unread_{}", content_id) }
+
+/// Everything the current user is watching, the ones with new posts first. Nothing if not logged in
+pub async fn get_watched(context: &PageContext) -> Result<Vec<WatchedThread>, Error>
+{
+    if context.layout_data.user.is_none() {
+        return Ok(Vec::new());
+    }
+
+    let watches = context.api_context.get_watches().await?;
+    if watches.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut request = FullRequest::new();
+    let query = Macro::notdeleted()
+        .and(Query::field("id").is_in(Param::value("ids", watches.iter().filter_map(|w| w.contentId).collect::<Vec<i64>>())))
+        .attach(&mut request)?;
+    request.requests.push(build_request!(RequestType::content, String::from(THREADFIELDS), query));
+    let result = context.api_context.post_request(&request).await?;
+    let threads = cast_result_required::<Content>(&result, &RequestType::content.to_string())?;
+
+    //Only threads with anything newer than what the watch has seen need their unread posts counted
+    let mut watched: Vec<WatchedThread> = Vec::new();
+    let mut request = FullRequest::new();
+    for watch in watches {
+        if let Some(thread) = threads.iter().find(|t| t.id.is_some() && t.id == watch.contentId) {
+            let thread_id = thread.id.unwrap_or_default();
+            let last_read = watch.lastCommentId.unwrap_or(0);
+            if thread.lastCommentId.unwrap_or(0) > last_read {
+                let query = Macro::basiccomments()
+                    .and(Query::field("contentId").eq(Param::value(&format!("cid_{}", thread_id), thread_id)))
+                    .and(Query::field("id").gt(Param::value(&format!("last_{}", thread_id), last_read)))
+                    .attach(&mut request)?;
+                let mut count_request = build_request!(RequestType::message, String::from("specialCount"), query);
+                count_request.name = Some(unread_key(thread_id));
+                request.requests.push(count_request);
+            }
+            watched.push(WatchedThread { thread: thread.clone(), watch, unread: 0 });
+        }
+    }
+
+    if !request.requests.is_empty() {
+        let result = context.api_context.post_request(&request).await?;
+        let counts = watched.iter().filter_map(|w| w.thread.id).filter_map(|id| {
+            cast_result_safe::<SpecialCount>(&result, &unread_key(id)).ok()
+                .and_then(|mut c| c.pop())
+                .map(|c| (id, c.specialCount))
+        }).collect::<HashMap<i64, i32>>();
+        for item in watched.iter_mut() {
+            item.unread = counts.get(&item.thread.id.unwrap_or_default()).copied().unwrap_or(0);
+        }
+    }
+
+    //Unread first, then whatever happened most recently
+    watched.sort_by(|a, b| (b.unread > 0).cmp(&(a.unread > 0))
+        .then_with(|| b.thread.lastActionDate.cmp(&a.thread.lastActionDate)));
+
+    Ok(watched)
+}
+
+pub async fn get_render(context: PageContext) -> Result<Response, Error>
+{
+    let watched = get_watched(&context).await?;
+    Ok(Response::Render(render(context.layout_data, watched)))
+}
@@ -1,6 +1,7 @@
 use bbscope::BBCode;
 
 use common::*;
+use common::render::*;
 use common::render::layout::*;
 use maud::*;
 
@@ -12,10 +13,10 @@ pub fn render(data: MainLayoutData, bbcode: &BBCode, text: Option<String>) -> St
         (data.links.style("/forpage/bbcodepreview.css"))
     }, html! {
         @if let Some(text) = text {
-            div."content bbcode" { (PreEscaped(bbcode.parse(&text))) }
+            div."content bbcode" { (PreEscaped(bbcode_html_unprofiled(bbcode, &text))) }
         }
         @else {
-            form method="POST" action={(data.links.http_root)"/widget/bbcodepreview"} {
+            form method="POST" action=(data.links.url(routes::widget_bbcodepreview())) {
                 textarea placeholder="Enter text to test here" name="text"{}
                 input type="submit" value="Test";
             }
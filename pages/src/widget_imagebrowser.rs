@@ -1,4 +1,5 @@
 use common::*;
+use common::forms::FileUploadForm;
 use common::render::*;
 use common::render::layout::*;
 
@@ -24,15 +25,13 @@ pub fn render(data: MainLayoutData, search: Search, images: Vec<Image>, previews
         @if let Some(_user) = &data.user {
             h3 { "Upload file:" }
             //This doesn't need an action since it's self posting but just in case...
-            form method="POST" action=(data.links.file_upload_root) enctype="multipart/form-data" {
+            form method="POST" action=(data.links.imagebrowser()) enctype="multipart/form-data" {
+                (csrf_input(&data))
                 (errorlist(errors))
                 @if let Some(error) = &search.error {
                     (errorlist(Some(vec![error.clone()])))
                 }
-                input #"fileinput" type="file" name="file" class="largeinput" accept="image/*";
-                input type="hidden" name="token" value=[&data.user_token];
-                input type="hidden" name="errorUrl" value={(data.current())"?error={{error}}"};
-                input type="hidden" name="successUrl" value={(data.current())"?preview={{hash}}"};
+                input #"fileinput" type="file" name="file" class="largeinput" accept="image/*" required;
                 input type="submit" value="Upload";
             }
             hr;
@@ -221,4 +220,21 @@ pub async fn query_render(context: PageContext, search: Search, per_page: i32) -
     Ok(Response::Render(render(context.layout_data, search, 
         images.into_iter().map(|i| i.into()).collect(), 
         previews.into_iter().map(|i| i.into()).collect(), None)))
-}
\ No newline at end of file
+}
+
+/// Upload the posted image, then show the browser with the new image in the preview (or the errors)
+pub async fn post_render(context: PageContext, mut search: Search, upload: FileUploadForm, per_page: i32) -> Result<Response,Error> {
+    let mut errors = Vec::new();
+    match prefab::upload_image(&context.api_context, upload).await {
+        Ok(content) => search.preview = content.hash,
+        Err(error) => errors.push(error.to_user_string())
+    }
+
+    let result = imagebrowser_request(&context.api_context, &search, per_page).await?;
+    let images = conversion::cast_result_safe::<Content>(&result, "content")?;
+    let previews = conversion::cast_result_safe::<Content>(&result, "preview")?;
+
+    Ok(Response::Render(render(context.layout_data, search, 
+        images.into_iter().map(|i| i.into()).collect(), 
+        previews.into_iter().map(|i| i.into()).collect(), Some(errors))))
+}
@@ -129,7 +129,7 @@ pub fn generate_qr_svgs(ptc_file: PtcData, config : QrConfig) -> Result<Vec<Stri
     let raw = general_purpose::STANDARD.decode(&ptc_file.base64).map_err(|e| Error::Other(e.to_string()))?;
     let rawlength = raw.len() as u32;
     let ftype = &raw[8..12]; //The 4 char code that describes the type
-    println!("raw length: {}\nftype: {}", rawlength, std::str::from_utf8(ftype).unwrap());
+    tracing::debug!("raw length: {}\nftype: {}", rawlength, std::str::from_utf8(ftype).unwrap());
 
     let mut enc = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
     enc.write_all(&raw).map_err(|e| Error::Other(e.to_string()))?;
@@ -146,7 +146,7 @@ pub fn generate_qr_svgs(ptc_file: PtcData, config : QrConfig) -> Result<Vec<Stri
 
     let resultmd5 : [u8;16] = md5::compute(&result).into();
     let qrcount = (result.len() as f32 / config.bytes_per_qr as f32).ceil() as u8;
-    println!("QR codes: {}", qrcount);
+    tracing::debug!("QR codes: {}", qrcount);
 
     let mut qrcodes : Vec<String> = Vec::new();
     for qrnum in 0u8..qrcount 
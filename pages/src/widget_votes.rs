@@ -2,6 +2,7 @@ use common::*;
 use common::prefab::*;
 use common::constants::{DOWNVOTE, UPVOTE, VOTETYPE};
 use common::forms::VoteForm;
+use common::render::csrf_input;
 use common::render::layout::*;
 use maud::*;
 
@@ -41,6 +42,7 @@ pub fn render(data: MainLayoutData, content: Content, user_vote: Option<ContentE
         div #"main" {
             @if data.user.is_some() {
                 form."nospacing" #"downvote" method="POST" action=(data.current()) { 
+                    (csrf_input(&data))
                     input type="hidden" name="vote" value=(DOWNVOTE);
                     input."notheme" type="submit" value="-" title="Downvote" data-current[real_vote==DOWNVOTE];
                 }
@@ -51,6 +53,7 @@ pub fn render(data: MainLayoutData, content: Content, user_vote: Option<ContentE
             }
             @if data.user.is_some() {
                 form."nospacing" #"upvote" method="POST" action=(data.current()) { 
+                    (csrf_input(&data))
                     input type="hidden" name="vote" value=(UPVOTE);
                     input."notheme" type="submit" value="+" title="Upvote" data-current[real_vote==UPVOTE];
                 }
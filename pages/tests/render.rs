@@ -0,0 +1,554 @@
+//Render whole pages against the mock api. These don't check the markup in any detail, just
+//that the pages make requests the api understands and that the expected data shows up.
+
+use bbscope::BBCode;
+use common::*;
+use common::forms::PageSearch;
+use contentapi::*;
+use contentapi::endpoints::ApiContext;
+use contentapi::mock::*;
+
+fn start_api() -> MockApi {
+    let data = MockData::from_json(include_str!("fixtures/basic.json")).expect("Fixture should parse");
+    MockApi::start(data).expect("Mock api should start")
+}
+
+async fn get_context(api: &MockApi, username: Option<&str>) -> PageContext
+{
+    let token = username.map(|u| api.login(u).expect("Fixture user should exist"));
+
+    #[cfg(feature = "profiling")]
+    let profiler = onestop::OneList::<onestop::OneDuration>::new();
+
+    #[cfg(feature = "profiling")]
+    let api_context = ApiContext::new_with_profiler(api.url(), token.clone(), profiler.clone());
+
+    #[cfg(not(feature = "profiling"))]
+    let api_context = ApiContext::new(api.url(), token.clone());
+
+    let csrf_token = common::csrf::csrf_token(Some(token.as_deref().unwrap_or("test-presession")));
+    let layout_data = MainLayoutData {
+        links: LinkConfig {
+            http_root: String::from("http://localhost"),
+            static_root: String::from("http://localhost/static"),
+            resource_root: String::from("http://localhost/static/resources"),
+            file_root: String::from("http://localhost/api/raw"),
+            file_upload_root: String::from("http://localhost/api/low"),
+            cache_bust: String::from("test")
+        },
+        user_config: UserConfig::default(),
+        current_path: String::from("/"),
+        override_nav_path: None,
+        user: api_context.get_me_safe().await,
+        user_token: token,
+        about_api: api_context.get_about().await.expect("Mock api should report status"),
+        raw_alert: None,
+        csrf_token,
+        csp_nonce: String::from("testnonce"),
+
+        #[cfg(feature = "profiling")]
+        profiler
+    };
+
+    PageContext { layout_data, api_context, bbcode: BBCode::default().unwrap() }
+}
+
+fn rendered(response: Result<Response, Error>) -> String {
+    match response {
+        Ok(Response::Render(page)) => page,
+        other => panic!("Expected a rendered page, got {:?}", other)
+    }
+}
+
+#[tokio::test]
+async fn forum_main_lists_threads()
+{
+    let api = start_api();
+    let page = rendered(pages::forum_main::get_render(get_context(&api, None).await, &vec![String::from("General")], 5).await);
+    assert!(page.contains("General"));
+    assert!(page.contains("Hello world"));
+    //Stickies only show up on the category page
+    assert!(!page.contains("Forum rules"));
+}
+
+#[tokio::test]
+async fn forum_category_shows_stickies()
+{
+    let api = start_api();
+    let page = rendered(pages::forum_category::get_hash_render(get_context(&api, None).await, String::from("general"), 20, None).await);
+    assert!(page.contains("Hello world"));
+    assert!(page.contains("Forum rules"));
+}
+
+#[tokio::test]
+async fn forum_thread_shows_posts()
+{
+    let api = start_api();
+    let page = rendered(pages::forum_thread::get_hash_render(get_context(&api, None).await, String::from("hello-world"), 20, None).await);
+    assert!(page.contains("First post!"));
+    assert!(page.contains("<b>Welcome</b>"));
+}
+
+#[tokio::test]
+async fn search_filters_pages()
+{
+    let api = start_api();
+    let search = PageSearch { search: Some(String::from("cool")), ..Default::default() };
+    let page = rendered(pages::search::get_render(get_context(&api, None).await, search, 20).await);
+    assert!(page.contains("Cool Game"));
+    assert!(!page.contains("Font Pack"));
+}
+
+#[tokio::test]
+async fn activity_shows_everything()
+{
+    let api = start_api();
+    let page = rendered(pages::activity::get_render(get_context(&api, None).await, Default::default(), 20).await);
+    assert!(page.contains("Cool Game"));
+    assert!(page.contains("Font Pack"));
+    assert!(page.contains("created an account"));
+}
+
+#[tokio::test]
+async fn user_page_shows_submissions()
+{
+    let api = start_api();
+    let page = rendered(pages::user::get_render(get_context(&api, None).await, String::from("alice")).await);
+    assert!(page.contains("<b>Hi</b>"));
+    assert!(page.contains("Cool Game"));
+    assert!(!page.contains("Font Pack"));
+}
+
+#[tokio::test]
+async fn posted_messages_show_up()
+{
+    let api = start_api();
+    let context = get_context(&api, Some("bob")).await;
+    let message = Message {
+        contentId: Some(10),
+        text: Some(String::from("A brand new post")),
+        ..Default::default()
+    };
+    context.api_context.post_message(&message).await.expect("Bob should be able to post");
+
+    let page = rendered(pages::forum_thread::get_hash_render(get_context(&api, None).await, String::from("hello-world"), 20, None).await);
+    assert!(page.contains("A brand new post"));
+
+    //Nobody can post in the rules
+    let message = Message { contentId: Some(11), text: Some(String::from("no")), ..Default::default() };
+    assert!(context.api_context.post_message(&message).await.is_err());
+}
+
+#[tokio::test]
+async fn stream_all_pages_through_everything()
+{
+    use futures_util::{StreamExt, TryStreamExt};
+
+    let api = start_api();
+    let mut context = get_context(&api, None).await;
+
+    //Page size of 1 so every message is its own page
+    let mut request = FullRequest::new();
+    request.requests.push(build_request!(RequestType::message, String::from("*"), String::from("!notdeleted()"), String::from("id"), 1));
+    request.requests.push(build_request!(RequestType::user, String::from("*"), String::from("id in @message.createUserId")));
+
+    let pages: Vec<RequestResult> = context.api_context.stream_pages(request.clone(), "test", None).try_collect().await.expect("Pages should stream");
+    assert_eq!(pages.len(), 5); //The last one is the short (empty) page
+    for page in &pages {
+        let messages = conversion::cast_result_required::<Message>(page, "message").unwrap();
+        let users = conversion::cast_result_required::<User>(page, "user").unwrap();
+        assert_eq!(users.iter().map(|u| Some(u.id)).collect::<Vec<_>>(), messages.iter().map(|m| m.createUserId).collect::<Vec<_>>());
+    }
+
+    let messages: Vec<Message> = context.api_context.stream_all(request).try_collect().await.expect("Messages should stream");
+    assert_eq!(messages.iter().map(|m| m.id).collect::<Vec<_>>(), vec![Some(100), Some(101), Some(102), Some(103)]);
+
+    //Errors come through as a single item, then the stream ends
+    let mut request = FullRequest::new();
+    request.requests.push(build_request!(RequestType::message, String::from("*"), String::from("nonsense =")));
+    let results: Vec<Result<Message, _>> = context.api_context.stream_all(request).collect().await;
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+}
+
+fn upload_form(filename: &str, mime: &str, chunks: Vec<&'static [u8]>) -> common::forms::FileUploadForm {
+    let data = futures_util::stream::iter(chunks.into_iter().map(|c| Ok(contentapi::endpoints::Bytes::from_static(c))));
+    common::forms::FileUploadForm { filename: String::from(filename), mime: String::from(mime), data: Box::pin(data) }
+}
+
+#[tokio::test]
+async fn uploaded_avatar_is_set()
+{
+    let api = start_api();
+    let page = rendered(pages::userhome::post_avatar_render(get_context(&api, Some("alice")).await, 
+        upload_form("me.png", "image/png", vec![b"\x89PNG", b"\r\n\x1a\n--not a boundary--", b"\r\nmore"])).await);
+
+    let alice = api.objects("user").into_iter().find(|u| u["id"] == 2).unwrap();
+    let avatar = alice["avatar"].as_str().unwrap().to_string();
+    assert!(page.contains(&avatar));
+    assert_eq!(api.file(&avatar).unwrap(), b"\x89PNG\r\n\x1a\n--not a boundary--\r\nmore");
+
+    //Not an image, not allowed
+    let page = rendered(pages::userhome::post_avatar_render(get_context(&api, Some("alice")).await, 
+        upload_form("virus.exe", "application/octet-stream", vec![b"MZ"])).await);
+    assert!(page.contains("Only images"));
+
+    //Saying it's an image doesn't make it one
+    let page = rendered(pages::userhome::post_avatar_render(get_context(&api, Some("alice")).await, 
+        upload_form("virus.png", "image/png", vec![b"MZ\x90\x00", b"\x03\x00"])).await);
+    assert!(page.contains("Only images"));
+}
+
+#[tokio::test]
+async fn base64_upload_works()
+{
+    let api = start_api();
+    let context = get_context(&api, Some("bob")).await;
+    let upload = contentapi::forms::FileUploadAsObject {
+        object: Content { name: Some(String::from("tiny")), contentType: Some(ContentType::FILE), ..Default::default() },
+        base64blob: String::from("aGVsbG8=")
+    };
+    let content = context.api_context.upload_file_base64(&upload).await.expect("Upload should work");
+    assert_eq!(api.file(&content.hash.unwrap()).unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn admin_logs_filter_by_type()
+{
+    let api = start_api();
+    let page = rendered(pages::admin::get_render(get_context(&api, Some("admin")).await, common::forms::AdminSearchParams::default()).await);
+    assert!(page.contains("User 2 created content 10"));
+    assert!(page.contains("User 1 banned user 3"));
+    assert!(page.contains("/forum/thread/hello-world"));   //Content targets link to the content
+    assert!(page.contains(" banned </span><a href=\"http://localhost/user/bob\""));
+
+    let search = common::forms::AdminSearchParams::from_query(vec![(String::from("logtype"), AdminLogType::BanCreate.to_i8().to_string())]);
+    let page = rendered(pages::admin::get_render(get_context(&api, Some("admin")).await, search).await);
+    assert!(!page.contains("User 2 created content 10"));
+    assert!(page.contains("User 1 banned user 3"));
+}
+
+#[tokio::test]
+async fn live_thread_gets_new_posts()
+{
+    use futures_util::StreamExt;
+
+    let api = start_api();
+    let poster = get_context(&api, Some("bob")).await;
+    //Starting from the very first event, so nothing is missed while the socket connects
+    let mut posts = Box::pin(pages::live::thread_stream(get_context(&api, None).await, 10, Some(0)).await.expect("Thread should exist"));
+
+    let elsewhere = Message { contentId: Some(20), text: Some(String::from("Posted on a program")), ..Default::default() };
+    poster.api_context.post_message(&elsewhere).await.expect("Bob should be able to post");
+    let message = Message { contentId: Some(10), text: Some(String::from("Live from the thread")), ..Default::default() };
+    poster.api_context.post_message(&message).await.expect("Bob should be able to post");
+
+    let update = tokio::time::timeout(std::time::Duration::from_secs(5), posts.next()).await
+        .expect("Post should be pushed").expect("Stream shouldn't end");
+    assert_eq!(update.event, "posts");
+    assert!(update.data.contains("Live from the thread"));
+    assert!(!update.data.contains("Posted on a program"));
+
+    //A browser that reconnects after that event doesn't get it again
+    let mut posts = Box::pin(pages::live::thread_stream(get_context(&api, None).await, 10, Some(update.id)).await.unwrap());
+    let message = Message { contentId: Some(10), text: Some(String::from("Second post")), ..Default::default() };
+    poster.api_context.post_message(&message).await.unwrap();
+    let update = tokio::time::timeout(std::time::Duration::from_secs(5), posts.next()).await.unwrap().unwrap();
+    assert!(update.data.contains("Second post"));
+    assert!(!update.data.contains("Live from the thread"));
+}
+
+#[tokio::test]
+async fn live_streams_share_a_socket()
+{
+    use futures_util::StreamExt;
+    use contentapi::live::{LiveHub, LiveSettings};
+
+    let api = start_api();
+    let settings = LiveSettings { ping_interval: std::time::Duration::from_millis(100), ..Default::default() };
+    let hub = std::sync::Arc::new(LiveHub::new(settings));
+    let tab = |token: &str| ApiContext::new(api.url(), Some(token.to_string())).with_live(hub.clone()).live_updates(Some(0));
+
+    //Two tabs with the same session are one socket, someone else is another
+    let bob = api.login("bob").unwrap();
+    let mut first = Box::pin(tab(&bob));
+    let mut second = Box::pin(tab(&bob));
+    let mut other = Box::pin(tab(&api.login("alice").unwrap()));
+
+    let poster = get_context(&api, Some("alice")).await;
+    let message = Message { contentId: Some(10), text: Some(String::from("Everyone sees this")), ..Default::default() };
+    poster.api_context.post_message(&message).await.unwrap();
+
+    for stream in [&mut first, &mut second, &mut other] {
+        let data = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(data.events_of(contentapi::live::LiveEventType::MESSAGE).count(), 1);
+    }
+    assert_eq!(api.live_sockets(), 2);
+
+    //Nobody reading, so the sockets close (after a ping interval at most)
+    drop((first, second, other));
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!(api.live_sockets(), 0);
+}
+
+#[tokio::test]
+async fn watches_count_unread_posts()
+{
+    let api = start_api();
+    let bob = get_context(&api, Some("bob")).await;
+    let watch = bob.api_context.post_watch_add(10).await.expect("Bob should be able to watch");
+    assert_eq!(watch.lastCommentId, Some(101)); //Starts out caught up
+
+    let page = rendered(pages::forum_thread::get_hash_render(get_context(&api, Some("bob")).await, String::from("hello-world"), 20, None).await);
+    assert!(page.contains("value=\"Unwatch\""));
+
+    let alice = get_context(&api, Some("alice")).await;
+    for text in ["Anyone there?", "Hello?"] {
+        let message = Message { contentId: Some(10), text: Some(String::from(text)), ..Default::default() };
+        alice.api_context.post_message(&message).await.expect("Alice should be able to post");
+    }
+
+    let watched = pages::userhome_watches::get_watched(&get_context(&api, Some("bob")).await).await.expect("Watches should load");
+    assert_eq!(watched.len(), 1);
+    assert_eq!(watched[0].unread, 2);
+    let page = rendered(pages::userhome_watches::get_render(get_context(&api, Some("bob")).await).await);
+    assert!(page.contains("Hello world"));
+
+    //Looking at the end of the thread catches you up
+    rendered(pages::forum_thread::get_hash_render(get_context(&api, Some("bob")).await, String::from("hello-world"), 20, None).await);
+    let watched = pages::userhome_watches::get_watched(&get_context(&api, Some("bob")).await).await.expect("Watches should load");
+    assert_eq!(watched[0].unread, 0);
+
+    //Watches are private
+    assert!(pages::userhome_watches::get_watched(&alice).await.expect("Watches should load").is_empty());
+
+    let form = common::forms::WatchForm { watch: false };
+    match pages::forum_thread::watch_render(get_context(&api, Some("bob")).await, 10, form).await {
+        Ok(Response::Redirect(link)) => assert!(link.ends_with("/forum/thread/hello-world")),
+        other => panic!("Expected a redirect, got {:?}", other)
+    }
+    assert!(bob.api_context.get_watches().await.expect("Watches should load").is_empty());
+}
+
+#[tokio::test]
+async fn direct_messages_are_private()
+{
+    let api = start_api();
+    let form = common::forms::DirectMessageForm {
+        recipients: String::from("bob"),
+        title: String::from("Secret plans"),
+        post: String::from("Don't tell admin")
+    };
+    let link = match pages::directmessages::post_compose_render(get_context(&api, Some("alice")).await, form).await {
+        Ok(Response::Redirect(link)) => link,
+        other => panic!("Expected a redirect, got {:?}", other)
+    };
+    assert!(link.contains("/forum/thread/"));
+
+    //Only the participants see it, and it's new to bob (but not alice, she wrote it)
+    let page = rendered(pages::directmessages::get_render(get_context(&api, Some("bob")).await, 20, None).await);
+    assert!(page.contains("Secret plans"));
+    assert!(page.contains("data-unread"));
+    let page = rendered(pages::directmessages::get_render(get_context(&api, Some("alice")).await, 20, None).await);
+    assert!(page.contains("Secret plans"));
+    assert!(!page.contains("data-unread"));
+    let page = rendered(pages::directmessages::get_render(get_context(&api, Some("admin")).await, 20, None).await);
+    assert!(!page.contains("Secret plans"));
+
+    //Reading it on the thread page marks it read
+    let hash = link.split("/forum/thread/").nth(1).and_then(|l| l.split('/').next()).unwrap_or_default().to_string();
+    let page = rendered(pages::forum_thread::get_hash_render(get_context(&api, Some("bob")).await, hash, 20, None).await);
+    assert!(page.contains("tell admin"));
+    assert!(page.contains("createpost")); //Bob can reply
+    let page = rendered(pages::directmessages::get_render(get_context(&api, Some("bob")).await, 20, None).await);
+    assert!(!page.contains("data-unread"));
+
+    //Nobody to send to
+    let form = common::forms::DirectMessageForm { recipients: String::from("alice nobody"), title: String::from("Hi"), post: String::from("Hi") };
+    let page = rendered(pages::directmessages::post_compose_render(get_context(&api, Some("alice")).await, form).await);
+    assert!(page.contains("No user named 'nobody'"));
+    assert!(page.contains("other than yourself"));
+}
+
+#[tokio::test]
+async fn settings_sync_to_account()
+{
+    let api = start_api();
+    let dark = common::UserConfig { theme: String::from("sbs-dark"), compact: true, ..Default::default() };
+    let page = rendered(Ok(pages::sessionsettings::post_render(get_context(&api, Some("bob")).await, dark, Some(true), Vec::new()).await));
+    assert!(page.contains("settings-synced"));
+
+    let bob = get_context(&api, Some("bob")).await;
+    let config = common::prefab::get_account_config(&bob.api_context).await.expect("Settings should load").expect("Settings should be synced");
+    assert_eq!(config.theme, "sbs-dark");
+    assert!(config.compact);
+    assert!(common::prefab::get_account_config(&get_context(&api, Some("alice")).await.api_context).await.expect("Settings should load").is_none());
+
+    //Once synced, a plain save keeps the account up to date
+    let blue = common::UserConfig { theme: String::from("sbs-blue"), ..Default::default() };
+    pages::sessionsettings::post_render(get_context(&api, Some("bob")).await, blue, None, Vec::new()).await;
+    let config = common::prefab::get_account_config(&bob.api_context).await.expect("Settings should load").expect("Settings should be synced");
+    assert_eq!(config.theme, "sbs-blue");
+
+    let page = rendered(Ok(pages::sessionsettings::post_render(get_context(&api, Some("bob")).await, config, Some(false), Vec::new()).await));
+    assert!(page.contains("settings-sync"));
+    assert!(!page.contains("settings-synced"));
+    assert!(common::prefab::get_account_config(&bob.api_context).await.expect("Settings should load").is_none());
+}
+
+#[tokio::test]
+async fn reactions_toggle_on_posts()
+{
+    let api = start_api();
+    let form = || common::forms::ReactionForm { reaction: String::from("plusone") };
+    match pages::forum_thread::react_render(get_context(&api, Some("bob")).await, 101, form(), 20).await {
+        Ok(Response::Redirect(link)) => assert!(link.contains("/forum/thread/hello-world")),
+        other => panic!("Expected a redirect, got {:?}", other)
+    }
+    pages::forum_thread::react_render(get_context(&api, Some("alice")).await, 101, form(), 20).await.expect("Alice should be able to react");
+
+    let page = rendered(pages::forum_thread::get_hash_render(get_context(&api, Some("bob")).await, String::from("hello-world"), 20, None).await);
+    assert!(page.contains("👍 2"));
+    assert!(page.contains("data-current"));
+
+    //Reacting the same way again takes it back off
+    pages::forum_thread::react_render(get_context(&api, Some("bob")).await, 101, form(), 20).await.expect("Bob should be able to unreact");
+    let page = rendered(pages::forum_thread::get_hash_render(get_context(&api, None).await, String::from("hello-world"), 20, None).await);
+    assert!(page.contains("👍 1"));
+    assert!(!page.contains("reactionpicker"));
+
+    let bad = common::forms::ReactionForm { reaction: String::from("nope") };
+    match pages::forum_thread::react_render(get_context(&api, Some("bob")).await, 101, bad, 20).await {
+        Ok(Response::RenderWithStatus(page, 400)) => {
+            assert!(page.contains("Unknown reaction 'nope'"));
+            assert!(page.contains("👍 1")); //Still the thread, nothing changed
+        },
+        other => panic!("Expected the thread with an error, got {:?}", other)
+    }
+}
+
+#[tokio::test]
+async fn tags_browse_by_keyword()
+{
+    let api = start_api();
+    let page = rendered(pages::tags::get_render(get_context(&api, None).await).await);
+    assert!(page.contains("/tags/game"));
+    assert!(page.contains("/tags/font"));
+
+    let page = rendered(pages::tags::get_tag_render(get_context(&api, None).await, String::from("game")).await);
+    assert!(page.contains("Cool Game"));
+    assert!(page.contains("Hello world"));
+    assert!(!page.contains("Font Pack"));
+
+    //Links round trip even for keywords that need encoding
+    let links = get_context(&api, None).await.layout_data.links;
+    let link = links.tag("c++ fun");
+    assert!(link.ends_with("/tags/c%2B%2B%20fun"));
+    assert_eq!(common::routes::from_segment(link.rsplit('/').next().unwrap()), "c++ fun");
+}
+
+#[tokio::test]
+async fn page_history_diffs_and_restores()
+{
+    let api = start_api();
+    let admin = get_context(&api, Some("admin")).await;
+    let mut game = admin.api_context.get_content_by_hash("cool-game", "*").await.expect("Cool game should exist");
+    game.text = Some(String::from("Play [b]now[/b]\nBring friends"));
+    let edited = admin.api_context.post_content(&game, Some(String::from("Mention friends"))).await.expect("Admin should be able to edit anything");
+    let revision = edited.lastRevisionId.expect("Edits should make a revision");
+
+    let query = pages::page_history::HistoryQuery { old: Some(201), new: Some(revision) };
+    let page = rendered(pages::page_history::get_render(get_context(&api, None).await, String::from("cool-game"), query).await);
+    assert!(page.contains("Mention friends"));
+    assert!(page.contains("<ins>+ Bring friends</ins>"));
+    assert!(!page.contains("<del>"));
+    assert!(!page.contains("restorerevision"));
+
+    //Only admins get to put it back
+    let form = || pages::page_history::RestoreForm { revision: 201 };
+    let page = rendered(pages::page_history::post_restore_render(get_context(&api, Some("alice")).await, String::from("cool-game"), form()).await);
+    assert!(page.contains("Only admins"));
+    match pages::page_history::post_restore_render(get_context(&api, Some("admin")).await, String::from("cool-game"), form()).await {
+        Ok(Response::Redirect(link)) => assert!(link.ends_with("/page/cool-game/history")),
+        other => panic!("Expected a redirect, got {:?}", other)
+    }
+    let restored = admin.api_context.get_content_by_hash("cool-game", "*").await.expect("Cool game should exist");
+    assert_eq!(restored.text.as_deref(), Some("Play [b]now[/b]"));
+    assert!(restored.lastRevisionId > Some(revision));
+}
+
+#[tokio::test]
+async fn rate_limit_page_says_when()
+{
+    let api = start_api();
+    let page = pages::ratelimited::render(get_context(&api, None).await.layout_data, 1);
+    assert!(page.contains("1 second"));
+    let page = pages::ratelimited::render(get_context(&api, None).await.layout_data, 90);
+    assert!(page.contains("2 minutes"));
+}
+
+#[tokio::test]
+async fn forms_carry_csrf_token()
+{
+    let api = start_api();
+    let context = get_context(&api, Some("alice")).await;
+    let session = context.layout_data.user_token.clone();
+    let token = common::csrf::csrf_token(session.as_deref());
+    assert_ne!(token, common::csrf::csrf_token(None));
+    assert!(common::csrf::csrf_valid(session.as_deref(), Some(&token)));
+    assert!(!common::csrf::csrf_valid(session.as_deref(), None));
+    assert!(!common::csrf::csrf_valid(None, Some(&token)));
+
+    //Logged out, every browser's pre-session gives it its own token, and no secret means no token at all
+    let anonymous = common::csrf::csrf_token(Some("presession-one"));
+    assert_ne!(anonymous, common::csrf::csrf_token(Some("presession-two")));
+    assert!(common::csrf::csrf_valid(Some("presession-one"), Some(&anonymous)));
+    assert!(!common::csrf::csrf_valid(Some("presession-two"), Some(&anonymous)));
+    assert!(common::csrf::csrf_token(None).is_empty());
+    assert!(!common::csrf::csrf_valid(None, Some("")));
+
+    //Every post form on the thread (watch, reactions, delete) has it
+    let page = rendered(pages::forum_thread::get_hash_render(context, String::from("hello-world"), 20, None).await);
+    let field = format!("<input type=\"hidden\" name=\"csrf\" value=\"{}\">", token);
+    assert!(page.contains("watchthread"));
+    assert_eq!(page.matches("method=\"POST\"").count(), page.matches(&field).count());
+}
+
+#[tokio::test]
+async fn inline_scripts_carry_nonce()
+{
+    let api = start_api();
+    let page = pages::about::render(get_context(&api, None).await.layout_data);
+    assert!(page.contains("<script nonce=\"testnonce\">"));
+    assert_eq!(page.matches("<script>").count(), 0);
+}
+
+#[tokio::test]
+async fn thread_render_shows_up_in_metrics()
+{
+    let api = start_api();
+    let context = get_context(&api, None).await;
+    rendered(pages::forum_thread::get_hash_render(context, String::from("hello-world"), 20, None).await);
+    //Other tests run at the same time, so only check that these are there at all
+    let metrics = contentapi::metrics::global().render();
+    assert!(metrics.contains("sbs_backend_request_duration_seconds_count{endpoint=\"/request\"}"));
+    assert!(metrics.contains("sbs_backend_reported_duration_seconds_count{name=\"total\"}"));
+    assert!(metrics.contains("sbs_bbcode_render_duration_seconds_count "));
+    assert_eq!(contentapi::metrics::endpoint_label("/history/123?x=1"), "/history/:id");
+}
+
+#[tokio::test]
+async fn links_come_from_the_route_table()
+{
+    use common::routes::{self, NewPostQuery};
+
+    assert_eq!(routes::index().as_str(), "/");
+    assert_eq!(routes::forum_post("hello world", 5).fragment("post_5").as_str(), "/forum/thread/hello%20world/5#post_5");
+    let editor = routes::forum_edit_post().query(&NewPostQuery { thread: String::from("hello-world"), reply: Some(3), widget: None });
+    assert_eq!(editor.as_str(), "/forum/edit/post?thread=hello-world&reply=3");
+
+    let api = start_api();
+    let page = rendered(pages::forum_thread::get_hash_render(get_context(&api, None).await, String::from("hello-world"), 20, None).await);
+    assert!(page.contains("href=\"http://localhost/forum/thread/hello-world\""));
+    assert!(page.contains("href=\"http://localhost/forum\""));
+}
@@ -0,0 +1,208 @@
+use std::time::Instant;
+
+use warp::hyper::header;
+
+use crate::reload::ConfigSource;
+use crate::{SESSIONCOOKIE, build_routes, build_state, health, init_logging};
+
+//The command line. Serving is what you'll want nearly every time, the rest are for checking on a
+//deployment or debugging one page. For the old way (just the environment name), see Cli::parse.
+
+pub const USAGE: &str = "\
+Usage: sbs-rust-contentapi [COMMAND] [OPTIONS]
+
+Commands:
+  serve              Run the site (the default)
+  check-config       Read and validate the config, then print it
+  render <path>      Render one page (like /forum?page=2) through all the routes and print it
+  ping-api           Ask the backend api about itself and report
+  help               Show this
+
+Options:
+  --config <file>    The base config file (default ./settings.toml)
+  --env <name>       Also read <config dir>/<base name>.<name>.toml over top of the base config
+  --bind <address>   serve: listen on this address instead of host_address
+  --session <token>  render: render the page logged in with this session token
+
+The old way still works too: `sbs-rust-contentapi Dev` is the same as `sbs-rust-contentapi serve --env Dev`";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Serve,
+    CheckConfig,
+    Render { path: String, session: Option<String> },
+    PingApi,
+    Help
+}
+
+#[derive(Debug)]
+pub struct Cli {
+    pub command: Command,
+    pub source: ConfigSource
+}
+
+fn option_value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String>
+{
+    args.next().filter(|v| !v.starts_with("--")).ok_or_else(|| format!("{} needs a value", option))
+}
+
+impl Cli {
+    /// Parse the arguments (without the program name)
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, String>
+    {
+        let mut args = args.peekable();
+        let mut source = ConfigSource::default();
+
+        let name = match args.peek().map(|a| a.as_str()) {
+            Some("serve" | "check-config" | "render" | "ping-api" | "help") => args.next().unwrap_or_default(),
+            //Before there were commands, the only argument was the environment
+            Some(environment) if !environment.starts_with('-') => {
+                source.environment = Some(environment.to_string());
+                args.next();
+                String::from("serve")
+            },
+            _ => String::from("serve")
+        };
+
+        let mut positional = Vec::new();
+        let mut session = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => source.set_config_path(&option_value(&mut args, "--config")?),
+                "--env" => source.environment = Some(option_value(&mut args, "--env")?),
+                "--bind" if name == "serve" => source.bind = Some(option_value(&mut args, "--bind")?),
+                "--session" if name == "render" => session = Some(option_value(&mut args, "--session")?),
+                "-h" | "--help" => return Ok(Self { command: Command::Help, source }),
+                option if option.starts_with('-') => return Err(format!("Unknown option for {}: {}", name, option)),
+                _ => positional.push(arg)
+            }
+        }
+
+        let command = match (name.as_str(), positional.len()) {
+            ("render", 1) => Command::Render { path: positional.remove(0), session },
+            ("render", _) => return Err(String::from("render needs exactly one path")),
+            (_, count) if count > 0 => return Err(format!("Unexpected argument for {}: {}", name, positional[0])),
+            ("check-config", _) => Command::CheckConfig,
+            ("ping-api", _) => Command::PingApi,
+            ("help", _) => Command::Help,
+            _ => Command::Serve
+        };
+
+        Ok(Self { command, source })
+    }
+}
+
+/// check-config: 0 if the config is good (and it's printed), 1 if not
+pub fn check_config(source: &ConfigSource) -> i32
+{
+    for file in source.files() {
+        eprintln!("Reading {}{}", file, if std::path::Path::new(&file).exists() { "" } else { " (not found)" });
+    }
+    match source.read_checked() {
+        Ok(config) => {
+            println!("{:#?}", config.redacted());
+            0
+        },
+        Err(error) => {
+            eprintln!("Config is invalid: {}", error);
+            1
+        }
+    }
+}
+
+/// ping-api: 0 if the backend answered, 1 if not
+pub async fn ping_api(source: &ConfigSource) -> i32
+{
+    let config = source.read();
+    init_logging(&config, true);
+    let endpoint = config.api_endpoint.clone();
+    let state = build_state(config);
+
+    let start = Instant::now();
+    match health::check_api(&state).await {
+        Ok(about) => {
+            println!("{} is up ({}ms): version {}, environment {}, runtime {}",
+                endpoint, start.elapsed().as_millis(), about.version, about.environment, about.runtime);
+            0
+        },
+        Err(error) => {
+            println!("{} is NOT ok ({}ms): {}", endpoint, start.elapsed().as_millis(), error.to_verbose_string());
+            1
+        }
+    }
+}
+
+/// render: the page goes to stdout, the status and headers to stderr. 0 for any success or redirect, 1 otherwise
+pub async fn render(source: &ConfigSource, path: &str, session: Option<String>) -> i32
+{
+    let config = source.read();
+    init_logging(&config, true);
+    let routes = build_routes(build_state(config));
+
+    let mut request = warp::test::request().method("GET").path(path);
+    if let Some(session) = session {
+        request = request.header(header::COOKIE, format!("{}={}", SESSIONCOOKIE, session));
+    }
+    let response = request.reply(&routes).await;
+
+    eprintln!("{}", response.status());
+    for (name, value) in response.headers() {
+        eprintln!("{}: {}", name, value.to_str().unwrap_or("(not text)"));
+    }
+    println!("{}", String::from_utf8_lossy(response.body()));
+
+    if response.status().is_success() || response.status().is_redirection() { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn no_arguments_serves() {
+        let cli = parse(&[]).unwrap();
+        assert_eq!(cli.command, Command::Serve);
+        assert_eq!(cli.source.environment, None);
+    }
+
+    #[test]
+    fn legacy_environment_name() {
+        let cli = parse(&["Dev"]).unwrap();
+        assert_eq!(cli.command, Command::Serve);
+        assert_eq!(cli.source.environment.as_deref(), Some("Dev"));
+        assert_eq!(cli.source.files(), vec!["./settings.toml", "./settings.Dev.toml"]);
+    }
+
+    #[test]
+    fn commands_and_options() {
+        let cli = parse(&["serve", "--bind", "0.0.0.0:80", "--config", "/etc/sbs/site.toml", "--env", "Prod"]).unwrap();
+        assert_eq!(cli.command, Command::Serve);
+        assert_eq!(cli.source.bind.as_deref(), Some("0.0.0.0:80"));
+        assert_eq!(cli.source.files(), vec!["/etc/sbs/site.toml", "/etc/sbs/site.Prod.toml"]);
+
+        let cli = parse(&["render", "/forum?page=2", "--session", "abc"]).unwrap();
+        assert_eq!(cli.command, Command::Render { path: String::from("/forum?page=2"), session: Some(String::from("abc")) });
+
+        assert_eq!(parse(&["check-config", "--help"]).unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn options_only_where_they_belong() {
+        assert!(parse(&["check-config", "--bind", "0.0.0.0:80"]).unwrap_err().contains("--bind"));
+        assert!(parse(&["serve", "--session", "abc"]).unwrap_err().contains("--session"));
+        assert!(parse(&["serve", "--nope"]).is_err());
+    }
+
+    #[test]
+    fn missing_values_and_arguments() {
+        assert_eq!(parse(&["serve", "--bind"]).unwrap_err(), "--bind needs a value");
+        assert_eq!(parse(&["serve", "--env", "--bind", "0.0.0.0:80"]).unwrap_err(), "--env needs a value");
+        assert!(parse(&["render"]).is_err());
+        assert!(parse(&["render", "/a", "/b"]).is_err());
+        assert!(parse(&["ping-api", "extra"]).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use serde::de::DeserializeOwned;
+use warp::{Filter, Rejection, filters::BoxedFilter, hyper::body::Bytes, reject::Reject};
+
+use common::csrf::*;
+
+use crate::{SESSIONCOOKIE, PRESESSIONCOOKIE};
+
+//Checking the csrf token (see common::csrf) means reading the form body, and warp only lets you read
+//it once, so the check and the form parsing happen together: use csrf_form instead of warp::body::form
+//on anything that changes state. Multipart uploads are checked in read_upload instead. The secret the
+//token comes from is the session, or the pre-session cookie if there's no session.
+
+/// The rejection for a form without the right csrf token. handle_rejection gives this a real page
+#[derive(Debug)]
+pub struct CsrfFailed;
+
+impl Reject for CsrfFailed {}
+
+/// The body wasn't a form we could read. Same as warp's BodyDeserializeError, which we can't make ourselves
+#[derive(Debug)]
+pub struct FormInvalid {
+    pub message: String
+}
+
+impl Reject for FormInvalid {}
+
+/// A new pre-session (see common::csrf). It has to be unguessable, so it's from the os rng; if that
+/// fails there's just no pre-session, and forms won't work until it doesn't
+pub fn new_presession() -> Option<String>
+{
+    let mut bytes = [0u8; 16];
+    match getrandom::getrandom(&mut bytes) {
+        Ok(_) => Some(bytes.iter().map(|b| format!("{:02x}", b)).collect()),
+        Err(error) => {
+            tracing::error!("Couldn't get random bytes for a pre-session: {}", error);
+            None
+        }
+    }
+}
+
+fn check_body(body: &Bytes, secret: Option<&str>) -> Result<(), Rejection>
+{
+    let fields = serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+        .map_err(|error| warp::reject::custom(FormInvalid { message: error.to_string() }))?;
+    let sent = fields.iter().find(|(key, _)| key == CSRFFIELD).map(|(_, value)| value.as_str());
+    if csrf_valid(secret, sent) {
+        Ok(())
+    }
+    else {
+        tracing::warn!("Rejecting form post with {} csrf token", if sent.is_some() { "the wrong" } else { "no" });
+        Err(warp::reject::custom(CsrfFailed))
+    }
+}
+
+/// Check the csrf token for the session, then parse the rest of the body as the form
+pub fn csrf_form<T: DeserializeOwned + Send>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+{
+    warp::body::bytes()
+        .and(warp::cookie::optional::<String>(SESSIONCOOKIE))
+        .and(warp::cookie::optional::<String>(PRESESSIONCOOKIE))
+        .and_then(|body: Bytes, session: Option<String>, presession: Option<String>| async move {
+            check_body(&body, session.or(presession).as_deref())?;
+            serde_urlencoded::from_bytes::<T>(&body).map_err(|error| warp::reject::custom(FormInvalid { message: error.to_string() }))
+        })
+}
+
+/// Just the csrf check, for posts where the form has nothing else in it (like the delete buttons)
+pub fn csrf_check() -> BoxedFilter<()>
+{
+    warp::body::bytes()
+        .and(warp::cookie::optional::<String>(SESSIONCOOKIE))
+        .and(warp::cookie::optional::<String>(PRESESSIONCOOKIE))
+        .and_then(|body: Bytes, session: Option<String>, presession: Option<String>| async move {
+            check_body(&body, session.or(presession).as_deref())
+        })
+        .untuple_one()
+        .boxed()
+}
@@ -25,6 +25,8 @@ macro_rules! wrap_from_error {
 
 wrap_from_error!(InvalidUri);
 wrap_from_error!(warp::http::Error);
+wrap_from_error!(warp::Error);
+wrap_from_error!(multer::Error);
 
 //
 ////This is so stupid. Oh well
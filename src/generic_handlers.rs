@@ -1,12 +1,24 @@
 use std::convert::Infallible;
+use std::sync::Arc;
 
 use common::LinkConfig;
-use warp::reject::InvalidQuery;
+use futures_util::{Stream, TryStreamExt};
+use warp::Buf;
+use warp::reject::{InvalidQuery, PayloadTooLarge};
 use warp::{Rejection, Reply};
 use warp::body::BodyDeserializeError;
 use warp::hyper::{StatusCode};
 
 use crate::{errors::*, SESSIONCOOKIE};
+use crate::ratelimit::RateLimited;
+use crate::csrf::{CsrfFailed, FormInvalid};
+use crate::state::GlobalState;
+use crate::security::{with_stamp, PageStamp};
+
+/// Turn a live page update into a server-sent event
+pub fn live_event(update: pages::live::LiveUpdate) -> Result<warp::sse::Event, Infallible> {
+    Ok(warp::sse::Event::default().event(update.event).id(update.id.to_string()).data(update.data))
+}
 
 pub fn get_status_from_error(error: &ErrorWrapper) -> (StatusCode, String)
 {
@@ -29,17 +41,35 @@ pub fn get_status_from_error(error: &ErrorWrapper) -> (StatusCode, String)
         common::Error::Data(derr,data) => {
             code = StatusCode::INTERNAL_SERVER_ERROR;
             message = derr.clone();
-            println!("DATA ERROR: {}\n{}", derr, data);
+            tracing::error!("DATA ERROR: {}\n{}", derr, data);
         }
     }
 
     (code, message)
 }
 
-pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+pub async fn handle_rejection(err: Rejection, state: Arc<GlobalState>) -> Result<Box<dyn Reply>, Infallible> {
     let code: StatusCode;
     let message: String;
-    if let Some(error) = err.find::<ErrorWrapper>() {
+    //This one gets a real page, since it's what normal people see when they click too fast
+    if let Some(limited) = err.find::<RateLimited>() {
+        let data = state.bare_layout_data("/");
+        let stamp = PageStamp { nonce: data.csp_nonce.clone(), presession: None };
+        let page = pages::ratelimited::render(data, limited.retry_after);
+        let reply = warp::reply::with_status(warp::reply::html(page), StatusCode::TOO_MANY_REQUESTS);
+        return Ok(Box::new(with_stamp(warp::reply::with_header(reply, "Retry-After", limited.retry_after.to_string()), &stamp)));
+    }
+    else if err.find::<CsrfFailed>().is_some() {
+        let data = state.bare_layout_data("/");
+        let stamp = PageStamp { nonce: data.csp_nonce.clone(), presession: None };
+        let page = pages::csrffailed::render(data);
+        return Ok(Box::new(with_stamp(warp::reply::with_status(warp::reply::html(page), StatusCode::FORBIDDEN), &stamp)));
+    }
+    else if let Some(error) = err.find::<FormInvalid>() {
+        code = StatusCode::BAD_REQUEST;
+        message = error.message.clone();
+    }
+    else if let Some(error) = err.find::<ErrorWrapper>() {
         (code, message) = get_status_from_error(error);
     }
     else if let Some(error) = err.find::<BodyDeserializeError>() {
@@ -50,16 +80,53 @@ pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible>
         code = StatusCode::BAD_REQUEST;
         message = error.to_string();
     }
+    else if err.find::<PayloadTooLarge>().is_some() {
+        code = StatusCode::PAYLOAD_TOO_LARGE;
+        message = String::from("That upload is too large!");
+    }
     else {
         code = StatusCode::NOT_FOUND;
         message = String::from("Couldn't figure out what to do with this URL!");
-        println!("UNHANDLED REJECTION (404): {:?}", err);
+        tracing::warn!("UNHANDLED REJECTION (404): {:?}", err);
     }
-    println!("Rejecting as {}: {}", code, message);
-    Ok(warp::reply::with_status(message, code))
+    tracing::info!("Rejecting as {}: {}", code, message);
+    Ok(Box::new(warp::reply::with_status(message, code)))
 }
 
 
+/// Pull the "file" part out of a multipart request body. The form is parsed as the body comes in and the
+/// file isn't read here at all, it's streamed straight to the api later (see ApiContext::upload_file). That 
+/// means anything after it in the form is never seen, so always put the file input last! The csrf token (for
+/// the secret: the session, or the pre-session if logged out, see common::csrf) has to come before it. Bodies over max_size error partway through the upload
+pub async fn read_upload<S, B>(content_type: String, body: S, secret: Option<String>, max_size: u64) -> 
+    Result<common::forms::FileUploadForm, Rejection>
+    where S: Stream<Item = Result<B, warp::Error>> + Send + 'static, B: Buf
+{
+    let boundary = errwrap!(multer::parse_boundary(&content_type))?;
+    let body = body.map_ok(|mut buf| buf.copy_to_bytes(buf.remaining()));
+    let constraints = multer::Constraints::new().size_limit(multer::SizeLimit::new().whole_stream(max_size));
+    let mut form = multer::Multipart::with_constraints(body, boundary, constraints);
+
+    let mut csrf: Option<String> = None;
+    while let Some(field) = errwrap!(form.next_field().await)? {
+        if field.name() == Some(common::csrf::CSRFFIELD) {
+            csrf = Some(errwrap!(field.text().await)?);
+        }
+        else if field.name() == Some("file") {
+            if !common::csrf::csrf_valid(secret.as_deref(), csrf.as_deref()) {
+                return Err(warp::reject::custom(CsrfFailed));
+            }
+            let filename = String::from(field.file_name().unwrap_or("upload"));
+            let mime = field.content_type().map(|m| m.to_string()).unwrap_or_else(|| String::from("application/octet-stream"));
+            //The field keeps reading from the body on its own, the rest of the form isn't needed
+            let data = field.map_err(|error| error.into());
+            return Ok(common::forms::FileUploadForm { filename, mime, data: Box::pin(data) });
+        }
+    }
+
+    errwrap!(Err(common::Error::Other(String::from("No file was uploaded!"))))
+}
+
 pub fn handle_response(response: common::Response, link_config: &LinkConfig) -> Result<impl Reply, Rejection>
 {
     handle_response_with_token(response, link_config, None, 0)
@@ -116,7 +183,10 @@ pub fn handle_response_with_anycookie(response: common::Response, link_config: &
 macro_rules! std_resp {
     ($render:expr,$context:expr) => {
         async move {
-            handle_response_with_error($render.await, &$context.global_state.link_config)
+            let span = $context.span.clone();
+            let stamp = $context.stamp.clone();
+            handle_response_with_error(tracing::Instrument::instrument($render, span).await, &$context.global_state.link_config)
+                .map(|reply| $crate::security::with_stamp(reply, &stamp))
         }
     };
 }
@@ -124,7 +194,7 @@ macro_rules! std_resp {
 #[macro_export]
 macro_rules! cf {
     ($ctx:ident.$setting:ident) => {
-        $ctx.global_state.config.$setting
+        $ctx.global_state.config().$setting
     };
 }
 
@@ -0,0 +1,59 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use contentapi::endpoints::{ApiContext, ApiError};
+use serde::Serialize;
+use warp::Reply;
+use warp::hyper::StatusCode;
+
+use crate::state::GlobalState;
+
+//For whatever's in front of us (the reverse proxy, or anything else that checks on us). Neither of these
+//go through the state filter: that talks to the backend for every request, and liveness shouldn't.
+
+#[derive(Serialize, Debug)]
+struct Readiness {
+    ready: bool,
+    version: Option<String>,
+    environment: Option<String>,
+    error: Option<String>
+}
+
+/// GET /healthz: the process is up and serving. Nothing else is checked
+pub fn healthz() -> impl Reply
+{
+    warp::reply::with_status("ok", StatusCode::OK)
+}
+
+/// Ask the backend about itself. Same client and policy as the pages, so this fails the same way they would 
+/// (including the breaker), but no cache: this has to actually reach the backend
+pub async fn check_api(state: &GlobalState) -> Result<contentapi::About, ApiError>
+{
+    let context = ApiContext::new(state.config().api_endpoint.clone(), None)
+        .with_client(state.api_client.clone())
+        .with_policy(state.api_policy.clone())
+        .with_request_id(state.next_request_id());
+    context.get_about().await
+}
+
+/// GET /readyz: we can actually serve pages, meaning the backend answers. Also not ready once we've started
+/// shutting down, so the proxy stops sending us new requests while the old ones finish
+pub async fn readyz(state: Arc<GlobalState>) -> Result<impl Reply, Infallible>
+{
+    let readiness = if state.shutting_down.load(Ordering::Relaxed) {
+        Readiness { ready: false, version: None, environment: None, error: Some(String::from("Shutting down")) }
+    }
+    else {
+        match check_api(&state).await {
+            Ok(about) => Readiness { ready: true, version: Some(about.version), environment: Some(about.environment), error: None },
+            Err(error) => {
+                tracing::warn!("Readiness check failed: {}", error.to_verbose_string());
+                Readiness { ready: false, version: None, environment: None, error: Some(error.to_user_string()) }
+            }
+        }
+    };
+
+    let status = if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    Ok(warp::reply::with_status(warp::reply::json(&readiness), status))
+}
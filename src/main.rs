@@ -1,25 +1,44 @@
-use std::{net::SocketAddr, sync::Arc};
+#![recursion_limit = "256"] //The full route filter (plus the logging wrapper) is a VERY deep type
+
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, sync::RwLock, sync::atomic::{AtomicBool, AtomicU64, Ordering}, time::Duration};
 
 use bbscope::{BBCode, BBCodeTagConfig, BBCodeLinkTarget};
 use chrono::SecondsFormat;
 use common::LinkConfig;
+use contentapi::policy::{RequestPolicy, CircuitBreaker};
+use contentapi::endpoints::{build_client, ClientSettings};
+use contentapi::cache::ResponseCache;
+use contentapi::live::{LiveHub, LiveSettings};
 
+use futures_util::StreamExt;
 use serde::Deserialize;
-use warp::{Filter, Rejection};
+use warp::{Filter, Rejection, path::FullPath};
 
 mod errors;
 mod generic_handlers;
 mod state;
 mod multi_routes;
+mod ratelimit;
+mod csrf;
+mod security;
+mod health;
+mod metrics;
+mod reload;
+mod cli;
+mod paths;
 
 use crate::errors::*;
 use crate::generic_handlers::*;
 use crate::state::*;
 use crate::multi_routes::*;
+use crate::ratelimit::*;
+use crate::csrf::*;
+use crate::security::*;
 
 static CONFIGNAME : &str = "settings";
 static SESSIONCOOKIE: &str = "sbs-rust-contentapi-session";
 static SETTINGSCOOKIE: &str = "sbs-rust-contentapi-settings";
+static PRESESSIONCOOKIE: &str = "sbs-rust-contentapi-presession"; //Logged out csrf secret, see common::csrf
 
 //The standard config we want here in this application. This macro is ugly but 
 //it produces a config object that can load from a chain of json files
@@ -40,6 +59,39 @@ onestop::create_config!{
         //file_maxsize: i32,
         body_maxsize: i32, //this can be used for a lot of things, I don't really care
         host_address: String,
+        api_timeout_ms: u64,
+        api_retries: u32,
+        api_retry_backoff_ms: u64,
+        api_breaker_threshold: u32,
+        api_breaker_cooldown_ms: u64,
+        api_pool_max_idle: usize,
+        api_pool_idle_timeout_ms: u64,
+        api_http2: bool,
+        api_cache_size: usize,
+        log_level: String,
+        log_format: String,
+        ratelimit_login: RateLimit,
+        ratelimit_write: RateLimit,
+        ratelimit_widget: RateLimit,
+        ratelimit_trust_forwarded: bool,
+        security_pages: SecurityPolicy,
+        security_widgets: SecurityPolicy,
+        security_static: SecurityPolicy,
+        shutdown_ready_delay_ms: u64,
+        shutdown_drain_timeout_ms: u64,
+        metrics_token: String,
+        metrics_address: String,
+    }
+}
+
+impl Config {
+    /// A copy that's safe to print or log: the secrets are blanked (the derived Debug would show them)
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        if !config.metrics_token.is_empty() {
+            config.metrics_token = String::from("(redacted)");
+        }
+        config
     }
 }
 
@@ -55,16 +107,29 @@ onestop::create_config!{
 #[tokio::main]
 async fn main() 
 {
-    let config = {
-        //Our env is passed on the command line. If none is, we pass "None" so only the base config is read
-        let args: Vec<String> = std::env::args().collect();
-        let environment = args.get(1).map(|x| &**x); //The compiler told me to do this
+    let cli = match cli::Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
 
-        let config = Config::read_with_environment_toml(CONFIGNAME, environment);
-        println!("Environment: {}\n{:#?}", environment.unwrap_or(""), config);
-        config
+    let code = match cli.command {
+        cli::Command::Serve => { serve(cli.source).await; 0 },
+        cli::Command::CheckConfig => cli::check_config(&cli.source),
+        cli::Command::PingApi => cli::ping_api(&cli.source).await,
+        cli::Command::Render { path, session } => cli::render(&cli.source, &path, session).await,
+        cli::Command::Help => { println!("{}", cli::USAGE); 0 }
     };
+    std::process::exit(code);
+}
 
+/// Set up the SINGULAR global state, which will be passed around with a counting reference.
+/// So when you see "clone" on this, it's not actually cloning all the data, it's just making
+/// a new pointer and incrementing a count.
+fn build_state(config: Config) -> Arc<GlobalState>
+{
     let bbcode = {
         let mut config = BBCodeTagConfig::default();
         config.link_target = BBCodeLinkTarget::None;
@@ -74,11 +139,30 @@ async fn main()
         BBCode::from_matchers(matchers)
     };
 
-    //Set up the SINGULAR global state, which will be passed around with a counting reference.
-    //So when you see "clone" on this, it's not actually cloning all the data, it's just making
-    //a new pointer and incrementing a count.
-    let global_state = Arc::new(GlobalState {
+    let api_policy = RequestPolicy {
+        timeout: if config.api_timeout_ms > 0 { Some(Duration::from_millis(config.api_timeout_ms)) } else { None },
+        retries: config.api_retries,
+        retry_backoff: Duration::from_millis(config.api_retry_backoff_ms),
+        breaker: Arc::new(CircuitBreaker::new(config.api_breaker_threshold, Duration::from_millis(config.api_breaker_cooldown_ms)))
+    };
+
+    let api_client = build_client(&ClientSettings {
+        pool_max_idle_per_host: config.api_pool_max_idle,
+        pool_idle_timeout: if config.api_pool_idle_timeout_ms > 0 { Some(Duration::from_millis(config.api_pool_idle_timeout_ms)) } else { None },
+        http2: config.api_http2
+    });
+
+    Arc::new(GlobalState {
         bbcode,
+        api_policy,
+        api_client,
+        api_cache: Arc::new(ResponseCache::new(config.api_cache_size)),
+        live_hub: Arc::new(LiveHub::new(LiveSettings::default())),
+        request_prefix: format!("{:x}", chrono::Utc::now().timestamp() & 0xffffff),
+        request_counter: AtomicU64::new(0),
+        rate_limiter: RateLimiter::default(),
+        shutting_down: AtomicBool::new(false),
+        draining: tokio::sync::watch::channel(false).0,
         link_config : {
             let root = config.http_root.clone();
             LinkConfig {
@@ -90,15 +174,31 @@ async fn main()
                 cache_bust : chrono::offset::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true) //.to_string()
             }
         },
-        config
-    });
+        config: RwLock::new(Arc::new(config))
+    })
+}
 
-    let address = global_state.config.host_address.parse::<SocketAddr>().unwrap();
+/// Every route the site has. Everything (even rejections) goes out through the security headers, 
+/// which need the path for the route group
+fn build_routes(global_state: Arc<GlobalState>) -> 
+    impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone + Send + Sync + 'static
+{
+    let global_for_reject = global_state.clone();
+    let global_for_security = global_state.clone();
 
     let fs_static_route = warp::path("static").and(warp::fs::dir("static")).boxed();
     let fs_favicon_route = warp::path("favicon.ico").and(warp::fs::file("static/resources/favicon.ico")).boxed();
     let fs_robots_route = warp::path("robots.txt").and(warp::fs::file("static/robots.txt")).boxed();
 
+    //For the proxy (see health). These skip the state filter, they shouldn't need a whole page context
+    let get_healthz_route = warp::get().and(paths::healthz()).map(health::healthz).boxed();
+    let global_for_ready = global_state.clone();
+    let get_readyz_route = warp::get()
+        .and(paths::readyz())
+        .and_then(move || health::readyz(global_for_ready.clone()))
+        .boxed();
+    let get_metrics_route = metrics::metrics_route(global_state.clone(), false);
+
     //This "state filter" should be placed at the end of your path but before you start collecting your
     //route-specific data. It will collect the path and the session cookie (if there is one) and create
     //a context with lots of useful data to pass to all the templates (but not ALL of it like before)
@@ -107,16 +207,41 @@ async fn main()
         .and(warp::method())
         .and(warp::cookie::optional::<String>(SESSIONCOOKIE))
         .and(warp::cookie::optional::<String>(SETTINGSCOOKIE))
-        .and_then(move |path, method, token, config_raw| {  //Create a closure that takes ownership of map_state to let it infinitely clone
-            println!("[{}] {:>5} - {:?}", chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true), &method, &path);
+        .and(warp::cookie::optional::<String>(PRESESSIONCOOKIE))
+        .and_then(move |path: FullPath, method, token, config_raw, presession| {  //Create a closure that takes ownership of map_state to let it infinitely clone
             let this_state = global_for_state.clone();
+            //Every request already runs in a span (see serve), it just doesn't have an id until now
+            let request_id = this_state.next_request_id();
+            let span = tracing::Span::current();
+            span.record("request_id", request_id.as_str());
+            tracing::info!("{:>5} - {}", &method, path.as_str());
             async move { 
-                errwrap!(RequestContext::generate(this_state, path, token, config_raw).await)
+                errwrap!(RequestContext::generate(this_state, path, token, presession, config_raw, request_id, span).await)
             }
         }).boxed();
     
     let global_for_form = global_state.clone();
-    let form_filter = warp::body::content_length_limit(global_for_form.config.body_maxsize as u64).boxed();
+    let form_filter = warp::body::content_length_limit(global_for_form.config().body_maxsize as u64).boxed();
+
+    //Every POST takes a token from one of these groups (see ratelimit). Most routes want the limit and
+    //the form filter together; the limits go first so a rejected request never gets its body read
+    let login_limit = ratelimit_filter(global_state.clone(), RateLimitGroup::Login);
+    let write_limit = ratelimit_filter(global_state.clone(), RateLimitGroup::Write);
+    let widget_limit = ratelimit_filter(global_state.clone(), RateLimitGroup::Widget);
+    let login_form_filter = login_limit.and(form_filter.clone()).boxed();
+    let write_form_filter = write_limit.clone().and(form_filter.clone()).boxed();
+    let widget_form_filter = widget_limit.and(form_filter.clone()).boxed();
+
+    //Multipart file uploads get the same limit as forms. The file is streamed to the api as it comes in, see read_upload
+    let upload_maxsize = global_for_form.config().body_maxsize as u64;
+    let upload_filter = warp::body::content_length_limit(upload_maxsize)
+        .and(warp::header::<String>("content-type"))
+        .and(warp::body::stream())
+        .and(warp::cookie::optional::<String>(SESSIONCOOKIE))
+        .and(warp::cookie::optional::<String>(PRESESSIONCOOKIE))
+        .and_then(move |content_type, body, session: Option<String>, presession: Option<String>| 
+            read_upload(content_type, body, session.or(presession), upload_maxsize))
+        .boxed();
 
     macro_rules! warp_get {
         ($filter:expr, $map:expr) => {
@@ -139,51 +264,53 @@ async fn main()
     }
 
     let get_index_route = warp_get_async!(
-        warp::path::end(), 
+        paths::index(),
         |context:RequestContext| std_resp!(pages::index::get_render(pc!(context)), context)
     );
 
-    let get_about_route = warp_get!(warp::path!("about"),
-        |context:RequestContext| warp::reply::html(pages::about::render(pc!(context.layout_data))));
+    let get_about_route = warp_get!(paths::about(),
+        |context:RequestContext| with_stamp(warp::reply::html(pages::about::render(pc!(context.layout_data))), &context.stamp));
 
-    let get_integrationtest_route = warp_get!(warp::path!("integrationtest"),
-        |context:RequestContext| warp::reply::html(pages::integrationtest::render(pc!(context.layout_data))));
+    let get_integrationtest_route = warp_get!(paths::integrationtest(),
+        |context:RequestContext| with_stamp(warp::reply::html(pages::integrationtest::render(pc!(context.layout_data))), &context.stamp));
 
     let get_admin_route = warp_get_async!(
-        warp::path!("admin").and(warp::query::<common::forms::AdminSearchParams>()),
-        |search, context:RequestContext| std_resp!(pages::admin::get_render(pc!(context), search), context)
+        paths::admin().and(warp::query::<Vec<(String, String)>>()),
+        |query, context:RequestContext| 
+            std_resp!(pages::admin::get_render(pc!(context), common::forms::AdminSearchParams::from_query(query)), context)
     );
 
     let get_documentation_route = warp_get_async!(
-        warp::path!("documentation"), 
+        paths::documentation(), 
         |context:RequestContext| std_resp!(pages::documentation::get_render(pc!(context)), context)
     );
 
-    let get_login_route = warp_get!(warp::path!("login"),
-        |context:RequestContext| warp::reply::html(pages::login::render(pc!(context.layout_data), None, None, None)));
+    let get_login_route = warp_get!(paths::login(),
+        |context:RequestContext| with_stamp(warp::reply::html(pages::login::render(pc!(context.layout_data), None, None, None)), &context.stamp));
 
-    let get_register_route = warp_get!(warp::path!("register"),
-        |context:RequestContext| warp::reply::html(pages::register::render(pc!(context.layout_data), None, None, None)));
+    let get_register_route = warp_get!(paths::register(),
+        |context:RequestContext| with_stamp(warp::reply::html(pages::register::render(pc!(context.layout_data), None, None, None)), &context.stamp));
 
-    let get_registerconfirm_route = warp_get!(warp::path!("register"/"confirm"),
-        |context:RequestContext| warp::reply::html(pages::registerconfirm::render(pc!(context.layout_data), None, None, None, None, false)));
+    let get_registerconfirm_route = warp_get!(paths::register_confirm(),
+        |context:RequestContext| with_stamp(warp::reply::html(pages::registerconfirm::render(pc!(context.layout_data), None, None, None, None, false)), &context.stamp));
 
-    let get_recover_route = warp_get!(warp::path!("recover"),
-        |context:RequestContext| warp::reply::html(pages::recover::render(pc!(context.layout_data), None, None)));
+    let get_recover_route = warp_get!(paths::recover(),
+        |context:RequestContext| with_stamp(warp::reply::html(pages::recover::render(pc!(context.layout_data), None, None)), &context.stamp));
 
-    let get_sessionsettings_route = warp_get!(warp::path!("sessionsettings"),
-        |context:RequestContext| warp::reply::html(pages::sessionsettings::render(pc!(context.layout_data), None)));
+    let get_sessionsettings_route = warp_get_async!(paths::sessionsettings(),
+        |context:RequestContext| std_resp!(pages::sessionsettings::get_render(pc!(context)), context)
+    );
 
-    let get_bbcodepreview_route = warp_get!(warp::path!("widget" / "bbcodepreview"),
-        |context:RequestContext| warp::reply::html(pages::widget_bbcodepreview::render(pc!(context.layout_data), &gs!(context.bbcode), None)));
+    let get_bbcodepreview_route = warp_get!(paths::widget_bbcodepreview(),
+        |context:RequestContext| with_stamp(warp::reply::html(pages::widget_bbcodepreview::render(pc!(context.layout_data), &gs!(context.bbcode), None)), &context.stamp));
 
 
 
-    let get_logout_route = warp_get_async!(warp::path!("logout"),
+    let get_logout_route = warp_get_async!(paths::logout(),
         |context:RequestContext| async move {
             //Logout is a Set-Cookie to empty string with Max-Age set to 0, then redirect to root
             handle_response_with_token(
-                common::Response::Redirect(String::from("/")),
+                common::Response::Redirect(common::routes::index().to_string()),
                 &context.global_state.link_config, 
                 Some(String::from("")), 
                 0
@@ -191,65 +318,76 @@ async fn main()
         });
 
     let post_sessionsettings_route = warp::post()
-        .and(warp::path!("sessionsettings"))
-        .and(form_filter.clone())
-        .and(warp::body::form::<common::UserConfig>())
+        .and(paths::sessionsettings())
+        .and(warp::query::<pages::sessionsettings::SyncQuery>())
+        .and(write_form_filter.clone())
+        .and(csrf_form::<common::UserConfig>())
         .and(state_filter.clone())
-        .and_then(|form: common::UserConfig, mut context: RequestContext| {
-            let mut errors: Option<Vec<String>> = None;
+        .and_then(|query: pages::sessionsettings::SyncQuery, form: common::UserConfig, context: RequestContext| {
+            let mut errors: Vec<String> = Vec::new();
             let mut cookie_raw: Option<String> = None;
             match serde_json::to_string(&form) {
                 Ok(cookie) => cookie_raw = Some(String::from(cookie)),
-                Err(error) => errors = Some(vec![error.to_string()])
+                Err(error) => errors.push(error.to_string())
             }
-            context.page_context.layout_data.user_config = form; //Is this safe? idk
             async move {
                 let gc = context.global_state.clone();
+                let span = context.span.clone();
+                let response = tracing::Instrument::instrument(
+                    pages::sessionsettings::post_render(context.page_context, form, query.sync, errors), span).await;
                 handle_response_with_anycookie(
-                    common::Response::Render(pages::sessionsettings::render(context.page_context.layout_data, errors)),
+                    response,
                     &gc.link_config, 
                     SETTINGSCOOKIE,
                     cookie_raw,
-                    gc.config.long_cookie_expire as i64
-                )
+                    gc.config().long_cookie_expire as i64
+                ).map(|reply| with_stamp(reply, &context.stamp))
             }
         })
         .boxed();
 
     let post_bbcodepreview_route = warp::post()
-        .and(warp::path!("widget" / "bbcodepreview"))
-        .and(form_filter.clone())
+        .and(paths::widget_bbcodepreview())
+        .and(widget_form_filter.clone())
         .and(warp::body::form::<common::forms::BasicText>())
         .and(state_filter.clone())
         .map(|form: common::forms::BasicText, context: RequestContext| {
-            warp::reply::html(pages::widget_bbcodepreview::render(context.page_context.layout_data, &context.global_state.bbcode, Some(form.text)))
+            with_stamp(warp::reply::html(pages::widget_bbcodepreview::render(context.page_context.layout_data, &context.global_state.bbcode, Some(form.text))), &context.stamp)
         })
         .boxed();
 
     let post_contentpreview_route = warp::post()
-        .and(warp::path!("widget" / "contentpreview"))
-        .and(form_filter.clone())
+        .and(paths::widget_contentpreview())
+        .and(widget_form_filter.clone())
         .and(warp::body::form::<pages::widget_contentpreview::ContentPreviewForm>())
         .and(state_filter.clone())
         .map(|form: pages::widget_contentpreview::ContentPreviewForm, context: RequestContext| {
-            warp::reply::html(pages::widget_contentpreview::render(context.page_context, form))
+            with_stamp(warp::reply::html(pages::widget_contentpreview::render(context.page_context, form)), &context.stamp)
         })
         .boxed();
 
     let get_search_route = warp_get_async!(
-        warp::path!("search").and(warp::query::<common::forms::PageSearch>()),
+        paths::search().and(warp::query::<common::forms::PageSearch>()),
         |search, context:RequestContext| 
             std_resp!(pages::search::get_render(pc!(context), search, cf!(context.default_display_pages)), context)
     );
 
+    let get_tags_route = warp_get_async!(paths::tags(),
+        |context:RequestContext| std_resp!(pages::tags::get_render(pc!(context)), context)
+    );
+
+    let get_tag_route = warp_get_async!(paths::tag(),
+        |keyword: String, context:RequestContext| std_resp!(pages::tags::get_tag_render(pc!(context), keyword), context)
+    );
+
     let get_searchall_route = warp_get_async!(
-        warp::path!("allsearch").and(warp::query::<pages::searchall::SearchAllForm>()),
+        paths::allsearch().and(warp::query::<pages::searchall::SearchAllForm>()),
         |search, context:RequestContext| 
             std_resp!(pages::searchall::get_render(pc!(context), search), context)
     );
 
     let get_activity_route = warp_get_async!(
-        warp::path!("activity").and(warp::query::<pages::activity::ActivityQuery>()),
+        paths::activity().and(warp::query::<pages::activity::ActivityQuery>()),
         |query, context:RequestContext| 
             std_resp!(pages::activity::get_render(pc!(context), query, cf!(context.default_activity_count)), context)
     );
@@ -259,7 +397,7 @@ async fn main()
     struct SimplePage { page: Option<i32> }
 
     let get_forum_category_route = warp_get_async!(
-        warp::path!("forum" / "category" / String).and(warp::query::<SimplePage>()),
+        paths::forum_category().and(warp::query::<SimplePage>()),
         |hash: String, page_struct: SimplePage, context:RequestContext| 
             std_resp!(
                 pages::forum_category::get_hash_render(pc!(context), hash, cf!(context.default_display_threads), page_struct.page), 
@@ -268,7 +406,7 @@ async fn main()
     ); 
 
     let get_forum_thread_route = warp_get_async!(
-        warp::path!("forum" / "thread" / String).and(warp::query::<SimplePage>()),
+        paths::forum_thread().and(warp::query::<SimplePage>()),
         |hash: String, page_struct: SimplePage, context:RequestContext| 
             std_resp!(
                 pages::forum_thread::get_hash_render(pc!(context), hash, cf!(context.default_display_posts), page_struct.page),
@@ -277,7 +415,7 @@ async fn main()
     ); 
 
     let get_forum_post_route = warp_get_async!(
-        warp::path!("forum" / "thread" / String / i64),
+        paths::forum_post(),
         |hash: String, post_id: i64, context:RequestContext| 
             std_resp!(
                 pages::forum_thread::get_hash_postid_render(pc!(context), hash, post_id, cf!(context.default_display_posts)),
@@ -286,19 +424,31 @@ async fn main()
     ); 
 
     let get_user_route = warp_get_async!(
-        warp::path!("user" / String),
+        paths::user(),
         |username: String, context:RequestContext| 
             std_resp!(pages::user::get_render(pc!(context), username), context)
     ); 
 
     let get_userhome_route = warp_get_async!(
-        warp::path!("userhome"),
+        paths::userhome(),
         |context:RequestContext| 
             std_resp!(pages::userhome::get_render(pc!(context)), context)
     ); 
 
+    let get_userhome_watches_route = warp_get_async!(
+        paths::userhome_watches(),
+        |context:RequestContext| 
+            std_resp!(pages::userhome_watches::get_render(pc!(context)), context)
+    ); 
+
+    let get_direct_messages_route = warp_get_async!(
+        paths::messages().and(warp::query::<SimplePage>()),
+        |page_struct: SimplePage, context:RequestContext| 
+            std_resp!(pages::directmessages::get_render(pc!(context), cf!(context.default_display_threads), page_struct.page), context)
+    ); 
+
     let get_imagebrowser_route = warp_get_async!(
-        warp::path!("widget" / "imagebrowser").and(warp::query::<pages::widget_imagebrowser::Search>()),
+        paths::widget_imagebrowser().and(warp::query::<pages::widget_imagebrowser::Search>()),
         |search, context:RequestContext| 
             std_resp!(
                 pages::widget_imagebrowser::query_render(pc!(context), search, cf!(context.default_imagebrowser_count)),
@@ -307,19 +457,40 @@ async fn main()
     );
 
     let get_widgetthread_route = warp_get_async!(
-        warp::path!("widget" / "thread").and(warp::query::<common::forms::ThreadQuery>()),
+        paths::widget_thread().and(warp::query::<common::forms::ThreadQuery>()),
         |search, context:RequestContext| 
             std_resp!(pages::widget_thread::get_render(pc!(context), search), context)
     );
 
     let get_votewidget_route = warp_get_async!(
-        warp::path!("widget" / "votes" / i64),
+        paths::widget_votes(),
         |content_id, context:RequestContext| 
             std_resp!(pages::widget_votes::get_render(pc!(context), content_id), context)
     );
 
+    //These hold the connection open and push events as they happen (see pages::live). Browsers send
+    //the last event they saw when they reconnect, so they pick up where they left off. That includes
+    //reconnecting to another server when this one shuts down, so they end as soon as draining starts
+    let get_live_thread_route = warp::get()
+        .and(paths::live_thread())
+        .and(warp::header::optional::<i64>("last-event-id"))
+        .and(state_filter.clone())
+        .and_then(|thread_id, last_id, context: RequestContext| async move {
+            let drained = context.global_state.drained();
+            let stream = errwrap!(pages::live::thread_stream(pc!(context), thread_id, last_id).await)?;
+            Ok::<_, Rejection>(warp::sse::reply(warp::sse::keep_alive().stream(stream.map(live_event).take_until(drained))))
+        }).boxed();
+
+    let get_live_activity_route = warp_get!(
+        paths::live_activity().and(warp::header::optional::<i64>("last-event-id")),
+        |last_id, context: RequestContext| {
+            let drained = context.global_state.drained();
+            warp::sse::reply(warp::sse::keep_alive().stream(pages::live::activity_stream(pc!(context), last_id).map(live_event).take_until(drained)))
+        }
+    );
+
     let get_recentactivity_route = warp_get_async!(
-        warp::path!("widget" / "recentactivity").and(warp::query::<pages::widget_recentactivity::RecentActivityConfig>()),
+        paths::widget_recentactivity().and(warp::query::<pages::widget_recentactivity::RecentActivityConfig>()),
         |query, context:RequestContext| 
             std_resp!(pages::widget_recentactivity::get_render(pc!(context), query), context)
     );
@@ -330,111 +501,179 @@ async fn main()
     }
 
     let get_qrwidget_route = warp_get_async!(
-        warp::path!("widget" / "qr" / String).and(warp::query::<QrParam>()),
+        paths::widget_qr().and(warp::query::<QrParam>()),
         |hash: String, qr_param : QrParam, context:RequestContext| 
             std_resp!(pages::widget_qr::get_render(pc!(context), &hash, 
                 if let Some(hd) = qr_param.high_density { hd } else { false }), context)
     );
 
     let post_votewidget_route = warp::post()
-        .and(warp::path!("widget" / "votes" / i64))
-        .and(form_filter.clone())
-        .and(warp::body::form::<common::forms::VoteForm>())
+        .and(paths::widget_votes())
+        .and(widget_form_filter.clone())
+        .and(csrf_form::<common::forms::VoteForm>())
         .and(state_filter.clone())
         .and_then(|content_id, form, context: RequestContext|
             std_resp!(pages::widget_votes::post_render(pc!(context), content_id, form), context)
         ).boxed();
 
+    let post_imagebrowser_route = warp::post()
+        .and(paths::widget_imagebrowser())
+        .and(warp::query::<pages::widget_imagebrowser::Search>())
+        .and(write_limit.clone())
+        .and(upload_filter.clone())
+        .and(state_filter.clone())
+        .and_then(|search, upload, context: RequestContext|
+            std_resp!(
+                pages::widget_imagebrowser::post_render(pc!(context), search, upload, cf!(context.default_imagebrowser_count)),
+                context
+            )
+        ).boxed();
+
     let post_recover_route = warp::post()
-        .and(warp::path!("recover"))
-        .and(form_filter.clone())
-        .and(warp::body::form::<contentapi::forms::UserSensitive>())
+        .and(paths::recover())
+        .and(login_form_filter.clone())
+        .and(csrf_form::<contentapi::forms::UserSensitive>())
         .and(state_filter.clone())
         .and_then(|form: contentapi::forms::UserSensitive, context: RequestContext| {
             async move {
                 let gc = context.global_state.clone();
                 let (response, token) = pages::recover::post_render(pc!(context), &form).await;
-                handle_response_with_token(response, &gc.link_config, token, gc.config.default_cookie_expire as i64)
+                handle_response_with_token(response, &gc.link_config, token, gc.config().default_cookie_expire as i64)
+                    .map(|reply| with_stamp(reply, &context.stamp))
             }
         }).boxed();
 
     let post_register_route = warp::post()
-        .and(warp::path!("register"))
-        .and(form_filter.clone())
-        .and(warp::body::form::<contentapi::forms::Register>())
+        .and(paths::register())
+        .and(login_form_filter.clone())
+        .and(csrf_form::<contentapi::forms::Register>())
         .and(state_filter.clone())
         .and_then(|form, context: RequestContext| 
             std_resp!(pages::register::post_render(pc!(context), &form), context) 
         ).boxed();
     
     let post_thread_delete_route = warp::post()
-        .and(warp::path!("forum" / "delete" / "thread" / i64))
+        .and(paths::forum_delete_thread())
+        .and(write_form_filter.clone())
+        .and(csrf_check())
         .and(state_filter.clone())
         .and_then(|thread_id, context: RequestContext|
             std_resp!(pages::forum_edit_thread::delete_render(pc!(context), thread_id), context)
         ).boxed();
 
+    let post_thread_watch_route = warp::post()
+        .and(paths::forum_watch())
+        .and(write_form_filter.clone())
+        .and(csrf_form::<common::forms::WatchForm>())
+        .and(state_filter.clone())
+        .and_then(|thread_id, form, context: RequestContext|
+            std_resp!(pages::forum_thread::watch_render(pc!(context), thread_id, form), context)
+        ).boxed();
+
+    let post_post_react_route = warp::post()
+        .and(paths::forum_react())
+        .and(write_form_filter.clone())
+        .and(csrf_form::<common::forms::ReactionForm>())
+        .and(state_filter.clone())
+        .and_then(|post_id, form, context: RequestContext|
+            std_resp!(pages::forum_thread::react_render(pc!(context), post_id, form, cf!(context.default_display_posts)), context)
+        ).boxed();
+
     let post_post_delete_route = warp::post()
-        .and(warp::path!("forum" / "delete" / "post" / i64))
+        .and(paths::forum_delete_post())
+        .and(write_form_filter.clone())
+        .and(csrf_check())
         .and(state_filter.clone())
         .and_then(|post_id, context: RequestContext|
             std_resp!(pages::forum_edit_post::delete_render(pc!(context), post_id), context)
         ).boxed();
 
     let post_page_delete_route = warp::post()
-        .and(warp::path!("page" / "delete" / i64))
+        .and(paths::page_delete())
+        .and(write_form_filter.clone())
+        .and(csrf_check())
         .and(state_filter.clone())
         .and_then(|page_id, context: RequestContext|
             std_resp!(pages::page_edit::delete_render(pc!(context), page_id), context)
         ).boxed();
     
+    let get_page_history_route = warp_get_async!(
+        paths::page_history().and(warp::query::<pages::page_history::HistoryQuery>()),
+        |hash: String, query, context:RequestContext| 
+            std_resp!(pages::page_history::get_render(pc!(context), hash, query), context)
+    );
+
+    let post_page_history_route = warp::post()
+        .and(paths::page_history())
+        .and(write_form_filter.clone())
+        .and(csrf_form::<pages::page_history::RestoreForm>())
+        .and(state_filter.clone())
+        .and_then(|hash: String, form, context: RequestContext|
+            std_resp!(pages::page_history::post_restore_render(pc!(context), hash, form), context)
+        ).boxed();
+
     let legacy_page_pid = warp_get_async!(
-        warp::path!("page").and(warp::query::<pages::page::PageQuery>()),
+        paths::page().and(warp::query::<pages::page::PageQuery>()),
         |query, context:RequestContext| 
             std_resp!(pages::page::get_pid_redirect(pc!(context), query), context)
     );
-        
-    warp::serve(
+
+    warp::path::full().and(
             fs_static_route
         .or(fs_favicon_route)
         .or(fs_robots_route)
+        .or(get_healthz_route)
+        .or(get_readyz_route)
+        .or(get_metrics_route)
         .or(get_index_route)
         .or(get_about_route)
         .or(get_search_route)
         .or(get_searchall_route)
+        .or(get_tags_route)
+        .or(get_tag_route)
         .or(get_admin_route)
         .or(get_documentation_route)
-        .or(post_admin_multi_route(&state_filter, &form_filter))
+        .or(post_admin_multi_route(&state_filter, &write_form_filter))
         .or(get_activity_route)
+        .or(get_live_activity_route)
+        .or(get_live_thread_route)
             .boxed()
         .or(get_forum_route(&state_filter)) //HEAVILY multiplexed! Lots of legacy forum paths!
-        .or(get_forum_edit_thread_route(&state_filter, &form_filter))
-        .or(get_forum_edit_post_route(&state_filter, &form_filter))
-        .or(get_page_edit_route(&state_filter, &form_filter))
+        .or(get_forum_edit_thread_route(&state_filter, &write_form_filter))
+        .or(get_forum_edit_post_route(&state_filter, &write_form_filter))
+        .or(get_page_edit_route(&state_filter, &write_form_filter))
         .or(post_thread_delete_route)
+        .or(post_thread_watch_route)
+        .or(post_post_react_route)
         .or(post_post_delete_route)
         .or(post_page_delete_route)
+        .or(get_page_history_route)
+        .or(post_page_history_route)
         .or(get_forum_category_route)
         .or(get_forum_thread_route)
         .or(get_forum_post_route)
             .boxed()
         .or(get_user_route)
-        .or(post_user_multi_route(&state_filter, &form_filter))
+        .or(post_user_multi_route(&state_filter, &write_form_filter))
         .or(get_userhome_route)
-        .or(post_userhome_multi_route(&state_filter, &form_filter)) //Multiplexed! Login OR send recovery!
+        .or(get_userhome_watches_route)
+        .or(get_direct_messages_route)
+        .or(get_direct_message_new_route(&state_filter, &write_form_filter))
+        .or(post_userhome_multi_route(&state_filter, &write_form_filter, &upload_filter)) //Multiplexed! Info, bio, sensitive OR avatar
         .or(get_login_route)
-        .or(post_login_multi_route(&state_filter, &form_filter)) //Multiplexed! Login OR send recovery!
+        .or(post_login_multi_route(&state_filter, &login_form_filter)) //Multiplexed! Login OR send recovery!
         .or(get_logout_route)
         .or(get_register_route)
         .or(post_register_route)
         .or(get_registerconfirm_route)
-        .or(post_registerconfirm_multi_route(&state_filter, &form_filter)) //Multiplexed! Confirm registration OR resend confirmation!
+        .or(post_registerconfirm_multi_route(&state_filter, &login_form_filter)) //Multiplexed! Confirm registration OR resend confirmation!
         .or(get_recover_route)
         .or(post_recover_route)
         .or(get_sessionsettings_route)
         .or(post_sessionsettings_route)
             .boxed()
         .or(get_imagebrowser_route)
+        .or(post_imagebrowser_route)
         .or(get_widgetthread_route)
         .or(get_votewidget_route)
         .or(post_votewidget_route)
@@ -445,7 +684,104 @@ async fn main()
         .or(post_bbcodepreview_route)
         .or(legacy_page_pid)
         .or(get_integrationtest_route)
-        .recover(handle_rejection)
-    ).run(address).await;
+        .recover(move |err| handle_rejection(err, global_for_reject.clone())))
+        .map(move |path: FullPath, reply| decorate(path, reply, &global_for_security.config()))
+}
+
+/// Run the site until we're told to stop
+async fn serve(source: reload::ConfigSource)
+{
+    let config = source.read();
+    init_logging(&config, false);
+    tracing::info!("Environment: {}\n{:#?}", source.environment.as_deref().unwrap_or(""), config.redacted());
+
+    let global_state = build_state(config);
+    let address = global_state.config().host_address.parse::<SocketAddr>().unwrap();
+    let (address, server) = warp::serve(build_routes(global_state.clone())
+        .with(warp::log::custom(|info| {
+            contentapi::metrics::global().observe_request(metrics::route_label(info.path()), 
+                info.method().as_str(), info.status().as_u16(), info.elapsed());
+            //The proxy checks these constantly, they'd drown out everything else
+            if info.path() == "/healthz" || info.path() == "/readyz" {
+                tracing::debug!(status = info.status().as_u16(), "{:>5} - {} finished", info.method(), info.path());
+            }
+            else {
+                tracing::info!(status = info.status().as_u16(), duration_ms = info.elapsed().as_millis() as u64, "{:>5} - {} finished", info.method(), info.path());
+            }
+        }))
+        //Outside the log, so the finished line above gets the request id too
+        .with(warp::trace(|_| tracing::info_span!("request", request_id = tracing::field::Empty)))
+    ).bind_with_graceful_shutdown(address, global_state.drained());
+
+    tracing::info!("Listening on {}", address);
+    let server = tokio::spawn(server);
+
+    //Metrics can get their own address, so only the scraper has to be able to reach them
+    let startup_config = global_state.config();
+    if !startup_config.metrics_address.is_empty() {
+        let metrics_address = startup_config.metrics_address.parse::<SocketAddr>().unwrap();
+        tracing::info!("Serving metrics on {}", metrics_address);
+        tokio::spawn(warp::serve(metrics::metrics_route(global_state.clone(), true)).bind(metrics_address));
+    }
+    else if startup_config.metrics_token.is_empty() {
+        tracing::info!("No metrics_address or metrics_token set, /metrics is off");
+    }
+
+    tokio::spawn(reload::watch_config(global_state.clone(), source));
+    shutdown_signal().await;
+
+    //Stop looking ready first, and keep serving for a bit so the proxy notices and stops sending us
+    //anything new. THEN stop taking connections and wait (only so long) for what's in flight
+    let config = global_state.config();
+    tracing::info!("Shutting down: not ready, waiting {}ms before draining", config.shutdown_ready_delay_ms);
+    global_state.shutting_down.store(true, Ordering::Relaxed);
+    tokio::time::sleep(Duration::from_millis(config.shutdown_ready_delay_ms)).await;
+    global_state.draining.send_replace(true);
+
+    //Live streams end when draining starts, but anything else that's stuck still needs a limit
+    match tokio::time::timeout(Duration::from_millis(config.shutdown_drain_timeout_ms), server).await {
+        Ok(_) => tracing::info!("All requests finished, shut down cleanly"),
+        Err(_) => tracing::warn!("Gave up waiting on in-flight requests after {}ms", config.shutdown_drain_timeout_ms)
+    }
+}
+
+/// Wait for SIGTERM (what service managers send) or SIGINT (ctrl-c)
+async fn shutdown_signal()
+{
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Couldn't listen for SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => tracing::info!("Got SIGTERM"),
+            _ = tokio::signal::ctrl_c() => tracing::info!("Got SIGINT")
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.expect("Couldn't listen for ctrl-c");
+        tracing::info!("Got ctrl-c");
+    }
+}
+
+/// Set up the global log output. RUST_LOG overrides the level in the config, if it's set
+fn init_logging(config: &Config, to_stderr: bool)
+{
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(&config.log_level)
+            //warp's request span logs its own start/finish lines, ours (in serve) already cover that
+            .add_directive("warp::filters::trace=off".parse().unwrap()));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    //The cli commands print their results to stdout, so logs can't go there
+    match (config.log_format.as_str(), to_stderr) {
+        ("json", false) => builder.json().init(),
+        ("json", true) => builder.json().with_writer(std::io::stderr).init(),
+        ("pretty", false) => builder.pretty().init(),
+        ("pretty", true) => builder.pretty().with_writer(std::io::stderr).init(),
+        (_, false) => builder.init(),
+        (_, true) => builder.with_writer(std::io::stderr).init()
+    }
 }
 
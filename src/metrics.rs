@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use warp::{Filter, Reply, filters::BoxedFilter};
+use warp::hyper::{StatusCode, header};
+
+use crate::paths;
+use crate::state::GlobalState;
+
+//GET /metrics, for prometheus to scrape. The numbers themselves are collected all over (see
+//contentapi::metrics); page requests are counted by the log wrapper at the end of main. Nobody
+//else should see these, so it's only served if there's a token to check or its own address to
+//bind (somewhere only the scraper can reach), and it's off entirely if neither is configured.
+
+/// One segment of a route's path, for matching request paths back to the route
+enum Segment {
+    Literal(&'static str),
+    Text,
+    Number
+}
+
+/// Every route in the table (common::sbs_routes), by name
+macro_rules! route_segments {
+    ($( $name:ident ( $($seg:tt)/ * ); )*) => {
+        const TABLE: &[(&str, &[Segment])] = &[ $( (stringify!($name), &[ $( route_segments!(@segment $seg) ),* ]), )* ];
+    };
+    (@segment $lit:literal) => { Segment::Literal($lit) };
+    (@segment {$param:ident : String}) => { Segment::Text };
+    (@segment {$param:ident : i64}) => { Segment::Number };
+}
+
+common::sbs_routes!(route_segments);
+
+/// Served outside the route table, but still worth their own label
+const STATICPATHS: &[&str] = &["static", "favicon.ico", "robots.txt"];
+
+/// The label a request path is counted under: the name of the route it matched. Anything else is
+/// "other", so someone poking at random urls can't make us keep a new set of numbers for each one
+pub fn route_label(path: &str) -> &'static str
+{
+    //Empty segments are skipped, same as warp does
+    let segments = path.split('/').filter(|s| !s.is_empty()).collect::<Vec<&str>>();
+    let matched = TABLE.iter().find(|(_, route)| {
+        route.len() == segments.len() && route.iter().zip(segments.iter()).all(|(expected, segment)| match expected {
+            Segment::Literal(literal) => literal == segment,
+            Segment::Text => true,
+            Segment::Number => segment.parse::<i64>().is_ok()
+        })
+    });
+    match (matched, segments.first()) {
+        (Some((name, _)), _) => name,
+        (None, Some(first)) => STATICPATHS.iter().find(|s| *s == first).copied().unwrap_or("other"),
+        (None, None) => "other"
+    }
+}
+
+/// Whether the token (from the authorization header) is good enough to see the metrics
+fn authorized(config_token: &str, authorization: Option<&str>) -> bool
+{
+    config_token.is_empty() || authorization.and_then(|a| a.strip_prefix("Bearer ")).map(|t| t.trim() == config_token).unwrap_or(false)
+}
+
+async fn metrics(state: Arc<GlobalState>, separate: bool, authorization: Option<String>) -> Result<Box<dyn Reply>, warp::Rejection>
+{
+    let config = state.config();
+    //On the main address, only if there's no separate one, and never without a token
+    if !separate && (!config.metrics_address.is_empty() || config.metrics_token.is_empty()) {
+        return Err(warp::reject::not_found());
+    }
+
+    if !authorized(&config.metrics_token, authorization.as_deref()) {
+        return Ok(Box::new(warp::reply::with_header(
+            warp::reply::with_status("Missing or wrong metrics token", StatusCode::UNAUTHORIZED),
+            header::WWW_AUTHENTICATE, "Bearer")));
+    }
+
+    Ok(Box::new(warp::reply::with_header(contentapi::metrics::global().render(),
+        header::CONTENT_TYPE, "text/plain; version=0.0.4")))
+}
+
+/// The /metrics route. Separate is for the server on metrics_address, otherwise it's the one on the main address
+pub fn metrics_route(state: Arc<GlobalState>, separate: bool) -> BoxedFilter<(Box<dyn Reply>,)>
+{
+    warp::get()
+        .and(paths::metrics())
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(move |authorization| metrics(state.clone(), separate, authorization))
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_come_from_the_route_table() {
+        assert_eq!(route_label("/"), "index");
+        assert_eq!(route_label("/forum"), "forum");
+        assert_eq!(route_label("/forum/thread/abc"), "forum_thread");
+        assert_eq!(route_label("/forum/thread/abc/12"), "forum_post");
+        assert_eq!(route_label("/forum/edit/post"), "forum_edit_post");
+        assert_eq!(route_label("/forum/react/12"), "forum_react");
+        assert_eq!(route_label("/forum/delete/thread/12"), "forum_delete_thread");
+        assert_eq!(route_label("/userhome/watches/"), "userhome_watches");
+        assert_eq!(route_label("/page/abc/history"), "page_history");
+        assert_eq!(route_label("/widget/votes/5"), "widget_votes");
+        assert_eq!(route_label("/static/main.css"), "static");
+        assert_eq!(route_label("/forum/react/abc"), "other");
+        assert_eq!(route_label("/forum/thread"), "other");
+        assert_eq!(route_label("/widget/nope"), "other");
+        assert_eq!(route_label("/wp-admin/install.php"), "other");
+    }
+}
@@ -8,6 +8,7 @@ use warp::filters::*;
 use crate::state::*;
 use crate::generic_handlers::*;
 use crate::*;
+use common::routes::*;
 
 /// 'GET':/forum is a heavily multiplexed route, since it could either be the root, the old fcid
 /// threadlist, the old ftid post list, or the old fpid direct link to post
@@ -75,41 +76,27 @@ pub fn get_forum_route(state_filter: &BoxedFilter<(RequestContext,)>) -> BoxedFi
         ).boxed(); 
 
     warp::get()
-        .and(warp::path!("forum"))
+        .and(paths::forum())
         .and(forum_fcid.or(forum_ftid).or(forum_fpid).or(forum_main))
         .boxed()
 }
 
 pub fn get_forum_edit_thread_route(state_filter: &BoxedFilter<(RequestContext,)>, form_filter: &BoxedFilter<()>) -> BoxedFilter<(impl Reply,)> 
 {
-    //struct doesn't need to escape this function!
-    #[allow(dead_code)]
-    #[derive(Deserialize, Debug)]
-    struct CategoryParameter { 
-        category: String
-    }
-
     let thread_new = warp::any()
-        .and(warp::query::<CategoryParameter>())
+        .and(warp::query::<NewThreadQuery>())
         .and(state_filter.clone())
-        .and_then(|catparam: CategoryParameter, context:RequestContext| 
+        .and_then(|catparam: NewThreadQuery, context:RequestContext| 
             std_resp!(
                 pages::forum_edit_thread::get_render(pc!(context), Some(catparam.category), None),
                 context
             ) 
         ).boxed(); 
-    
-    //Don't forget to add the other stuff!
-    #[allow(dead_code)]
-    #[derive(Deserialize, Debug)]
-    struct ThreadParameter { 
-        thread: String
-    }
 
     let thread_edit = warp::any()
-        .and(warp::query::<ThreadParameter>())
+        .and(warp::query::<EditThreadQuery>())
         .and(state_filter.clone())
-        .and_then(|threadparam: ThreadParameter, context:RequestContext| 
+        .and_then(|threadparam: EditThreadQuery, context:RequestContext| 
             std_resp!(
                 pages::forum_edit_thread::get_render(pc!(context), None, Some(threadparam.thread)),
                 context
@@ -117,13 +104,13 @@ pub fn get_forum_edit_thread_route(state_filter: &BoxedFilter<(RequestContext,)>
         ).boxed(); 
 
     let thread_post = warp::any()
-        .and(warp::body::form::<common::forms::ThreadForm>())
+        .and(csrf_form::<common::forms::ThreadForm>())
         .and(state_filter.clone())
         .and_then(|form: common::forms::ThreadForm, context: RequestContext| {
             std_resp!(pages::forum_edit_thread::post_render(pc!(context), form), context) 
         }).boxed();
 
-    warp::path!("forum" / "edit" / "thread")
+    paths::forum_edit_thread()
         .and(warp::get().and(thread_new.or(thread_edit))
             .or(warp::post().and(form_filter.clone()).and(thread_post)))
         .boxed()
@@ -131,38 +118,21 @@ pub fn get_forum_edit_thread_route(state_filter: &BoxedFilter<(RequestContext,)>
 
 pub fn get_forum_edit_post_route(state_filter: &BoxedFilter<(RequestContext,)>, form_filter: &BoxedFilter<()>) -> BoxedFilter<(impl Reply,)> 
 {
-    //struct doesn't need to escape this function!
-    #[allow(dead_code)]
-    #[derive(Deserialize, Debug)]
-    struct NewPostParameters { 
-        thread: String,
-        reply: Option<i64>,
-        widget: Option<bool>
-    }
-
     let post_new = warp::any()
-        .and(warp::query::<NewPostParameters>())
+        .and(warp::query::<NewPostQuery>())
         .and(state_filter.clone())
-        .and_then(|param: NewPostParameters, context:RequestContext| 
+        .and_then(|param: NewPostQuery, context:RequestContext| 
             std_resp!(
                 pages::forum_edit_post::get_render(pc!(context), Some(param.thread), None, param.reply, 
                     if let Some(wid) = param.widget {wid} else { false }),
                 context
             ) 
         ).boxed(); 
-    
-    //Don't forget to add the other stuff!
-    #[allow(dead_code)]
-    #[derive(Deserialize, Debug)]
-    struct EditPostParameter { 
-        post: i64,
-        widget: Option<bool>
-    }
 
     let post_edit = warp::any()
-        .and(warp::query::<EditPostParameter>())
+        .and(warp::query::<EditPostQuery>())
         .and(state_filter.clone())
-        .and_then(|param: EditPostParameter, context:RequestContext| 
+        .and_then(|param: EditPostQuery, context:RequestContext| 
             std_resp!(
                 pages::forum_edit_post::get_render(pc!(context), None, Some(param.post), None, 
                     if let Some(wid) = param.widget {wid} else { false }),
@@ -171,48 +141,56 @@ pub fn get_forum_edit_post_route(state_filter: &BoxedFilter<(RequestContext,)>,
         ).boxed(); 
 
     let post_post = warp::any()
-        .and(warp::body::form::<common::forms::PostForm>())
+        .and(csrf_form::<common::forms::PostForm>())
         .and(state_filter.clone())
         .and_then(|form: common::forms::PostForm, context: RequestContext| {
             std_resp!(pages::forum_edit_post::post_render(pc!(context), form), context) 
         }).boxed();
 
-    warp::path!("forum" / "edit" / "post")
+    paths::forum_edit_post()
         .and(warp::get().and(post_new.or(post_edit))
             .or(warp::post().and(form_filter.clone()).and(post_post)))
         .boxed()
 }
 
-pub fn get_page_edit_route(state_filter: &BoxedFilter<(RequestContext,)>, form_filter: &BoxedFilter<()>) -> BoxedFilter<(impl Reply,)> 
+pub fn get_direct_message_new_route(state_filter: &BoxedFilter<(RequestContext,)>, form_filter: &BoxedFilter<()>) -> BoxedFilter<(impl Reply,)> 
 {
-    //struct doesn't need to escape this function!
-    #[allow(dead_code)]
-    #[derive(Deserialize, Debug)]
-    struct SubtypeParameter { 
-        mode: String
-    }
+    let message_new = warp::any()
+        .and(warp::query::<NewMessageQuery>())
+        .and(state_filter.clone())
+        .and_then(|param: NewMessageQuery, context:RequestContext| 
+            std_resp!(pages::directmessages::get_compose_render(pc!(context), param.to), context) 
+        ).boxed(); 
+
+    let message_post = warp::any()
+        .and(csrf_form::<common::forms::DirectMessageForm>())
+        .and(state_filter.clone())
+        .and_then(|form: common::forms::DirectMessageForm, context: RequestContext| {
+            std_resp!(pages::directmessages::post_compose_render(pc!(context), form), context) 
+        }).boxed();
 
+    paths::messages_new()
+        .and(warp::get().and(message_new)
+            .or(warp::post().and(form_filter.clone()).and(message_post)))
+        .boxed()
+}
+
+pub fn get_page_edit_route(state_filter: &BoxedFilter<(RequestContext,)>, form_filter: &BoxedFilter<()>) -> BoxedFilter<(impl Reply,)> 
+{
     let page_new = warp::any()
-        .and(warp::query::<SubtypeParameter>())
+        .and(warp::query::<NewPageQuery>())
         .and(state_filter.clone())
-        .and_then(|param: SubtypeParameter, context:RequestContext| 
+        .and_then(|param: NewPageQuery, context:RequestContext| 
             std_resp!(
                 pages::page_edit::get_render(pc!(context), Some(param.mode), None),
                 context
             ) 
         ).boxed(); 
-    
-    //Don't forget to add the other stuff!
-    #[allow(dead_code)]
-    #[derive(Deserialize, Debug)]
-    struct PageParameter { 
-        page: String
-    }
 
     let page_edit = warp::any()
-        .and(warp::query::<PageParameter>())
+        .and(warp::query::<EditPageQuery>())
         .and(state_filter.clone())
-        .and_then(|param: PageParameter, context:RequestContext| 
+        .and_then(|param: EditPageQuery, context:RequestContext| 
             std_resp!(
                 pages::page_edit::get_render(pc!(context), None, Some(param.page)),
                 context
@@ -220,13 +198,13 @@ pub fn get_page_edit_route(state_filter: &BoxedFilter<(RequestContext,)>, form_f
         ).boxed(); 
 
     let page_post = warp::any()
-        .and(warp::body::form::<common::forms::PageForm>())
+        .and(csrf_form::<common::forms::PageForm>())
         .and(state_filter.clone())
         .and_then(|form: common::forms::PageForm, context: RequestContext| {
             std_resp!(pages::page_edit::post_render(pc!(context), form), context) 
         }).boxed();
 
-    warp::path!("page" / "edit")
+    paths::page_edit()
         .and(warp::get().and(page_new.or(page_edit))
             .or(warp::post().and(form_filter.clone()).and(page_post)))
         .boxed()
@@ -239,35 +217,36 @@ pub fn post_login_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, for
 {
     // The standard login post, main endpoint
     let login_post = warp::any()
-        .and(warp::body::form::<pages::login::Login>())
+        .and(csrf_form::<pages::login::Login>())
         .and(state_filter.clone())
         .and_then(|form: pages::login::Login, context: RequestContext| {
             let gc = context.global_state.clone();
             let login = form.to_api_login(
-                gc.config.default_cookie_expire, 
-                gc.config.long_cookie_expire);
+                gc.config().default_cookie_expire, 
+                gc.config().long_cookie_expire);
             async move {
                 let (response,token) = pages::login::post_login_render(pc!(context), &login).await;
                 handle_response_with_token(response, &gc.link_config, token, login.expireSeconds)
+                    .map(|reply| with_stamp(reply, &context.stamp))
             }
         }).boxed();
     
     // The secondary endpoint, to send account recovery emails
     let recover_email_post = warp::any()
         .and(qflag!(recover)) 
-        .and(warp::body::form::<common::forms::EmailGeneric>())
+        .and(csrf_form::<common::forms::EmailGeneric>())
         .and(state_filter.clone())
         .and_then(|_query, form: common::forms::EmailGeneric, context: RequestContext| {
             async move {
                 let gc = context.global_state.clone();
                 let response = pages::login::post_login_recover(pc!(context), &form).await;
-                handle_response(response, &gc.link_config)
+                handle_response(response, &gc.link_config).map(|reply| with_stamp(reply, &context.stamp))
             }
         }).boxed();
 
     //ALL post routes!
     warp::post()
-        .and(warp::path!("login"))
+        .and(paths::login())
         .and(form_filter.clone())
         .and(recover_email_post.or(login_post))
         .boxed()
@@ -280,13 +259,14 @@ pub fn post_registerconfirm_multi_route(state_filter: &BoxedFilter<(RequestConte
 {
     // Primary endpoint: finish up confirmation. Because of that, we might get a token back (on success)
     let registerconfirm_post = warp::any()
-        .and(warp::body::form::<contentapi::forms::RegisterConfirm>())
+        .and(csrf_form::<contentapi::forms::RegisterConfirm>())
         .and(state_filter.clone())
         .and_then(|form, context: RequestContext| {
             async move {
                 let gc = context.global_state.clone();
                 let (response,token) = pages::registerconfirm::post_render(pc!(context), &form).await;
-                handle_response_with_token(response, &gc.link_config, token, gc.config.default_cookie_expire as i64)
+                handle_response_with_token(response, &gc.link_config, token, gc.config().default_cookie_expire as i64)
+                    .map(|reply| with_stamp(reply, &context.stamp))
             }
         })
         .boxed();
@@ -294,28 +274,28 @@ pub fn post_registerconfirm_multi_route(state_filter: &BoxedFilter<(RequestConte
     // Secondary endpoint: resend confirmation email
     let registerconfirm_email_post = warp::any()
         .and(qflag!(resend)) 
-        .and(warp::body::form::<common::forms::EmailGeneric>())
+        .and(csrf_form::<common::forms::EmailGeneric>())
         .and(state_filter.clone())
         .and_then(|_query, form: common::forms::EmailGeneric, context: RequestContext| 
             std_resp!(pages::registerconfirm::post_email_render(pc!(context), &form), context)
         ).boxed();
 
     warp::post()
-        .and(warp::path!("register"/"confirm"))
+        .and(paths::register_confirm())
         .and(form_filter.clone())
         .and(registerconfirm_email_post.or(registerconfirm_post))
         .boxed()
 
 }
 
-/// 'POST':/userhome is a 3 way multiplexed route, where you can post user updates (primary), user bio updates
-/// (secondary), and finally sensitive updates
-pub fn post_userhome_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, form_filter: &BoxedFilter<()>) -> 
-    BoxedFilter<(impl Reply,)> 
+/// 'POST':/userhome is a 4 way multiplexed route, where you can post user updates (primary), user bio updates
+/// (secondary), sensitive updates, and finally avatar uploads
+pub fn post_userhome_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, form_filter: &BoxedFilter<()>,
+    upload_filter: &BoxedFilter<(common::forms::FileUploadForm,)>) -> BoxedFilter<(impl Reply,)> 
 {
     // Primary endpoint: update regular user data
     let userhome_post = warp::any()
-        .and(warp::body::form::<common::forms::UserUpdate>())
+        .and(csrf_form::<common::forms::UserUpdate>())
         .and(state_filter.clone())
         .and_then(|form, context: RequestContext| 
             std_resp!(pages::userhome::post_info_render(pc!(context), form), context)
@@ -324,7 +304,7 @@ pub fn post_userhome_multi_route(state_filter: &BoxedFilter<(RequestContext,)>,
     // Secondary endpoint: user bio updates
     let userhome_bio_post = warp::any()
         .and(qflag!(bio)) 
-        .and(warp::body::form::<common::forms::BasicPage>())
+        .and(csrf_form::<common::forms::BasicPage>())
         .and(state_filter.clone())
         .and_then(|_query, form, context: RequestContext| 
             std_resp!(pages::userhome::post_bio_render(pc!(context), form), context)
@@ -333,16 +313,25 @@ pub fn post_userhome_multi_route(state_filter: &BoxedFilter<(RequestContext,)>,
     // Tertiary endpoint: user sensitive updates
     let userhome_sensitive_post = warp::any()
         .and(qflag!(sensitive)) 
-        .and(warp::body::form::<contentapi::forms::UserSensitive>())
+        .and(csrf_form::<contentapi::forms::UserSensitive>())
         .and(state_filter.clone())
         .and_then(|_query, form, context: RequestContext| 
             std_resp!(pages::userhome::post_sensitive_render(pc!(context), form), context) 
         ).boxed();
 
+    // Quaternary endpoint: avatar upload. This is multipart, so it has to go before anything that reads a form
+    let userhome_avatar_post = warp::any()
+        .and(qflag!(avatar)) 
+        .and(upload_filter.clone())
+        .and(state_filter.clone())
+        .and_then(|_query, upload, context: RequestContext| 
+            std_resp!(pages::userhome::post_avatar_render(pc!(context), upload), context) 
+        ).boxed();
+
     warp::post()
-        .and(warp::path!("userhome"))
+        .and(paths::userhome())
         .and(form_filter.clone())
-        .and(userhome_bio_post.or(userhome_sensitive_post).or(userhome_post))
+        .and(userhome_avatar_post.or(userhome_bio_post).or(userhome_sensitive_post).or(userhome_post))
         .boxed()
 
 }
@@ -353,7 +342,7 @@ pub fn post_admin_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, for
     let admin_registrationconfig_post = warp::any()
         .and(qflag!(registrationconfig)) 
         //For now, we use the direct form
-        .and(warp::body::form::<contentapi::forms::RegistrationConfig>())
+        .and(csrf_form::<contentapi::forms::RegistrationConfig>())
         .and(state_filter.clone())
         .and_then(|_query, form, context: RequestContext| 
             std_resp!(pages::admin::post_registrationconfig(pc!(context), form), context)
@@ -361,7 +350,7 @@ pub fn post_admin_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, for
 
     let admin_frontpage_post = warp::any()
         .and(qflag!(frontpage)) 
-        .and(warp::body::form::<common::forms::BasicPage>())
+        .and(csrf_form::<common::forms::BasicPage>())
         .and(state_filter.clone())
         .and_then(|_query, form, context: RequestContext| 
             std_resp!(pages::admin::post_frontpage(pc!(context), form), context)
@@ -369,7 +358,7 @@ pub fn post_admin_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, for
 
     let admin_docscustom_post = warp::any()
         .and(qflag!(docscustom)) 
-        .and(warp::body::form::<common::forms::BasicPage>())
+        .and(csrf_form::<common::forms::BasicPage>())
         .and(state_filter.clone())
         .and_then(|_query, form, context: RequestContext| 
             std_resp!(pages::admin::post_docscustom(pc!(context), form), context)
@@ -377,14 +366,14 @@ pub fn post_admin_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, for
 
     let admin_alert_post = warp::any()
         .and(qflag!(alert)) 
-        .and(warp::body::form::<common::forms::BasicPage>())
+        .and(csrf_form::<common::forms::BasicPage>())
         .and(state_filter.clone())
         .and_then(|_query, form, context: RequestContext| 
             std_resp!(pages::admin::post_alert(pc!(context), form), context)
         ).boxed();
 
     warp::post()
-        .and(warp::path!("admin"))
+        .and(paths::admin())
         .and(form_filter.clone())
         .and(admin_registrationconfig_post.or(admin_frontpage_post).or(admin_alert_post).or(admin_docscustom_post))
         .boxed()
@@ -394,11 +383,13 @@ pub fn post_admin_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, for
 pub fn post_user_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, form_filter: &BoxedFilter<()>) -> 
     BoxedFilter<(impl Reply,)> 
 {
-    let base_route = warp::post().and(warp::path!("user" / String)).and(form_filter.clone());
+    //The flag goes before the form filter so only the route actually taken counts against the rate limit
+    let base_route = warp::post().and(paths::user());
 
     let user_ban_route = base_route.clone()
         .and(qflag!(ban)) 
-        .and(warp::body::form::<common::forms::BanForm>())
+        .and(form_filter.clone())
+        .and(csrf_form::<common::forms::BanForm>())
         .and(state_filter.clone())
         .and_then(|username, _query, form, context: RequestContext| 
             std_resp!(pages::user::post_ban(pc!(context), username, form), context)
@@ -406,7 +397,8 @@ pub fn post_user_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, form
 
     let user_unban_route = base_route.clone()
         .and(qflag!(unban)) 
-        .and(warp::body::form::<common::forms::UnbanForm>())
+        .and(form_filter.clone())
+        .and(csrf_form::<common::forms::UnbanForm>())
         .and(state_filter.clone())
         .and_then(|username, _query, form, context: RequestContext| 
             std_resp!(pages::user::post_unban(pc!(context), username, form), context)
@@ -414,7 +406,8 @@ pub fn post_user_multi_route(state_filter: &BoxedFilter<(RequestContext,)>, form
 
     let user_updateinfo_route = base_route.clone()
         .and(qflag!(userinfo)) 
-        .and(warp::body::form::<common::forms::UserUpdate>())
+        .and(form_filter.clone())
+        .and(csrf_form::<common::forms::UserUpdate>())
         .and(state_filter.clone())
         .and_then(|username, _query, form, context: RequestContext| 
             std_resp!(pages::user::post_userinfo(pc!(context), username, form), context)
@@ -0,0 +1,43 @@
+use warp::{Filter, filters::BoxedFilter};
+
+//The path half of every route, from the same table the links are built from (see common::routes),
+//so the two can't drift apart. Each function matches exactly its path (nothing left over) and
+//extracts the params in order (String params already percent decoded); the method, query and
+//everything else is still up to the route.
+
+/// Turns the route table into a filter function per route
+macro_rules! route_filters {
+    ($( $name:ident ( $($seg:tt)/ * ); )*) => {
+        $( route_filters!(@filter $name [] [warp::any()] $($seg)*); )*
+    };
+    (@filter $name:ident [$($ty:ident)*] [$filter:expr]) => {
+        pub fn $name() -> BoxedFilter<($($ty,)*)> {
+            $filter.and(warp::path::end()).boxed()
+        }
+    };
+    (@filter $name:ident [$($tys:ident)*] [$filter:expr] $lit:literal $($rest:tt)*) => {
+        route_filters!(@filter $name [$($tys)*] [$filter.and(warp::path($lit))] $($rest)*);
+    };
+    (@filter $name:ident [$($tys:ident)*] [$filter:expr] {$param:ident : String} $($rest:tt)*) => {
+        route_filters!(@filter $name [$($tys)* String] [$filter.and(warp::path::param::<String>().map(|raw: String| common::routes::from_segment(&raw)))] $($rest)*);
+    };
+    (@filter $name:ident [$($tys:ident)*] [$filter:expr] {$param:ident : $ty:ident} $($rest:tt)*) => {
+        route_filters!(@filter $name [$($tys)* $ty] [$filter.and(warp::path::param::<$ty>())] $($rest)*);
+    };
+}
+
+common::sbs_routes!(route_filters);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn string_params_are_decoded() {
+        let keyword = warp::test::request().path("/tags/c%2B%2B%20fun").filter(&tag()).await.unwrap();
+        assert_eq!(keyword, "c++ fun");
+        let (hash, post) = warp::test::request().path("/forum/thread/abc/12").filter(&forum_post()).await.unwrap();
+        assert_eq!((hash.as_str(), post), ("abc", 12));
+        assert!(warp::test::request().path("/tags/a/b").filter(&tag()).await.is_err());
+    }
+}
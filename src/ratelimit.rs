@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use warp::{Filter, filters::BoxedFilter, reject::Reject};
+
+use crate::SESSIONCOOKIE;
+use crate::state::GlobalState;
+
+//Token buckets for the POST routes. Everything that posts costs a token from the bucket for the
+//client's ip AND (if logged in) the bucket for their session token, so neither switching networks
+//nor logging out gets you out of it. Each group of routes has its own buckets and limits: login
+//stuff is tight since each one is a password guess, widgets are loose since pages call them a lot.
+
+/// Don't look for stale buckets more often than this
+const SWEEPINTERVAL: Duration = Duration::from_secs(60);
+
+/// A limit that never refills (per_minute 0) still has to let go of its buckets sometime, or they'd
+/// pile up forever. They're forgotten (so back to a full burst) this long after they were last used
+const NOREFILLTTL: Duration = Duration::from_secs(60 * 60);
+
+/// One group's limit, from settings.toml. A burst of 0 turns limiting off for the group
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct RateLimit {
+    pub burst: u32,      //How many you can do all at once (the bucket size)
+    pub per_minute: u32  //How fast the bucket fills back up
+}
+
+impl RateLimit {
+    fn per_second(&self) -> f64 { self.per_minute as f64 / 60.0 }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RateLimitGroup {
+    Login,  //Login, register, recovery, all the stuff that sends emails or checks passwords
+    Write,  //Anything that changes something through the api
+    Widget  //Previews and votes, which pages can call a lot but still cost us something
+}
+
+impl RateLimitGroup {
+    pub fn limit<'a>(&self, config: &'a crate::Config) -> &'a RateLimit {
+        match self {
+            RateLimitGroup::Login => &config.ratelimit_login,
+            RateLimitGroup::Write => &config.ratelimit_write,
+            RateLimitGroup::Widget => &config.ratelimit_widget
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum BucketKey {
+    Ip(IpAddr),
+    Token(String)
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    full_at: Instant //When it'll be back to a full burst if left alone
+}
+
+impl Bucket {
+    /// Fill the bucket back up for however long it's been since it was last touched
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = if now >= self.full_at { limit.burst as f64 } else { (self.tokens + elapsed * limit.per_second()).min(limit.burst as f64) };
+        self.last = now;
+    }
+
+    fn take(&mut self, limit: &RateLimit) {
+        self.tokens -= 1.0;
+        let rate = limit.per_second();
+        let refill = if rate > 0.0 { Duration::from_secs_f64((limit.burst as f64 - self.tokens) / rate) } else { NOREFILLTTL };
+        self.full_at = self.last.checked_add(refill).unwrap_or(self.last + NOREFILLTTL);
+    }
+
+    /// Seconds until there's a token again
+    fn wait(&self, limit: &RateLimit, now: Instant) -> f64 {
+        let rate = limit.per_second();
+        if self.tokens >= 1.0 { 0.0 }
+        else if rate > 0.0 { (1.0 - self.tokens) / rate }
+        else { self.full_at.saturating_duration_since(now).as_secs_f64() }
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    buckets: HashMap<(RateLimitGroup, BucketKey), Bucket>,
+    last_sweep: Instant
+}
+
+/// Every bucket for every group. One of these lives in the global state
+#[derive(Debug)]
+pub struct RateLimiter {
+    state: Mutex<LimiterState>
+}
+
+/// The rejection for going over the limit. handle_rejection turns this into the 429 page
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after: u64 //Seconds
+}
+
+impl Reject for RateLimited {}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self { state: Mutex::new(LimiterState { buckets: HashMap::new(), last_sweep: Instant::now() }) }
+    }
+}
+
+impl RateLimiter {
+    /// Take a token from the ip's bucket and the session's bucket (if there is one) in the given group.
+    /// Either both are taken or neither; if either bucket is empty, you get back how many seconds until it isn't
+    pub fn check(&self, group: RateLimitGroup, limit: &RateLimit, ip: Option<IpAddr>, token: Option<&str>) -> Result<(), u64>
+    {
+        self.check_at(group, limit, ip, token, Instant::now())
+    }
+
+    fn check_at(&self, group: RateLimitGroup, limit: &RateLimit, ip: Option<IpAddr>, token: Option<&str>, now: Instant) -> Result<(), u64>
+    {
+        if limit.burst == 0 {
+            return Ok(());
+        }
+
+        let mut keys = Vec::new();
+        if let Some(ip) = ip { keys.push(BucketKey::Ip(ip)); }
+        if let Some(token) = token.filter(|t| !t.is_empty()) { keys.push(BucketKey::Token(token.to_string())); }
+
+        //A poisoned lock means something panicked mid-update; the buckets are still fine to use
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        //Anything that's filled all the way back up is the same as not having a bucket at all, and
+        //tokens are whatever the client sends us, so these would pile up forever otherwise
+        if now.saturating_duration_since(state.last_sweep) > SWEEPINTERVAL {
+            state.buckets.retain(|_, bucket| bucket.full_at > now);
+            state.last_sweep = now;
+        }
+
+        let mut wait: f64 = 0.0;
+        for key in &keys {
+            let bucket = state.buckets.entry((group, key.clone())).or_insert(Bucket { tokens: limit.burst as f64, last: now, full_at: now });
+            bucket.refill(limit, now);
+            wait = wait.max(bucket.wait(limit, now));
+        }
+
+        if wait > 0.0 {
+            return Err(wait.ceil() as u64);
+        }
+
+        for key in keys {
+            if let Some(bucket) = state.buckets.get_mut(&(group, key)) {
+                bucket.take(limit);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Who the request is from. Behind a reverse proxy, the remote address is always the proxy, so if the config
+/// says to trust it, use the LAST address in x-forwarded-for (the one the proxy added; anything before it is
+/// whatever the client claimed)
+fn client_ip(remote: Option<SocketAddr>, forwarded: Option<String>, trust_forwarded: bool) -> Option<IpAddr>
+{
+    if trust_forwarded {
+        if let Some(ip) = forwarded.as_deref().and_then(|f| f.rsplit(',').next()).and_then(|ip| ip.trim().parse::<IpAddr>().ok()) {
+            return Some(ip);
+        }
+    }
+    remote.map(|r| r.ip())
+}
+
+/// A filter which rejects with RateLimited if the client is over the limit for the given group. Put it
+/// AFTER the path (and anything else that picks the route) so only the route actually taken costs a token
+pub fn ratelimit_filter(state: Arc<GlobalState>, group: RateLimitGroup) -> BoxedFilter<()>
+{
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::cookie::optional::<String>(SESSIONCOOKIE))
+        .and_then(move |remote: Option<SocketAddr>, forwarded: Option<String>, token: Option<String>| {
+            let state = state.clone();
+            async move {
+                let config = state.config();
+                let ip = client_ip(remote, forwarded, config.ratelimit_trust_forwarded);
+                match state.rate_limiter.check(group, group.limit(&config), ip, token.as_deref()) {
+                    Ok(()) => Ok(()),
+                    Err(retry_after) => {
+                        tracing::warn!("Rate limited ({:?}) {:?}, retry in {}s", group, ip, retry_after);
+                        Err(warp::reject::custom(RateLimited { retry_after }))
+                    }
+                }
+            }
+        })
+        .untuple_one()
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: Option<IpAddr> = Some(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+    const OTHERIP: Option<IpAddr> = Some(IpAddr::V4(std::net::Ipv4Addr::BROADCAST));
+
+    fn bucket_count(limiter: &RateLimiter) -> usize {
+        limiter.state.lock().unwrap().buckets.len()
+    }
+
+    #[test]
+    fn buckets_refill() {
+        let limiter = RateLimiter::default();
+        let limit = RateLimit { burst: 2, per_minute: 60 };
+        let start = Instant::now();
+        assert_eq!(limiter.check_at(RateLimitGroup::Write, &limit, IP, None, start), Ok(()));
+        assert_eq!(limiter.check_at(RateLimitGroup::Write, &limit, IP, None, start), Ok(()));
+        assert_eq!(limiter.check_at(RateLimitGroup::Write, &limit, IP, None, start), Err(1));
+        //Other groups have their own buckets
+        assert_eq!(limiter.check_at(RateLimitGroup::Login, &limit, IP, None, start), Ok(()));
+        assert_eq!(limiter.check_at(RateLimitGroup::Write, &limit, IP, None, start + Duration::from_secs(1)), Ok(()));
+        assert_eq!(limiter.check_at(RateLimitGroup::Write, &limit, IP, None, start + Duration::from_secs(1)), Err(1));
+    }
+
+    #[test]
+    fn takes_both_or_neither() {
+        let limiter = RateLimiter::default();
+        let limit = RateLimit { burst: 1, per_minute: 1 };
+        let now = Instant::now();
+        //Use up the session's bucket from somewhere else
+        assert_eq!(limiter.check_at(RateLimitGroup::Write, &limit, OTHERIP, Some("session"), now), Ok(()));
+        assert_eq!(limiter.check_at(RateLimitGroup::Write, &limit, IP, Some("session"), now), Err(60));
+        //Which didn't cost this ip anything
+        assert_eq!(limiter.check_at(RateLimitGroup::Write, &limit, IP, None, now), Ok(()));
+        assert_eq!(limiter.check_at(RateLimitGroup::Write, &limit, IP, None, now), Err(60));
+    }
+
+    #[test]
+    fn sweeps_full_buckets() {
+        let limiter = RateLimiter::default();
+        let limit = RateLimit { burst: 5, per_minute: 60 };
+        let never = RateLimit { burst: 1, per_minute: 0 };
+        let start = Instant::now();
+        limiter.check_at(RateLimitGroup::Write, &limit, IP, Some("session"), start).unwrap();
+        limiter.check_at(RateLimitGroup::Login, &never, IP, None, start).unwrap();
+        assert_eq!(limiter.check_at(RateLimitGroup::Login, &never, IP, None, start), Err(NOREFILLTTL.as_secs()));
+        assert_eq!(bucket_count(&limiter), 3);
+
+        //The refilling ones are full again long before the next sweep, the one that never refills isn't
+        let later = start + SWEEPINTERVAL + Duration::from_secs(1);
+        limiter.check_at(RateLimitGroup::Widget, &limit, None, None, later).unwrap();
+        assert_eq!(bucket_count(&limiter), 1);
+        assert!(limiter.check_at(RateLimitGroup::Login, &never, IP, None, later).is_err());
+
+        let much_later = start + NOREFILLTTL + SWEEPINTERVAL + Duration::from_secs(2);
+        limiter.check_at(RateLimitGroup::Widget, &limit, None, None, much_later).unwrap();
+        assert_eq!(bucket_count(&limiter), 0);
+        assert_eq!(limiter.check_at(RateLimitGroup::Login, &never, IP, None, much_later), Ok(()));
+    }
+}
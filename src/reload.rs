@@ -0,0 +1,250 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::{Config, OptConfig, CONFIGNAME};
+use crate::state::GlobalState;
+
+//Reloading settings.toml (and the environment's overlay) without a restart, whenever one of them
+//changes or we get a SIGHUP. A bad file never replaces a good config: if anything fails to parse or
+//validate, the old one stays. Some settings were already used to build things at startup (the
+//address we're bound to, the api client, the logger...), so changes to those only warn.
+
+/// How often to check whether the config files changed
+const WATCHINTERVAL: Duration = Duration::from_secs(2);
+
+/// Where the config comes from: a base file, the environment's overlay next to it (like settings.Dev.toml),
+/// and anything given on the command line over top of both
+#[derive(Clone, Debug)]
+pub struct ConfigSource {
+    pub dir: String,
+    pub basename: String,
+    pub environment: Option<String>,
+    pub bind: Option<String> //Overrides host_address
+}
+
+impl Default for ConfigSource {
+    fn default() -> Self {
+        Self { dir: String::from("."), basename: String::from(CONFIGNAME), environment: None, bind: None }
+    }
+}
+
+impl ConfigSource {
+    /// Use the given base file instead of ./settings.toml. The overlay is looked for next to it
+    pub fn set_config_path(&mut self, path: &str) {
+        let path = std::path::Path::new(path);
+        self.dir = path.parent().map(|p| p.to_string_lossy().into_owned()).filter(|p| !p.is_empty()).unwrap_or_else(|| String::from("."));
+        self.basename = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| String::from(CONFIGNAME));
+    }
+
+    /// The files the config is read from, in order, same as Config::read_with_environment_toml_dir
+    pub fn files(&self) -> Vec<String> {
+        let mut files = vec![format!("{}/{}.toml", self.dir, self.basename)];
+        if let Some(environment) = &self.environment {
+            files.push(format!("{}/{}.{}.toml", self.dir, self.basename, environment));
+        }
+        files
+    }
+
+    fn apply_overrides(&self, config: &mut Config) {
+        if let Some(bind) = &self.bind {
+            config.host_address = bind.clone();
+        }
+    }
+
+    /// Read the config the way it's always been read at startup: anything that doesn't parse is skipped
+    pub fn read(&self) -> Config {
+        let mut config = Config::read_with_environment_toml_dir(&self.dir, &self.basename, self.environment.as_deref());
+        self.apply_overrides(&mut config);
+        config
+    }
+
+    /// Read the config, but unlike read (which just skips anything it can't parse), fail if any of the files
+    /// are broken or the result doesn't make sense. The overlay is allowed to not exist
+    pub fn read_checked(&self) -> Result<Config, String> {
+        for (index, file) in self.files().iter().enumerate() {
+            match std::fs::read_to_string(file) {
+                Ok(data) => { toml::from_str::<OptConfig>(&data).map_err(|e| format!("{}: {}", file, e))?; },
+                Err(error) if index == 0 => return Err(format!("{}: {}", file, error)),
+                Err(_) => {}
+            }
+        }
+        let config = self.read();
+        validate(&config)?;
+        Ok(config)
+    }
+}
+
+fn modified(files: &[String]) -> Vec<Option<SystemTime>>
+{
+    files.iter().map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok()).collect()
+}
+
+/// Things that parse fine but would break pages
+fn validate(config: &Config) -> Result<(), String>
+{
+    let counts = [
+        ("default_imagebrowser_count", config.default_imagebrowser_count),
+        ("default_category_threads", config.default_category_threads),
+        ("default_display_threads", config.default_display_threads),
+        ("default_display_posts", config.default_display_posts),
+        ("default_display_pages", config.default_display_pages),
+        ("default_activity_count", config.default_activity_count)
+    ];
+    if let Some((name, count)) = counts.iter().find(|(_, count)| *count <= 0) {
+        return Err(format!("{} must be more than 0 (was {})", name, count));
+    }
+    if config.default_cookie_expire <= 0 || config.long_cookie_expire <= 0 {
+        return Err(String::from("Cookie expirations must be more than 0"));
+    }
+    config.host_address.parse::<std::net::SocketAddr>().map_err(|e| format!("host_address: {}", e))?;
+    if !config.metrics_address.is_empty() {
+        config.metrics_address.parse::<std::net::SocketAddr>().map_err(|e| format!("metrics_address: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Keep the running value for settings that only take effect at startup, warning about any that changed
+macro_rules! keep_running {
+    ($old:ident, $new:ident, $($field:ident),*$(,)?) => {
+        $(
+            if format!("{:?}", $old.$field) != format!("{:?}", $new.$field) {
+                tracing::warn!("{} changed, but that needs a restart! Still using {:?}", stringify!($field), $old.$field);
+                $new.$field = $old.$field.clone();
+            }
+        )*
+    };
+}
+
+/// Everything that was used to build something at startup stays as it was
+fn keep_startup_settings(old: &Config, config: &mut Config)
+{
+    keep_running!(old, config,
+        host_address, metrics_address, http_root, api_endpoint, api_fileraw, body_maxsize,
+        api_timeout_ms, api_retries, api_retry_backoff_ms, api_breaker_threshold, api_breaker_cooldown_ms,
+        api_pool_max_idle, api_pool_idle_timeout_ms, api_http2, api_cache_size, log_level, log_format);
+}
+
+/// Read the config again and swap it in if it's good
+fn reload(state: &GlobalState, source: &ConfigSource)
+{
+    let mut config = match source.read_checked() {
+        Ok(config) => config,
+        Err(error) => {
+            tracing::error!("Not reloading config, keeping the old one: {}", error);
+            return;
+        }
+    };
+
+    keep_startup_settings(&state.config(), &mut config);
+    tracing::info!("Reloaded config");
+    tracing::debug!("{:#?}", config.redacted());
+    state.set_config(config);
+}
+
+/// Reload whenever the config files change or we get a SIGHUP. Runs forever
+pub async fn watch_config(state: Arc<GlobalState>, source: ConfigSource)
+{
+    let files = source.files();
+    let mut last_modified = modified(&files);
+    let mut interval = tokio::time::interval(WATCHINTERVAL);
+
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("Couldn't listen for SIGHUP");
+
+    loop
+    {
+        #[cfg(unix)]
+        let hup = tokio::select! {
+            _ = interval.tick() => false,
+            _ = hangup.recv() => true
+        };
+
+        #[cfg(not(unix))]
+        let hup = { interval.tick().await; false };
+
+        let now_modified = modified(&files);
+        if hup {
+            tracing::info!("Got SIGHUP, reloading config");
+        }
+        else if now_modified != last_modified {
+            tracing::info!("Config files changed, reloading");
+        }
+        else {
+            continue;
+        }
+
+        last_modified = now_modified;
+        reload(&state, &source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory with a copy of the real settings.toml and whatever overlay is given
+    fn source_with(name: &str, overlay: Option<&str>) -> ConfigSource {
+        let dir = std::env::temp_dir().join(format!("sbs-reload-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/settings.toml"), dir.join("settings.toml")).unwrap();
+        if let Some(overlay) = overlay {
+            std::fs::write(dir.join("settings.Test.toml"), overlay).unwrap();
+        }
+        ConfigSource { dir: dir.to_string_lossy().into_owned(), environment: Some(String::from("Test")), ..Default::default() }
+    }
+
+    #[test]
+    fn overlay_is_checked() {
+        assert!(source_with("missing", None).read_checked().is_ok());
+
+        let config = source_with("good", Some("default_display_posts = 7")).read_checked().unwrap();
+        assert_eq!(config.default_display_posts, 7);
+
+        let error = source_with("broken", Some("default_display_posts = \"lots\"")).read_checked().unwrap_err();
+        assert!(error.contains("settings.Test.toml"), "{}", error);
+
+        let error = source_with("invalid", Some("default_display_posts = 0")).read_checked().unwrap_err();
+        assert!(error.contains("default_display_posts"), "{}", error);
+    }
+
+    #[test]
+    fn validate_catches_bad_values() {
+        let config = source_with("validate", None).read();
+        assert!(validate(&config).is_ok());
+
+        let mut bad = config.clone();
+        bad.long_cookie_expire = 0;
+        assert!(validate(&bad).is_err());
+
+        let mut bad = config.clone();
+        bad.host_address = String::from("nowhere");
+        assert!(validate(&bad).unwrap_err().contains("host_address"));
+
+        let mut bad = config;
+        bad.metrics_address = String::from("nowhere");
+        assert!(validate(&bad).unwrap_err().contains("metrics_address"));
+    }
+
+    #[test]
+    fn printed_config_hides_secrets() {
+        let mut config = source_with("redact", None).read();
+        config.metrics_token = String::from("hunter2");
+        assert!(!format!("{:#?}", config.redacted()).contains("hunter2"));
+        assert_eq!(config.metrics_token, "hunter2");
+    }
+
+    #[test]
+    fn startup_settings_are_kept() {
+        let old = source_with("keep", None).read();
+        let mut config = old.clone();
+        config.host_address = String::from("127.0.0.1:1");
+        config.log_level = String::from("trace");
+        config.default_display_posts = old.default_display_posts + 1;
+
+        keep_startup_settings(&old, &mut config);
+        assert_eq!(config.host_address, old.host_address);
+        assert_eq!(config.log_level, old.log_level);
+        assert_eq!(config.default_display_posts, old.default_display_posts + 1);
+    }
+}
@@ -0,0 +1,174 @@
+use serde::Deserialize;
+use warp::Reply;
+use warp::http::HeaderValue;
+use warp::hyper::header;
+use warp::path::FullPath;
+
+use crate::{Config, PRESESSIONCOOKIE};
+
+//The security headers every response gets on the way out (see the end of main). Which ones depends on
+//the route group: pages can't be framed at all, widgets are MEANT to be framed, static files don't need
+//a csp. The csp allows inline scripts only with the page's nonce; handlers stamp each page's nonce on
+//the response in NONCEHEADER (see with_stamp), and it's taken back off here. A new pre-session (see
+//common::csrf) goes out the same way, and becomes a cookie here.
+
+/// The private header pages use to pass their csp nonce out to decorate. Never leaves the server
+pub static NONCEHEADER: &str = "x-sbs-csp-nonce";
+
+/// Same, for a pre-session cookie the browser should be given
+pub static PRESESSIONHEADER: &str = "x-sbs-presession";
+
+/// What a rendered page hands back to decorate
+#[derive(Clone, Debug, Default)]
+pub struct PageStamp {
+    pub nonce: String,              //The nonce the page's inline scripts were given (empty for none)
+    pub presession: Option<String>  //Set if the browser had no session or pre-session, so its forms are tied to this
+}
+
+/// Where the nonce goes in the configured csp
+const NONCEPLACEHOLDER: &str = "{nonce}";
+
+/// The headers for one route group, from settings.toml. Empty (or 0) leaves that header off
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct SecurityPolicy {
+    pub csp: String,             //Content-Security-Policy, with {nonce} wherever the nonce goes
+    pub frame_ancestors: String, //Who can frame us. Added to the csp, and 'none'/'self' also set X-Frame-Options
+    pub hsts_max_age: u64,       //Seconds. ONLY set this if the site is always served over https!
+    pub referrer_policy: String,
+    pub nosniff: bool            //X-Content-Type-Options
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteGroup {
+    Pages,
+    Widgets,
+    Static
+}
+
+impl RouteGroup {
+    pub fn from_path(path: &str) -> Self {
+        let first = path.trim_start_matches('/').split('/').next().unwrap_or("");
+        match first {
+            "widget" => RouteGroup::Widgets,
+            "static" | "favicon.ico" | "robots.txt" => RouteGroup::Static,
+            _ => RouteGroup::Pages
+        }
+    }
+
+    pub fn policy<'a>(&self, config: &'a Config) -> &'a SecurityPolicy {
+        match self {
+            RouteGroup::Pages => &config.security_pages,
+            RouteGroup::Widgets => &config.security_widgets,
+            RouteGroup::Static => &config.security_static
+        }
+    }
+}
+
+/// A fresh nonce for a page's inline scripts. Has to be unguessable, so it's from the os rng, and if
+/// that fails there's no nonce at all (the csp then allows no inline scripts, see build_csp)
+pub fn new_nonce() -> Option<String>
+{
+    let mut bytes = [0u8; 16];
+    match getrandom::getrandom(&mut bytes) {
+        Ok(()) => Some(bytes.iter().map(|b| format!("{:02x}", b)).collect()),
+        Err(error) => {
+            //The page mostly works without its inline scripts
+            tracing::error!("Couldn't get random bytes for the csp nonce: {}", error);
+            None
+        }
+    }
+}
+
+/// Stamp a page's nonce (and pre-session) onto its response, so decorate can put them in the headers
+pub fn with_stamp(reply: impl Reply, stamp: &PageStamp) -> warp::reply::Response
+{
+    let mut response = warp::reply::with_header(reply, NONCEHEADER, stamp.nonce.as_str()).into_response();
+    if let Some(value) = stamp.presession.as_deref().and_then(|p| HeaderValue::from_str(p).ok()) {
+        response.headers_mut().insert(PRESESSIONHEADER, value);
+    }
+    response
+}
+
+fn build_csp(policy: &SecurityPolicy, nonce: &str) -> String
+{
+    let mut csp = if nonce.is_empty() {
+        //Without a nonce, any source that needed one goes. A directive left with no sources allows nothing
+        policy.csp.split(';').map(|directive| {
+            directive.split(' ').filter(|source| !source.contains(NONCEPLACEHOLDER)).collect::<Vec<_>>().join(" ")
+        }).collect::<Vec<_>>().join(";")
+    }
+    else {
+        policy.csp.replace(NONCEPLACEHOLDER, nonce)
+    };
+    if !policy.frame_ancestors.is_empty() {
+        if !csp.is_empty() && !csp.trim_end().ends_with(';') { csp.push(';'); }
+        if !csp.is_empty() { csp.push(' '); }
+        csp.push_str(&format!("frame-ancestors {}", policy.frame_ancestors));
+    }
+    csp
+}
+
+/// Add the security headers for the route group the path is in. Anything a handler already set is left alone
+pub fn decorate(path: FullPath, reply: impl Reply, config: &Config) -> warp::reply::Response
+{
+    let mut response = reply.into_response();
+    let headers = response.headers_mut();
+    //Responses that didn't render a page have no scripts to allow, so no nonce
+    let nonce = headers.remove(NONCEHEADER).and_then(|n| n.to_str().ok().map(String::from)).unwrap_or_default();
+    let policy = RouteGroup::from_path(path.as_str()).policy(config);
+
+    //Lax, not Strict: following a link here from elsewhere has to send it, or we'd hand out a new one and
+    //break the forms in every other tab
+    if let Some(presession) = headers.remove(PRESESSIONHEADER).and_then(|p| p.to_str().ok().map(String::from)) {
+        if let Ok(cookie) = HeaderValue::from_str(&format!("{}={}; Path=/; HttpOnly; SameSite=Lax", PRESESSIONCOOKIE, presession)) {
+            headers.append(header::SET_COOKIE, cookie);
+        }
+    }
+
+    let mut set = |name: header::HeaderName, value: String| {
+        if value.is_empty() || headers.contains_key(&name) { return; }
+        match HeaderValue::from_str(&value) {
+            Ok(value) => { headers.insert(name, value); },
+            Err(error) => tracing::warn!("Bad value for {} header: {}", name, error)
+        }
+    };
+
+    set(header::CONTENT_SECURITY_POLICY, build_csp(policy, &nonce));
+    set(header::X_FRAME_OPTIONS, String::from(match policy.frame_ancestors.trim() {
+        "'none'" => "DENY",
+        "'self'" => "SAMEORIGIN",
+        _ => "" //Can't say anything else with this header, the csp covers it
+    }));
+    if policy.hsts_max_age > 0 {
+        set(header::STRICT_TRANSPORT_SECURITY, format!("max-age={}", policy.hsts_max_age));
+    }
+    set(header::REFERRER_POLICY, policy.referrer_policy.clone());
+    if policy.nosniff {
+        set(header::X_CONTENT_TYPE_OPTIONS, String::from("nosniff"));
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(csp: &str) -> SecurityPolicy {
+        SecurityPolicy { csp: String::from(csp), frame_ancestors: String::from("'none'"), ..Default::default() }
+    }
+
+    #[test]
+    fn csp_gets_the_nonce() {
+        assert_eq!(build_csp(&policy("default-src 'self'; script-src 'self' 'nonce-{nonce}'"), "abc"),
+            "default-src 'self'; script-src 'self' 'nonce-abc'; frame-ancestors 'none'");
+    }
+
+    #[test]
+    fn csp_without_nonce_drops_it() {
+        assert_eq!(build_csp(&policy("default-src 'self'; script-src 'self' 'nonce-{nonce}'"), ""),
+            "default-src 'self'; script-src 'self'; frame-ancestors 'none'");
+        assert_eq!(build_csp(&policy("script-src 'nonce-{nonce}'"), ""), "script-src; frame-ancestors 'none'");
+    }
+}
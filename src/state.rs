@@ -1,19 +1,89 @@
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use bbscope::BBCode;
-use contentapi::endpoints::{ApiContext};
+use contentapi::endpoints::{ApiContext, ApiClient};
+use contentapi::policy::RequestPolicy;
+use contentapi::cache::ResponseCache;
+use contentapi::live::LiveHub;
 use common::{LinkConfig, MainLayoutData, UserConfig, PageContext};
 use warp::path::FullPath;
 
 use crate::Config;
+use crate::ratelimit::RateLimiter;
+use crate::security::PageStamp;
 
 
-/// The unchanging configuration for the current runtime. Mostly values read from 
-/// config, but some other constructed data too
+/// The configuration for the current runtime. Mostly values read from config, but some 
+/// other constructed data too. Only the config itself can change while running (see reload)
 pub struct GlobalState {
     pub link_config: LinkConfig,
     pub bbcode: BBCode,
-    pub config: Config
+    pub api_policy: RequestPolicy, //Shared so the circuit breaker sees every request
+    pub api_client: ApiClient,     //Shared so connections to the backend are reused
+    pub api_cache: Arc<ResponseCache>,
+    pub live_hub: Arc<LiveHub>,     //Shared so every tab a user has open streams from one backend socket
+    pub request_prefix: String,     //Different every run, so request ids from separate runs don't get mixed up in the logs
+    pub request_counter: AtomicU64,
+    pub rate_limiter: RateLimiter,
+    pub shutting_down: AtomicBool,  //Set on SIGTERM/SIGINT, see health::readyz
+    pub draining: tokio::sync::watch::Sender<bool>, //Set once we stop taking connections, see drained
+    pub config: RwLock<Arc<Config>> //Use config() to read it
+}
+
+impl GlobalState {
+    /// The config as of right now. Hold on to it for a whole request so it doesn't change halfway through
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Swap in a new config for everything from now on
+    pub fn set_config(&self, config: Config) {
+        *self.config.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(config);
+    }
+
+    /// Finishes once shutdown starts draining requests. Anything that would otherwise run forever (like
+    /// the live streams) should stop when this does, or shutdown waits on it until it gives up
+    pub fn drained(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let mut draining = self.draining.subscribe();
+        async move {
+            while !*draining.borrow() {
+                if draining.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// A new id to follow a single request through the logs (and any errors it produces)
+    pub fn next_request_id(&self) -> String {
+        format!("{}-{:x}", self.request_prefix, self.request_counter.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Layout data for pages rendered without asking the api anything (no user, no alert, default settings).
+    /// Only for pages that have to stay cheap, like the rate limit page
+    pub fn bare_layout_data(&self, path: &str) -> MainLayoutData {
+        MainLayoutData {
+            links: self.link_config.clone(),
+            user_config: UserConfig::default(),
+            current_path: String::from(path),
+            override_nav_path: None,
+            user: None,
+            user_token: None,
+            about_api: contentapi::About { 
+                version: String::new(), 
+                environment: String::new(), 
+                runtime: String::new(), 
+                contact: String::new() 
+            },
+            raw_alert: None,
+            csrf_token: String::new(), //No session or pre-session, so these pages can't have forms
+            csp_nonce: crate::security::new_nonce().unwrap_or_default(),
+
+            #[cfg(feature = "profiling")]
+            profiler: onestop::OneList::<onestop::OneDuration>::new()
+        }
+    }
 }
 
 /// A context generated for each request. Even if the request doesn't need all the data,
@@ -22,6 +92,8 @@ pub struct GlobalState {
 pub struct RequestContext {
     pub global_state: Arc<GlobalState>,
     pub page_context: PageContext,
+    pub span: tracing::Span, //Everything logged while handling this request should be in this span
+    pub stamp: PageStamp,    //The nonce is the same as the layout data's, but still here after that's been moved into a page
     //pub bbcode: BBCode, //Clones are cheap?
     //pub api_context: ApiContext,
     //pub layout_data: MainLayoutData,
@@ -31,42 +103,86 @@ pub struct RequestContext {
 }
 
 impl RequestContext {
-    pub async fn generate(state: Arc<GlobalState>, path: FullPath, token: Option<String>, config_raw: Option<String>) -> 
+    pub async fn generate(state: Arc<GlobalState>, path: FullPath, token: Option<String>, presession: Option<String>, config_raw: Option<String>, 
+        request_id: String, span: tracing::Span) -> 
         Result<Self, common::Error> 
     {
         #[cfg(feature = "profiling")]
         let profiler = onestop::OneList::<onestop::OneDuration>::new(); //One profiler per request
 
         #[cfg(feature = "profiling")]
-        let mut context = ApiContext::new_with_profiler(
-            state.config.api_endpoint.clone(), 
+        let context = ApiContext::new_with_profiler(
+            state.config().api_endpoint.clone(), 
             token.clone(),
             profiler.clone()
-        );
+        ).with_client(state.api_client.clone()).with_policy(state.api_policy.clone()).with_cache(state.api_cache.clone())
+         .with_live(state.live_hub.clone()).with_request_id(request_id);
 
         #[cfg(not(feature = "profiling"))]
         let context = ApiContext::new(
-            state.config.api_endpoint.clone(), 
+            state.config().api_endpoint.clone(), 
             token.clone()
+        ).with_client(state.api_client.clone()).with_policy(state.api_policy.clone()).with_cache(state.api_cache.clone())
+         .with_live(state.live_hub.clone()).with_request_id(request_id);
+
+        //Everything every page needs, all at once. The account settings only count if the token turns out
+        //to be good, but asking for them anyway is cheaper than waiting on the user first
+        let mut alert_context = context.clone();
+        let (user, account_config, about_api, alert) = tokio::join!(
+            context.get_me_safe(),
+            async {
+                match token {
+                    Some(_) => common::prefab::get_account_config(&context).await,
+                    None => Ok(None)
+                }
+            },
+            context.get_about(),
+            common::prefab::get_system_alert(&mut alert_context)
         );
 
-        let user_config = if let Some(config) = config_raw {
+        //Settings synced to the account follow the user around, so they win over whatever's on this device
+        let account_config = match user {
+            Some(_) => account_config.unwrap_or_else(|error| {
+                tracing::warn!("Couldn't load account settings: {}", error.to_verbose_string());
+                None
+            }),
+            None => None
+        };
+
+        let user_config = if let Some(config) = account_config {
+            config
+        }
+        else if let Some(config) = config_raw {
             serde_json::from_str::<UserConfig>(&config)?
         }
         else {
             UserConfig::default()
         };
 
+        //Logged out, forms are tied to a random pre-session cookie instead (see common::csrf). A browser
+        //that doesn't have one yet gets one with this page
+        let (presession, new_presession) = match (&token, presession) {
+            (Some(_), _) => (None, None),
+            (None, Some(presession)) => (Some(presession), None),
+            (None, None) => {
+                let presession = crate::csrf::new_presession();
+                (presession.clone(), presession)
+            }
+        };
+        let csrf_token = common::csrf::csrf_token(token.as_deref().or(presession.as_deref()));
+        let csp_nonce = crate::security::new_nonce().unwrap_or_default(); //Empty allows no inline scripts
         let layout_data = MainLayoutData 
         {
             links: state.link_config.clone(),
             user_config, //Local settings
             current_path: String::from(path.as_str()),
             override_nav_path: None,
-            user: context.get_me_safe().await,
+            user,
             user_token: token,
-            about_api: context.get_about().await?,
-            raw_alert: (common::prefab::get_system_alert(&mut context).await?).and_then(|x| x.text),
+            about_api: about_api?,
+            raw_alert: alert?.and_then(|x| x.text),
+            csrf_token,
+            csp_nonce: csp_nonce.clone(),
 
             #[cfg(feature = "profiling")]
             profiler: profiler.clone()
@@ -82,6 +198,8 @@ impl RequestContext {
             },
             //Custom construct bbcode so we copy the matchers but NOT the profiler!
             global_state: state,
+            span,
+            stamp: PageStamp { nonce: csp_nonce, presession: new_presession.clone() },
             profiler
         });
 
@@ -92,6 +210,8 @@ impl RequestContext {
             global_state: state,
             api_context: context,
             layout_data,
+            span,
+            stamp: PageStamp { nonce: csp_nonce, presession: new_presession.clone() },
         });
     }
 
@@ -5,7 +5,22 @@ var originalTitle = document.title;
 
 if(document.getElementById("newlinkplaceholder"))
 {
-    setInterval(() => check_new_activity(newlinkplaceholder.textContent), NEWACTIVITYINTERVAL);
+    //Prefer being told about new activity as it happens; only poll if the browser can't do that
+    if(window.EventSource && newlinkplaceholder.hasAttribute("data-live"))
+        listen_new_activity(newlinkplaceholder.getAttribute("data-live"));
+    else
+        setInterval(() => check_new_activity(newlinkplaceholder.textContent), NEWACTIVITYINTERVAL);
+}
+
+function listen_new_activity(link)
+{
+    var total = 0;
+    var source = new EventSource(link);
+    source.addEventListener("activity", (e) =>
+    {
+        total += Number(e.data);
+        make_or_update_alert(total);
+    });
 }
 
 function check_new_activity(link)
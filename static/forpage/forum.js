@@ -3,6 +3,7 @@ make_all_galleries();
 
 upgrade_edits();
 upgrade_replies();
+live_posts();
 
 function lazy_iframes()
 {
@@ -120,4 +121,25 @@ function upgrade_replies()
             }
         };
     }
-}
\ No newline at end of file
+}
+
+//New posts get pushed to us as they're made (see pages::live), already rendered. They don't get the
+//fancy inline edit/reply stuff, but the plain links work fine
+function live_posts()
+{
+    var container = document.getElementById("liveposts");
+    if(!container || !window.EventSource)
+        return;
+
+    var source = new EventSource(container.getAttribute("data-live"));
+    source.addEventListener("posts", (e) =>
+    {
+        var html = e.data;
+        var noposts = document.getElementById("noposts");
+        if(noposts) {
+            noposts.parentNode.removeChild(noposts);
+            html = html.replace(/^<hr class="smaller">/, "");
+        }
+        container.insertAdjacentHTML("beforeend", html);
+    });
+}